@@ -0,0 +1,337 @@
+//! Actors: state owned by a thread of its own and driven by messages.
+//!
+//! An actor implements `Handler<M>` for each message type `M` it accepts.
+//! Spawning it returns an `Addr`, which sends messages to the actor's
+//! mailbox, an `mpmc` queue that the actor's thread drains one message at
+//! a time, so handlers get `&mut self` without any locking.
+//!
+//! ```
+//! use myriad::actor::{self, Actor, Handler};
+//!
+//! struct Counter(u64);
+//! #[derive(Debug)]
+//! struct Add(u64);
+//!
+//! impl Actor for Counter {}
+//!
+//! impl Handler<Add> for Counter {
+//!     type Reply = u64;
+//!     fn handle(&mut self, Add(n): Add) -> u64 {
+//!         self.0 += n;
+//!         self.0
+//!     }
+//! }
+//!
+//! let counter = actor::spawn(Counter(0));
+//! counter.send(Add(2)).unwrap();
+//! assert_eq!(counter.call(Add(3)).unwrap(), 5);
+//! ```
+//!
+//! An actor stops once every `Addr` is dropped and its mailbox is empty,
+//! or when a handler panics. `spawn_supervised` instead replaces an actor
+//! that panicked with a fresh one and carries on with the rest of the
+//! mailbox.
+
+use mpmc::{self, Receiver, Sender};
+use pool::join::{self, Promise};
+use pool::JoinHandle;
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+pub trait Actor: Send + 'static {
+    /// Called on the actor's thread before it handles its first message
+    fn started(&mut self) {}
+
+    /// Called once the actor's mailbox is closed and empty. Not called if
+    /// a handler panicked.
+    fn stopped(&mut self) {}
+}
+
+/// Handling of messages of type `M`
+pub trait Handler<M>: Actor {
+    type Reply: Send + 'static;
+
+    fn handle(&mut self, msg: M) -> Self::Reply;
+}
+
+/// A message, and where to send the reply, waiting in a mailbox
+trait Envelope<A>: Send {
+    /// Handle the message, returning `false` if the handler panicked
+    fn deliver(self: Box<Self>, actor: &mut A) -> bool;
+
+    /// Unwrap the message of an envelope that couldn't be delivered
+    fn into_message(self: Box<Self>) -> Box<dyn Any + Send>;
+}
+
+struct Message<M, R> {
+    msg: M,
+    reply: Option<Promise<R>>,
+}
+
+impl<A, M> Envelope<A> for Message<M, A::Reply>
+where
+    A: Handler<M>,
+    M: Send + 'static,
+{
+    fn deliver(self: Box<Self>, actor: &mut A) -> bool {
+        let Message { msg, reply } = *self;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| actor.handle(msg)));
+        let ok = result.is_ok();
+        if let Some(reply) = reply {
+            reply.complete(result);
+        }
+        ok
+    }
+
+    fn into_message(self: Box<Self>) -> Box<dyn Any + Send> {
+        Box::new(self.msg)
+    }
+}
+
+type Mailbox<A> = Box<dyn Envelope<A>>;
+
+/// A handle for sending messages to an actor
+pub struct Addr<A> {
+    mailbox: Sender<Mailbox<A>>,
+}
+
+/// Spawn `actor` on a thread of its own. It stops for good if one of its
+/// handlers panics.
+pub fn spawn<A: Actor>(actor: A) -> Addr<A> {
+    let mut actor = Some(actor);
+    start(move || actor.take())
+}
+
+/// Spawn an actor created by `factory` on a thread of its own. Whenever a
+/// handler panics, the actor is dropped and `factory` creates a new one to
+/// handle the rest of its mailbox.
+pub fn spawn_supervised<A, F>(mut factory: F) -> Addr<A>
+where
+    A: Actor,
+    F: FnMut() -> A + Send + 'static,
+{
+    start(move || Some(factory()))
+}
+
+/// Run actors from `next` until the mailbox closes, or until `next` returns
+/// `None` after a panic
+fn start<A, F>(mut next: F) -> Addr<A>
+where
+    A: Actor,
+    F: FnMut() -> Option<A> + Send + 'static,
+{
+    // Dropping the inbox after a panic drops the envelopes left in it,
+    // failing their calls, including any a sender slips in as it goes
+    let (mailbox, inbox) = mpmc::Builder::new().drain_on_drop().queue();
+    thread::Builder::new()
+        .name("myriad-actor".into())
+        .spawn(move || {
+            while let Some(mut actor) = next() {
+                if run(&mut actor, &inbox) {
+                    actor.stopped();
+                    return;
+                }
+            }
+            drop(inbox);
+        })
+        .expect("failed to spawn actor thread");
+    Addr { mailbox }
+}
+
+/// Deliver messages to `actor` until the mailbox closes, returning `true`,
+/// or a handler panics, returning `false`
+fn run<A: Actor>(actor: &mut A, inbox: &Receiver<Mailbox<A>>) -> bool {
+    actor.started();
+    while let Ok(envelope) = inbox.recv() {
+        if !envelope.deliver(actor) {
+            return false;
+        }
+    }
+    true
+}
+
+impl<A: Actor> Addr<A> {
+    fn post<M>(&self, msg: M, reply: Option<Promise<A::Reply>>) -> Result<(), M>
+    where
+        A: Handler<M>,
+        M: Send + 'static,
+    {
+        self.mailbox
+            .send(Box::new(Message { msg, reply }))
+            .map_err(|envelope| {
+                *envelope
+                    .into_message()
+                    .downcast::<M>()
+                    .expect("envelope holds a message of its own type")
+            })
+    }
+
+    /// Queue `msg` for the actor without waiting for it to be handled.
+    /// Returns `msg` if the actor has stopped.
+    pub fn send<M>(&self, msg: M) -> Result<(), M>
+    where
+        A: Handler<M>,
+        M: Send + 'static,
+    {
+        self.post(msg, None)
+    }
+
+    /// Queue `msg` for the actor, returning a handle for the reply. Returns
+    /// `msg` if the actor has stopped.
+    pub fn ask<M>(&self, msg: M) -> Result<JoinHandle<A::Reply>, M>
+    where
+        A: Handler<M>,
+        M: Send + 'static,
+    {
+        let (promise, handle) = join::oneshot();
+        self.post(msg, Some(promise)).map(|()| handle)
+    }
+
+    /// Send `msg` and block until the actor replies. Fails with the panic
+    /// payload if the handler panicked, or if the actor stopped before it
+    /// got to `msg`.
+    pub fn call<M>(&self, msg: M) -> thread::Result<A::Reply>
+    where
+        A: Handler<M>,
+        M: Send + 'static,
+    {
+        match self.ask(msg) {
+            Ok(handle) => handle.join(),
+            Err(_) => Err(Box::new("actor has stopped")),
+        }
+    }
+}
+
+impl<A> Clone for Addr<A> {
+    fn clone(&self) -> Addr<A> {
+        Addr {
+            mailbox: self.mailbox.clone(),
+        }
+    }
+}
+
+impl<A> fmt::Debug for Addr<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Addr")
+            .field("queued", &self.mailbox.size_hint())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    struct Counter {
+        count: u64,
+        stopped: Option<Sender<u64>>,
+    }
+
+    #[derive(Debug)]
+    struct Add(u64);
+    #[derive(Debug)]
+    struct Get;
+    struct Fail;
+
+    impl Actor for Counter {
+        fn stopped(&mut self) {
+            if let Some(stopped) = self.stopped.take() {
+                stopped.send(self.count).unwrap();
+            }
+        }
+    }
+
+    impl Handler<Add> for Counter {
+        type Reply = ();
+        fn handle(&mut self, Add(n): Add) {
+            self.count += n;
+        }
+    }
+
+    impl Handler<Get> for Counter {
+        type Reply = u64;
+        fn handle(&mut self, _: Get) -> u64 {
+            self.count
+        }
+    }
+
+    impl Handler<Fail> for Counter {
+        type Reply = ();
+        fn handle(&mut self, _: Fail) {
+            panic!("handler failed");
+        }
+    }
+
+    fn counter() -> Counter {
+        Counter {
+            count: 0,
+            stopped: None,
+        }
+    }
+
+    #[test]
+    fn stops_when_dropped() {
+        let (tx, rx) = mpmc::queue();
+        let addr = spawn(Counter {
+            count: 0,
+            stopped: Some(tx),
+        });
+        for i in 0..10 {
+            addr.clone().send(Add(i)).unwrap();
+        }
+        let reply = addr.ask(Get).unwrap();
+        drop(addr);
+        assert_eq!(reply.join().unwrap(), 45);
+        assert_eq!(rx.recv(), Ok(45));
+    }
+
+    #[test]
+    fn panic_stops_unsupervised() {
+        let addr = spawn(counter());
+        addr.send(Add(1)).unwrap();
+        assert!(addr.call(Fail).is_err());
+        // The mailbox closes once the actor's thread has unwound
+        while addr.send(Add(1)).is_ok() {
+            thread::yield_now();
+        }
+        assert!(addr.call(Get).is_err());
+    }
+
+    #[test]
+    fn calls_racing_a_panic_fail() {
+        let addr = spawn(counter());
+        let (done, finished) = mpmc::queue();
+        let callers = (0..4)
+            .map(|_| {
+                let addr = addr.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    while addr.call(Get).is_ok() {}
+                    done.send(()).unwrap();
+                })
+            })
+            .collect::<Vec<_>>();
+        assert!(addr.call(Fail).is_err());
+        // A call left queued in the mailbox would block its caller for as
+        // long as `addr` is alive
+        for _ in 0..callers.len() {
+            assert_eq!(finished.recv_timeout(Duration::from_secs(5)), Ok(()));
+        }
+        for caller in callers {
+            caller.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn supervised_restart() {
+        let addr = spawn_supervised(counter);
+        addr.send(Add(5)).unwrap();
+        assert_eq!(addr.call(Get).unwrap(), 5);
+        assert!(addr.call(Fail).is_err());
+        addr.send(Add(2)).unwrap();
+        assert_eq!(addr.call(Get).unwrap(), 2);
+    }
+}
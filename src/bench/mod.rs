@@ -0,0 +1,197 @@
+//! Measure channel backends on the machine at hand.
+//!
+//! Which backend is fastest depends on the number of producers and
+//! consumers, on how many cores they get, and on the machine's cache
+//! topology, so `run` lets users measure their own setup instead of
+//! relying on someone else's numbers:
+//!
+//! ```
+//! use myriad::bench::{self, Backend, Config};
+//!
+//! let report = bench::run(Config {
+//!     backend: Backend::Stack,
+//!     producers: 2,
+//!     consumers: 2,
+//!     messages: 1000,
+//! });
+//! assert_eq!(report.messages, 2000);
+//! println!("{}", report);
+//! ```
+
+use mpmc::{self, Error, Receiver, Sender};
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use sync::Barrier;
+
+/// The channel implementation to measure
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Queue,
+    Stack,
+    Priority,
+}
+
+/// What to measure
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub backend: Backend,
+    /// Number of sending threads
+    pub producers: usize,
+    /// Number of receiving threads
+    pub consumers: usize,
+    /// Messages sent by each producer
+    pub messages: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            backend: Backend::Queue,
+            producers: 1,
+            consumers: 1,
+            messages: 100_000,
+        }
+    }
+}
+
+/// The results of a run. Latency is measured from just before a message
+/// is sent to just after it is received.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Report {
+    /// Messages sent and received
+    pub messages: u64,
+    /// Time from the producers starting to the last message received
+    pub elapsed: Duration,
+    /// Messages per second
+    pub throughput: f64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} messages in {:?} ({:.0}/s), latency p50 {:?} p90 {:?} p99 {:?} max {:?}",
+            self.messages, self.elapsed, self.throughput, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+fn channel(backend: Backend) -> (Sender<Instant>, Receiver<Instant>) {
+    match backend {
+        Backend::Queue => mpmc::queue(),
+        Backend::Stack => mpmc::stack(),
+        Backend::Priority => mpmc::priority(),
+    }
+}
+
+/// The latency below which `fraction` of `sorted` falls
+fn percentile(sorted: &[Duration], fraction: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_secs(0);
+    }
+    let index = ((sorted.len() - 1) as f64 * fraction).round() as usize;
+    sorted[index]
+}
+
+/// Run `config` to completion on freshly spawned threads and report how it
+/// went.
+///
+/// Panics if `producers` or `consumers` is zero.
+pub fn run(config: Config) -> Report {
+    assert!(
+        config.producers > 0 && config.consumers > 0,
+        "a benchmark needs producers and consumers"
+    );
+    let (tx, rx) = channel(config.backend);
+    // Everyone starts together, and the clock starts once they have
+    let start = Arc::new(Barrier::new(config.producers + config.consumers + 1));
+
+    let consumers = (0..config.consumers)
+        .map(|_| {
+            let (rx, start) = (rx.clone(), start.clone());
+            thread::spawn(move || {
+                let mut latencies = Vec::new();
+                start.wait();
+                loop {
+                    match rx.recv() {
+                        Ok(sent) => latencies.push(sent.elapsed()),
+                        Err(Error::Disconnected) => return latencies,
+                        Err(_) => (),
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(rx);
+    let producers = (0..config.producers)
+        .map(|_| {
+            let (tx, start) = (tx.clone(), start.clone());
+            let messages = config.messages;
+            thread::spawn(move || {
+                start.wait();
+                for _ in 0..messages {
+                    // Consumers only stop once every producer is done
+                    let _ = tx.send(Instant::now());
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(tx);
+
+    start.wait();
+    let began = Instant::now();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    let mut latencies = Vec::with_capacity(config.producers * config.messages);
+    for consumer in consumers {
+        latencies.extend(consumer.join().unwrap());
+    }
+    let elapsed = began.elapsed();
+    latencies.sort_unstable();
+
+    Report {
+        messages: latencies.len() as u64,
+        elapsed,
+        throughput: latencies.len() as f64 / elapsed.as_secs_f64(),
+        p50: percentile(&latencies, 0.5),
+        p90: percentile(&latencies, 0.9),
+        p99: percentile(&latencies, 0.99),
+        max: latencies.last().cloned().unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_backend() {
+        for &backend in &[Backend::Queue, Backend::Stack, Backend::Priority] {
+            let report = run(Config {
+                backend,
+                producers: 3,
+                consumers: 2,
+                messages: 500,
+            });
+            assert_eq!(report.messages, 1500);
+            assert!(report.p50 <= report.p90 && report.p90 <= report.p99);
+            assert!(report.p99 <= report.max);
+            assert!(report.throughput > 0.0);
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let sorted = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&sorted, 0.5), Duration::from_millis(51));
+        assert_eq!(percentile(&sorted, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&[], 0.5), Duration::from_secs(0));
+    }
+}
@@ -0,0 +1,547 @@
+//! The parts of the `crossbeam-channel` API that go beyond plain channels,
+//! built with the `crossbeam-compat` feature.
+//!
+//! `after`, `tick` and `never` return receivers that behave like channels
+//! but are driven by the clock instead of by senders, so they have the same
+//! `Receiver` type as `unbounded`'s and mix freely with real channels in a
+//! `Select`. `Select` itself follows crossbeam's shape: register each
+//! receiver and get back an index, select an operation, then complete it
+//! on the receiver with that index. A blocked `select` sleeps until one
+//! of its channels gets a message or disconnects, or its next timer is
+//! due, like `mpmc::Select`.
+//!
+//! ```
+//! use myriad::compat::crossbeam::{after, unbounded, Select};
+//! use std::time::Duration;
+//!
+//! let (tx, rx) = unbounded::<u32>();
+//! let timeout = after(Duration::from_millis(10));
+//! let mut select = Select::new();
+//! let message = select.recv(&rx);
+//! let timed_out = select.recv(&timeout);
+//! let operation = select.select();
+//! match operation.index() {
+//!     i if i == message => println!("got {:?}", operation.recv(&rx)),
+//!     i if i == timed_out => assert!(operation.recv(&timeout).is_ok()),
+//!     _ => unreachable!(),
+//! }
+//! # drop(tx);
+//! ```
+//!
+//! Operations are receives only, and bounded channels are left to
+//! `compat::mpsc::sync_channel`. The error types are std's, which
+//! crossbeam's mirror.
+
+use mpmc::{self, Error, Watch, Watcher};
+use primitive::blocking::Mutex;
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+/// An unbounded channel
+pub fn unbounded<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpmc::queue();
+    (
+        Sender { inner: tx },
+        Receiver {
+            flavor: Flavor::Channel(rx),
+        },
+    )
+}
+
+/// A receiver that gets a single message, the time it was due, once
+/// `duration` has passed
+pub fn after(duration: Duration) -> Receiver<Instant> {
+    Receiver {
+        flavor: Flavor::At {
+            deadline: Instant::now() + duration,
+            fired: Arc::new(AtomicBool::new(false)),
+            make: |at| at,
+        },
+    }
+}
+
+/// A receiver that gets a message every `period`, the time it was due.
+/// Ticks that pass while nobody is receiving are skipped, and the next one
+/// is due a full period after the late one was received.
+pub fn tick(period: Duration) -> Receiver<Instant> {
+    Receiver {
+        flavor: Flavor::Tick {
+            next: Arc::new(Mutex::new(Instant::now() + period)),
+            period,
+            make: |at| at,
+        },
+    }
+}
+
+/// A receiver that never gets a message and never disconnects, for
+/// disabling an operation in a `Select`
+pub fn never<T: Send>() -> Receiver<T> {
+    Receiver {
+        flavor: Flavor::Never,
+    }
+}
+
+/// The sending half of an `unbounded` channel
+pub struct Sender<T: Send> {
+    inner: mpmc::Sender<T>,
+}
+
+impl<T: Send> Sender<T> {
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        self.inner.send(msg).map_err(SendError)
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+enum Flavor<T: Send> {
+    Channel(mpmc::Receiver<T>),
+    /// From `after`. Clones share `fired`, so only one of them gets the
+    /// message. `make` is the identity, and only there because the type
+    /// can't say that `T` is `Instant`.
+    At {
+        deadline: Instant,
+        fired: Arc<AtomicBool>,
+        make: fn(Instant) -> T,
+    },
+    /// From `tick`, with clones sharing the schedule
+    Tick {
+        next: Arc<Mutex<Instant>>,
+        period: Duration,
+        make: fn(Instant) -> T,
+    },
+    Never,
+}
+
+/// The receiving half of a channel, or a timer from `after`, `tick` or
+/// `never`
+pub struct Receiver<T: Send> {
+    flavor: Flavor<T>,
+}
+
+impl<T: Send> Receiver<T> {
+    /// When a timer's next message is due
+    fn due(&self) -> Option<Instant> {
+        match self.flavor {
+            Flavor::At {
+                deadline,
+                ref fired,
+                ..
+            } if !fired.load(Ordering::Relaxed) => Some(deadline),
+            Flavor::Tick { ref next, .. } => {
+                Some(*next.lock().unwrap_or_else(PoisonError::into_inner))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.flavor {
+            Flavor::Channel(ref rx) => match rx.try_recv() {
+                Ok(msg) => Ok(msg),
+                Err(Error::Empty) => Err(TryRecvError::Empty),
+                Err(_) => Err(TryRecvError::Disconnected),
+            },
+            Flavor::At {
+                deadline,
+                ref fired,
+                make,
+            } => {
+                if Instant::now() >= deadline && !fired.swap(true, Ordering::Relaxed) {
+                    Ok(make(deadline))
+                } else {
+                    Err(TryRecvError::Empty)
+                }
+            }
+            Flavor::Tick {
+                ref next,
+                period,
+                make,
+            } => {
+                let mut next = next.lock().unwrap_or_else(PoisonError::into_inner);
+                let now = Instant::now();
+                if now >= *next {
+                    let due = *next;
+                    *next = now + period;
+                    Ok(make(due))
+                } else {
+                    Err(TryRecvError::Empty)
+                }
+            }
+            Flavor::Never => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if let Flavor::Channel(ref rx) = self.flavor {
+            let result = match deadline {
+                Some(deadline) => {
+                    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => rx.recv(),
+            };
+            return match result {
+                Ok(msg) => Ok(msg),
+                Err(Error::Timeout) => Err(RecvTimeoutError::Timeout),
+                Err(_) => Err(RecvTimeoutError::Disconnected),
+            };
+        }
+        loop {
+            if let Ok(msg) = self.try_recv() {
+                return Ok(msg);
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            // A timer that will never fire again sleeps until the
+            // deadline, or for good
+            match self.due().into_iter().chain(deadline).min() {
+                Some(wake) => thread::sleep(wake.saturating_duration_since(now)),
+                None => thread::park(),
+            }
+        }
+    }
+
+    /// Block until a message arrives, or the channel is disconnected and
+    /// empty. Timers never disconnect.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+}
+
+impl<T: Send> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        let flavor = match self.flavor {
+            Flavor::Channel(ref rx) => Flavor::Channel(rx.clone()),
+            Flavor::At {
+                deadline,
+                ref fired,
+                make,
+            } => Flavor::At {
+                deadline,
+                fired: fired.clone(),
+                make,
+            },
+            Flavor::Tick {
+                ref next,
+                period,
+                make,
+            } => Flavor::Tick {
+                next: next.clone(),
+                period,
+                make,
+            },
+            Flavor::Never => Flavor::Never,
+        };
+        Receiver { flavor }
+    }
+}
+
+impl<T: Send> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flavor = match self.flavor {
+            Flavor::Channel(_) => "channel",
+            Flavor::At { .. } => "after",
+            Flavor::Tick { .. } => "tick",
+            Flavor::Never => "never",
+        };
+        f.debug_struct("Receiver").field("flavor", &flavor).finish()
+    }
+}
+
+type Taken = Result<Box<dyn Any>, RecvError>;
+
+/// A receiver registered with a `Select`, with its message type erased
+trait Operation {
+    /// The message or disconnect, if the operation is ready
+    fn take(&self) -> Option<Taken>;
+
+    /// Wake `watcher` when a channel's operation may be ready. Timers
+    /// give their `due` time instead.
+    fn watch<'a>(&'a self, watcher: &Watcher) -> Option<Watch<'a>>;
+
+    fn due(&self) -> Option<Instant>;
+
+    fn addr(&self) -> *const ();
+}
+
+impl<T: Send + 'static> Operation for Receiver<T> {
+    fn take(&self) -> Option<Taken> {
+        match self.try_recv() {
+            Ok(msg) => Some(Ok(Box::new(msg))),
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+            Err(TryRecvError::Empty) => None,
+        }
+    }
+
+    fn watch<'a>(&'a self, watcher: &Watcher) -> Option<Watch<'a>> {
+        match self.flavor {
+            Flavor::Channel(ref rx) => Some(rx.watch(watcher)),
+            _ => None,
+        }
+    }
+
+    fn due(&self) -> Option<Instant> {
+        Receiver::due(self)
+    }
+
+    fn addr(&self) -> *const () {
+        self as *const Receiver<T> as *const ()
+    }
+}
+
+/// No operation was ready for `Select::try_select`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrySelectError;
+
+/// No operation became ready before `Select::select_timeout` timed out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelectTimeoutError;
+
+/// Receives from whichever of several receivers is ready first, with each
+/// receiver carrying its own type of message. A disconnected channel counts
+/// as ready, so its receive completes with an error.
+pub struct Select<'a> {
+    operations: Vec<&'a dyn Operation>,
+    /// The operation to check first next time, so ready ones take turns
+    next: usize,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Select<'a> {
+        Select {
+            operations: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Add a receive from `r`, and return the index `select` reports when
+    /// it chooses this one
+    pub fn recv<T: Send + 'static>(&mut self, r: &'a Receiver<T>) -> usize {
+        self.operations.push(r);
+        self.operations.len() - 1
+    }
+
+    fn selected(&mut self, index: usize, taken: Taken) -> SelectedOperation<'a> {
+        self.next = (index + 1) % self.operations.len();
+        SelectedOperation {
+            index,
+            addr: self.operations[index].addr(),
+            taken,
+            _borrow: PhantomData,
+        }
+    }
+
+    /// Complete whichever operation is ready now, if any
+    pub fn try_select(&mut self) -> Result<SelectedOperation<'a>, TrySelectError> {
+        for offset in 0..self.operations.len() {
+            let index = (self.next + offset) % self.operations.len();
+            if let Some(taken) = self.operations[index].take() {
+                return Ok(self.selected(index, taken));
+            }
+        }
+        Err(TrySelectError)
+    }
+
+    fn select_until(&mut self, deadline: Option<Instant>) -> Option<SelectedOperation<'a>> {
+        assert!(!self.operations.is_empty(), "no operations to select from");
+        // Watch before the first check, so a message sent after it wakes
+        // the watcher
+        let watcher = Watcher::new();
+        let _watches = self
+            .operations
+            .iter()
+            .filter_map(|&operation| operation.watch(&watcher))
+            .collect::<Vec<_>>();
+        let mut selected = None;
+        loop {
+            // Wake for the next timer, which no channel will do
+            let wake = self
+                .operations
+                .iter()
+                .filter_map(|operation| operation.due())
+                .chain(deadline)
+                .min();
+            let ready = watcher.wait_until(
+                &mut || {
+                    selected = self.try_select().ok();
+                    selected.is_some()
+                },
+                wake,
+            );
+            if ready || wake == deadline {
+                return selected;
+            }
+        }
+    }
+
+    /// Block until an operation is ready and complete it.
+    ///
+    /// Panics if no operations were added.
+    pub fn select(&mut self) -> SelectedOperation<'a> {
+        self.select_until(None)
+            .expect("selecting without a deadline returned")
+    }
+
+    /// Like `select`, giving up after `timeout`
+    pub fn select_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<SelectedOperation<'a>, SelectTimeoutError> {
+        self.select_until(Some(Instant::now() + timeout))
+            .ok_or(SelectTimeoutError)
+    }
+}
+
+impl<'a> Default for Select<'a> {
+    fn default() -> Select<'a> {
+        Select::new()
+    }
+}
+
+impl<'a> fmt::Debug for Select<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Select")
+            .field("operations", &self.operations.len())
+            .finish()
+    }
+}
+
+/// An operation `Select` chose. Its message was already received, and
+/// `recv` hands it over.
+#[must_use = "the selected operation's message is lost unless `recv` is called"]
+pub struct SelectedOperation<'a> {
+    index: usize,
+    addr: *const (),
+    taken: Taken,
+    _borrow: PhantomData<&'a ()>,
+}
+
+impl<'a> SelectedOperation<'a> {
+    /// The index `Select::recv` returned for this operation
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The result of the receive.
+    ///
+    /// Panics if `r` isn't the receiver this operation was added with.
+    pub fn recv<T: Send + 'static>(self, r: &Receiver<T>) -> Result<T, RecvError> {
+        assert!(
+            Operation::addr(r) == self.addr,
+            "completed a selected operation with the wrong receiver"
+        );
+        self.taken.map(|msg| {
+            *msg.downcast()
+                .expect("a receiver's messages all have its type")
+        })
+    }
+}
+
+impl<'a> fmt::Debug for SelectedOperation<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SelectedOperation")
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn timers() {
+        let start = Instant::now();
+        let once = after(Duration::from_millis(5));
+        assert_eq!(once.try_recv(), Err(TryRecvError::Empty));
+        assert!(once.recv().unwrap() >= start + Duration::from_millis(5));
+        // Fired once, and for every clone
+        assert_eq!(
+            once.clone().recv_timeout(Duration::from_millis(5)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let ticks = tick(Duration::from_millis(2));
+        let first = ticks.recv().unwrap();
+        assert!(ticks.recv().unwrap() >= first + Duration::from_millis(2));
+
+        assert_eq!(
+            never::<u32>().recv_timeout(Duration::from_millis(2)),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn selects_ready_operation() {
+        let (tx, rx) = unbounded();
+        let (_names_tx, names) = unbounded::<String>();
+        let timeout = after(Duration::from_secs(60));
+        tx.send(3u32).unwrap();
+        let mut select = Select::new();
+        let name = select.recv(&names);
+        let number = select.recv(&rx);
+        select.recv(&timeout);
+        let operation = select.select();
+        assert_eq!(operation.index(), number);
+        assert_eq!(operation.recv(&rx), Ok(3));
+        assert!(select.try_select().is_err());
+        assert_eq!(
+            select.select_timeout(Duration::from_millis(2)).unwrap_err(),
+            SelectTimeoutError
+        );
+
+        // A disconnected channel is ready, with an error
+        drop(tx);
+        let operation = select.select();
+        assert_ne!(operation.index(), name);
+        assert_eq!(operation.recv(&rx), Err(RecvError));
+    }
+
+    #[test]
+    fn select_times_out_with_after() {
+        let (_tx, rx) = unbounded::<u32>();
+        let timeout = after(Duration::from_millis(5));
+        let mut select = Select::new();
+        select.recv(&rx);
+        let timed_out = select.recv(&timeout);
+        let operation = select.select();
+        assert_eq!(operation.index(), timed_out);
+        assert!(operation.recv(&timeout).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong receiver")]
+    fn wrong_receiver() {
+        let (tx, rx) = unbounded::<u32>();
+        let other = rx.clone();
+        tx.send(1).unwrap();
+        let mut select = Select::new();
+        select.recv(&rx);
+        let _ = select.select().recv(&other);
+    }
+}
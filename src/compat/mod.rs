@@ -0,0 +1,7 @@
+//! Drop-in replacements for other channel APIs, backed by `mpmc`
+//! channels, so code written against them can switch to this crate by
+//! changing its imports.
+
+#[cfg(feature = "crossbeam-compat")]
+pub mod crossbeam;
+pub mod mpsc;
@@ -0,0 +1,334 @@
+//! A drop-in replacement for `std::sync::mpsc`.
+//!
+//! The constructors, types and methods have the same names and signatures
+//! as the standard library's, and the error types are the standard
+//! library's own, so switching is a matter of changing the import:
+//!
+//! ```
+//! use myriad::compat::mpsc::{channel, RecvTimeoutError};
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let (tx, rx) = channel();
+//! thread::spawn(move || tx.send(1).unwrap());
+//! assert_eq!(rx.recv(), Ok(1));
+//! assert_eq!(
+//!     rx.recv_timeout(Duration::from_millis(1)),
+//!     Err(RecvTimeoutError::Disconnected)
+//! );
+//! ```
+//!
+//! Both flavors are `mpmc::queue` channels underneath. `sync_channel`
+//! bounds the queue with a `Semaphore` whose permits are free slots: a
+//! send takes one, blocking while there are none, and every receive hands
+//! one back. A bound of zero, a rendezvous channel in std, holds one
+//! message here, so `send` returns once the message is queued rather than
+//! once it is received.
+
+use mpmc::{self, Error};
+use std::fmt;
+use std::mem::ManuallyDrop;
+use std::sync::Arc;
+use std::time::Duration;
+use sync::Semaphore;
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+/// Enough permits to let every blocked sender through, once the receiver
+/// is gone, without overflowing the count
+const RELEASE_ALL: usize = usize::MAX / 2;
+
+/// An unbounded channel, like `std::sync::mpsc::channel`
+pub fn channel<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = mpmc::queue();
+    (
+        Sender { inner: tx },
+        Receiver {
+            inner: ManuallyDrop::new(rx),
+            slots: None,
+        },
+    )
+}
+
+/// A channel holding at most `bound` messages, like
+/// `std::sync::mpsc::sync_channel`
+pub fn sync_channel<T: Send + 'static>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let (tx, rx) = mpmc::queue();
+    let slots = Arc::new(Semaphore::new(bound.max(1)));
+    (
+        SyncSender {
+            inner: tx,
+            slots: slots.clone(),
+        },
+        Receiver {
+            inner: ManuallyDrop::new(rx),
+            slots: Some(slots),
+        },
+    )
+}
+
+/// The sending half of a `channel`
+pub struct Sender<T: Send> {
+    inner: mpmc::Sender<T>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Send `t` without blocking, or return it if the receiver is gone
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner.send(t).map_err(SendError)
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// The sending half of a `sync_channel`
+pub struct SyncSender<T: Send> {
+    inner: mpmc::Sender<T>,
+    slots: Arc<Semaphore>,
+}
+
+impl<T: Send> SyncSender<T> {
+    /// Send `t`, blocking while the channel is full, or return it if the
+    /// receiver is gone
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.slots.acquire().forget();
+        self.inner.send(t).map_err(SendError)
+    }
+
+    /// Send `t` if there is room for it right now
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        match self.slots.try_acquire() {
+            Some(slot) => {
+                slot.forget();
+                self.inner.send(t).map_err(TrySendError::Disconnected)
+            }
+            // Once the receiver is gone there are always permits, so
+            // this can't hide a disconnect
+            None => Err(TrySendError::Full(t)),
+        }
+    }
+}
+
+impl<T: Send> Clone for SyncSender<T> {
+    fn clone(&self) -> SyncSender<T> {
+        SyncSender {
+            inner: self.inner.clone(),
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SyncSender").finish_non_exhaustive()
+    }
+}
+
+/// The receiving half of either kind of channel. Unlike the other halves
+/// it can't be cloned, as in std.
+pub struct Receiver<T: Send> {
+    /// Dropped by hand, so the channel disconnects before blocked senders
+    /// are let through
+    inner: ManuallyDrop<mpmc::Receiver<T>>,
+    /// Free slots of a `sync_channel`
+    slots: Option<Arc<Semaphore>>,
+}
+
+impl<T: Send> Receiver<T> {
+    fn received(&self, t: T) -> T {
+        if let Some(ref slots) = self.slots {
+            slots.add_permits(1);
+        }
+        t
+    }
+
+    /// Receive a message if one is queued
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.inner.try_recv() {
+            Ok(t) => Ok(self.received(t)),
+            Err(Error::Empty) => Err(TryRecvError::Empty),
+            Err(_) => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    /// Block until a message arrives, or every sender is gone and the
+    /// channel is empty
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.inner.recv() {
+            Ok(t) => Ok(self.received(t)),
+            Err(_) => Err(RecvError),
+        }
+    }
+
+    /// Like `recv`, giving up after `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match self.inner.recv_timeout(timeout) {
+            Ok(t) => Ok(self.received(t)),
+            Err(Error::Timeout) => Err(RecvTimeoutError::Timeout),
+            Err(_) => Err(RecvTimeoutError::Disconnected),
+        }
+    }
+
+    /// Receive messages until every sender is gone
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// Receive the messages queued right now, without blocking
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // Not used again, and `Drop` only runs once
+        unsafe { ManuallyDrop::drop(&mut self.inner) };
+        // Senders blocked on a full channel wake to find it disconnected
+        if let Some(ref slots) = self.slots {
+            slots.add_permits(RELEASE_ALL);
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// Blocking iterator returned by `Receiver::iter`
+#[derive(Debug)]
+pub struct Iter<'a, T: Send> {
+    rx: &'a Receiver<T>,
+}
+
+impl<'a, T: Send> Iterator for Iter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+/// Non-blocking iterator returned by `Receiver::try_iter`
+#[derive(Debug)]
+pub struct TryIter<'a, T: Send> {
+    rx: &'a Receiver<T>,
+}
+
+impl<'a, T: Send> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// Blocking iterator that owns its `Receiver`
+#[derive(Debug)]
+pub struct IntoIter<T: Send> {
+    rx: Receiver<T>,
+}
+
+impl<T: Send> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T: Send> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Send> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn iterators() {
+        let (tx, rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        let senders: Vec<_> = (0..3)
+            .map(|i| {
+                let tx = tx.clone();
+                thread::spawn(move || tx.send(i).unwrap())
+            })
+            .collect();
+        drop(tx);
+        let mut all: Vec<_> = rx.into_iter().collect();
+        all.sort_unstable();
+        assert_eq!(all, vec![0, 1, 2]);
+        for sender in senders {
+            sender.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn sync_channel_bounds_sends() {
+        let (tx, rx) = sync_channel(2);
+        tx.send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+        let blocked = {
+            let tx = tx.clone();
+            thread::spawn(move || tx.send(3))
+        };
+        assert_eq!(rx.recv(), Ok(1));
+        blocked.join().unwrap().unwrap();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn disconnects() {
+        let (tx, rx) = sync_channel(1);
+        tx.send(1).unwrap();
+        let blocked = {
+            let tx = tx.clone();
+            thread::spawn(move || tx.send(2))
+        };
+        thread::sleep(Duration::from_millis(10));
+        drop(rx);
+        // The blocked sender gets its message back
+        assert_eq!(blocked.join().unwrap(), Err(SendError(2)));
+        assert_eq!(tx.try_send(3), Err(TrySendError::Disconnected(3)));
+
+        let (tx, rx) = channel::<u32>();
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
@@ -0,0 +1,287 @@
+//! The epoch collector. See the parent module for how it works.
+
+#[cfg(not(all(feature = "deterministic", not(loom))))]
+use primitive::{fence, Arc, AtomicUsize, Mutex, Ordering::*};
+// The collector's state outlives any one `testing::sched` run and is
+// shared with every other test in the process, so its operations stay out
+// of the schedule. Otherwise what earlier runs left behind would change
+// how many turns a run takes, and a seed wouldn't replay. Nothing here
+// blocks while holding a lock, so it can't stall the scheduler either.
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::mem;
+#[cfg(all(feature = "deterministic", not(loom)))]
+use std::sync::{
+    atomic::{fence, AtomicUsize, Ordering::*},
+    Arc, Mutex,
+};
+#[cfg(not(loom))]
+use sync::Lazy;
+
+use super::Deferred;
+
+/// Deferred functions a thread collects before handing them to the global
+/// list and trying to collect garbage
+const BAG_SIZE: usize = 64;
+
+/// Bit set in `Local::state` while the thread is pinned
+const PINNED: usize = 1;
+
+struct Global {
+    epoch: AtomicUsize,
+    participants: Mutex<Vec<Arc<Local>>>,
+    /// Full bags of deferred functions, tagged with the epoch they were
+    /// sealed in
+    garbage: Mutex<Vec<(usize, Vec<Deferred>)>>,
+}
+
+/// Per-thread participant state
+struct Local {
+    /// `epoch << 1 | PINNED` while pinned, 0 otherwise
+    state: AtomicUsize,
+    /// Number of live guards on the owning thread. Only the owning thread
+    /// touches this, it is atomic just so `Local` can be shared.
+    guards: AtomicUsize,
+}
+
+/// A thread's registration with the collector. Unregisters on drop, which
+/// is on thread exit for the thread-local handle.
+struct Handle {
+    /// `GLOBAL` itself. loom tears its statics down before the main
+    /// thread's thread-locals, so a handle keeps its own reference for its
+    /// drop.
+    global: Arc<Global>,
+    local: Arc<Local>,
+    /// Functions deferred on this thread, not yet handed to `GLOBAL`
+    bag: RefCell<Vec<Deferred>>,
+}
+
+#[cfg(not(loom))]
+static GLOBAL: Lazy<Arc<Global>> = Lazy::new(|| Arc::new(Global::new()));
+
+#[cfg(not(loom))]
+thread_local! {
+    static HANDLE: Handle = Handle::register();
+}
+
+// loom resets its statics and thread-locals between the executions it
+// explores, so they must be its own
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref GLOBAL: Arc<Global> = Arc::new(Global::new());
+}
+
+#[cfg(loom)]
+loom::thread_local! {
+    static HANDLE: Handle = Handle::register();
+}
+
+impl Handle {
+    fn register() -> Handle {
+        let local = Arc::new(Local {
+            state: AtomicUsize::new(0),
+            guards: AtomicUsize::new(0),
+        });
+        GLOBAL.participants.lock().unwrap().push(local.clone());
+        Handle {
+            global: GLOBAL.clone(),
+            local,
+            bag: RefCell::new(Vec::with_capacity(BAG_SIZE)),
+        }
+    }
+
+    /// Hand the bag to `GLOBAL`, to be run two epochs from now. The epoch
+    /// is read after everything in the bag was deferred, so it is no
+    /// earlier than the epoch any of it was deferred in.
+    fn seal(&self) {
+        let bag = mem::replace(&mut *self.bag.borrow_mut(), Vec::with_capacity(BAG_SIZE));
+        if !bag.is_empty() {
+            let epoch = self.global.epoch.load(Relaxed);
+            self.global.garbage.lock().unwrap().push((epoch, bag));
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.seal();
+        self.global
+            .participants
+            .lock()
+            .unwrap()
+            .retain(|local| !Arc::ptr_eq(local, &self.local));
+    }
+}
+
+impl Global {
+    fn new() -> Global {
+        Global {
+            epoch: AtomicUsize::new(0),
+            participants: Mutex::new(Vec::new()),
+            garbage: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Advance the global epoch if every pinned thread has observed the
+    /// current one, and run all garbage that is now unreachable.
+    ///
+    /// Collection is opportunistic: a thread that finds either lock taken
+    /// skips that step rather than queueing behind another collector, and
+    /// the garbage waits for the next attempt.
+    fn collect(&self) {
+        fence(SeqCst);
+        let epoch = self.epoch.load(Relaxed);
+        let advanced = match self.participants.try_lock() {
+            Ok(participants) => participants.iter().all(|local| {
+                let state = local.state.load(Relaxed);
+                state & PINNED == 0 || state >> 1 == epoch
+            }),
+            Err(_) => false,
+        };
+        if advanced {
+            let _ = self
+                .epoch
+                .compare_exchange(epoch, epoch.wrapping_add(1), Release, Relaxed);
+        }
+        fence(Acquire);
+
+        let epoch = self.epoch.load(Relaxed);
+        let ready = match self.garbage.try_lock() {
+            Ok(mut garbage) => {
+                let (ready, pending) = mem::take(&mut *garbage)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|&(sealed, _)| epoch.wrapping_sub(sealed) >= 2);
+                *garbage = pending;
+                ready
+            }
+            Err(_) => return,
+        };
+        // Run outside the lock, deferred functions may defer more garbage
+        for deferred in ready.into_iter().flat_map(|(_, bag)| bag) {
+            deferred();
+        }
+    }
+}
+
+/// Keeps the current thread pinned while alive. Shared pointers loaded
+/// while a guard is alive won't be freed until after it is dropped.
+pub struct Guard {
+    /// The thread-local handle, which outlives every guard since guards
+    /// can't leave the thread, or else `_owned`. A raw pointer avoids
+    /// touching the refcount on the pin path.
+    handle: *const Handle,
+    /// A handle registered just for this guard, when the thread-local one
+    /// has already been destroyed
+    _owned: Option<Box<Handle>>,
+    // Guards are tied to the thread that pinned
+    _marker: PhantomData<*const ()>,
+}
+
+/// Pin the current thread.
+///
+/// Pinning is reentrant: nested guards are cheap and the thread stays
+/// pinned until the outermost guard is dropped. Pinning from a
+/// thread-local destructor, after the thread's handle is gone, registers
+/// a temporary participant for the guard instead, which is slower but
+/// just as safe.
+pub fn pin() -> Guard {
+    let (handle, owned) = match HANDLE.try_with(|handle| handle as *const Handle) {
+        Ok(handle) => (handle, None),
+        Err(_) => {
+            let owned = Box::new(Handle::register());
+            (&*owned as *const Handle, Some(owned))
+        }
+    };
+    let local = unsafe { &*(*handle).local };
+    let guards = local.guards.load(Relaxed);
+    local.guards.store(guards + 1, Relaxed);
+    if guards == 0 {
+        let epoch = GLOBAL.epoch.load(Relaxed);
+        local.state.store(epoch << 1 | PINNED, Relaxed);
+        // The pin must be visible before any shared pointer is loaded
+        fence(SeqCst);
+    }
+    Guard {
+        handle,
+        _owned: owned,
+        _marker: PhantomData,
+    }
+}
+
+impl Guard {
+    fn handle(&self) -> &Handle {
+        unsafe { &*self.handle }
+    }
+
+    fn local(&self) -> &Local {
+        &self.handle().local
+    }
+
+    /// Run `f` once no thread pinned at this moment can still be using
+    /// memory that has been unlinked before this call
+    pub fn defer<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let full = {
+            let mut bag = self.handle().bag.borrow_mut();
+            bag.push(Box::new(f));
+            bag.len() >= BAG_SIZE
+        };
+        if full {
+            self.handle().seal();
+            GLOBAL.collect();
+        }
+    }
+
+    /// Hand this thread's deferred functions to the collector, then try to
+    /// advance the epoch and run any garbage that is ready
+    pub fn flush(&self) {
+        self.handle().seal();
+        GLOBAL.collect();
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let guards = self.local().guards.load(Relaxed) - 1;
+        self.local().guards.store(guards, Relaxed);
+        if guards == 0 {
+            self.local().state.store(0, Release);
+        }
+        // Then `_owned`, if any, seals its bag and unregisters
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn reentrant() {
+        let a = pin();
+        let b = pin();
+        drop(a);
+        assert_ne!(b.local().state.load(Relaxed) & PINNED, 0);
+        drop(b);
+        HANDLE.with(|handle| assert_eq!(handle.local.state.load(Relaxed), 0));
+    }
+
+    #[test]
+    fn defers_into_local_bag() {
+        thread::spawn(|| {
+            let guard = pin();
+            guard.defer(|| ());
+            assert_eq!(guard.handle().bag.borrow().len(), 1);
+            guard.flush();
+            assert!(guard.handle().bag.borrow().is_empty());
+            for _ in 0..BAG_SIZE - 1 {
+                guard.defer(|| ());
+            }
+            assert_eq!(guard.handle().bag.borrow().len(), BAG_SIZE - 1);
+            // Filling the bag hands it over
+            guard.defer(|| ());
+            assert!(guard.handle().bag.borrow().is_empty());
+        })
+        .join()
+        .unwrap();
+    }
+}
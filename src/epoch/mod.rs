@@ -0,0 +1,155 @@
+//! Epoch-based memory reclamation.
+//!
+//! Lock-free structures can't free a node as soon as it is unlinked, since
+//! another thread may have loaded a pointer to it just before. With epochs,
+//! every thread `pin`s itself before touching shared pointers, and
+//! unlinked memory is handed to `Guard::defer` instead of being freed
+//! directly. Deferred memory is only freed once every pinned thread has
+//! moved on to a later epoch, at which point nobody can still hold a
+//! pointer to it.
+//!
+//! The global epoch advances when every currently pinned thread has
+//! observed it. Garbage deferred in epoch `e` is freed once the global
+//! epoch reaches `e + 2`: any thread that could have seen the pointer was
+//! pinned in epoch `e` or `e - 1`, and both must have unpinned for the
+//! epoch to advance twice.
+//!
+//! Pinning is a thread-local store and a fence, without any atomic
+//! read-modify-write, so read-mostly structures built on epochs have cheap
+//! read paths. Deferring is cheap too: each thread collects deferred
+//! functions in a bag of its own, and only takes the global garbage lock
+//! once per bag of them, when it hands over the full bag.
+//!
+//! Under Miri, or with the `refcount-reclaim` feature, a simpler collector
+//! takes the place of epochs: it counts live guards, and runs deferred
+//! functions as soon as the count drops to zero. It is slow and never
+//! frees anything while some thread stays pinned, but it has no unsafe
+//! code or fences of its own to get wrong, so Miri checks the structures
+//! built on it rather than the collector. `mpmc` queue and stack channels
+//! also switch to a mutex-backed structure under Miri, see `mpmc::Builder`.
+
+use std::fmt;
+
+#[cfg(not(any(miri, feature = "refcount-reclaim")))]
+mod collector;
+#[cfg(any(miri, feature = "refcount-reclaim"))]
+mod refcount;
+
+#[cfg(not(any(miri, feature = "refcount-reclaim")))]
+pub use self::collector::{pin, Guard};
+#[cfg(any(miri, feature = "refcount-reclaim"))]
+pub use self::refcount::{pin, Guard};
+
+type Deferred = Box<dyn FnOnce() + Send>;
+
+impl Guard {
+    /// Drop the boxed value at `ptr` once it is unreachable.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from `Box::into_raw`, must already be unlinked
+    /// from any shared structure so no new references can be created, and
+    /// must not be freed by anyone else.
+    pub unsafe fn defer_destroy<T: Send + 'static>(&self, ptr: *mut T) {
+        struct SendPtr<T>(*mut T);
+        unsafe impl<T: Send> Send for SendPtr<T> {}
+        impl<T> SendPtr<T> {
+            // Keeps the closure capturing the wrapper, not the raw pointer
+            unsafe fn destroy(self) {
+                drop(Box::from_raw(self.0));
+            }
+        }
+
+        let ptr = SendPtr(ptr);
+        self.defer(move || unsafe { ptr.destroy() });
+    }
+}
+
+impl fmt::Debug for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Guard { .. }")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicBool, Ordering::*};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Other tests may be pinned concurrently, so keep flushing for a while
+    fn flush_until(ran: &AtomicBool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !ran.load(SeqCst) && Instant::now() < deadline {
+            pin().flush();
+            thread::yield_now();
+        }
+        ran.load(SeqCst)
+    }
+
+    #[test]
+    fn deferred_runs_after_unpin() {
+        let ran = Arc::new(AtomicBool::new(false));
+        {
+            let guard = pin();
+            let flag = ran.clone();
+            guard.defer(move || flag.store(true, SeqCst));
+            guard.flush();
+            guard.flush();
+            // Still pinned in the epoch the garbage was deferred in
+            assert!(!ran.load(SeqCst));
+        }
+        assert!(flush_until(&ran));
+    }
+
+    #[test]
+    fn pinned_thread_blocks_collection() {
+        let ran = Arc::new(AtomicBool::new(false));
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let reader = thread::spawn(move || {
+            let _guard = pin();
+            pinned_tx.send(()).unwrap();
+            done_rx.recv().unwrap();
+        });
+        pinned_rx.recv().unwrap();
+        let flag = ran.clone();
+        pin().defer(move || flag.store(true, SeqCst));
+        for _ in 0..4 {
+            pin().flush();
+        }
+        assert!(!ran.load(SeqCst));
+        done_tx.send(()).unwrap();
+        reader.join().unwrap();
+        assert!(flush_until(&ran));
+    }
+
+    #[test]
+    fn pin_in_tls_destructor() {
+        struct PinOnDrop(Arc<AtomicBool>);
+        impl Drop for PinOnDrop {
+            fn drop(&mut self) {
+                let flag = self.0.clone();
+                pin().defer(move || flag.store(true, SeqCst));
+            }
+        }
+        thread_local! {
+            static LATE: RefCell<Option<PinOnDrop>> = const { RefCell::new(None) };
+        }
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = ran.clone();
+        thread::spawn(move || {
+            // Registered before `HANDLE`, so destructors that run in
+            // reverse order drop it after `HANDLE` is gone
+            LATE.with(|late| *late.borrow_mut() = Some(PinOnDrop(flag)));
+            drop(pin());
+        })
+        .join()
+        .unwrap();
+        assert!(flush_until(&ran));
+    }
+}
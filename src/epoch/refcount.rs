@@ -0,0 +1,69 @@
+//! Reclamation by counting guards, for running under Miri. Every deferred
+//! function waits until no guard is alive anywhere, at which point nobody
+//! can hold a pointer to what it frees.
+
+use super::Deferred;
+use primitive::blocking::{Mutex, MutexGuard};
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::PoisonError;
+
+struct State {
+    /// Live guards on all threads
+    guards: usize,
+    garbage: Vec<Deferred>,
+}
+
+// One lock orders every pin, defer and unpin, so the count is exact
+static STATE: Mutex<State> = Mutex::new(State {
+    guards: 0,
+    garbage: Vec::new(),
+});
+
+fn state() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Keeps deferred functions from running while alive
+pub struct Guard {
+    // Tied to the thread that pinned, like the epoch collector's guards
+    _marker: PhantomData<*const ()>,
+}
+
+/// Pin the current thread. Safe to call anywhere, including thread-local
+/// destructors.
+pub fn pin() -> Guard {
+    state().guards += 1;
+    Guard {
+        _marker: PhantomData,
+    }
+}
+
+impl Guard {
+    /// Run `f` once no guard alive at this moment is still alive
+    pub fn defer<F: FnOnce() + Send + 'static>(&self, f: F) {
+        state().garbage.push(Box::new(f));
+    }
+
+    /// Garbage runs as soon as the last guard is dropped, and this one is
+    /// still alive, so there is nothing to do
+    pub fn flush(&self) {}
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let ready = {
+            let mut state = state();
+            state.guards -= 1;
+            if state.guards == 0 {
+                mem::take(&mut state.garbage)
+            } else {
+                Vec::new()
+            }
+        };
+        // Run outside the lock, deferred functions may pin and defer more
+        for deferred in ready {
+            deferred();
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! A C interface to queue channels, built with the `ffi` feature.
+//!
+//! Messages are `void *` payloads, which the channel moves between
+//! threads without looking at: what they point to, and freeing it, is up
+//! to the caller. Both halves of a channel are opaque handles that can be
+//! cloned and must each be freed, and payloads still queued when the last
+//! handle goes are dropped without being freed. To link against it, build
+//! the `cdylib` with the feature and declare:
+//!
+//! ```c
+//! typedef struct MyriadSender MyriadSender;
+//! typedef struct MyriadReceiver MyriadReceiver;
+//!
+//! enum { MYRIAD_OK, MYRIAD_EMPTY, MYRIAD_DISCONNECTED, MYRIAD_TIMEOUT };
+//!
+//! void myriad_channel(MyriadSender **sender, MyriadReceiver **receiver);
+//! MyriadSender *myriad_sender_clone(const MyriadSender *sender);
+//! void myriad_sender_free(MyriadSender *sender);
+//! MyriadReceiver *myriad_receiver_clone(const MyriadReceiver *receiver);
+//! void myriad_receiver_free(MyriadReceiver *receiver);
+//!
+//! int myriad_send(const MyriadSender *sender, void *payload);
+//! int myriad_recv(const MyriadReceiver *receiver, void **payload);
+//! int myriad_try_recv(const MyriadReceiver *receiver, void **payload);
+//! int myriad_recv_timeout(const MyriadReceiver *receiver, void **payload,
+//!                         uint64_t millis);
+//! ```
+//!
+//! Every function takes handles made by `myriad_channel` or a clone, not
+//! yet freed; handles can be shared between threads.
+
+use mpmc::{self, Error, Receiver, Sender};
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::time::Duration;
+
+pub const MYRIAD_OK: c_int = 0;
+/// Nothing was queued, from `myriad_try_recv`
+pub const MYRIAD_EMPTY: c_int = 1;
+/// Every handle on the other side is gone, and nothing is left to receive
+pub const MYRIAD_DISCONNECTED: c_int = 2;
+/// Nothing arrived in time, from `myriad_recv_timeout`
+pub const MYRIAD_TIMEOUT: c_int = 3;
+
+/// A pointer the channel only moves, so sending it is up to whoever
+/// handed it over
+struct Payload(*mut c_void);
+
+unsafe impl Send for Payload {}
+
+pub struct MyriadSender(Sender<Payload>);
+
+pub struct MyriadReceiver(Receiver<Payload>);
+
+/// Create a queue channel, storing its two halves in `sender` and
+/// `receiver`.
+///
+/// # Safety
+///
+/// Both pointers must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_channel(
+    sender: *mut *mut MyriadSender,
+    receiver: *mut *mut MyriadReceiver,
+) {
+    let (tx, rx) = mpmc::queue();
+    *sender = Box::into_raw(Box::new(MyriadSender(tx)));
+    *receiver = Box::into_raw(Box::new(MyriadReceiver(rx)));
+}
+
+/// # Safety
+///
+/// `sender` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_sender_clone(sender: *const MyriadSender) -> *mut MyriadSender {
+    Box::into_raw(Box::new(MyriadSender((*sender).0.clone())))
+}
+
+/// Free a sender handle, disconnecting the channel if it was the last.
+/// Null is ignored.
+///
+/// # Safety
+///
+/// `sender` must be null or a live handle, which isn't used again.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_sender_free(sender: *mut MyriadSender) {
+    if !sender.is_null() {
+        drop(Box::from_raw(sender));
+    }
+}
+
+/// # Safety
+///
+/// `receiver` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_receiver_clone(
+    receiver: *const MyriadReceiver,
+) -> *mut MyriadReceiver {
+    Box::into_raw(Box::new(MyriadReceiver((*receiver).0.clone())))
+}
+
+/// Free a receiver handle, disconnecting the channel if it was the last.
+/// Null is ignored.
+///
+/// # Safety
+///
+/// `receiver` must be null or a live handle, which isn't used again.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_receiver_free(receiver: *mut MyriadReceiver) {
+    if !receiver.is_null() {
+        drop(Box::from_raw(receiver));
+    }
+}
+
+/// Send `payload`, without blocking. Returns `MYRIAD_DISCONNECTED`, and
+/// the caller keeps the payload, if every receiver is gone.
+///
+/// # Safety
+///
+/// `sender` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_send(sender: *const MyriadSender, payload: *mut c_void) -> c_int {
+    match (*sender).0.send(Payload(payload)) {
+        Ok(()) => MYRIAD_OK,
+        Err(_) => MYRIAD_DISCONNECTED,
+    }
+}
+
+/// Store a received payload in `payload`, or null on failure
+unsafe fn received(result: Result<Payload, Error>, payload: *mut *mut c_void) -> c_int {
+    let (value, status) = match result {
+        Ok(Payload(value)) => (value, MYRIAD_OK),
+        Err(Error::Empty) => (ptr::null_mut(), MYRIAD_EMPTY),
+        Err(Error::Timeout) => (ptr::null_mut(), MYRIAD_TIMEOUT),
+        Err(_) => (ptr::null_mut(), MYRIAD_DISCONNECTED),
+    };
+    *payload = value;
+    status
+}
+
+/// Block until a payload arrives, or every sender is gone and the channel
+/// is empty.
+///
+/// # Safety
+///
+/// `receiver` must be a live handle and `payload` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_recv(
+    receiver: *const MyriadReceiver,
+    payload: *mut *mut c_void,
+) -> c_int {
+    received((*receiver).0.recv(), payload)
+}
+
+/// Receive a payload if one is queued.
+///
+/// # Safety
+///
+/// `receiver` must be a live handle and `payload` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_try_recv(
+    receiver: *const MyriadReceiver,
+    payload: *mut *mut c_void,
+) -> c_int {
+    received((*receiver).0.try_recv(), payload)
+}
+
+/// Like `myriad_recv`, giving up after `millis` milliseconds.
+///
+/// # Safety
+///
+/// `receiver` must be a live handle and `payload` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn myriad_recv_timeout(
+    receiver: *const MyriadReceiver,
+    payload: *mut *mut c_void,
+    millis: u64,
+) -> c_int {
+    let timeout = Duration::from_millis(millis);
+    received((*receiver).0.recv_timeout(timeout), payload)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    fn channel() -> (*mut MyriadSender, *mut MyriadReceiver) {
+        let (mut tx, mut rx) = (ptr::null_mut(), ptr::null_mut());
+        unsafe { myriad_channel(&mut tx, &mut rx) };
+        (tx, rx)
+    }
+
+    #[test]
+    fn payloads_cross_threads() {
+        let (tx, rx) = channel();
+        let sender = unsafe { myriad_sender_clone(tx) } as usize;
+        unsafe { myriad_sender_free(tx) };
+        let producer = thread::spawn(move || {
+            let tx = sender as *mut MyriadSender;
+            for i in 1..4usize {
+                let payload = Box::into_raw(Box::new(i)) as *mut c_void;
+                assert_eq!(unsafe { myriad_send(tx, payload) }, MYRIAD_OK);
+            }
+            unsafe { myriad_sender_free(tx) };
+        });
+        let mut total = 0;
+        let mut payload = ptr::null_mut();
+        while unsafe { myriad_recv(rx, &mut payload) } == MYRIAD_OK {
+            total += *unsafe { Box::from_raw(payload as *mut usize) };
+        }
+        producer.join().unwrap();
+        assert_eq!(total, 6);
+        assert!(payload.is_null());
+        unsafe { myriad_receiver_free(rx) };
+    }
+
+    #[test]
+    fn status_codes() {
+        let (tx, rx) = channel();
+        let mut payload = ptr::null_mut();
+        unsafe {
+            assert_eq!(myriad_try_recv(rx, &mut payload), MYRIAD_EMPTY);
+            assert_eq!(myriad_recv_timeout(rx, &mut payload, 1), MYRIAD_TIMEOUT);
+            let other = myriad_receiver_clone(rx);
+            myriad_receiver_free(rx);
+            myriad_receiver_free(other);
+            assert_eq!(myriad_send(tx, ptr::null_mut()), MYRIAD_DISCONNECTED);
+            myriad_sender_free(tx);
+            myriad_sender_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// Frames longer than this are refused unless `max_frame_len` says
+/// otherwise, so a corrupt length can't make a reader allocate gigabytes
+const DEFAULT_MAX_FRAME_LEN: usize = 16 << 20;
+
+/// Writes each frame as its length, a big-endian `u32`, then its bytes
+pub struct FrameWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W) -> FrameWriter<W> {
+        FrameWriter { inner }
+    }
+
+    /// Write one frame. Fails with `InvalidInput` if it is too long for its
+    /// length to fit in a `u32`.
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(frame.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "frame is too long to encode")
+        })?;
+        self.inner.write_all(&len.to_be_bytes())?;
+        self.inner.write_all(frame)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> fmt::Debug for FrameWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrameWriter").finish_non_exhaustive()
+    }
+}
+
+/// Reads the frames a `FrameWriter` wrote, as an iterator or one at a time
+pub struct FrameReader<R> {
+    inner: R,
+    max_frame_len: usize,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> FrameReader<R> {
+        FrameReader {
+            inner,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+        }
+    }
+
+    /// Refuse frames longer than `len` bytes. Defaults to 16 MiB.
+    pub fn max_frame_len(mut self, len: usize) -> FrameReader<R> {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Read the next frame, or `None` if the stream ends between frames.
+    /// A stream that ends part way through one fails with
+    /// `UnexpectedEof`, and a frame over the limit with `InvalidData`.
+    pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut len = [0; 4];
+        let mut read = 0;
+        while read < len.len() {
+            match self.inner.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(count) => read += count,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > self.max_frame_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame is longer than the limit",
+            ));
+        }
+        let mut frame = vec![0; len];
+        self.inner.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        self.read_frame().transpose()
+    }
+}
+
+impl<R> fmt::Debug for FrameReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrameReader")
+            .field("max_frame_len", &self.max_frame_len)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use io::{Reader, Writer};
+    use mpmc::queue;
+
+    #[test]
+    fn round_trip() {
+        let (tx, rx) = queue::<Vec<u8>>();
+        // Chunks small enough to split frames and their lengths
+        let mut writer = FrameWriter::new(Writer::new(tx, 3));
+        for frame in [&b"first"[..], b"", b"third"] {
+            writer.write_frame(frame).unwrap();
+        }
+        drop(writer);
+        let frames = FrameReader::new(Reader::new(rx))
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            frames,
+            vec![b"first".to_vec(), Vec::new(), b"third".to_vec()]
+        );
+    }
+
+    #[test]
+    fn bad_streams() {
+        let mut truncated = FrameReader::new(&[0, 0, 0, 4, b'a'][..]);
+        let err = truncated.read_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let mut partial_len = FrameReader::new(&[0, 0][..]);
+        let err = partial_len.read_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let mut oversized = FrameReader::new(&[0, 0, 1, 0][..]).max_frame_len(255);
+        let err = oversized.read_frame().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
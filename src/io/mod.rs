@@ -0,0 +1,38 @@
+//! Byte streams over channels.
+//!
+//! `Reader` reads the buffers a channel carries as one continuous stream,
+//! so code written against `std::io::Read` or `BufRead` can consume a
+//! channel directly. Buffers are anything that is `AsRef<[u8]>`, so
+//! `Vec<u8>`, boxed slices and shared byte types all work, and message
+//! boundaries aren't preserved: a read may span several buffers or stop
+//! part way through one.
+//!
+//! `Writer` goes the other way, collecting what is written to it and
+//! sending it on in chunks of at least a set size, so each message costs
+//! a send no matter how small the writes are:
+//!
+//! ```
+//! use myriad::io::{Reader, Writer};
+//! use myriad::mpmc;
+//! use std::io::{BufRead, Write};
+//!
+//! let (tx, rx) = mpmc::queue::<Vec<u8>>();
+//! let mut writer = Writer::new(tx, 64);
+//! writeln!(writer, "one").unwrap();
+//! writeln!(writer, "two").unwrap();
+//! drop(writer);
+//! let lines: Vec<_> = Reader::new(rx).lines().map(Result::unwrap).collect();
+//! assert_eq!(lines, ["one", "two"]);
+//! ```
+//!
+//! `FrameWriter` and `FrameReader` put message boundaries back, prefixing
+//! each frame with its length. They work over any `Write` and `Read`, so
+//! the same frames can cross a channel, a pipe or a socket.
+
+mod frame;
+mod reader;
+mod writer;
+
+pub use self::frame::{FrameReader, FrameWriter};
+pub use self::reader::Reader;
+pub use self::writer::Writer;
@@ -0,0 +1,119 @@
+use mpmc::Receiver;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+/// Reads the buffers received from a channel as a stream. Reading blocks
+/// while the channel is empty, and reaches the end of the stream once
+/// every sender is gone and the buffers left have been read.
+pub struct Reader<B: Send> {
+    receiver: Receiver<B>,
+    /// The buffer being read, and how much of it has been
+    current: Option<(B, usize)>,
+}
+
+impl<B: AsRef<[u8]> + Send> Reader<B> {
+    pub fn new(receiver: Receiver<B>) -> Reader<B> {
+        Reader {
+            receiver,
+            current: None,
+        }
+    }
+
+    /// The channel, and the part of the current buffer not yet read
+    pub fn into_inner(self) -> (Receiver<B>, Option<Vec<u8>>) {
+        let rest = self
+            .current
+            .map(|(buffer, read)| buffer.as_ref()[read..].to_vec());
+        (self.receiver, rest)
+    }
+}
+
+impl<B: AsRef<[u8]> + Send> BufRead for Reader<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self
+            .current
+            .as_ref()
+            .is_none_or(|(buffer, read)| *read == buffer.as_ref().len())
+        {
+            match self.receiver.recv() {
+                Ok(buffer) => self.current = Some((buffer, 0)),
+                // The end of the stream
+                Err(_) => {
+                    self.current = None;
+                    return Ok(&[]);
+                }
+            }
+        }
+        match self.current {
+            Some((ref buffer, read)) => Ok(&buffer.as_ref()[read..]),
+            None => Ok(&[]),
+        }
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Some((ref buffer, ref mut read)) = self.current {
+            *read = (*read + amount).min(buffer.as_ref().len());
+        }
+    }
+}
+
+impl<B: AsRef<[u8]> + Send> Read for Reader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = {
+            let available = self.fill_buf()?;
+            let count = available.len().min(buf.len());
+            buf[..count].copy_from_slice(&available[..count]);
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<B: Send> fmt::Debug for Reader<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Reader")
+            .field("receiver", &self.receiver)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::queue;
+    use std::thread;
+
+    #[test]
+    fn stitches_buffers() {
+        let (tx, rx) = queue();
+        for chunk in ["hel", "", "lo\nwor", "ld\n"] {
+            tx.send(chunk.as_bytes().to_vec()).unwrap();
+        }
+        drop(tx);
+        let mut reader = Reader::new(rx);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "hello\n");
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "world\n");
+        assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn blocks_for_more() {
+        let (tx, rx) = queue::<&'static [u8]>();
+        let mut reader = Reader::new(rx);
+        let sender = thread::spawn(move || {
+            tx.send(b"ab").unwrap();
+            tx.send(b"cd").unwrap();
+        });
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"abc");
+        sender.join().unwrap();
+        let (_, rest) = reader.into_inner();
+        assert_eq!(rest, Some(b"d".to_vec()));
+    }
+}
@@ -0,0 +1,101 @@
+use mpmc::Sender;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+
+/// Collects written bytes and sends them to a channel in chunks: once at
+/// least `chunk_size` bytes are buffered, on `flush`, and on drop.
+pub struct Writer<B: From<Vec<u8>> + Send> {
+    sender: Sender<B>,
+    buffer: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl<B: From<Vec<u8>> + Send> Writer<B> {
+    /// Panics if `chunk_size` is zero.
+    pub fn new(sender: Sender<B>, chunk_size: usize) -> Writer<B> {
+        assert!(chunk_size > 0, "a chunk needs room for a byte");
+        Writer {
+            sender,
+            buffer: Vec::with_capacity(chunk_size),
+            chunk_size,
+        }
+    }
+
+    /// Send whatever is buffered as one chunk
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::replace(&mut self.buffer, Vec::with_capacity(self.chunk_size));
+        self.sender
+            .send(B::from(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "channel is disconnected"))
+    }
+}
+
+impl<B: From<Vec<u8>> + Send> Write for Writer<B> {
+    /// Fails with `BrokenPipe` once every receiver is gone
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= self.chunk_size {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+impl<B: From<Vec<u8>> + Send> Drop for Writer<B> {
+    fn drop(&mut self) {
+        // The same as a send on a channel that closed just after
+        let _ = self.send();
+    }
+}
+
+impl<B: From<Vec<u8>> + Send> fmt::Debug for Writer<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Writer")
+            .field("buffered", &self.buffer.len())
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use io::Reader;
+    use mpmc::queue;
+    use std::io::Read;
+
+    #[test]
+    fn sends_chunks() {
+        let (tx, rx) = queue::<Vec<u8>>();
+        let mut writer = Writer::new(tx, 4);
+        writer.write_all(b"ab").unwrap();
+        assert!(rx.try_recv().is_err());
+        writer.write_all(b"cdef").unwrap();
+        assert_eq!(rx.try_recv().ok(), Some(b"abcdef".to_vec()));
+        write!(writer, "{}", 7).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(rx.try_recv().ok(), Some(b"7".to_vec()));
+        writer.write_all(b"end").unwrap();
+        drop(writer);
+        let mut rest = String::new();
+        Reader::new(rx).read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "end");
+    }
+
+    #[test]
+    fn broken_pipe() {
+        let (tx, rx) = queue::<Box<[u8]>>();
+        let mut writer = Writer::new(tx, 1);
+        drop(rx);
+        let err = writer.write(b"x").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
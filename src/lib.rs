@@ -1 +1,33 @@
-pub mod mpmc;
+#[cfg(target_os = "linux")]
+extern crate libc;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(feature = "parking_lot")]
+extern crate parking_lot;
+// pyo3's macros name `::core`, which this edition only finds at the root
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+pub mod actor;
+pub mod bench;
+pub mod compat;
+pub mod epoch;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod io;
+pub mod mpmc;
+pub mod pipeline;
+pub mod pool;
+mod primitive;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod sync;
+pub mod testing;
+pub mod thread;
+pub mod timer;
@@ -0,0 +1,191 @@
+//! Receivers that transform messages as they are received.
+//!
+//! `Receiver::map` and `Receiver::filter` wrap a receiver and apply their
+//! closure on the receiving thread, inside each `recv` or `try_recv`, so a
+//! light per-message step needs no thread or channel of its own. Messages
+//! a `Filter` rejects are taken off the channel and dropped, and count as
+//! received.
+//!
+//! `Receiver::tee` is the exception: every message has to reach two
+//! receivers, so it needs a thread to copy them across.
+
+use super::{queue, Error, Receiver};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A receiver whose messages are passed through `f`, returned by
+/// `Receiver::map`
+pub struct Map<T: Send, F> {
+    receiver: Receiver<T>,
+    f: F,
+}
+
+impl<T: Send, U, F: Fn(T) -> U> Map<T, F> {
+    pub(super) fn new(receiver: Receiver<T>, f: F) -> Map<T, F> {
+        Map { receiver, f }
+    }
+
+    pub fn try_recv(&self) -> Result<U, Error> {
+        self.receiver.try_recv().map(&self.f)
+    }
+
+    pub fn recv(&self) -> Result<U, Error> {
+        self.receiver.recv().map(&self.f)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<U, Error> {
+        self.receiver.recv_timeout(timeout).map(&self.f)
+    }
+
+    /// The wrapped receiver
+    pub fn into_inner(self) -> Receiver<T> {
+        self.receiver
+    }
+}
+
+impl<T: Send, F> fmt::Debug for Map<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Map")
+            .field("receiver", &self.receiver)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A receiver that only yields messages matching `predicate`, returned by
+/// `Receiver::filter`
+pub struct Filter<T: Send, P> {
+    receiver: Receiver<T>,
+    predicate: P,
+}
+
+impl<T: Send, P: Fn(&T) -> bool> Filter<T, P> {
+    pub(super) fn new(receiver: Receiver<T>, predicate: P) -> Filter<T, P> {
+        Filter {
+            receiver,
+            predicate,
+        }
+    }
+
+    /// Receive the first matching message already queued, dropping those
+    /// before it. `Empty` if none of them match.
+    pub fn try_recv(&self) -> Result<T, Error> {
+        loop {
+            let data = self.receiver.try_recv()?;
+            if (self.predicate)(&data) {
+                return Ok(data);
+            }
+        }
+    }
+
+    pub fn recv(&self) -> Result<T, Error> {
+        loop {
+            let data = self.receiver.recv()?;
+            if (self.predicate)(&data) {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// Like `recv`, giving up once `timeout` has passed since the call,
+    /// however many rejected messages arrived in the meantime
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let data = self.receiver.recv_timeout(left)?;
+            if (self.predicate)(&data) {
+                return Ok(data);
+            }
+        }
+    }
+
+    /// The wrapped receiver
+    pub fn into_inner(self) -> Receiver<T> {
+        self.receiver
+    }
+}
+
+impl<T: Send, P> fmt::Debug for Filter<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Filter")
+            .field("receiver", &self.receiver)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Copy every message from `input` to two new channels, on a thread that
+/// exits once `input` disconnects or both new receivers are gone
+pub(super) fn tee<T: Send + Clone + 'static>(input: Receiver<T>) -> (Receiver<T>, Receiver<T>) {
+    let (left, left_rx) = queue();
+    let (right, right_rx) = queue();
+    thread::Builder::new()
+        .name("myriad-tee".into())
+        .spawn(move || {
+            while let Ok(item) = input.recv() {
+                // One side going away leaves the other fed
+                let left_gone = left.send(item.clone()).is_err();
+                if right.send(item).is_err() && left_gone {
+                    break;
+                }
+            }
+        })
+        .expect("failed to spawn tee thread");
+    (left_rx, right_rx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn map() {
+        let (tx, rx) = queue();
+        let rx = rx.map(|i: u32| i.to_string());
+        tx.send(1).unwrap();
+        assert_eq!(rx.try_recv(), Ok("1".to_string()));
+        assert!(rx.try_recv() == Err(Error::Empty));
+        let sender = thread::spawn(move || tx.send(2).unwrap());
+        assert_eq!(rx.recv(), Ok("2".to_string()));
+        sender.join().unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(1)) == Err(Error::Disconnected));
+    }
+
+    #[test]
+    fn filter() {
+        let (tx, rx) = queue();
+        let rx = rx.filter(|i: &u32| *i != 3 && *i != 5);
+        for i in 1..6 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+        // 3 is dropped on the way
+        assert_eq!(rx.recv(), Ok(4));
+        // 5 is dropped on the way to finding nothing
+        assert!(rx.try_recv() == Err(Error::Empty));
+        assert_eq!(tx.stats().received, 5);
+        tx.send(5).unwrap();
+        assert!(rx.recv_timeout(Duration::from_millis(5)) == Err(Error::Timeout));
+        drop(tx);
+        assert!(rx.into_inner().recv() == Err(Error::Disconnected));
+    }
+
+    #[test]
+    fn tee() {
+        let (tx, rx) = queue();
+        let (left, right) = rx.tee();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(left.recv(), Ok(1));
+        assert_eq!(right.recv(), Ok(1));
+        assert_eq!(right.recv(), Ok(2));
+        drop(right);
+        tx.send(3).unwrap();
+        drop(tx);
+        assert_eq!(left.recv(), Ok(2));
+        assert_eq!(left.recv(), Ok(3));
+        assert!(left.recv() == Err(Error::Disconnected));
+    }
+}
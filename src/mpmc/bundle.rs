@@ -0,0 +1,112 @@
+//! Declaring a set of channels together.
+//!
+//! An actor or stage with several inputs needs a channel built for each
+//! one, and its senders handed to one side and its receivers to the other.
+//! `channels!` declares a struct of senders and a struct of receivers with
+//! a field per channel, and a `new` that builds a queue channel for each
+//! field and returns both halves:
+//!
+//! ```
+//! # #[macro_use] extern crate myriad;
+//! # fn main() {
+//! #[derive(Debug)]
+//! enum Command {
+//!     Stop,
+//! }
+//!
+//! channels! {
+//!     pub struct Inputs / Outputs {
+//!         commands: Command,
+//!         /// Sequence numbers to process
+//!         work: u64,
+//!     }
+//! }
+//!
+//! let (inputs, outputs) = Inputs::new();
+//! inputs.work.send(1).unwrap();
+//! inputs.commands.send(Command::Stop).unwrap();
+//! assert_eq!(outputs.work.recv(), Ok(1));
+//! assert!(matches!(outputs.commands.recv(), Ok(Command::Stop)));
+//! # }
+//! ```
+//!
+//! Both structs are `Clone` and `Debug`, and their fields are public, so
+//! either side can be broken up further.
+
+/// Declare a struct of senders and a struct of receivers, one field for
+/// each channel
+#[macro_export]
+macro_rules! channels {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $senders:ident / $receivers:ident {
+            $($(#[$field_attr:meta])* $field:ident: $t:ty),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Debug)]
+        $vis struct $senders {
+            $($(#[$field_attr])* pub $field: $crate::mpmc::Sender<$t>,)*
+        }
+
+        $(#[$attr])*
+        #[derive(Clone, Debug)]
+        $vis struct $receivers {
+            $($(#[$field_attr])* pub $field: $crate::mpmc::Receiver<$t>,)*
+        }
+
+        impl $senders {
+            /// Build a queue channel for every field, returning the
+            /// senders and the receivers
+            $vis fn new() -> ($senders, $receivers) {
+                $(let $field = $crate::mpmc::queue::<$t>();)*
+                (
+                    $senders {
+                        $($field: $field.0,)*
+                    },
+                    $receivers {
+                        $($field: $field.1,)*
+                    },
+                )
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use mpmc::Error;
+    use std::thread;
+
+    channels! {
+        struct Senders / Receivers {
+            numbers: u32,
+            names: String,
+        }
+    }
+
+    #[test]
+    fn halves_cross_threads() {
+        let (senders, receivers) = Senders::new();
+        let worker = thread::spawn(move || {
+            let n = receivers.numbers.recv().unwrap();
+            let name = receivers.names.recv().unwrap();
+            format!("{} {}", n, name)
+        });
+        let other = senders.clone();
+        other.numbers.send(3).unwrap();
+        senders.names.send("ducks".into()).unwrap();
+        assert_eq!(worker.join().unwrap(), "3 ducks");
+        assert_eq!(other.numbers.send(4), Err(4));
+    }
+
+    #[test]
+    fn fields_are_separate_channels() {
+        let (senders, receivers) = Senders::new();
+        senders.numbers.send(1).unwrap();
+        assert!(receivers.names.try_recv() == Err(Error::Empty));
+        drop(senders);
+        assert_eq!(receivers.numbers.recv(), Ok(1));
+        assert!(receivers.names.recv() == Err(Error::Disconnected));
+    }
+}
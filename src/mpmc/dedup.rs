@@ -0,0 +1,119 @@
+//! A first-in-first-out queue that drops pushes whose key matches an item
+//! that is already queued. Useful for "work item already pending" patterns,
+//! where scheduling the same job twice is wasted effort.
+
+use super::*;
+use primitive::{lock, Mutex};
+use std::collections::{HashSet, VecDeque};
+
+struct State<T, K> {
+    items: VecDeque<T>,
+    pending: HashSet<K>,
+}
+
+/// A FIFO queue with at most one pending item per key
+pub struct Dedup<T, K, F> {
+    state: Mutex<State<T, K>>,
+    key: F,
+}
+
+impl<T, K: Hash + Eq, F: Fn(&T) -> K> Dedup<T, K, F> {
+    pub fn new(key: F) -> Self {
+        Dedup {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                pending: HashSet::new(),
+            }),
+            key,
+        }
+    }
+}
+
+impl<T, K, F> LockFree<T> for Dedup<T, K, F>
+where
+    T: Send,
+    K: Send + Hash + Eq,
+    F: Fn(&T) -> K + Send + Sync,
+{
+    fn push(&self, item: T) -> bool {
+        let key = (self.key)(&item);
+        let mut state = lock(&self.state);
+        // An equal item is still waiting to be consumed, drop this one
+        if !state.pending.insert(key) {
+            return false;
+        }
+        state.items.push_back(item);
+        true
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut state = lock(&self.state);
+        // Key the item before taking it, so a panicking key function
+        // leaves it queued rather than losing it
+        let key = (self.key)(state.items.front()?);
+        state.pending.remove(&key);
+        state.items.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.state).items.len()
+    }
+
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>)) {
+        f(&mut lock(&self.state).items.iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drops_pending_duplicates() {
+        let dedup = Dedup::new(|x: &u32| *x);
+        assert!(dedup.push(1));
+        assert!(dedup.push(2));
+        assert!(!dedup.push(1));
+        assert!(!dedup.push(2));
+        assert_eq!(dedup.len(), 2);
+        assert_eq!(dedup.pop(), Some(1));
+        assert_eq!(dedup.pop(), Some(2));
+        assert_eq!(dedup.pop(), None);
+    }
+
+    #[test]
+    fn requeue_after_pop() {
+        let dedup = Dedup::new(|x: &u32| *x);
+        dedup.push(1);
+        assert_eq!(dedup.pop(), Some(1));
+        dedup.push(1);
+        assert_eq!(dedup.pop(), Some(1));
+        assert_eq!(dedup.pop(), None);
+    }
+
+    #[test]
+    fn key_extractor() {
+        let dedup = Dedup::new(|job: &(u32, &str)| job.0);
+        dedup.push((1, "first"));
+        dedup.push((1, "second"));
+        dedup.push((2, "third"));
+        assert_eq!(dedup.pop(), Some((1, "first")));
+        assert_eq!(dedup.pop(), Some((2, "third")));
+        assert_eq!(dedup.pop(), None);
+    }
+
+    #[test]
+    fn channel() {
+        let (tx, rx) = dedup();
+        tx.send("a").unwrap();
+        tx.send("a").unwrap();
+        tx.send("b").unwrap();
+        assert_eq!(tx.size_hint(), 2);
+        // The duplicate was never queued
+        assert_eq!(tx.stats().sent, 2);
+        assert_eq!(tx.stats().high_water, 2);
+        assert_eq!(rx.try_recv(), Ok("a"));
+        assert_eq!(rx.try_recv(), Ok("b"));
+        assert_eq!(rx.try_recv(), Err(Error::Empty));
+    }
+}
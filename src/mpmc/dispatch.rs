@@ -0,0 +1,188 @@
+//! Routing messages to worker channels by key.
+//!
+//! A `Dispatcher` owns one channel per worker and sends each message to
+//! the worker chosen for its key with rendezvous hashing: every worker
+//! gets a score from hashing it together with the key, and the highest
+//! score wins. All messages with the same key go to the same worker, and
+//! when workers come and go only the keys that must move do: removing a
+//! worker moves just its keys, and adding one takes its fair share from
+//! the others.
+
+use super::{queue, Receiver, Sender};
+use primitive::{AtomicUsize, Ordering::*};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use sync::ShardedLock;
+
+/// Identifies a worker of a `Dispatcher`
+pub type WorkerId = usize;
+
+pub struct Dispatcher<T: Send, F> {
+    key: F,
+    /// Consulted on every dispatch, and changed rarely
+    workers: ShardedLock<Vec<(WorkerId, Sender<T>)>>,
+    next_id: AtomicUsize,
+}
+
+fn score<K: Hash + ?Sized>(key: &K, worker: WorkerId) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    worker.hash(&mut hasher);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<T, K, F> Dispatcher<T, F>
+where
+    T: Send + 'static,
+    K: Hash,
+    F: Fn(&T) -> K,
+{
+    /// A dispatcher with no workers yet, routing messages by `key`
+    pub fn new(key: F) -> Dispatcher<T, F> {
+        Dispatcher {
+            key,
+            workers: ShardedLock::new(Vec::new()),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a worker, returning its id and the channel it should consume.
+    /// From now on it receives the keys it wins from the other workers.
+    pub fn add_worker(&self) -> (WorkerId, Receiver<T>) {
+        let id = self.next_id.fetch_add(1, Relaxed);
+        let (tx, rx) = queue();
+        self.workers.write().push((id, tx));
+        (id, rx)
+    }
+
+    /// Remove a worker, handing its keys to the others. Its channel
+    /// disconnects once it has received the messages already sent to it.
+    /// Returns `false` if there is no such worker.
+    ///
+    /// Messages for one key are delivered in order to one worker, but
+    /// across a change of workers a key's new worker may start on its
+    /// messages before the old one has finished.
+    pub fn remove_worker(&self, id: WorkerId) -> bool {
+        let mut workers = self.workers.write();
+        let len = workers.len();
+        workers.retain(|&(worker, _)| worker != id);
+        workers.len() != len
+    }
+
+    /// Number of workers
+    pub fn workers(&self) -> usize {
+        self.workers.read().len()
+    }
+
+    /// The worker that messages with `key` currently go to, if there are
+    /// any workers
+    pub fn route<Q: Hash + ?Sized>(&self, key: &Q) -> Option<WorkerId> {
+        self.workers
+            .read()
+            .iter()
+            .map(|&(id, _)| id)
+            .max_by_key(|&id| score(key, id))
+    }
+
+    /// Send `item` to the worker for its key. Returns `item` if there are
+    /// no workers, or if the chosen worker's receiver is gone.
+    pub fn dispatch(&self, item: T) -> Result<(), T> {
+        let key = (self.key)(&item);
+        let workers = self.workers.read();
+        match workers.iter().max_by_key(|&&(id, _)| score(&key, id)) {
+            Some((_, tx)) => tx.send(item),
+            None => Err(item),
+        }
+    }
+}
+
+impl<T: Send, F> fmt::Debug for Dispatcher<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let workers = self.workers.read();
+        f.debug_struct("Dispatcher")
+            .field(
+                "workers",
+                &workers.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::Error;
+
+    fn drain<T: Send>(rx: &Receiver<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        while let Ok(item) = rx.try_recv() {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn same_key_same_worker() {
+        let dispatcher = Dispatcher::new(|&(key, _): &(u32, u32)| key);
+        assert_eq!(dispatcher.dispatch((0, 0)), Err((0, 0)));
+        let workers = (0..4).map(|_| dispatcher.add_worker()).collect::<Vec<_>>();
+        for seq in 0..10 {
+            for key in 0..20 {
+                dispatcher.dispatch((key, seq)).unwrap();
+            }
+        }
+        let mut seen = 0;
+        for (id, rx) in &workers {
+            let items = drain(rx);
+            seen += items.len();
+            for key in 0..20 {
+                let seqs = items
+                    .iter()
+                    .filter(|&&(k, _)| k == key)
+                    .map(|&(_, seq)| seq)
+                    .collect::<Vec<_>>();
+                // Each key lives entirely on its routed worker, in order
+                if dispatcher.route(&key) == Some(*id) {
+                    assert_eq!(seqs, (0..10).collect::<Vec<_>>());
+                } else {
+                    assert!(seqs.is_empty());
+                }
+            }
+        }
+        assert_eq!(seen, 200);
+    }
+
+    #[test]
+    fn rebalance_moves_only_needed_keys() {
+        let dispatcher = Dispatcher::new(|&key: &u32| key);
+        let ids = (0..4).map(|_| dispatcher.add_worker()).collect::<Vec<_>>();
+        let before = (0..1000)
+            .map(|key| dispatcher.route(&key))
+            .collect::<Vec<_>>();
+
+        let removed = ids[1].0;
+        assert!(dispatcher.remove_worker(removed));
+        assert!(!dispatcher.remove_worker(removed));
+        assert_eq!(ids[1].1.try_recv(), Err(Error::Disconnected));
+        for key in 0..1000 {
+            let after = dispatcher.route(&key);
+            if before[key as usize] != Some(removed) {
+                assert_eq!(after, before[key as usize]);
+            }
+            assert_ne!(after, Some(removed));
+        }
+
+        let (added, _rx) = dispatcher.add_worker();
+        let mut moved = 0;
+        for key in 0..1000 {
+            let after = dispatcher.route(&key);
+            if after != before[key as usize] && before[key as usize] != Some(removed) {
+                assert_eq!(after, Some(added));
+                moved += 1;
+            }
+        }
+        // Roughly a quarter of the remaining keys
+        assert!(moved > 100 && moved < 400, "moved {} keys", moved);
+    }
+}
@@ -0,0 +1,131 @@
+//! Spreading messages over per-worker channels.
+//!
+//! Where a `Dispatcher` picks a worker by key, a `Distributor` has no
+//! notion of which worker a message belongs to and just keeps the load
+//! even, taking the channels in turn or picking the one with the fewest
+//! messages queued.
+
+use super::Sender;
+use primitive::{AtomicUsize, Ordering::*};
+use std::fmt;
+
+/// How a `Distributor` picks the channel for each message
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Distribution {
+    /// Each channel in turn. The default.
+    #[default]
+    RoundRobin,
+    /// The channel with the fewest messages queued, first among equals.
+    /// Suits work whose messages take uneven time to handle.
+    LeastLoaded,
+}
+
+/// Sends each message to one of several channels
+pub struct Distributor<T: Send> {
+    senders: Vec<Sender<T>>,
+    distribution: Distribution,
+    /// The channel round robin picks next
+    next: AtomicUsize,
+}
+
+impl<T: Send> Distributor<T> {
+    pub fn new(senders: Vec<Sender<T>>) -> Distributor<T> {
+        Distributor {
+            senders,
+            distribution: Distribution::RoundRobin,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Set how channels are picked. Defaults to
+    /// `Distribution::RoundRobin`.
+    pub fn distribution(mut self, distribution: Distribution) -> Distributor<T> {
+        self.distribution = distribution;
+        self
+    }
+
+    /// Send `item` to the next channel. A channel whose receivers are gone
+    /// is passed over for the one after it, and `item` is returned only if
+    /// every channel is disconnected.
+    pub fn send(&self, mut item: T) -> Result<(), T> {
+        let count = self.senders.len();
+        if count == 0 {
+            return Err(item);
+        }
+        let start = match self.distribution {
+            Distribution::RoundRobin => self.next.fetch_add(1, Relaxed) % count,
+            Distribution::LeastLoaded => (0..count)
+                .min_by_key(|&index| self.senders[index].size_hint())
+                .unwrap_or(0),
+        };
+        for offset in 0..count {
+            match self.senders[(start + offset) % count].send(item) {
+                Ok(()) => return Ok(()),
+                Err(returned) => item = returned,
+            }
+        }
+        Err(item)
+    }
+
+    /// Number of channels
+    pub fn len(&self) -> usize {
+        self.senders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+}
+
+impl<T: Send> fmt::Debug for Distributor<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Distributor")
+            .field("channels", &self.senders.len())
+            .field("distribution", &self.distribution)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::queue;
+
+    #[test]
+    fn round_robin() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| queue()).unzip();
+        let distributor = Distributor::new(senders);
+        for i in 0..7 {
+            distributor.send(i).unwrap();
+        }
+        let queued = receivers
+            .iter()
+            .map(|rx| rx.stats().queued)
+            .collect::<Vec<_>>();
+        assert_eq!(queued, vec![3, 2, 2]);
+        // Disconnected channels are skipped
+        let mut receivers = receivers.into_iter();
+        drop((receivers.next(), receivers.next()));
+        let last = receivers.next().unwrap();
+        distributor.send(7).unwrap();
+        distributor.send(8).unwrap();
+        assert_eq!(last.stats().queued, 4);
+        drop(last);
+        assert_eq!(distributor.send(9), Err(9));
+    }
+
+    #[test]
+    fn least_loaded() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..2).map(|_| queue()).unzip();
+        senders[0].send(0).unwrap();
+        senders[0].send(0).unwrap();
+        let distributor = Distributor::new(senders).distribution(Distribution::LeastLoaded);
+        for i in 1..4 {
+            distributor.send(i).unwrap();
+        }
+        // The emptier channel catches up, then ties go to the first
+        assert_eq!(receivers[0].stats().queued, 3);
+        assert_eq!(receivers[1].stats().queued, 2);
+        assert_eq!(receivers[1].try_recv().ok(), Some(1));
+    }
+}
@@ -0,0 +1,114 @@
+//! Fault injection for the lock-free structures' retry loops.
+//!
+//! The queue and stack call `inject` at the points where a race between
+//! two threads does its damage: right after loading a shared pointer,
+//! just before the CAS that acts on it, and right after the CAS succeeds.
+//! A test installs a hook on its threads with `with_hook`, and the hook
+//! can yield or sleep there, widening the window in which another thread
+//! unlinks, frees or reuses the node just loaded. That provokes ABA and
+//! reclamation bugs far more often than plain contention does.
+//!
+//! Outside of tests `inject` is empty and compiles away.
+
+/// Where in a retry loop `inject` was called
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Point {
+    /// A shared pointer has just been loaded
+    AfterLoad,
+    /// The CAS acting on it is about to run
+    BeforeCas,
+    /// The CAS succeeded
+    AfterCas,
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+pub fn inject(_: Point) {}
+
+#[cfg(test)]
+pub use self::hooks::{inject, with_hook};
+
+#[cfg(test)]
+mod hooks {
+    use super::Point;
+    use std::cell::RefCell;
+
+    type Hook = Box<dyn FnMut(Point)>;
+
+    thread_local! {
+        static HOOK: RefCell<Option<Hook>> = const { RefCell::new(None) };
+    }
+
+    pub fn inject(point: Point) {
+        HOOK.with(|hook| {
+            // A hook that pops or pushes itself doesn't recurse
+            if let Ok(mut hook) = hook.try_borrow_mut() {
+                if let Some(ref mut hook) = *hook {
+                    hook(point);
+                }
+            }
+        });
+    }
+
+    /// Run `body` with `hook` called at every injection point this thread
+    /// reaches. Hooks are per thread, so tests running alongside are left
+    /// alone.
+    pub fn with_hook<H: FnMut(Point) + 'static, R, F: FnOnce() -> R>(hook: H, body: F) -> R {
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                HOOK.with(|hook| *hook.borrow_mut() = None);
+            }
+        }
+        HOOK.with(|slot| *slot.borrow_mut() = Some(Box::new(hook)));
+        let _reset = Reset;
+        body()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{queue::Queue, stack::Stack, LockFree};
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn points<F: FnOnce()>(body: F) -> Vec<Point> {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        with_hook(move |point| log.borrow_mut().push(point), body);
+        let seen = seen.borrow().clone();
+        seen
+    }
+
+    #[test]
+    fn hooks_reach_every_point() {
+        let all = [Point::AfterLoad, Point::BeforeCas, Point::AfterCas];
+        let queue = Queue::new();
+        let stack = Stack::new();
+        for seen in [
+            points(|| {
+                queue.push(1);
+            }),
+            points(|| {
+                queue.pop();
+            }),
+            points(|| {
+                stack.push(1);
+            }),
+            points(|| {
+                stack.pop();
+            }),
+        ] {
+            assert!(all.iter().all(|point| seen.contains(point)), "{:?}", seen);
+        }
+    }
+
+    #[test]
+    fn hook_is_removed() {
+        let queue = Queue::new();
+        with_hook(|_| panic!("injected"), || ());
+        queue.push(1);
+        assert_eq!(queue.pop(), Some(1));
+    }
+}
@@ -0,0 +1,129 @@
+//! Channels that can live in a `static`.
+//!
+//! A `Sender` and `Receiver` share an allocation made when the channel is
+//! built, so neither can be made in a constant. `StaticChannel` has a
+//! `const` constructor instead, and builds the channel the first time it
+//! is used:
+//!
+//! ```
+//! use myriad::mpmc::StaticChannel;
+//!
+//! static EVENTS: StaticChannel<&'static str> = StaticChannel::new();
+//!
+//! EVENTS.send("started").unwrap();
+//! assert_eq!(EVENTS.try_recv().ok(), Some("started"));
+//! ```
+//!
+//! The static holds a sender and a receiver of its own for good, so the
+//! channel never disconnects and sends always succeed.
+
+use super::{queue, Error, Receiver, Sender};
+use std::fmt;
+use std::time::Duration;
+use sync::OnceCell;
+
+pub struct StaticChannel<T: Send> {
+    channel: OnceCell<(Sender<T>, Receiver<T>)>,
+    make: fn() -> (Sender<T>, Receiver<T>),
+}
+
+impl<T: Send + 'static> StaticChannel<T> {
+    /// A queue channel, built on first use
+    pub const fn new() -> StaticChannel<T> {
+        StaticChannel::with(queue)
+    }
+
+    /// A channel built by `make` on first use, such as `mpmc::stack` or a
+    /// function wrapping a `Builder`
+    pub const fn with(make: fn() -> (Sender<T>, Receiver<T>)) -> StaticChannel<T> {
+        StaticChannel {
+            channel: OnceCell::new(),
+            make,
+        }
+    }
+
+    fn channel(&self) -> &(Sender<T>, Receiver<T>) {
+        self.channel.get_or_init(self.make)
+    }
+
+    /// A sender for the channel, to hand to code that takes one
+    pub fn sender(&self) -> Sender<T> {
+        self.channel().0.clone()
+    }
+
+    /// A receiver for the channel, to hand to code that takes one
+    pub fn receiver(&self) -> Receiver<T> {
+        self.channel().1.clone()
+    }
+
+    pub fn send(&self, data: T) -> Result<(), T> {
+        self.channel().0.send(data)
+    }
+
+    pub fn try_recv(&self) -> Result<T, Error> {
+        self.channel().1.try_recv()
+    }
+
+    pub fn recv(&self) -> Result<T, Error> {
+        self.channel().1.recv()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, Error> {
+        self.channel().1.recv_timeout(timeout)
+    }
+}
+
+impl<T: Send + 'static> Default for StaticChannel<T> {
+    fn default() -> StaticChannel<T> {
+        StaticChannel::new()
+    }
+}
+
+impl<T: Send> fmt::Debug for StaticChannel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.channel.get() {
+            Some((_, receiver)) => f.debug_tuple("StaticChannel").field(receiver).finish(),
+            None => f.write_str("StaticChannel(<uninit>)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::stack;
+    use std::thread;
+
+    static JOBS: StaticChannel<u32> = StaticChannel::with(stack);
+
+    #[test]
+    fn shared_across_threads() {
+        let workers: Vec<_> = (0..3)
+            .map(|i| thread::spawn(move || JOBS.send(i).unwrap()))
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let rx = JOBS.receiver();
+        let mut jobs = vec![
+            rx.recv().unwrap(),
+            JOBS.recv().unwrap(),
+            JOBS.recv().unwrap(),
+        ];
+        jobs.sort_unstable();
+        assert_eq!(jobs, vec![0, 1, 2]);
+        // The static's own handles keep it connected
+        drop(rx);
+        assert!(JOBS.try_recv() == Err(Error::Empty));
+        assert!(JOBS.sender().send(3).is_ok());
+        assert_eq!(JOBS.recv_timeout(Duration::from_millis(1)), Ok(3));
+    }
+
+    #[test]
+    fn builds_on_first_use() {
+        let channel = StaticChannel::<u8>::new();
+        assert_eq!(format!("{:?}", channel), "StaticChannel(<uninit>)");
+        channel.send(1).unwrap();
+        assert!(format!("{:?}", channel).contains("len: 1"));
+    }
+}
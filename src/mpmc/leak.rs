@@ -0,0 +1,184 @@
+//! Node accounting for the `leak-check` feature.
+//!
+//! Each node the lock-free queue and stack allocate holds a `Token`, which
+//! counts the node as allocated when created and as freed when dropped,
+//! whether by a pop's deferred destruction or by the structure's own
+//! `Drop`. Without the feature both types are empty and cost nothing.
+//!
+//! The counters are plain std atomics even under loom or the
+//! deterministic scheduler, as they take no part in the structures'
+//! synchronization.
+
+#[cfg(feature = "leak-check")]
+pub use self::enabled::Allocations;
+#[cfg(feature = "leak-check")]
+pub(crate) use self::enabled::Token;
+
+#[cfg(not(feature = "leak-check"))]
+pub(crate) use self::disabled::{Allocations, Token};
+
+#[cfg(feature = "leak-check")]
+mod enabled {
+    use epoch;
+    use std::fmt;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::sync::Arc;
+    use std::thread;
+
+    struct Counts {
+        allocated: AtomicUsize,
+        freed: AtomicUsize,
+    }
+
+    /// Counts the nodes one channel's structure allocates and frees. Clones
+    /// share the counts, which outlive the channel, so frees deferred past
+    /// its drop are still counted.
+    #[derive(Clone)]
+    pub struct Allocations {
+        counts: Arc<Counts>,
+    }
+
+    impl Allocations {
+        pub(crate) fn new() -> Allocations {
+            Allocations {
+                counts: Arc::new(Counts {
+                    allocated: AtomicUsize::new(0),
+                    freed: AtomicUsize::new(0),
+                }),
+            }
+        }
+
+        pub(crate) fn token(&self) -> Token {
+            self.counts.allocated.fetch_add(1, Relaxed);
+            Token(self.counts.clone())
+        }
+
+        pub fn allocated(&self) -> usize {
+            self.counts.allocated.load(Relaxed)
+        }
+
+        pub fn freed(&self) -> usize {
+            self.counts.freed.load(Relaxed)
+        }
+
+        /// Nodes allocated and not yet freed, including the sentinel a
+        /// live queue always holds and popped nodes still waiting on the
+        /// epoch collector
+        pub fn live(&self) -> usize {
+            // A free racing with this can be seen without its alloc
+            let freed = self.freed();
+            self.allocated().saturating_sub(freed)
+        }
+
+        /// Panic unless every node has been freed. Call it once the channel
+        /// is dropped and the threads that used it have exited, which
+        /// hands their deferred frees to the collector. This thread's are
+        /// flushed here, and the collector gets a few rounds to run them.
+        pub fn assert_no_leaks(&self) {
+            for _ in 0..100 {
+                if self.live() == 0 {
+                    return;
+                }
+                epoch::pin().flush();
+                thread::yield_now();
+            }
+            panic!(
+                "{} of {} nodes were never freed",
+                self.live(),
+                self.allocated()
+            );
+        }
+    }
+
+    impl fmt::Debug for Allocations {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("Allocations")
+                .field("allocated", &self.allocated())
+                .field("freed", &self.freed())
+                .finish()
+        }
+    }
+
+    pub struct Token(Arc<Counts>);
+
+    impl Drop for Token {
+        fn drop(&mut self) {
+            self.0.freed.fetch_add(1, Relaxed);
+        }
+    }
+}
+
+#[cfg(not(feature = "leak-check"))]
+mod disabled {
+    pub struct Allocations;
+
+    impl Allocations {
+        pub fn new() -> Allocations {
+            Allocations
+        }
+
+        pub fn token(&self) -> Token {
+            Token
+        }
+    }
+
+    pub struct Token;
+}
+
+#[cfg(all(test, feature = "leak-check"))]
+mod test {
+    use mpmc::{queue, stack};
+    use std::thread;
+
+    #[test]
+    fn counts_nodes() {
+        let (tx, rx) = queue();
+        let allocations = match tx.allocations() {
+            Some(allocations) => allocations,
+            // Mutex-backed
+            None => return,
+        };
+        // The sentinel
+        assert_eq!(allocations.live(), 1);
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(allocations.allocated(), 11);
+        for _ in 0..5 {
+            rx.recv().unwrap();
+        }
+        // Items still queued when the channel goes are freed with it
+        drop((tx, rx));
+        allocations.assert_no_leaks();
+        assert_eq!(allocations.freed(), 11);
+    }
+
+    #[test]
+    fn no_leaks_across_threads() {
+        for &make in &[queue::<u64>, stack::<u64>] {
+            let (tx, rx) = make();
+            let allocations = match tx.allocations() {
+                Some(allocations) => allocations,
+                None => return,
+            };
+            let workers: Vec<_> = (0..3)
+                .map(|_| {
+                    let (tx, rx) = (tx.clone(), rx.clone());
+                    thread::spawn(move || {
+                        for i in 0..200 {
+                            tx.send(i).unwrap();
+                            if i % 3 != 0 {
+                                let _ = rx.try_recv();
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            drop((tx, rx));
+            allocations.assert_no_leaks();
+        }
+    }
+}
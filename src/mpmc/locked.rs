@@ -0,0 +1,129 @@
+//! A queue or stack behind a plain mutex.
+//!
+//! Slower than the lock-free backends under contention, but simple enough
+//! to be obviously correct. That makes it the oracle the lock-free
+//! backends are tested against, and the backend `queue` and `stack`
+//! channels use instead when built with the `mutex-backend` feature or run
+//! under Miri, where ThreadSanitizer and Miri can check everything around
+//! the channel without tripping over the lock-free internals.
+
+use super::*;
+use primitive::{lock, Mutex};
+use std::collections::VecDeque;
+
+pub struct Locked<T> {
+    items: Mutex<VecDeque<T>>,
+    /// Pop the most recent item rather than the oldest
+    lifo: bool,
+}
+
+impl<T> Locked<T> {
+    /// Items come out in the order they were pushed, like `Queue`
+    pub fn fifo() -> Self {
+        Locked {
+            items: Mutex::new(VecDeque::new()),
+            lifo: false,
+        }
+    }
+
+    /// The most recently pushed item comes out first, like `Stack`
+    pub fn lifo() -> Self {
+        Locked {
+            items: Mutex::new(VecDeque::new()),
+            lifo: true,
+        }
+    }
+}
+
+impl<T: Send> LockFree<T> for Locked<T> {
+    fn push(&self, item: T) -> bool {
+        lock(&self.items).push_back(item);
+        true
+    }
+
+    fn push_batch(&self, items: Vec<T>) -> usize {
+        let count = items.len();
+        lock(&self.items).extend(items);
+        count
+    }
+
+    fn pop(&self) -> Option<T> {
+        let mut items = lock(&self.items);
+        if self.lifo {
+            items.pop_back()
+        } else {
+            items.pop_front()
+        }
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.items).len()
+    }
+
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>)) {
+        let items = lock(&self.items);
+        if self.lifo {
+            f(&mut items.iter().rev())
+        } else {
+            f(&mut items.iter())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::queue::Queue;
+    use mpmc::stack::Stack;
+
+    /// Apply the same seeded mix of operations to `subject` and `oracle`,
+    /// and check that they agree after every one
+    fn agree(seed: u64, subject: &dyn LockFree<u64>, oracle: &dyn LockFree<u64>) {
+        let mut x = seed | 1;
+        let mut next = move || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        for i in 0..2000 {
+            match next() % 5 {
+                0 | 1 => {
+                    subject.push(i);
+                    oracle.push(i);
+                }
+                2 => {
+                    let batch = (0..next() % 8).map(|j| i * 8 + j).collect::<Vec<_>>();
+                    subject.push_batch(batch.clone());
+                    oracle.push_batch(batch);
+                }
+                3 => assert_eq!(subject.pop(), oracle.pop(), "seed {} op {}", seed, i),
+                _ => {
+                    let max = (next() % 8) as usize;
+                    assert_eq!(
+                        subject.pop_batch(max),
+                        oracle.pop_batch(max),
+                        "seed {} op {}",
+                        seed,
+                        i
+                    );
+                }
+            }
+            assert_eq!(subject.len(), oracle.len(), "seed {} op {}", seed, i);
+        }
+    }
+
+    #[test]
+    fn queue_matches_oracle() {
+        for seed in 0..8 {
+            agree(seed, &Queue::new(), &Locked::fifo());
+        }
+    }
+
+    #[test]
+    fn stack_matches_oracle() {
+        for seed in 0..8 {
+            agree(seed, &Stack::new(), &Locked::lifo());
+        }
+    }
+}
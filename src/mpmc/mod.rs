@@ -1,218 +1,1219 @@
-use std::fmt;
-use std::ops::Deref;
-use std::sync::atomic::*;
-use std::sync::{Arc, Condvar, Mutex};
-
-mod queue;
-mod stack;
-
-pub fn queue<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
-    let inner = Arc::new(Inner {
-        data: Box::new(queue::Queue::new()),
-        guard: Mutex::new(false),
-        waker: Condvar::new(),
-        connected: AtomicBool::new(true),
-        sleepers: AtomicUsize::new(0),
-    });
-    (Sender::new(inner.clone()), Receiver::new(inner.clone()))
-}
-
-pub fn stack<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
-    let inner = Arc::new(Inner {
-        data: Box::new(stack::Stack::new()),
-        guard: Mutex::new(false),
-        waker: Condvar::new(),
-        connected: AtomicBool::new(true),
-        sleepers: AtomicUsize::new(0),
-    });
-    (Sender::new(inner.clone()), Receiver::new(inner.clone()))
-}
-
-pub trait LockFree<T> {
-    fn push(&self, item: T);
-    fn pop(&self) -> Option<T>;
-    fn len(&self) -> usize;
-}
-
-struct Inner<T: Send> {
-    data: Box<LockFree<T>>,
-    connected: AtomicBool,
-    guard: Mutex<bool>,
-    waker: Condvar,
-    sleepers: AtomicUsize,
-}
-
-unsafe impl<T: Send> Send for Sender<T> {}
-unsafe impl<T: Send> Sync for Sender<T> {}
-unsafe impl<T: Send> Send for Receiver<T> {}
-unsafe impl<T: Send> Sync for Receiver<T> {}
-
-pub struct Sender<T: Send> {
-    inner: Arc<SendInner<T>>,
-}
-
-pub struct Receiver<T: Send> {
-    inner: Arc<RecvInner<T>>,
-}
-
-struct SendInner<T: Send> {
-    inner: Arc<Inner<T>>,
-}
-
-struct RecvInner<T: Send> {
-    inner: Arc<Inner<T>>,
-}
-
-impl<T: Send> Deref for RecvInner<T> {
-    type Target = Arc<Inner<T>>;
-    fn deref(&self) -> &Arc<Inner<T>> {
-        &self.inner
-    }
-}
-
-impl<T: Send> Deref for SendInner<T> {
-    type Target = Arc<Inner<T>>;
-    fn deref(&self) -> &Arc<Inner<T>> {
-        &self.inner
-    }
-}
-
-impl<T: Send> Drop for RecvInner<T> {
-    fn drop(&mut self) {
-        self.inner.connected.store(false, Ordering::Release);
-    }
-}
-
-impl<T: Send> Drop for SendInner<T> {
-    fn drop(&mut self) {
-        // Disconnect
-        self.inner.connected.store(false, Ordering::Release);
-        // Wake sleepers
-        if self.inner.sleepers.load(Ordering::Acquire) > 0 {
-            *self.inner.guard.lock().unwrap() = true;
-            self.inner.waker.notify_all();
-        }
-    }
-}
-
-impl<T: Send> Sender<T> {
-    fn new(inner: Arc<Inner<T>>) -> Sender<T> {
-        Sender {
-            inner: Arc::new(SendInner { inner }),
-        }
-    }
-
-    pub fn send(&self, data: T) -> Result<(), T> {
-        // Use stricter ordering than release, because this value
-        // can be changed by dropping receivers
-        if self.inner.connected.load(Ordering::Acquire) {
-            self.inner.data.push(data);
-            if self.inner.sleepers.load(Ordering::Acquire) > 0 {
-                *self.inner.guard.lock().unwrap() = true;
-                self.inner.waker.notify_one();
-            }
-            Ok(())
-        } else {
-            // Return ownership
-            Err(data)
-        }
-    }
-
-    pub fn size_hint(&self) -> usize {
-        self.inner.data.len()
-    }
-
-    /// Close the channel
-    pub fn close(self) {}
-}
-
-impl<T: Send> Clone for Sender<T> {
-    fn clone(&self) -> Sender<T> {
-        Sender {
-            inner: self.inner.clone(),
-        }
-    }
-}
-
-#[derive(PartialEq)]
-pub enum Error {
-    Empty,
-    Disconnected,
-}
-
-impl<T: Send> Clone for Receiver<T> {
-    fn clone(&self) -> Receiver<T> {
-        Receiver {
-            inner: self.inner.clone(),
-        }
-    }
-}
-
-impl<T: Send> Receiver<T> {
-    fn new(inner: Arc<Inner<T>>) -> Receiver<T> {
-        Receiver {
-            inner: Arc::new(RecvInner { inner }),
-        }
-    }
-
-    /// Non-blocking attempt to receive data from the channel
-    pub fn try_recv(&self) -> Result<T, Error> {
-        match self.inner.data.pop() {
-            Some(data) => Ok(data),
-            None => {
-                if self.inner.connected.load(Ordering::Acquire) {
-                    Err(Error::Empty)
-                } else {
-                    Err(Error::Disconnected)
-                }
-            }
-        }
-    }
-
-    /// Block until data is received from the channel
-    pub fn recv(&self) -> Result<T, Error> {
-        match self.try_recv() {
-            Ok(data) => return Ok(data),
-            Err(Error::Disconnected) => return Err(Error::Disconnected),
-            Err(Error::Empty) => (),
-        };
-
-        let ret;
-        let mut guard = self.inner.guard.lock().unwrap();
-        self.inner.sleepers.fetch_add(1, Ordering::Relaxed);
-        loop {
-            match self.try_recv() {
-                Ok(data) => {
-                    ret = Ok(data);
-                    break;
-                }
-                Err(Error::Disconnected) => {
-                    ret = Err(Error::Disconnected);
-                    break;
-                }
-                Err(Error::Empty) => {}
-            };
-            guard = self.inner.waker.wait(guard).unwrap();
-        }
-        self.inner.sleepers.fetch_sub(1, Ordering::Relaxed);
-        ret
-    }
-}
-
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Error::Disconnected => write!(f, "Receiver Error: channel is disconnected"),
-            Error::Empty => write!(f, "Receiver Error: channel is empty"),
-        }
-    }
-}
-
-impl fmt::Debug for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Error::Disconnected => write!(f, "Receiver Error: channel is disconnected"),
-            Error::Empty => write!(f, "Receiver Error: channel is empty"),
-        }
-    }
-}
+use pool;
+use primitive::*;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+use std::cell::Cell;
+use std::fmt;
+use std::hash::Hash;
+use std::iter::FromIterator;
+use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
+use sync::{CachePadded, CancellationToken};
+
+mod adapter;
+mod batched;
+mod bundle;
+mod dedup;
+mod dispatch;
+mod distribute;
+mod fault;
+mod global;
+mod leak;
+mod locked;
+mod peek;
+mod priority;
+mod queue;
+mod registry;
+mod select;
+#[cfg(feature = "debug-checks")]
+mod sequenced;
+mod set;
+mod stack;
+mod strategy;
+// The scenarios run their own threads, outside any loom model
+#[cfg(all(test, not(loom)))]
+mod stress;
+mod watch;
+
+pub use self::adapter::{Filter, Map};
+pub use self::batched::{BatchedReceiver, BatchedSender};
+pub use self::dispatch::{Dispatcher, WorkerId};
+pub use self::distribute::{Distribution, Distributor};
+pub use self::global::StaticChannel;
+#[cfg(feature = "leak-check")]
+pub use self::leak::Allocations;
+pub use self::registry::{ChannelInfo, Registry};
+pub use self::select::{Fairness, Select};
+pub use self::set::{Received, ReceiverSet};
+pub use self::strategy::{BlockStrategy, CondvarPark, SpinOnly, SpinThenPark, SpinThenYield};
+use self::watch::Notifier;
+pub(crate) use self::watch::{Watch, Watcher};
+
+/// Configures a channel before creating it.
+///
+/// Nodes come from the global allocator on the sending thread. On
+/// multi-socket Linux machines the kernel's first-touch policy usually
+/// places fresh pages on the sender's NUMA node, and `numa_local` makes
+/// sure of it. Confining the threads on both ends of a channel to one
+/// node, for example with `pool::Affinity::NumaNodes`, keeps its traffic
+/// local.
+pub struct Builder {
+    strategy: Box<dyn BlockStrategy>,
+    wake: WakePolicy,
+    drain_on_drop: bool,
+    numa_local: bool,
+}
+
+/// Which sleeping receivers a send wakes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WakePolicy {
+    /// Wake one receiver per message. The default, and the right choice
+    /// when each receiver handles a message at a time.
+    #[default]
+    One,
+    /// Wake every receiver on each message, for receivers that each check
+    /// the channel for something of their own
+    All,
+    /// Wake one receiver only once this many messages are queued, so a
+    /// receiver that drains batches isn't woken for every message.
+    ///
+    /// Messages below the threshold stay queued until more arrive or the
+    /// channel disconnects, so receivers should use `recv_timeout` to
+    /// bound how long a partial batch waits.
+    Threshold(usize),
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            strategy: Box::new(SpinThenPark::new()),
+            wake: WakePolicy::One,
+            drain_on_drop: false,
+            numa_local: false,
+        }
+    }
+
+    /// Set how receivers wait on an empty channel. Defaults to
+    /// `SpinThenPark`.
+    pub fn block_strategy<S: BlockStrategy + 'static>(mut self, strategy: S) -> Builder {
+        self.strategy = Box::new(strategy);
+        self
+    }
+
+    /// Set which receivers a send wakes. Defaults to `WakePolicy::One`.
+    pub fn wake_policy(mut self, wake: WakePolicy) -> Builder {
+        self.wake = wake;
+        self
+    }
+
+    /// Drop the queued messages as soon as the last `Receiver` is dropped,
+    /// rather than when the last `Sender` is. Use this when messages hold
+    /// resources like file handles that shouldn't wait for the senders.
+    ///
+    /// A send that races with the last receiver's drop may still succeed,
+    /// but its message is dropped straight away too. This costs every send
+    /// an extra fence.
+    pub fn drain_on_drop(mut self) -> Builder {
+        self.drain_on_drop = true;
+        self
+    }
+
+    /// Allocate nodes from the NUMA node of the sending thread, even if
+    /// the process runs under another memory policy such as
+    /// `numactl --interleave`. The first send from each thread applies
+    /// `pool::prefer_local_memory` to it, which also covers whatever else
+    /// the thread allocates afterwards. Best-effort: a send still succeeds
+    /// where the policy can't be set.
+    pub fn numa_local(mut self) -> Builder {
+        self.numa_local = true;
+        self
+    }
+
+    fn build<T: Send + 'static>(self, data: Box<dyn LockFree<T>>) -> (Sender<T>, Receiver<T>) {
+        let inner = Arc::new(Inner {
+            data,
+            connected: AtomicBool::new(true),
+            strategy: Notifier::new(self.strategy),
+            wake: self.wake,
+            drain_on_drop: self.drain_on_drop,
+            numa_local: self.numa_local,
+            send: CachePadded::new(SendSide {
+                sent: AtomicU64::new(0),
+                high_water: AtomicUsize::new(0),
+                senders: AtomicUsize::new(1),
+            }),
+            recv: CachePadded::new(RecvSide {
+                received: AtomicU64::new(0),
+                receivers: AtomicUsize::new(1),
+            }),
+        });
+        let sender = Sender {
+            inner: inner.clone(),
+        };
+        (sender, Receiver { inner })
+    }
+
+    pub fn queue<T: Send + 'static>(self) -> (Sender<T>, Receiver<T>) {
+        let data: Box<dyn LockFree<_>> = if cfg!(any(miri, feature = "mutex-backend")) {
+            Box::new(locked::Locked::fifo())
+        } else {
+            Box::new(queue::Queue::new())
+        };
+        // Messages travel tagged, and receives check each sender's order
+        #[cfg(feature = "debug-checks")]
+        let data = Box::new(sequenced::Sequenced::new(data));
+        self.build(data)
+    }
+
+    pub fn stack<T: Send + 'static>(self) -> (Sender<T>, Receiver<T>) {
+        if cfg!(any(miri, feature = "mutex-backend")) {
+            self.build(Box::new(locked::Locked::lifo()))
+        } else {
+            self.build(Box::new(stack::Stack::new()))
+        }
+    }
+
+    /// See [`priority`](fn.priority.html)
+    pub fn priority<T: Send + Ord + 'static>(self) -> (Sender<T>, Receiver<T>) {
+        self.build(Box::new(priority::Priority::new()))
+    }
+
+    /// See [`dedup`](fn.dedup.html)
+    pub fn dedup<T: Send + Hash + Eq + Clone + 'static>(self) -> (Sender<T>, Receiver<T>) {
+        self.build(Box::new(dedup::Dedup::new(T::clone)))
+    }
+
+    /// See [`dedup_by_key`](fn.dedup_by_key.html)
+    pub fn dedup_by_key<T, K, F>(self, key: F) -> (Sender<T>, Receiver<T>)
+    where
+        T: Send + 'static,
+        K: Send + Hash + Eq + 'static,
+        F: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        self.build(Box::new(dedup::Dedup::new(key)))
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+pub fn queue<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
+    Builder::new().queue()
+}
+
+pub fn stack<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
+    Builder::new().stack()
+}
+
+/// A channel that always receives the greatest queued message first
+pub fn priority<T: Send + Ord + 'static>() -> (Sender<T>, Receiver<T>) {
+    Builder::new().priority()
+}
+
+/// A FIFO channel that silently drops a send if an equal message is
+/// already queued and has not yet been received.
+pub fn dedup<T: Send + Hash + Eq + Clone + 'static>() -> (Sender<T>, Receiver<T>) {
+    Builder::new().dedup()
+}
+
+/// A FIFO channel that silently drops a send if a queued, unreceived
+/// message already has the same key.
+///
+/// Both ends call `key`, so it has to be `Sync`:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// let calls = Cell::new(0);
+/// let (tx, rx) = myriad::mpmc::dedup_by_key(move |x: &u32| {
+///     calls.set(calls.get() + 1);
+///     *x
+/// });
+/// ```
+pub fn dedup_by_key<T, K, F>(key: F) -> (Sender<T>, Receiver<T>)
+where
+    T: Send + 'static,
+    K: Send + Hash + Eq + 'static,
+    F: Fn(&T) -> K + Send + Sync + 'static,
+{
+    Builder::new().dedup_by_key(key)
+}
+
+/// Shared by every handle of a channel, and so across threads. Items are
+/// only ever moved in and out, never borrowed by two threads at once, so
+/// implementations need to be `Send` and `Sync` for `T: Send` alone.
+pub trait LockFree<T>: Send + Sync {
+    /// Push `item`, or drop it and return `false` if the structure
+    /// doesn't take it, as dedup does for a duplicate
+    fn push(&self, item: T) -> bool;
+
+    /// Push every item, in order, and return how many were taken.
+    /// Structures that can link a batch in with a single atomic operation
+    /// override this.
+    fn push_batch(&self, items: Vec<T>) -> usize {
+        let mut count = 0;
+        for item in items {
+            if self.push(item) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    fn pop(&self) -> Option<T>;
+
+    /// Pop up to `out.len()` items into `out`, in order, and return how
+    /// many. Structures that can unlink several items with a single atomic
+    /// operation override this.
+    fn pop_slice(&self, out: &mut [MaybeUninit<T>]) -> usize {
+        let mut count = 0;
+        for slot in out {
+            match self.pop() {
+                Some(item) => slot.write(item),
+                None => break,
+            };
+            count += 1;
+        }
+        count
+    }
+
+    /// Pop up to `max` items, in order
+    fn pop_batch(&self, max: usize) -> Vec<T> {
+        let mut items = Vec::with_capacity(max);
+        let count = self.pop_slice(&mut items.spare_capacity_mut()[..max]);
+        // `pop_slice` initialized that many
+        unsafe { items.set_len(count) };
+        items
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call `f` once with the queued items, in the order they would be
+    /// popped where the structure has one, without taking them. Pops are
+    /// held off under the structure's own synchronization until `f`
+    /// returns, so `f` should be quick.
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>));
+
+    /// Node counts, for structures that allocate nodes
+    #[cfg(feature = "leak-check")]
+    fn allocations(&self) -> Option<Allocations> {
+        None
+    }
+}
+
+/// A channel's state. The fields read by both ends but rarely written
+/// come first and share a cache line, while the counters each end writes
+/// are grouped on lines of their own, so neither end's writes evict what
+/// the other is reading.
+///
+/// Every `Sender` and `Receiver` holds a strong reference, and nothing
+/// else does, so the channel is torn down only once the last handle is
+/// gone. A handle that is leaked instead keeps it alive for good, which
+/// is what makes a late operation through it safe. `data` is then dropped
+/// with exclusive access, and its `Drop` frees whatever nodes are left
+/// without synchronizing with anyone.
+struct Inner<T: Send> {
+    data: Box<dyn LockFree<T>>,
+    connected: AtomicBool,
+    /// The channel's block strategy, which also wakes any `Select`s
+    /// waiting on the channel
+    strategy: Notifier,
+    wake: WakePolicy,
+    drain_on_drop: bool,
+    numa_local: bool,
+    send: CachePadded<SendSide>,
+    recv: CachePadded<RecvSide>,
+}
+
+/// Counters written by senders
+struct SendSide {
+    sent: AtomicU64,
+    high_water: AtomicUsize,
+    /// Live `Sender`s. The channel disconnects when this drops to zero.
+    senders: AtomicUsize,
+}
+
+/// Counters written by receivers
+struct RecvSide {
+    received: AtomicU64,
+    /// Live `Receiver`s. The channel disconnects when this drops to zero.
+    receivers: AtomicUsize,
+}
+
+/// A snapshot of a channel's counters. The counters are read one at a
+/// time while other threads keep sending and receiving, so they are only
+/// approximately consistent with each other.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Messages currently queued
+    pub queued: usize,
+    /// Messages sent since the channel was created
+    pub sent: u64,
+    /// Messages received since the channel was created
+    pub received: u64,
+    /// The most messages that have been queued at once, estimated from
+    /// `sent - received` at each send
+    pub high_water: usize,
+    /// Receivers asleep waiting for a message. Always zero with block
+    /// strategies that never sleep.
+    pub sleepers: usize,
+}
+
+/// One line, for periodic health logging:
+///
+/// ```
+/// let (tx, rx) = myriad::mpmc::queue();
+/// tx.send(()).unwrap();
+/// assert_eq!(
+///     rx.stats().to_string(),
+///     "queued 1, sent 1, received 0, high water 1, sleepers 0"
+/// );
+/// ```
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "queued {}, sent {}, received {}, high water {}, sleepers {}",
+            self.queued, self.sent, self.received, self.high_water, self.sleepers
+        )
+    }
+}
+
+thread_local! {
+    /// Whether this thread's memory policy was already made local
+    static LOCAL_MEMORY: Cell<bool> = const { Cell::new(false) };
+}
+
+impl<T: Send> Inner<T> {
+    /// Call before allocating nodes on the sending thread
+    fn place_nodes(&self) {
+        if self.numa_local && !LOCAL_MEMORY.with(Cell::get) {
+            // Only tried once, an unsupported platform won't start
+            // supporting it
+            let _ = pool::prefer_local_memory();
+            LOCAL_MEMORY.with(|done| done.set(true));
+        }
+    }
+
+    /// Account for `count` messages that were just pushed, and wake
+    /// receivers as the wake policy asks
+    fn pushed(&self, count: u64) {
+        let sent = self.send.sent.fetch_add(count, Ordering::Relaxed) + count;
+        let depth = sent.saturating_sub(self.recv.received.load(Ordering::Relaxed)) as usize;
+        // Avoid the read-modify-write unless this is a new maximum
+        if depth > self.send.high_water.load(Ordering::Relaxed) {
+            self.send.high_water.fetch_max(depth, Ordering::Relaxed);
+        }
+        if self.drain_on_drop {
+            // Pairs with the fence in `Receiver::drop`: either this sees
+            // the last receiver gone, or its drain sees the push
+            fence(Ordering::SeqCst);
+            if self.recv.receivers.load(Ordering::Relaxed) == 0 {
+                self.drain();
+                return;
+            }
+        }
+        match self.wake {
+            WakePolicy::One if count == 1 => self.strategy.notify_one(),
+            // A batch may have something for every sleeper
+            WakePolicy::One | WakePolicy::All => self.strategy.notify_all(),
+            WakePolicy::Threshold(threshold) => {
+                if depth >= threshold {
+                    self.strategy.notify_one();
+                }
+            }
+        }
+    }
+
+    /// Drop every queued message
+    fn drain(&self) {
+        while self.data.pop().is_some() {}
+    }
+
+    /// The fields both handles' `Debug` print
+    fn debug(&self, name: &str, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct(name)
+            .field("connected", &self.connected.load(Ordering::Relaxed))
+            .field("senders", &self.send.senders.load(Ordering::Relaxed))
+            .field("receivers", &self.recv.receivers.load(Ordering::Relaxed))
+            .field("len", &self.data.len())
+            .finish()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            queued: self.data.len(),
+            sent: self.send.sent.load(Ordering::Relaxed),
+            received: self.recv.received.load(Ordering::Relaxed),
+            high_water: self.send.high_water.load(Ordering::Relaxed),
+            sleepers: self.strategy.sleepers(),
+        }
+    }
+}
+
+impl<T: Send> Drop for Inner<T> {
+    fn drop(&mut self) {
+        // The handle counts are released before the references are, so
+        // both have reached zero by now
+        debug_assert_eq!(self.send.senders.load(Ordering::Relaxed), 0);
+        debug_assert_eq!(self.recv.receivers.load(Ordering::Relaxed), 0);
+    }
+}
+
+/// Lets a cancellation token wake receivers blocked on the channel. The
+/// token only calls the waker while it is registered, and
+/// `recv_cancellable` unregisters before returning, so the pointer never
+/// outlives the borrow of the channel.
+struct StrategyPtr(*const dyn BlockStrategy);
+unsafe impl Send for StrategyPtr {}
+unsafe impl Sync for StrategyPtr {}
+
+impl StrategyPtr {
+    fn notify_all(&self) {
+        unsafe { (*self.0).notify_all() }
+    }
+}
+
+/// The sending half of a channel. Clones send to the same channel, which
+/// disconnects once the last of them is dropped.
+///
+/// Both halves are `Send` and `Sync` whenever messages are `Send`, since
+/// each message moves to one receiver and is never shared. Messages that
+/// aren't `Send` are rejected:
+///
+/// ```compile_fail
+/// use std::rc::Rc;
+/// let (tx, rx) = myriad::mpmc::queue::<Rc<u32>>();
+/// ```
+pub struct Sender<T: Send> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The receiving half of a channel. Clones receive from the same channel,
+/// which disconnects once the last of them is dropped.
+pub struct Receiver<T: Send> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.inner.recv.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.inner.connected.store(false, Ordering::Release);
+            if self.inner.drain_on_drop {
+                fence(Ordering::SeqCst);
+                self.inner.drain();
+            }
+        }
+    }
+}
+
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.send.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Disconnect
+            self.inner.connected.store(false, Ordering::Release);
+            // Wake sleepers
+            self.inner.strategy.notify_all();
+        }
+    }
+}
+
+impl<T: Send> Sender<T> {
+    /// Send `data`, or return it if every receiver has been dropped.
+    ///
+    /// Sending never blocks or takes a lock, unless the channel was built
+    /// with `CondvarPark` or a `Select` is waiting on it. Beyond the push
+    /// itself it costs a bump of the `sent` counter and checks for sleeping
+    /// receivers and waiting selects, a fence and a load each, and only
+    /// when one of them is asleep does it make a syscall to wake it.
+    pub fn send(&self, data: T) -> Result<(), T> {
+        // Use stricter ordering than release, because this value
+        // can be changed by dropping receivers
+        if self.inner.connected.load(Ordering::Acquire) {
+            // A message the structure drops was never queued, so it
+            // neither counts as sent nor wakes anyone. If the last
+            // receiver went away since the check above, `pushed` drains
+            // this message along with whatever else is left.
+            self.inner.place_nodes();
+            if self.inner.data.push(data) {
+                self.inner.pushed(1);
+            }
+            Ok(())
+        } else {
+            // Return ownership
+            Err(data)
+        }
+    }
+
+    pub fn size_hint(&self) -> usize {
+        self.inner.data.len()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// Node counts for the channel's structure, if it allocates nodes.
+    /// Queue and stack channels do, unless they are backed by a mutex.
+    #[cfg(feature = "leak-check")]
+    pub fn allocations(&self) -> Option<Allocations> {
+        self.inner.data.allocations()
+    }
+
+    /// Close the channel
+    pub fn close(self) {}
+
+    /// A sender that buffers up to `capacity` messages before pushing them
+    /// onto the channel together. See `BatchedSender`.
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn batched(&self, capacity: usize) -> BatchedSender<T> {
+        BatchedSender::new(self.clone(), capacity)
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        // Relaxed like `Arc::clone`: the new handle comes from an existing
+        // one, so the count can't be at zero
+        self.inner.send.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Sends each item in turn. Once every receiver is gone the rest are
+/// dropped unsent, as there is no way to hand them back.
+///
+/// Where collecting into a `Receiver` seeds a channel that is already
+/// closed, this seeds one that stays open for more:
+///
+/// ```
+/// let (mut tx, rx) = myriad::mpmc::queue();
+/// tx.extend(vec![1, 2]);
+/// tx.send(3).unwrap();
+/// assert_eq!(rx.stats().queued, 3);
+/// ```
+impl<T: Send> Extend<T> for Sender<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            if self.send(item).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Whether receivers can block. Without threads, as on wasm32 built
+/// without the `atomics` target feature, nothing could wake them.
+const CAN_BLOCK: bool = !cfg!(all(target_arch = "wasm32", not(target_feature = "atomics")));
+
+#[derive(PartialEq)]
+pub enum Error {
+    Empty,
+    Disconnected,
+    /// The cancellation token passed to `recv_cancellable` was cancelled
+    Cancelled,
+    /// Nothing was received before the timeout passed to `recv_timeout`
+    Timeout,
+}
+
+impl<T: Send> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.inner.recv.receivers.fetch_add(1, Ordering::Relaxed);
+        Receiver {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// A queue channel holding the collected items, in order, with no senders
+/// left, so receivers disconnect once they have taken them all:
+///
+/// ```
+/// use myriad::mpmc::{Error, Receiver};
+/// let rx: Receiver<u32> = (1..3).collect();
+/// assert_eq!(rx.recv(), Ok(1));
+/// assert_eq!(rx.recv(), Ok(2));
+/// assert!(rx.recv() == Err(Error::Disconnected));
+/// ```
+impl<T: Send + 'static> FromIterator<T> for Receiver<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Receiver<T> {
+        let (mut tx, rx) = queue();
+        tx.extend(iter);
+        rx
+    }
+}
+
+impl<T: Send> Receiver<T> {
+    /// Non-blocking attempt to receive data from the channel
+    pub fn try_recv(&self) -> Result<T, Error> {
+        let data = match self.inner.data.pop() {
+            Some(data) => data,
+            None if self.inner.connected.load(Ordering::Acquire) => return Err(Error::Empty),
+            // The last messages may have been sent between the pop and
+            // the disconnect, so look once more. Acquiring the disconnect
+            // makes every message sent before it visible.
+            None => self.inner.data.pop().ok_or(Error::Disconnected)?,
+        };
+        self.inner.recv.received.fetch_add(1, Ordering::Relaxed);
+        Ok(data)
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    /// Wake `watcher` whenever a receiver would be woken, until the watch
+    /// is dropped
+    pub(crate) fn watch<'a>(&'a self, watcher: &Watcher) -> Watch<'a> {
+        self.inner.strategy.watch(watcher)
+    }
+
+    /// Node counts for the channel's structure, if it allocates nodes.
+    /// Queue and stack channels do, unless they are backed by a mutex.
+    #[cfg(feature = "leak-check")]
+    pub fn allocations(&self) -> Option<Allocations> {
+        self.inner.data.allocations()
+    }
+
+    /// Serialize the queued messages as a sequence, in the order they would
+    /// be received, without receiving them. Priority channels give them in
+    /// no particular order. Messages sent meanwhile may or may not be
+    /// included. Receives wait until the serializer is done, so this is
+    /// for crash dumps and checkpoints rather than for every message.
+    #[cfg(feature = "serde")]
+    pub fn snapshot<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+    {
+        let mut serializer = Some(serializer);
+        let mut result = None;
+        self.inner.data.peek(&mut |items| {
+            // Collecting first gives formats that need it the length
+            let items = items.collect::<Vec<_>>();
+            if let Some(serializer) = serializer.take() {
+                result = Some(serializer.collect_seq(items));
+            }
+        });
+        result.expect("peek calls its closure once")
+    }
+
+    /// Block until a message is received, then append it and any others
+    /// already queued to `out`, up to `max` in all. Returns how many were
+    /// appended, which is only zero if `max` is. Queues claim the whole
+    /// run with a single CAS.
+    pub fn recv_batch(&self, out: &mut Vec<T>, max: usize) -> Result<usize, Error> {
+        if max == 0 {
+            return Ok(0);
+        }
+        let count = self.claim(out, max);
+        if count > 0 {
+            return Ok(count);
+        }
+        out.push(self.recv()?);
+        Ok(1 + self.claim(out, max - 1))
+    }
+
+    /// Append up to `max` queued messages to `out` without blocking, and
+    /// return how many. Fails like `try_recv` if there were none.
+    pub fn try_recv_batch(&self, out: &mut Vec<T>, max: usize) -> Result<usize, Error> {
+        if max == 0 {
+            return Ok(0);
+        }
+        match self.claim(out, max) {
+            0 if self.inner.connected.load(Ordering::Acquire) => Err(Error::Empty),
+            // Look once more, as in `try_recv`
+            0 => match self.claim(out, max) {
+                0 => Err(Error::Disconnected),
+                count => Ok(count),
+            },
+            count => Ok(count),
+        }
+    }
+
+    fn claim(&self, out: &mut Vec<T>, max: usize) -> usize {
+        out.reserve(max);
+        let len = out.len();
+        let count = self
+            .inner
+            .data
+            .pop_slice(&mut out.spare_capacity_mut()[..max]);
+        // `pop_slice` initialized that many past the old length
+        unsafe { out.set_len(len + count) };
+        self.inner
+            .recv
+            .received
+            .fetch_add(count as u64, Ordering::Relaxed);
+        count
+    }
+
+    /// A receiver that claims up to `capacity` messages at a time and
+    /// serves them from a buffer of its own. See `BatchedReceiver`.
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn batched(&self, capacity: usize) -> BatchedReceiver<T> {
+        BatchedReceiver::new(self.clone(), capacity)
+    }
+
+    /// A receiver that passes each message through `f` as it is
+    /// received. See `Map`.
+    pub fn map<U, F: Fn(T) -> U>(self, f: F) -> Map<T, F> {
+        Map::new(self, f)
+    }
+
+    /// A receiver that drops messages not matching `predicate` as it
+    /// receives them. See `Filter`.
+    pub fn filter<P: Fn(&T) -> bool>(self, predicate: P) -> Filter<T, P> {
+        Filter::new(self, predicate)
+    }
+
+    /// Split the channel into two, each receiving a copy of every message,
+    /// to tap a channel for debugging or metrics without taking messages
+    /// from its consumers. A thread of its own copies them across, so a
+    /// side that falls behind queues its copies rather than holding up the
+    /// other.
+    pub fn tee(self) -> (Receiver<T>, Receiver<T>)
+    where
+        T: Clone + 'static,
+    {
+        adapter::tee(self)
+    }
+
+    /// Block until data is received from the channel.
+    ///
+    /// How the receiver waits is up to the channel's `BlockStrategy`. With
+    /// the default, `SpinThenPark`, it spins with backoff for a few
+    /// microseconds, so messages that arrive shortly after the call are
+    /// picked up without a syscall on either side, and only then parks on
+    /// a futex until a sender wakes it.
+    ///
+    /// Built for wasm32 without the `atomics` target feature, where there
+    /// are no other threads to send, it and the other blocking receives
+    /// poll instead, returning `Empty` rather than waiting.
+    pub fn recv(&self) -> Result<T, Error> {
+        self.recv_until(None, None)
+    }
+
+    /// Block until data is received from the channel, or `timeout` elapses
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, Error> {
+        self.recv_until(None, Some(timeout))
+    }
+
+    /// Block until data is received from the channel, or `token` is
+    /// cancelled. Data already queued is returned even if the token has
+    /// been cancelled.
+    pub fn recv_cancellable(&self, token: &CancellationToken) -> Result<T, Error> {
+        self.recv_until(Some(token), None)
+    }
+
+    fn recv_until(
+        &self,
+        token: Option<&CancellationToken>,
+        timeout: Option<Duration>,
+    ) -> Result<T, Error> {
+        match self.try_recv() {
+            Ok(data) => return Ok(data),
+            Err(Error::Disconnected) => return Err(Error::Disconnected),
+            Err(_) if !CAN_BLOCK => return Err(Error::Empty),
+            Err(_) => (),
+        };
+        // Not before, as there may be no clock to read either
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        let key = token.map(|token| {
+            let strategy = StrategyPtr(&self.inner.strategy as &dyn BlockStrategy);
+            token.register(Box::new(move || strategy.notify_all()))
+        });
+        let mut ret = None;
+        self.inner.strategy.wait(
+            &mut || {
+                ret = match self.try_recv() {
+                    Ok(data) => Some(Ok(data)),
+                    Err(Error::Disconnected) => Some(Err(Error::Disconnected)),
+                    Err(_) if token.is_some_and(CancellationToken::is_cancelled) => {
+                        Some(Err(Error::Cancelled))
+                    }
+                    Err(_) => None,
+                };
+                ret.is_some()
+            },
+            deadline,
+        );
+        if let (Some(token), Some(key)) = (token, key) {
+            token.unregister(key);
+        }
+        ret.unwrap_or(Err(Error::Timeout))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "Receiver Error: channel is disconnected"),
+            Error::Empty => write!(f, "Receiver Error: channel is empty"),
+            Error::Cancelled => write!(f, "Receiver Error: receive was cancelled"),
+            Error::Timeout => write!(f, "Receiver Error: receive timed out"),
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.debug("Sender", f)
+    }
+}
+
+impl<T: Send> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.debug("Receiver", f)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "Receiver Error: channel is disconnected"),
+            Error::Empty => write!(f, "Receiver Error: channel is empty"),
+            Error::Cancelled => write!(f, "Receiver Error: receive was cancelled"),
+            Error::Timeout => write!(f, "Receiver Error: receive timed out"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn recv_wakes_on_send() {
+        let (tx, rx) = stack();
+        let handle = thread::spawn(move || rx.recv());
+        thread::sleep(Duration::from_millis(10));
+        tx.send(1).unwrap();
+        assert_eq!(handle.join().unwrap(), Ok(1));
+    }
+
+    #[test]
+    fn recv_wakes_on_disconnect() {
+        let (tx, rx) = stack::<u32>();
+        let handle = thread::spawn(move || rx.recv());
+        thread::sleep(Duration::from_millis(10));
+        tx.close();
+        assert_eq!(handle.join().unwrap(), Err(Error::Disconnected));
+    }
+
+    #[test]
+    fn try_recv_after_disconnect() {
+        // Polling must see every message sent before the disconnect, even
+        // when the last send and the drop race with an empty pop
+        for _ in 0..200 {
+            let (tx, rx) = queue();
+            let sender = thread::spawn(move || {
+                for i in 0..100 {
+                    tx.send(i).unwrap();
+                }
+            });
+            let mut received = 0;
+            loop {
+                match rx.try_recv() {
+                    Ok(_) => received += 1,
+                    Err(Error::Empty) => thread::yield_now(),
+                    Err(error) => {
+                        assert_eq!(error, Error::Disconnected);
+                        break;
+                    }
+                }
+            }
+            sender.join().unwrap();
+            assert_eq!(received, 100);
+        }
+    }
+
+    #[test]
+    fn last_handle_disconnects() {
+        let (tx, rx) = queue::<u32>();
+        let other = tx.clone();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Err(Error::Empty));
+        other.send(1).unwrap();
+        drop(other);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(Error::Disconnected));
+
+        let (tx, rx) = queue();
+        let clone = rx.clone();
+        drop(rx);
+        tx.send(2).unwrap();
+        drop(clone);
+        assert_eq!(tx.send(3), Err(3));
+    }
+
+    #[test]
+    fn teardown_waits_for_last_handle() {
+        let item = Arc::new(());
+        let (tx, rx) = queue();
+        tx.send(item.clone()).unwrap();
+        let mut leaked = std::mem::ManuallyDrop::new(rx.clone());
+        drop((tx, rx));
+        // The channel and its message outlive the other handles
+        assert_eq!(Arc::strong_count(&item), 2);
+        assert_eq!(leaked.stats().queued, 1);
+        unsafe { std::mem::ManuallyDrop::drop(&mut leaked) };
+        assert_eq!(Arc::strong_count(&item), 1);
+    }
+
+    #[test]
+    fn debug() {
+        let (tx, rx) = queue();
+        tx.send(1).unwrap();
+        let other = rx.clone();
+        assert_eq!(
+            format!("{:?}", tx),
+            "Sender { connected: true, senders: 1, receivers: 2, len: 1 }"
+        );
+        drop((tx, other));
+        assert_eq!(
+            format!("{:?}", rx),
+            "Receiver { connected: false, senders: 0, receivers: 1, len: 1 }"
+        );
+    }
+
+    #[test]
+    fn collect_and_extend() {
+        let rx: Receiver<_> = vec![1, 2, 3].into_iter().collect();
+        let mut all = Vec::new();
+        assert_eq!(rx.try_recv_batch(&mut all, 10), Ok(3));
+        assert_eq!(all, vec![1, 2, 3]);
+        assert!(rx.try_recv() == Err(Error::Disconnected));
+
+        let (mut tx, rx) = stack();
+        tx.extend(0..3);
+        drop(rx);
+        // Nothing to receive them, so the rest are dropped
+        tx.extend(3..6);
+        assert_eq!(tx.stats().sent, 3);
+    }
+
+    #[test]
+    fn handles_are_send_and_sync() {
+        fn check<T: Send + Sync>() {}
+        // `Cell` is `Send` but not `Sync`, which is all messages need
+        check::<Sender<std::cell::Cell<u32>>>();
+        check::<Receiver<std::cell::Cell<u32>>>();
+    }
+
+    #[test]
+    fn drain_on_drop() {
+        let handle = Arc::new(());
+        let (tx, rx) = Builder::new().drain_on_drop().queue();
+        for _ in 0..10 {
+            tx.send(handle.clone()).unwrap();
+        }
+        let clone = rx.clone();
+        drop(rx);
+        assert_eq!(Arc::strong_count(&handle), 11);
+        drop(clone);
+        assert_eq!(Arc::strong_count(&handle), 1);
+        assert_eq!(tx.size_hint(), 0);
+    }
+
+    #[test]
+    fn drain_on_drop_races_sends() {
+        for _ in 0..20 {
+            let handle = Arc::new(());
+            let (tx, rx) = Builder::new().drain_on_drop().queue();
+            let senders = (0..3)
+                .map(|_| {
+                    let tx = tx.clone();
+                    let handle = handle.clone();
+                    thread::spawn(move || while tx.send(handle.clone()).is_ok() {})
+                })
+                .collect::<Vec<_>>();
+            thread::yield_now();
+            drop(rx);
+            for sender in senders {
+                sender.join().unwrap();
+            }
+            // Sends that passed the connected check before the drop still
+            // had their messages dropped, though `tx` is alive
+            assert_eq!(Arc::strong_count(&handle), 1);
+            assert_eq!(tx.size_hint(), 0);
+        }
+    }
+
+    #[test]
+    fn many_sleepers() {
+        let (tx, rx) = stack();
+        let handles = (0..4)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || rx.recv())
+            })
+            .collect::<Vec<_>>();
+        thread::sleep(Duration::from_millis(10));
+        for i in 0..4 {
+            tx.send(i).unwrap();
+        }
+        let mut received = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        received.sort();
+        assert_eq!(received, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn wake_threshold() {
+        let (tx, rx) = Builder::new().wake_policy(WakePolicy::Threshold(3)).queue();
+        let handle = thread::spawn(move || {
+            let first = rx.recv();
+            (first, rx.try_recv(), rx.try_recv())
+        });
+        thread::sleep(Duration::from_millis(20));
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        // Below the threshold, so the receiver is still asleep
+        assert!(!handle.is_finished());
+        tx.send(3).unwrap();
+        assert_eq!(handle.join().unwrap(), (Ok(1), Ok(2), Ok(3)));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn snapshot() {
+        let (tx, rx) = queue();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        let value = rx.snapshot(serde_json::value::Serializer).unwrap();
+        assert_eq!(value, serde_json::json!([0, 1, 2]));
+        assert_eq!(rx.stats().queued, 3);
+        let (tx, rx) = stack();
+        tx.send("a").unwrap();
+        tx.send("b").unwrap();
+        let mut out = Vec::new();
+        rx.snapshot(&mut serde_json::Serializer::new(&mut out))
+            .unwrap();
+        assert_eq!(out, br#"["b","a"]"#);
+        // Nothing was received
+        assert_eq!(rx.try_recv(), Ok("b"));
+    }
+
+    #[test]
+    fn recv_batch() {
+        let (tx, rx) = queue();
+        let handle = thread::spawn(move || {
+            let mut out = Vec::new();
+            let first = rx.recv_batch(&mut out, 4);
+            (first, out)
+        });
+        thread::sleep(Duration::from_millis(10));
+        tx.send(0).unwrap();
+        let (first, mut out) = handle.join().unwrap();
+        assert_eq!((first, &out[..]), (Ok(1), &[0][..]));
+
+        let (tx, rx) = stack();
+        for i in 1..=6 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv_batch(&mut out, 4), Ok(4));
+        assert_eq!(out, vec![0, 6, 5, 4, 3]);
+        assert_eq!(rx.stats().received, 4);
+        drop(tx);
+        assert_eq!(rx.recv_batch(&mut out, 4), Ok(2));
+        assert_eq!(rx.recv_batch(&mut out, 4), Err(Error::Disconnected));
+        assert_eq!(rx.try_recv_batch(&mut out, 4), Err(Error::Disconnected));
+
+        let (tx, rx) = queue();
+        assert_eq!(rx.try_recv_batch(&mut out, 4), Err(Error::Empty));
+        tx.send(7).unwrap();
+        assert_eq!(rx.try_recv_batch(&mut out, 4), Ok(1));
+        assert_eq!(out.last(), Some(&7));
+    }
+
+    #[test]
+    fn stats() {
+        let (tx, rx) = queue();
+        for i in 0..4 {
+            tx.send(i).unwrap();
+        }
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+        tx.send(4).unwrap();
+        assert_eq!(
+            rx.stats(),
+            Stats {
+                queued: 3,
+                sent: 5,
+                received: 2,
+                high_water: 4,
+                sleepers: 0,
+            }
+        );
+        assert_eq!(tx.stats(), rx.stats());
+
+        let (tx, rx) = Builder::new()
+            .block_strategy(CondvarPark::new())
+            .stack::<u32>();
+        let sleeper = thread::spawn(move || rx.recv());
+        while tx.stats().sleepers == 0 {
+            thread::yield_now();
+        }
+        assert_eq!(
+            tx.stats().to_string(),
+            "queued 0, sent 0, received 0, high water 0, sleepers 1"
+        );
+        tx.send(1).unwrap();
+        assert_eq!(sleeper.join().unwrap(), Ok(1));
+    }
+
+    #[test]
+    fn numa_local() {
+        let (tx, rx) = Builder::new().numa_local().queue();
+        thread::spawn(move || {
+            assert!(!LOCAL_MEMORY.with(Cell::get));
+            tx.send(1).unwrap();
+            assert!(LOCAL_MEMORY.with(Cell::get));
+        })
+        .join()
+        .unwrap();
+        assert_eq!(rx.try_recv(), Ok(1));
+        // Channels without the option leave the thread alone
+        let (tx, _rx) = queue();
+        thread::spawn(move || {
+            tx.send(1).unwrap();
+            assert!(!LOCAL_MEMORY.with(Cell::get));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn recv_cancellable() {
+        let (tx, rx) = stack();
+        let token = CancellationToken::new();
+        tx.send(1).unwrap();
+        token.cancel();
+        assert_eq!(rx.recv_cancellable(&token), Ok(1));
+        assert_eq!(rx.recv_cancellable(&token), Err(Error::Cancelled));
+
+        let token = CancellationToken::new();
+        let child = token.child_token();
+        let handle = thread::spawn(move || rx.recv_cancellable(&child));
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+        assert_eq!(handle.join().unwrap(), Err(Error::Cancelled));
+        drop(tx);
+    }
+}
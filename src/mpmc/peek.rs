@@ -0,0 +1,56 @@
+//! Looking at the items in a lock-free backend without taking them.
+//!
+//! A pop moves its item out of the node as soon as it has claimed it, and
+//! the popper may then drop it while someone else is still reading it in
+//! place. So `Peekers` counts the threads looking, and a pop that has
+//! claimed items waits for that count to reach zero before moving them
+//! out. A peeker counts itself before loading the head, and a pop claims
+//! by moving the head before checking the count. Both are `SeqCst`, so
+//! either the pop sees the peeker and waits, or the peeker sees the moved
+//! head and never reaches the claimed items.
+//!
+//! Pushes don't wait, and pops only wait while someone is peeking, which
+//! costs them a load the rest of the time.
+
+use primitive::{AtomicUsize, Ordering::*};
+use sync::Backoff;
+
+pub struct Peekers {
+    count: AtomicUsize,
+}
+
+/// Holds off pops from moving items out until dropped
+pub struct Peeking<'a> {
+    peekers: &'a Peekers,
+}
+
+impl Peekers {
+    pub fn new() -> Peekers {
+        Peekers {
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count the calling thread as peeking. Load the head after this.
+    pub fn enter(&self) -> Peeking<'_> {
+        self.count.fetch_add(1, SeqCst);
+        Peeking { peekers: self }
+    }
+
+    /// Called by a pop after its `SeqCst` claim, before it moves anything
+    /// out
+    pub fn wait(&self) {
+        let backoff = Backoff::new();
+        while self.count.load(SeqCst) != 0 {
+            backoff.snooze();
+        }
+    }
+}
+
+impl<'a> Drop for Peeking<'a> {
+    fn drop(&mut self) {
+        // Pairs with the load in `wait`, so the peeker's reads are done
+        // before the pop moves the items out
+        self.peekers.count.fetch_sub(1, Release);
+    }
+}
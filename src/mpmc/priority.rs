@@ -0,0 +1,106 @@
+//! A priority queue, where `pop` always returns the greatest item queued.
+//! Items that compare equal come out in no particular order, so callers
+//! that need FIFO within a priority should include a sequence number in
+//! the ordering.
+
+use super::*;
+use primitive::{lock, Mutex};
+use std::collections::BinaryHeap;
+
+pub struct Priority<T> {
+    heap: Mutex<BinaryHeap<T>>,
+}
+
+impl<T: Ord> Priority<T> {
+    pub fn new() -> Self {
+        Priority {
+            heap: Mutex::new(BinaryHeap::new()),
+        }
+    }
+}
+
+impl<T: Send + Ord> LockFree<T> for Priority<T> {
+    fn push(&self, item: T) -> bool {
+        lock(&self.heap).push(item);
+        true
+    }
+
+    fn pop(&self) -> Option<T> {
+        lock(&self.heap).pop()
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.heap).len()
+    }
+
+    /// The heap's order, not the order pops would return
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>)) {
+        f(&mut lock(&self.heap).iter())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering::*};
+    use std::thread;
+
+    #[test]
+    fn greatest_first() {
+        let queue = Priority::new();
+        for &i in &[3, 1, 4, 1, 5, 9, 2, 6] {
+            queue.push(i);
+        }
+        assert_eq!(queue.len(), 8);
+        let mut popped = Vec::new();
+        while let Some(i) = queue.pop() {
+            popped.push(i);
+        }
+        assert_eq!(popped, vec![9, 6, 5, 4, 3, 2, 1, 1]);
+    }
+
+    #[test]
+    fn channel() {
+        let (tx, rx) = priority();
+        let handle = thread::spawn(move || rx.recv());
+        thread::sleep(std::time::Duration::from_millis(10));
+        tx.send(1).unwrap();
+        assert_eq!(handle.join().unwrap(), Ok(1));
+    }
+
+    static ARMED: AtomicBool = AtomicBool::new(true);
+
+    /// Panics the first time it's compared with zero
+    #[derive(Debug, PartialEq, Eq)]
+    struct Touchy(u32);
+
+    impl PartialOrd for Touchy {
+        fn partial_cmp(&self, other: &Touchy) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Touchy {
+        fn cmp(&self, other: &Touchy) -> std::cmp::Ordering {
+            if self.0 == 0 || other.0 == 0 {
+                assert!(!ARMED.swap(false, SeqCst), "compared with zero");
+            }
+            self.0.cmp(&other.0)
+        }
+    }
+
+    #[test]
+    fn survives_panicking_ord() {
+        let (tx, rx) = priority();
+        tx.send(Touchy(1)).unwrap();
+        let sender = tx.clone();
+        let panicked = thread::spawn(move || sender.send(Touchy(0)).is_ok());
+        assert!(panicked.join().is_err());
+        // The lock was poisoned, but the channel still works and nothing
+        // was lost
+        assert!(tx.send(Touchy(2)).is_ok());
+        let mut received = (0..3).map(|_| rx.recv().unwrap().0).collect::<Vec<_>>();
+        received.sort();
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+}
@@ -1,167 +1,556 @@
-//! A first-in-first-out queue that supports multiple producers and multiple
-//! consumers using atomics.
-
-use super::*;
-use std::ptr;
-use std::sync::atomic::{AtomicPtr, Ordering::*};
-
-/// Linked list node
-struct Node<T> {
-    data: Option<T>,
-    next: *mut Node<T>,
-}
-
-impl<T> Node<T> {
-    fn new(data: Option<T>) -> *mut Self {
-        Box::into_raw(Box::new(Node {
-            data,
-            next: ptr::null_mut(),
-        }))
-    }
-}
-
-/// A FIFO queue
-pub struct Queue<T> {
-    head: AtomicPtr<Node<T>>,
-    tail: AtomicPtr<Node<T>>,
-}
-
-impl<T> Queue<T> {
-    pub fn new() -> Self {
-        let empty = Node::new(None);
-        Queue {
-            head: AtomicPtr::new(empty),
-            tail: AtomicPtr::new(empty),
-        }
-    }
-}
-
-impl<T> LockFree<T> for Queue<T> {
-    fn push(&self, data: T) {
-        let new_tail = Node::new(None);
-        unsafe {
-            loop {
-                // Tail will always point to an empty value
-                let tail = self.tail.load(Acquire);
-
-                if tail == self.tail.compare_and_swap(tail, new_tail, Release) {
-                    (*tail).data = Some(data);
-                    (*tail).next = new_tail;
-                    break;
-                }
-            }
-        }
-    }
-
-    fn pop(&self) -> Option<T> {
-        unsafe {
-            loop {
-                let head = self.head.load(Acquire);
-                if (*head).next.is_null() {
-                    return None;
-                }
-                if head == self.head.compare_and_swap(head, (*head).next, Release) {
-                    let mut node = Box::from_raw(head);
-                    return node.data.take();
-                }
-            }
-        }
-    }
-
-    fn len(&self) -> usize {
-        let mut len = 0;
-        unsafe {
-            let mut head = self.head.load(Acquire);
-            loop {
-                if !(*head).next.is_null() {
-                    head = (*head).next;
-                    len += 1;
-                } else {
-                    return len;
-                }
-            }
-        }
-    }
-}
-
-impl<T> Drop for Queue<T> {
-    fn drop(&mut self) {
-        unsafe {
-            let head = self.head.swap(ptr::null_mut(), SeqCst);
-            if !head.is_null() {
-                let mut node = Box::from_raw(head);
-                loop {
-                    if !node.next.is_null() {
-                        node = Box::from_raw(node.next);
-                    } else {
-                        break;
-                    }
-                }
-            }
-        }
-    }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::sync::{atomic::AtomicUsize, Arc};
-
-    #[derive(Debug)]
-    struct Sentinel(Arc<AtomicUsize>);
-    impl Drop for Sentinel {
-        fn drop(&mut self) {
-            self.0.fetch_add(1, Relaxed);
-        }
-    }
-
-    #[test]
-    fn pop_and_drop() {
-        let guard = Arc::new(AtomicUsize::new(0));
-        let queue = Queue::new();
-
-        queue.push(Sentinel(guard.clone()));
-        queue.push(Sentinel(guard.clone()));
-        queue.push(Sentinel(guard.clone()));
-        queue.pop().unwrap();
-        assert_eq!(1, guard.load(Acquire));
-        queue.pop().unwrap();
-        assert_eq!(2, guard.load(Acquire));
-        queue.pop().unwrap();
-        assert_eq!(3, guard.load(Acquire));
-    }
-
-    #[test]
-    fn drop() {
-        let guard = Arc::new(AtomicUsize::new(0));
-        {
-            let queue = Queue::new();
-
-            queue.push(Sentinel(guard.clone()));
-            queue.push(Sentinel(guard.clone()));
-            queue.push(Sentinel(guard.clone()));
-        }
-        assert_eq!(3, guard.load(Acquire));
-    }
-
-    #[test]
-    fn len() {
-        let queue = Queue::new();
-        for i in 0..100 {
-            queue.push(i);
-        }
-        assert_eq!(queue.len(), 100)
-    }
-
-    #[test]
-    fn sanity() {
-        let queue = Queue::new();
-        queue.push(10);
-        queue.push(5);
-        queue.push(0);
-        assert_eq!(queue.pop(), Some(10));
-        assert_eq!(queue.pop(), Some(5));
-        assert_eq!(queue.pop(), Some(0));
-        assert_eq!(queue.pop(), None);
-    }
-}
+//! A first-in-first-out queue that supports multiple producers and multiple
+//! consumers using atomics.
+
+use super::fault::{self, Point};
+use super::peek::Peekers;
+use super::*;
+use epoch;
+use primitive::{AtomicPtr, AtomicUsize, Ordering::*};
+use std::iter;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use sync::{Backoff, CachePadded, Contention};
+
+/// Linked list node. The node at `head` is a sentinel whose data has
+/// already been taken, or was never there. Popping turns the node it took
+/// the data from into the next sentinel, so apart from the first sentinel
+/// every node is allocated by exactly one push.
+struct Node<T> {
+    data: MaybeUninit<T>,
+    next: AtomicPtr<Node<T>>,
+    _token: leak::Token,
+}
+
+impl<T> Node<T> {
+    fn new(data: MaybeUninit<T>, allocations: &leak::Allocations) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            data,
+            next: AtomicPtr::new(ptr::null_mut()),
+            _token: allocations.token(),
+        }))
+    }
+}
+
+/// A Michael-Scott FIFO queue. `head` and `tail` are contended by
+/// consumers and producers respectively, so they are kept on separate
+/// cache lines.
+///
+/// A producer links a fully written node after the last one with a
+/// release CAS of its `next`, then swings `tail` to it. Anyone who finds
+/// `tail` lagging behind a linked node, because that second step hasn't
+/// happened yet, swings it forward themselves rather than waiting, so a
+/// stalled producer never blocks the others. Consumers acquire `next`
+/// before reading its data, and never move `head` past `tail`.
+///
+/// Every operation runs pinned, and popped sentinels are freed through the
+/// epoch collector, since other threads may still be reading them.
+/// This also rules out ABA: a node's address can't be reused while anyone
+/// who loaded it is still pinned.
+pub struct Queue<T> {
+    head: CachePadded<AtomicPtr<Node<T>>>,
+    tail: CachePadded<AtomicPtr<Node<T>>>,
+    /// Shared by the retry loops on both ends
+    contention: Contention,
+    /// Items pushed and not yet popped. Pushes count their items before
+    /// linking them in, so this never drops below the true length.
+    len: CachePadded<AtomicUsize>,
+    peekers: Peekers,
+    allocations: leak::Allocations,
+    /// The queue owns its items, which raw pointers alone don't say, so
+    /// without this it would be `Send` for any `T`
+    _owns: PhantomData<T>,
+}
+
+// Items are moved in and out but never shared, so sharing the queue only
+// needs `T: Send`
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        let allocations = leak::Allocations::new();
+        let sentinel = Node::new(MaybeUninit::uninit(), &allocations);
+        Queue {
+            head: CachePadded::new(AtomicPtr::new(sentinel)),
+            tail: CachePadded::new(AtomicPtr::new(sentinel)),
+            contention: Contention::new(),
+            len: CachePadded::new(AtomicUsize::new(0)),
+            peekers: Peekers::new(),
+            allocations,
+            _owns: PhantomData,
+        }
+    }
+
+    /// Called by a pop that found nothing after the sentinel. Pushes count
+    /// their items before linking them in, so a nonzero `len` means a
+    /// push may be between the two steps, with an item that is as good as
+    /// queued. Rather than report the queue empty under it, give the
+    /// producer a few rounds of `backoff` to finish, and return whether to
+    /// look again. A producer stalled for longer still gets `None`
+    /// reported past it, so pops stay lock-free.
+    fn publishing(&self, backoff: &Backoff) -> bool {
+        if self.len.load(Relaxed) == 0 || backoff.is_completed() {
+            return false;
+        }
+        backoff.snooze();
+        true
+    }
+
+    /// Append the chain of `count` nodes from `first` to `last`, which
+    /// must be linked to each other and not yet visible to anyone else
+    fn link(&self, first: *mut Node<T>, last: *mut Node<T>, count: usize) {
+        self.len.fetch_add(count, Relaxed);
+        let backoff = self.contention.backoff();
+        let _guard = epoch::pin();
+        unsafe {
+            loop {
+                let tail = self.tail.load(Acquire);
+                let next = (*tail).next.load(Acquire);
+                fault::inject(Point::AfterLoad);
+                if next.is_null() {
+                    fault::inject(Point::BeforeCas);
+                    // Release publishes the chain's data with the link
+                    if (*tail)
+                        .next
+                        .compare_exchange_weak(next, first, Release, Relaxed)
+                        .is_ok()
+                    {
+                        fault::inject(Point::AfterCas);
+                        // Failing means someone already helped us along.
+                        // Helpers only move one node at a time, and later
+                        // operations will walk `tail` along the rest.
+                        let _ = self.tail.compare_exchange(tail, last, Release, Relaxed);
+                        return;
+                    }
+                } else {
+                    let _ = self.tail.compare_exchange(tail, next, Release, Relaxed);
+                }
+                backoff.spin();
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> LockFree<T> for Queue<T> {
+    fn push(&self, data: T) -> bool {
+        let node = Node::new(MaybeUninit::new(data), &self.allocations);
+        self.link(node, node, 1);
+        true
+    }
+
+    fn push_batch(&self, items: Vec<T>) -> usize {
+        let mut items = items.into_iter();
+        let first = match items.next() {
+            Some(data) => Node::new(MaybeUninit::new(data), &self.allocations),
+            None => return 0,
+        };
+        // Nobody else can see the chain yet, so plain stores do. Linking
+        // it in publishes all of it.
+        let mut last = first;
+        let mut count = 1;
+        for data in items {
+            let node = Node::new(MaybeUninit::new(data), &self.allocations);
+            unsafe { (*last).next.store(node, Relaxed) };
+            last = node;
+            count += 1;
+        }
+        self.link(first, last, count);
+        count
+    }
+
+    fn pop(&self) -> Option<T> {
+        let backoff = self.contention.backoff();
+        let publishing = Backoff::new();
+        let guard = epoch::pin();
+        unsafe {
+            loop {
+                let head = self.head.load(Acquire);
+                let tail = self.tail.load(Acquire);
+                // Pairs with the release CAS in `push`, making the data of
+                // `next` visible
+                let next = (*head).next.load(Acquire);
+                fault::inject(Point::AfterLoad);
+                if next.is_null() {
+                    if self.publishing(&publishing) {
+                        continue;
+                    }
+                    return None;
+                }
+                if head == tail {
+                    // Help the producer that linked `next`, so `head` can
+                    // move without overtaking `tail`
+                    let _ = self.tail.compare_exchange(tail, next, Release, Relaxed);
+                    continue;
+                }
+                fault::inject(Point::BeforeCas);
+                // SeqCst for `peekers`, see `peek`
+                if self
+                    .head
+                    .compare_exchange_weak(head, next, SeqCst, Relaxed)
+                    .is_ok()
+                {
+                    fault::inject(Point::AfterCas);
+                    self.peekers.wait();
+                    // `next` is the new sentinel, so its data is ours to
+                    // move out. Dropping the node later leaves it alone.
+                    let data = ptr::read((*next).data.as_ptr());
+                    guard.defer_destroy(head);
+                    self.len.fetch_sub(1, Relaxed);
+                    return Some(data);
+                }
+                backoff.spin();
+            }
+        }
+    }
+
+    fn pop_slice(&self, out: &mut [MaybeUninit<T>]) -> usize {
+        let max = out.len();
+        if max == 0 {
+            return 0;
+        }
+        let backoff = self.contention.backoff();
+        let publishing = Backoff::new();
+        let guard = epoch::pin();
+        unsafe {
+            loop {
+                let head = self.head.load(Acquire);
+                let tail = self.tail.load(Acquire);
+                let next = (*head).next.load(Acquire);
+                fault::inject(Point::AfterLoad);
+                if next.is_null() {
+                    if self.publishing(&publishing) {
+                        continue;
+                    }
+                    return 0;
+                }
+                if head == tail {
+                    let _ = self.tail.compare_exchange(tail, next, Release, Relaxed);
+                    continue;
+                }
+                // Find the last node to claim, without passing `tail`. It
+                // becomes the new sentinel.
+                let mut last = next;
+                let mut count = 1;
+                while count < max && last != tail {
+                    let after = (*last).next.load(Acquire);
+                    if after.is_null() {
+                        break;
+                    }
+                    last = after;
+                    count += 1;
+                }
+                fault::inject(Point::BeforeCas);
+                if self
+                    .head
+                    .compare_exchange_weak(head, last, SeqCst, Relaxed)
+                    .is_ok()
+                {
+                    fault::inject(Point::AfterCas);
+                    self.peekers.wait();
+                    let mut node = head;
+                    for slot in &mut out[..count] {
+                        let next = (*node).next.load(Relaxed);
+                        slot.write(ptr::read((*next).data.as_ptr()));
+                        guard.defer_destroy(node);
+                        node = next;
+                    }
+                    self.len.fetch_sub(count, Relaxed);
+                    return count;
+                }
+                backoff.spin();
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Relaxed)
+    }
+
+    /// Walk from the sentinel while pinned, so no node is freed under the
+    /// walk. Pops that claim an item meanwhile wait to move it out until
+    /// the walk is done, see `peek.rs`.
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>)) {
+        let _guard = epoch::pin();
+        let _peeking = self.peekers.enter();
+        let mut node = self.head.load(SeqCst);
+        let mut items = iter::from_fn(|| unsafe {
+            // Acquire pairs with the release CAS in `link`
+            node = (*node).next.load(Acquire);
+            node.as_ref().map(|node| &*node.data.as_ptr())
+        });
+        f(&mut items);
+    }
+
+    #[cfg(feature = "leak-check")]
+    fn allocations(&self) -> Option<leak::Allocations> {
+        Some(self.allocations.clone())
+    }
+}
+
+impl<T> fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Queue")
+            .field("len", &self.len.load(Relaxed))
+            .finish()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        unsafe {
+            // The sentinel holds no data. Loads rather than `get_mut`,
+            // which loom's atomics don't have.
+            let mut node = Box::from_raw(self.head.load(Relaxed));
+            loop {
+                let next = node.next.load(Relaxed);
+                if next.is_null() {
+                    break;
+                }
+                node = Box::from_raw(next);
+                ptr::drop_in_place(node.data.as_mut_ptr());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    #[derive(Debug)]
+    struct Sentinel(Arc<AtomicUsize>);
+    impl Drop for Sentinel {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Nodes from the sentinel to the tail
+    fn nodes<T>(queue: &Queue<T>) -> usize {
+        let mut count = 1;
+        let mut node = queue.head.load(Acquire);
+        while node != queue.tail.load(Acquire) {
+            node = unsafe { (*node).next.load(Acquire) };
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn pop_waits_for_publishing_push() {
+        use super::super::fault::with_hook;
+        use std::sync::mpsc;
+        use std::thread;
+
+        let queue = Arc::new(Queue::new());
+        let (go_tx, go_rx) = mpsc::channel();
+        let (linked_tx, linked_rx) = mpsc::channel();
+        let producer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                // Counted, not yet linked, until the consumer has looked
+                let hook = move |point| match point {
+                    Point::BeforeCas => go_rx.recv().unwrap(),
+                    Point::AfterCas => linked_tx.send(()).unwrap(),
+                    _ => (),
+                };
+                with_hook(hook, || {
+                    queue.push(7);
+                })
+            })
+        };
+        while queue.len() == 0 {
+            thread::yield_now();
+        }
+        let mut looks = 0;
+        let hook = move |point| {
+            if point == Point::AfterLoad {
+                looks += 1;
+                match looks {
+                    1 => go_tx.send(()).unwrap(),
+                    2 => linked_rx.recv().unwrap(),
+                    _ => (),
+                }
+            }
+        };
+        assert_eq!(with_hook(hook, || queue.pop()), Some(7));
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn peek_holds_off_pops() {
+        use std::sync::atomic::AtomicBool;
+        use std::thread;
+        use std::time::Duration;
+
+        let queue = Arc::new(Queue::new());
+        queue.push(String::from("a"));
+        queue.push(String::from("b"));
+        let popped = Arc::new(AtomicBool::new(false));
+        let mut popper = None;
+        queue.peek(&mut |items| {
+            let (queue, done) = (queue.clone(), popped.clone());
+            popper = Some(thread::spawn(move || {
+                let item = queue.pop();
+                done.store(true, SeqCst);
+                item
+            }));
+            // The pop may claim "a", but can't move it out from under us
+            thread::sleep(Duration::from_millis(20));
+            assert_eq!(items.collect::<Vec<_>>(), ["a", "b"]);
+            assert!(!popped.load(SeqCst));
+        });
+        assert_eq!(popper.unwrap().join().unwrap().as_deref(), Some("a"));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn popped_node_becomes_sentinel() {
+        let queue = Queue::new();
+        queue.push(1);
+        let last = queue.tail.load(Acquire);
+        assert_eq!(queue.pop(), Some(1));
+        // No fresh sentinel: the node that held the item took its place
+        assert_eq!(queue.head.load(Acquire), last);
+        for i in 0..10 {
+            queue.push(i);
+        }
+        queue.pop();
+        // One node per item, plus the sentinel
+        assert_eq!(nodes(&queue), 10);
+    }
+
+    #[test]
+    fn pop_and_drop() {
+        let guard = Arc::new(AtomicUsize::new(0));
+        let queue = Queue::new();
+
+        queue.push(Sentinel(guard.clone()));
+        queue.push(Sentinel(guard.clone()));
+        queue.push(Sentinel(guard.clone()));
+        queue.pop().unwrap();
+        assert_eq!(1, guard.load(Acquire));
+        queue.pop().unwrap();
+        assert_eq!(2, guard.load(Acquire));
+        queue.pop().unwrap();
+        assert_eq!(3, guard.load(Acquire));
+    }
+
+    #[test]
+    fn drop() {
+        let guard = Arc::new(AtomicUsize::new(0));
+        {
+            let queue = Queue::new();
+
+            queue.push(Sentinel(guard.clone()));
+            queue.push(Sentinel(guard.clone()));
+            queue.push(Sentinel(guard.clone()));
+        }
+        assert_eq!(3, guard.load(Acquire));
+    }
+
+    #[test]
+    fn len() {
+        let queue = Queue::new();
+        for i in 0..100 {
+            queue.push(i);
+        }
+        assert_eq!(queue.len(), 100);
+        queue.pop();
+        assert_eq!(queue.pop_batch(10).len(), 10);
+        queue.push_batch(vec![1, 2, 3]);
+        assert_eq!(queue.len(), 92)
+    }
+
+    #[test]
+    fn sanity() {
+        let queue = Queue::new();
+        queue.push(10);
+        queue.push(5);
+        queue.push(0);
+        assert_eq!(queue.pop(), Some(10));
+        assert_eq!(queue.pop(), Some(5));
+        assert_eq!(queue.pop(), Some(0));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn concurrent_producers() {
+        let queue = Arc::new(Queue::new());
+        let handles = (0..4)
+            .map(|id| {
+                let queue = queue.clone();
+                ::std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        queue.push((id, i));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut last = [None; 4];
+        let mut popped = 0;
+        while popped < 4000 {
+            if let Some((id, i)) = queue.pop() {
+                // Each producer's items come out in the order it pushed them
+                assert!(last[id].is_none_or(|last| last < i));
+                last[id] = Some(i);
+                popped += 1;
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(queue.pop(), None);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+    use primitive::{thread, Arc};
+
+    #[test]
+    fn push_while_popping() {
+        loom::model(|| {
+            let queue = Arc::new(Queue::new());
+            let producer = {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    queue.push(1);
+                    queue.push(2);
+                })
+            };
+            let mut popped = Vec::new();
+            while popped.len() < 2 {
+                match queue.pop() {
+                    Some(item) => popped.push(item),
+                    None => thread::yield_now(),
+                }
+            }
+            producer.join().unwrap();
+            assert_eq!(popped, [1, 2]);
+            assert!(queue.is_empty());
+        });
+    }
+
+    #[test]
+    fn racing_pops() {
+        loom::model(|| {
+            let queue = Arc::new(Queue::new());
+            queue.push(1);
+            queue.push(2);
+            let other = {
+                let queue = queue.clone();
+                thread::spawn(move || queue.pop())
+            };
+            let mine = queue.pop();
+            let theirs = other.join().unwrap();
+            // Each item goes to exactly one popper, in order
+            let mut popped = vec![mine.unwrap(), theirs.unwrap()];
+            popped.sort();
+            assert_eq!(popped, [1, 2]);
+            assert_eq!(queue.pop(), None);
+        });
+    }
+}
@@ -0,0 +1,202 @@
+//! Finding channels by name.
+//!
+//! A `Registry` maps names to channels of any message type, so code that
+//! only shares a name, such as a plugin and its host, can find each other's
+//! channels. Looking a channel up asks for its message type, and finds
+//! nothing if the channel carries another. `Registry::global` is one for
+//! the whole process; registries made with `new` are independent of it.
+//!
+//! A registered channel stays connected, as the registry holds a sender
+//! and a receiver for it until it is removed.
+
+use super::{Receiver, Sender, Stats};
+use std::any::{self, Any};
+use std::collections::HashMap;
+use std::fmt;
+use sync::{Lazy, ShardedLock};
+
+static GLOBAL: Lazy<Registry> = Lazy::new(Registry::new);
+
+/// What the registry needs of a channel without knowing its message type
+trait Registered: Send + Sync {
+    fn stats(&self) -> Stats;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Send + 'static> Registered for (Sender<T>, Receiver<T>) {
+    fn stats(&self) -> Stats {
+        self.0.stats()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+struct Entry {
+    channel: Box<dyn Registered>,
+    message_type: &'static str,
+}
+
+/// A registered channel, as listed by `Registry::channels`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelInfo {
+    pub name: String,
+    /// The message type's name, from `std::any::type_name`
+    pub message_type: &'static str,
+    pub stats: Stats,
+}
+
+pub struct Registry {
+    /// Looked up often, and registered into rarely
+    channels: ShardedLock<HashMap<String, Entry>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            channels: ShardedLock::new(HashMap::new()),
+        }
+    }
+
+    /// The registry shared by the whole process
+    pub fn global() -> &'static Registry {
+        &GLOBAL
+    }
+
+    /// Register a channel under `name` by a handle for each half. Fails,
+    /// returning the handles, if the name is taken.
+    pub fn register<T: Send + 'static>(
+        &self,
+        name: &str,
+        sender: Sender<T>,
+        receiver: Receiver<T>,
+    ) -> Result<(), (Sender<T>, Receiver<T>)> {
+        let mut channels = self.channels.write();
+        if channels.contains_key(name) {
+            return Err((sender, receiver));
+        }
+        let entry = Entry {
+            channel: Box::new((sender, receiver)),
+            message_type: any::type_name::<T>(),
+        };
+        channels.insert(name.to_string(), entry);
+        Ok(())
+    }
+
+    fn find<T: Send + 'static, R, F>(&self, name: &str, f: F) -> Option<R>
+    where
+        F: FnOnce(&(Sender<T>, Receiver<T>)) -> R,
+    {
+        let channels = self.channels.read();
+        channels
+            .get(name)
+            .and_then(|entry| entry.channel.as_any().downcast_ref())
+            .map(f)
+    }
+
+    /// A sender for the channel registered under `name`, unless there is
+    /// none or its messages aren't `T`s
+    pub fn sender<T: Send + 'static>(&self, name: &str) -> Option<Sender<T>> {
+        self.find(name, |(sender, _)| sender.clone())
+    }
+
+    /// A receiver for the channel registered under `name`, unless there is
+    /// none or its messages aren't `T`s
+    pub fn receiver<T: Send + 'static>(&self, name: &str) -> Option<Receiver<T>> {
+        self.find(name, |(_, receiver)| receiver.clone())
+    }
+
+    /// Forget the channel registered under `name`, which disconnects once
+    /// any other handles for it are dropped. Returns `false` if there is
+    /// no such channel.
+    pub fn remove(&self, name: &str) -> bool {
+        self.channels.write().remove(name).is_some()
+    }
+
+    /// Every registered channel, sorted by name
+    pub fn channels(&self) -> Vec<ChannelInfo> {
+        let mut infos: Vec<_> = self
+            .channels
+            .read()
+            .iter()
+            .map(|(name, entry)| ChannelInfo {
+                name: name.clone(),
+                message_type: entry.message_type,
+                stats: entry.channel.stats(),
+            })
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::new()
+    }
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let channels = self.channels.read();
+        let mut names: Vec<_> = channels.keys().collect();
+        names.sort();
+        f.debug_struct("Registry")
+            .field("channels", &names)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::{queue, stack};
+
+    #[test]
+    fn lookup_by_name_and_type() {
+        let registry = Registry::new();
+        let (tx, rx) = queue::<u32>();
+        registry.register("jobs", tx, rx).unwrap();
+        let (tx, rx) = stack::<u32>();
+        assert!(registry.register("jobs", tx, rx).is_err());
+
+        registry.sender::<u32>("jobs").unwrap().send(7).unwrap();
+        assert_eq!(
+            registry.receiver::<u32>("jobs").unwrap().try_recv().ok(),
+            Some(7)
+        );
+        assert!(registry.sender::<String>("jobs").is_none());
+        assert!(registry.receiver::<u32>("missing").is_none());
+
+        assert!(registry.remove("jobs"));
+        assert!(!registry.remove("jobs"));
+        assert!(registry.sender::<u32>("jobs").is_none());
+    }
+
+    #[test]
+    fn lists_channels() {
+        let registry = Registry::new();
+        let (tx, rx) = queue::<String>();
+        tx.send("queued".into()).unwrap();
+        registry.register("logs", tx, rx).unwrap();
+        let (tx, rx) = queue::<u8>();
+        registry.register("bytes", tx, rx).unwrap();
+        let infos = registry.channels();
+        let names: Vec<_> = infos.iter().map(|info| info.name.as_str()).collect();
+        assert_eq!(names, vec!["bytes", "logs"]);
+        assert_eq!(infos[1].message_type, any::type_name::<String>());
+        assert_eq!(infos[1].stats.queued, 1);
+        assert_eq!(
+            format!("{:?}", registry),
+            r#"Registry { channels: ["bytes", "logs"] }"#
+        );
+
+        // The process-wide registry works the same way
+        let (tx, rx) = queue::<()>();
+        Registry::global()
+            .register("registry test", tx, rx)
+            .unwrap();
+        assert!(Registry::global().sender::<()>("registry test").is_some());
+    }
+}
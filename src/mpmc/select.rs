@@ -0,0 +1,224 @@
+//! Receiving from whichever of several channels has a message.
+//!
+//! `Select` checks its channels in turn and returns the first message it
+//! finds. Which channel it checks first is up to its `Fairness`: if it
+//! always started with the same one, a channel that is never empty would
+//! starve all the others.
+//!
+//! A blocked `select` registers one `Watcher` with every channel and
+//! sleeps on it, so it wakes as soon as any channel gets a message or
+//! disconnects, and not at all while they are idle. Under
+//! `WakePolicy::Threshold`, it is woken when a receiver would be.
+
+use super::{Error, Receiver, Watcher};
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// Which channel a `Select` checks first
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fairness {
+    /// Start one past the channel that last had a message, so ready
+    /// channels take turns. The default.
+    #[default]
+    RoundRobin,
+    /// Start at a random channel
+    Random,
+    /// Always start at the first channel, so earlier channels take
+    /// priority over later ones and can starve them
+    Biased,
+}
+
+/// Receives from a set of channels carrying the same type of message
+pub struct Select<'a, T: Send> {
+    receivers: Vec<&'a Receiver<T>>,
+    fairness: Fairness,
+    /// The channel to check first next time
+    next: Cell<usize>,
+    /// Xorshift state for `Fairness::Random`
+    seed: Cell<u64>,
+}
+
+impl<'a, T: Send> Select<'a, T> {
+    pub fn new(receivers: Vec<&'a Receiver<T>>) -> Select<'a, T> {
+        let seed = RandomState::new().build_hasher().finish() | 1;
+        Select {
+            receivers,
+            fairness: Fairness::RoundRobin,
+            next: Cell::new(0),
+            seed: Cell::new(seed),
+        }
+    }
+
+    /// Set which channel is checked first. Defaults to
+    /// `Fairness::RoundRobin`.
+    pub fn fairness(mut self, fairness: Fairness) -> Select<'a, T> {
+        self.fairness = fairness;
+        self
+    }
+
+    fn start(&self) -> usize {
+        match self.fairness {
+            Fairness::RoundRobin => self.next.get(),
+            Fairness::Random => {
+                let mut x = self.seed.get();
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                self.seed.set(x);
+                (x % self.receivers.len() as u64) as usize
+            }
+            Fairness::Biased => 0,
+        }
+    }
+
+    fn received(&self, index: usize, item: T) -> (usize, T) {
+        self.next.set((index + 1) % self.receivers.len());
+        (index, item)
+    }
+
+    /// Receive from the first channel that has a message, without
+    /// blocking, along with that channel's index. Fails with
+    /// `Disconnected` once every channel is disconnected and empty.
+    pub fn try_select(&self) -> Result<(usize, T), Error> {
+        if self.receivers.is_empty() {
+            return Err(Error::Disconnected);
+        }
+        let start = self.start();
+        let mut disconnected = 0;
+        for offset in 0..self.receivers.len() {
+            let index = (start + offset) % self.receivers.len();
+            match self.receivers[index].try_recv() {
+                Ok(item) => return Ok(self.received(index, item)),
+                Err(Error::Disconnected) => disconnected += 1,
+                Err(_) => (),
+            }
+        }
+        if disconnected == self.receivers.len() {
+            Err(Error::Disconnected)
+        } else {
+            Err(Error::Empty)
+        }
+    }
+
+    /// Block until any of the channels has a message
+    pub fn select(&self) -> Result<(usize, T), Error> {
+        self.select_until(None)
+    }
+
+    /// Block until any of the channels has a message, or `timeout` elapses
+    pub fn select_timeout(&self, timeout: Duration) -> Result<(usize, T), Error> {
+        self.select_until(Some(Instant::now() + timeout))
+    }
+
+    fn select_until(&self, deadline: Option<Instant>) -> Result<(usize, T), Error> {
+        // Watch before the first check, so a message sent after it wakes
+        // the watcher
+        let watcher = Watcher::new();
+        let _watches = self
+            .receivers
+            .iter()
+            .map(|rx| rx.watch(&watcher))
+            .collect::<Vec<_>>();
+        let mut result = None;
+        watcher.wait_until(
+            &mut || match self.try_select() {
+                Err(Error::Empty) => false,
+                selected => {
+                    result = Some(selected);
+                    true
+                }
+            },
+            deadline,
+        );
+        result.unwrap_or(Err(Error::Timeout))
+    }
+}
+
+impl<'a, T: Send> fmt::Debug for Select<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Select")
+            .field("channels", &self.receivers.len())
+            .field("fairness", &self.fairness)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc;
+    use std::thread;
+
+    #[test]
+    fn round_robin_doesnt_starve() {
+        let channels = (0..3).map(|_| mpmc::queue()).collect::<Vec<_>>();
+        for (tx, _) in &channels {
+            for i in 0..10 {
+                tx.send(i).unwrap();
+            }
+        }
+        let select = Select::new(channels.iter().map(|(_, rx)| rx).collect());
+        let order = (0..6)
+            .map(|_| select.try_select().unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn biased_and_random() {
+        let channels = (0..3).map(|_| mpmc::queue()).collect::<Vec<_>>();
+        for (tx, _) in &channels {
+            for i in 0..100 {
+                tx.send(i).unwrap();
+            }
+        }
+        let receivers = || channels.iter().map(|(_, rx)| rx).collect();
+        let biased = Select::new(receivers()).fairness(Fairness::Biased);
+        for _ in 0..10 {
+            assert_eq!(biased.try_select().unwrap().0, 0);
+        }
+        let random = Select::new(receivers()).fairness(Fairness::Random);
+        let mut seen = [0; 3];
+        for _ in 0..150 {
+            seen[random.try_select().unwrap().0] += 1;
+        }
+        assert!(seen.iter().all(|&count| count > 0));
+    }
+
+    #[test]
+    fn blocks_and_disconnects() {
+        let (tx_a, rx_a) = mpmc::queue::<u32>();
+        let (tx_b, rx_b) = mpmc::queue();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx_b.send(7).unwrap();
+        });
+        let select = Select::new(vec![&rx_a, &rx_b]);
+        assert_eq!(select.select(), Ok((1, 7)));
+        handle.join().unwrap();
+        assert_eq!(
+            select.select_timeout(Duration::from_millis(5)),
+            Err(Error::Timeout)
+        );
+        drop(tx_a);
+        assert_eq!(select.select(), Err(Error::Disconnected));
+    }
+
+    #[test]
+    fn disconnect_wakes_blocked_select() {
+        let (tx_a, rx_a) = mpmc::queue::<u32>();
+        let (tx_b, rx_b) = mpmc::queue::<u32>();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(tx_a);
+            thread::sleep(Duration::from_millis(10));
+            drop(tx_b);
+        });
+        let select = Select::new(vec![&rx_a, &rx_b]);
+        assert_eq!(select.select(), Err(Error::Disconnected));
+        handle.join().unwrap();
+    }
+}
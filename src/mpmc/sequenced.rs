@@ -0,0 +1,143 @@
+//! Per-producer ordering checks for FIFO backends, built with the
+//! `debug-checks` feature.
+//!
+//! A queue never promises a global order between producers, but a message
+//! a thread sends after another must not be received before it.
+//! `Sequenced` wraps a backend, tags each message with its sending thread
+//! and a number that thread hands out in order, and panics when a
+//! receiving thread sees a thread's numbers go backwards. Comparing within
+//! one receiving thread keeps the check sound with several consumers,
+//! whose pops can finish out of order.
+
+use super::*;
+use primitive::{lock, Mutex};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize as StdAtomicUsize;
+
+pub struct Tagged<T> {
+    producer: usize,
+    seq: u64,
+    item: T,
+}
+
+static THREADS: StdAtomicUsize = StdAtomicUsize::new(0);
+
+thread_local! {
+    static THREAD: (usize, Cell<u64>) = (THREADS.fetch_add(1, Ordering::Relaxed), Cell::new(0));
+}
+
+fn tag<T>(item: T) -> Tagged<T> {
+    THREAD.with(|&(producer, ref next)| {
+        let seq = next.get();
+        next.set(seq + 1);
+        Tagged {
+            producer,
+            seq,
+            item,
+        }
+    })
+}
+
+pub struct Sequenced<T> {
+    inner: Box<dyn LockFree<Tagged<T>>>,
+    /// The last number each consumer received from each producer
+    seen: Mutex<HashMap<(usize, usize), u64>>,
+}
+
+impl<T> Sequenced<T> {
+    pub fn new(inner: Box<dyn LockFree<Tagged<T>>>) -> Self {
+        Sequenced {
+            inner,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn check(&self, tagged: Tagged<T>) -> T {
+        let consumer = THREAD.with(|&(id, _)| id);
+        let mut seen = lock(&self.seen);
+        if let Some(last) = seen.insert((consumer, tagged.producer), tagged.seq) {
+            assert!(
+                tagged.seq > last,
+                "thread {} received message {} from thread {} after message {}",
+                consumer,
+                tagged.seq,
+                tagged.producer,
+                last
+            );
+        }
+        tagged.item
+    }
+}
+
+impl<T: Send> LockFree<T> for Sequenced<T> {
+    fn push(&self, item: T) -> bool {
+        self.inner.push(tag(item))
+    }
+
+    fn push_batch(&self, items: Vec<T>) -> usize {
+        self.inner.push_batch(items.into_iter().map(tag).collect())
+    }
+
+    fn pop(&self) -> Option<T> {
+        self.inner.pop().map(|tagged| self.check(tagged))
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>)) {
+        self.inner
+            .peek(&mut |items| f(&mut items.map(|tagged| &tagged.item)))
+    }
+
+    #[cfg(feature = "leak-check")]
+    fn allocations(&self) -> Option<Allocations> {
+        self.inner.allocations()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use primitive::thread;
+
+    #[test]
+    fn queue_keeps_producer_order() {
+        let (tx, rx) = queue();
+        let producers: Vec<_> = (0..3)
+            .map(|_| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        tx.send(i).unwrap();
+                    }
+                    let mut batched = tx.batched(8);
+                    for i in 0..50 {
+                        batched.send(i).unwrap();
+                    }
+                    batched.flush().unwrap();
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || while rx.recv_timeout(Duration::from_millis(50)).is_ok() {})
+            })
+            .collect();
+        for handle in producers.into_iter().chain(consumers) {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "after message")]
+    fn catches_reordering() {
+        let stack = Sequenced::new(Box::new(locked::Locked::lifo()));
+        stack.push(1);
+        stack.push(2);
+        stack.pop();
+        stack.pop();
+    }
+}
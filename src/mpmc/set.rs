@@ -0,0 +1,271 @@
+//! Consumers that balance load across a set of channels.
+//!
+//! Each consumer in a `ReceiverSet` has a home channel that it prefers,
+//! and when that runs dry it steals from its siblings. A channel is only
+//! ever drained by one consumer at a time: receiving leases the channel
+//! until the `Received` guard is dropped, so messages from the same
+//! channel are still handled one after another, in order, even when they
+//! are handled by different consumers.
+
+use super::{Error, Receiver};
+use primitive::{Arc, AtomicBool, Ordering::*};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a consumer waits on its own channel before checking its
+/// siblings again
+const STEAL_INTERVAL: Duration = Duration::from_millis(1);
+
+struct Shard<T: Send> {
+    receiver: Receiver<T>,
+    /// Held by the consumer handling a message from this channel
+    leased: AtomicBool,
+}
+
+impl<T: Send> Shard<T> {
+    fn lease(&self) -> bool {
+        self.leased
+            .compare_exchange(false, true, Acquire, Relaxed)
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.leased.store(false, Release);
+    }
+}
+
+/// One consumer of a set of channels
+pub struct ReceiverSet<T: Send> {
+    shards: Arc<Vec<Shard<T>>>,
+    home: usize,
+}
+
+/// A message, holding the lease on the channel it came from until dropped
+pub struct Received<'a, T: Send> {
+    item: Option<T>,
+    index: usize,
+    shard: &'a Shard<T>,
+}
+
+impl<T: Send> ReceiverSet<T> {
+    /// Create a consumer for each receiver, the `i`th preferring the `i`th
+    /// receiver. Consumers can be cloned to share a home channel.
+    pub fn new(receivers: Vec<Receiver<T>>) -> Vec<ReceiverSet<T>> {
+        let shards = Arc::new(
+            receivers
+                .into_iter()
+                .map(|receiver| Shard {
+                    receiver,
+                    leased: AtomicBool::new(false),
+                })
+                .collect::<Vec<_>>(),
+        );
+        (0..shards.len())
+            .map(|home| ReceiverSet {
+                shards: shards.clone(),
+                home,
+            })
+            .collect()
+    }
+
+    /// Index of the channel this consumer prefers
+    pub fn home(&self) -> usize {
+        self.home
+    }
+
+    /// Receive from the home channel if it has a message, or else from the
+    /// first sibling that does, without blocking. Fails with
+    /// `Disconnected` once every channel is disconnected and empty.
+    pub fn try_recv(&self) -> Result<Received<'_, T>, Error> {
+        let mut disconnected = 0;
+        for offset in 0..self.shards.len() {
+            let index = (self.home + offset) % self.shards.len();
+            let shard = &self.shards[index];
+            if !shard.lease() {
+                continue;
+            }
+            match shard.receiver.try_recv() {
+                Ok(item) => {
+                    return Ok(Received {
+                        item: Some(item),
+                        index,
+                        shard,
+                    })
+                }
+                Err(Error::Disconnected) => disconnected += 1,
+                Err(_) => (),
+            }
+            shard.release();
+        }
+        if disconnected == self.shards.len() {
+            Err(Error::Disconnected)
+        } else {
+            Err(Error::Empty)
+        }
+    }
+
+    /// Block until any of the channels has a message
+    pub fn recv(&self) -> Result<Received<'_, T>, Error> {
+        self.recv_until(None)
+    }
+
+    /// Block until any of the channels has a message, or `timeout` elapses
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Received<'_, T>, Error> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<Received<'_, T>, Error> {
+        let home = &self.shards[self.home];
+        loop {
+            match self.try_recv() {
+                Err(Error::Empty) => (),
+                result => return result,
+            }
+            let mut wait = STEAL_INTERVAL;
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::Timeout);
+                }
+                wait = wait.min(deadline - now);
+            }
+            // Block on the home channel, so its messages are picked up
+            // straight away, while siblings are only checked between waits
+            if !home.lease() {
+                thread::sleep(wait);
+                continue;
+            }
+            match home.receiver.recv_timeout(wait) {
+                Ok(item) => {
+                    return Ok(Received {
+                        item: Some(item),
+                        index: self.home,
+                        shard: home,
+                    })
+                }
+                Err(Error::Disconnected) => {
+                    home.release();
+                    // The siblings may still have messages
+                    thread::sleep(wait);
+                }
+                Err(_) => home.release(),
+            }
+        }
+    }
+}
+
+impl<T: Send> Clone for ReceiverSet<T> {
+    fn clone(&self) -> ReceiverSet<T> {
+        ReceiverSet {
+            shards: self.shards.clone(),
+            home: self.home,
+        }
+    }
+}
+
+impl<'a, T: Send> Received<'a, T> {
+    /// Index of the channel the message came from
+    pub fn channel(&self) -> usize {
+        self.index
+    }
+
+    /// Take the message, releasing its channel to other consumers
+    pub fn into_inner(mut self) -> T {
+        self.item.take().unwrap()
+    }
+}
+
+impl<'a, T: Send> Deref for Received<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.item.as_ref().unwrap()
+    }
+}
+
+impl<'a, T: Send> DerefMut for Received<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item.as_mut().unwrap()
+    }
+}
+
+impl<'a, T: Send> Drop for Received<'a, T> {
+    fn drop(&mut self) {
+        self.shard.release();
+    }
+}
+
+impl<T: Send> fmt::Debug for ReceiverSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReceiverSet")
+            .field("channels", &self.shards.len())
+            .field("home", &self.home)
+            .finish()
+    }
+}
+
+impl<'a, T: Send + fmt::Debug> fmt::Debug for Received<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Received")
+            .field("item", &**self)
+            .field("channel", &self.index)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::queue;
+
+    #[test]
+    fn prefers_home_then_steals() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| queue()).unzip();
+        let consumers = ReceiverSet::new(receivers);
+        senders[0].send("a").unwrap();
+        senders[1].send("b").unwrap();
+        let first = consumers[1].recv().unwrap();
+        assert_eq!((*first, first.channel()), ("b", 1));
+        drop(first);
+        // Nothing left at home, so steal
+        let stolen = consumers[1].recv().unwrap();
+        assert_eq!((*stolen, stolen.channel()), ("a", 0));
+    }
+
+    #[test]
+    fn one_consumer_per_channel() {
+        let (tx, rx) = queue();
+        let consumers = ReceiverSet::new(vec![rx]);
+        let thief = consumers[0].clone();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        let first = consumers[0].recv().unwrap();
+        // The channel stays leased until the first message is handled
+        assert_eq!(thief.try_recv().err(), Some(Error::Empty));
+        assert_eq!(first.into_inner(), 1);
+        assert_eq!(thief.try_recv().unwrap().into_inner(), 2);
+    }
+
+    #[test]
+    fn timeout_and_disconnect() {
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..2).map(|_| queue::<u32>()).unzip();
+        let consumers = ReceiverSet::new(receivers);
+        let timeout = Duration::from_millis(5);
+        assert_eq!(
+            consumers[0].recv_timeout(timeout).err(),
+            Some(Error::Timeout)
+        );
+        let mut senders = senders.into_iter();
+        drop(senders.next());
+        let last = senders.next().unwrap();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            last.send(9).unwrap();
+        });
+        // Stolen from the live sibling even though home is disconnected
+        assert_eq!(consumers[0].recv().unwrap().into_inner(), 9);
+        handle.join().unwrap();
+        assert_eq!(consumers[0].recv().err(), Some(Error::Disconnected));
+    }
+}
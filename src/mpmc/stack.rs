@@ -1,159 +1,373 @@
-//! A last-in-first-out stack that supports multiple producers and multiple
-//! consumers using atomics.
-
-use super::*;
-use std::ptr;
-use std::sync::atomic::{AtomicPtr, Ordering::*};
-
-struct Node<T> {
-    data: Option<T>,
-    next: *mut Node<T>,
-}
-
-pub struct Stack<T> {
-    head: AtomicPtr<Node<T>>,
-}
-
-impl<T> Stack<T> {
-    pub fn new() -> Stack<T> {
-        Stack {
-            head: AtomicPtr::new(ptr::null_mut()),
-        }
-    }
-}
-
-impl<T> LockFree<T> for Stack<T> {
-    fn push(&self, item: T) {
-        let new_head = Box::into_raw(Box::new(Node {
-            data: Some(item),
-            next: ptr::null_mut(),
-        }));
-        unsafe {
-            loop {
-                let head = self.head.load(Acquire);
-                (*new_head).next = head;
-                if head == self.head.compare_and_swap(head, new_head, Relaxed) {
-                    break;
-                }
-            }
-        }
-    }
-
-    fn pop(&self) -> Option<T> {
-        loop {
-            let head = self.head.load(Acquire);
-            if head.is_null() {
-                return None;
-            } else {
-                unsafe {
-                    let next = (*head).next;
-                    if head == self.head.compare_and_swap(head, next, Release) {
-                        let mut node = Box::from_raw(head);
-                        return node.data.take();
-                    }
-                }
-            }
-        }
-    }
-
-    fn len(&self) -> usize {
-        let mut len = 0;
-        unsafe {
-            let mut head = self.head.load(Acquire);
-            loop {
-                if !head.is_null() {
-                    head = (*head).next;
-                    len += 1;
-                } else {
-                    return len;
-                }
-            }
-        }
-    }
-}
-
-impl<T> Drop for Stack<T> {
-    fn drop(&mut self) {
-        unsafe {
-            let head = self.head.swap(ptr::null_mut(), SeqCst);
-            if !head.is_null() {
-                let mut node = Box::from_raw(head);
-                loop {
-                    if !node.next.is_null() {
-                        node = Box::from_raw(node.next);
-                    } else {
-                        break;
-                    }
-                }
-            }
-        }
-    }
-}
-
-#[cfg(test)]
-
-mod test {
-    use super::*;
-    use std::sync::atomic::{AtomicUsize, Ordering::*};
-    use std::sync::Arc;
-    #[derive(Debug)]
-    struct Sentinel(Arc<AtomicUsize>);
-    impl Drop for Sentinel {
-        fn drop(&mut self) {
-            self.0.fetch_add(1, Relaxed);
-        }
-    }
-
-    #[test]
-    fn pop_and_drop() {
-        let guard = Arc::new(AtomicUsize::new(0));
-        let stack = Stack::new();
-
-        stack.push(Sentinel(guard.clone()));
-        stack.push(Sentinel(guard.clone()));
-        stack.push(Sentinel(guard.clone()));
-        stack.pop().unwrap();
-        assert_eq!(1, guard.load(Acquire));
-        stack.pop().unwrap();
-        assert_eq!(2, guard.load(Acquire));
-        stack.pop().unwrap();
-        assert_eq!(3, guard.load(Acquire));
-        assert!(stack.pop().is_none());
-    }
-
-    #[test]
-    fn drop() {
-        let guard = Arc::new(AtomicUsize::new(0));
-        {
-            let stack = Stack::new();
-
-            stack.push(Sentinel(guard.clone()));
-            stack.push(Sentinel(guard.clone()));
-            stack.push(Sentinel(guard.clone()));
-        }
-        assert_eq!(3, guard.load(Acquire));
-    }
-
-    #[test]
-    fn len() {
-        let stack = Stack::new();
-        let mut len = 0;
-        for i in 0..100 {
-            stack.push(i);
-            len += 1;
-        }
-        assert_eq!(stack.len(), len)
-    }
-
-    #[test]
-    fn sanity() {
-        let stack = Stack::new();
-        stack.push(10);
-        stack.push(5);
-        stack.push(0);
-        assert_eq!(stack.pop(), Some(0));
-        assert_eq!(stack.pop(), Some(5));
-        assert_eq!(stack.pop(), Some(10));
-        assert_eq!(stack.pop(), None);
-    }
-}
+//! A last-in-first-out stack that supports multiple producers and multiple
+//! consumers using atomics.
+
+use super::fault::{self, Point};
+use super::peek::Peekers;
+use super::*;
+use epoch;
+use primitive::{AtomicPtr, AtomicUsize, Ordering::*};
+use std::iter;
+use std::marker::PhantomData;
+use std::ptr;
+use sync::{Backoff, Contention};
+
+struct Node<T> {
+    data: Option<T>,
+    next: *mut Node<T>,
+    _token: leak::Token,
+}
+
+// Only sent to the epoch collector once unlinked, when `next` is no
+// longer followed
+unsafe impl<T: Send> Send for Node<T> {}
+
+/// A LIFO stack. A pushed node is published by the release CAS that makes
+/// it the head, and popping acquires the head before reading its `next`
+/// and data.
+///
+/// Popped nodes are freed through the epoch collector, since a concurrent
+/// pop may have loaded the same head and still be reading its `next`.
+/// Keeping the node alive until then also rules out ABA on `head`.
+pub struct Stack<T> {
+    head: AtomicPtr<Node<T>>,
+    contention: Contention,
+    /// Items pushed and not yet popped. Pushes count their items before
+    /// linking them in, so this never drops below the true length.
+    len: AtomicUsize,
+    peekers: Peekers,
+    allocations: leak::Allocations,
+    /// The stack owns its items, which raw pointers alone don't say, so
+    /// without this it would be `Send` for any `T`
+    _owns: PhantomData<T>,
+}
+
+// Items are moved in and out but never shared, so sharing the stack only
+// needs `T: Send`
+unsafe impl<T: Send> Sync for Stack<T> {}
+
+impl<T> Node<T> {
+    fn new(item: T, next: *mut Node<T>, allocations: &leak::Allocations) -> *mut Node<T> {
+        Box::into_raw(Box::new(Node {
+            data: Some(item),
+            next,
+            _token: allocations.token(),
+        }))
+    }
+}
+
+impl<T> Stack<T> {
+    pub fn new() -> Stack<T> {
+        Stack {
+            head: AtomicPtr::new(ptr::null_mut()),
+            contention: Contention::new(),
+            len: AtomicUsize::new(0),
+            peekers: Peekers::new(),
+            allocations: leak::Allocations::new(),
+            _owns: PhantomData,
+        }
+    }
+
+    /// Put the chain of `count` nodes from `top` down to `bottom` on the
+    /// stack. They must be linked to each other and not yet visible to
+    /// anyone else.
+    fn link(&self, top: *mut Node<T>, bottom: *mut Node<T>, count: usize) {
+        self.len.fetch_add(count, Relaxed);
+        let backoff = self.contention.backoff();
+        // Only the pointer is needed here, not the node behind it, so
+        // failure can be relaxed
+        let mut head = self.head.load(Relaxed);
+        loop {
+            fault::inject(Point::AfterLoad);
+            unsafe { (*bottom).next = head };
+            fault::inject(Point::BeforeCas);
+            match self.head.compare_exchange_weak(head, top, Release, Relaxed) {
+                Ok(_) => {
+                    fault::inject(Point::AfterCas);
+                    break;
+                }
+                Err(current) => head = current,
+            }
+            backoff.spin();
+        }
+    }
+}
+
+impl<T: Send + 'static> LockFree<T> for Stack<T> {
+    fn push(&self, item: T) -> bool {
+        let node = Node::new(item, ptr::null_mut(), &self.allocations);
+        self.link(node, node, 1);
+        true
+    }
+
+    fn push_batch(&self, items: Vec<T>) -> usize {
+        let count = items.len();
+        let mut items = items.into_iter();
+        let bottom = match items.next() {
+            Some(item) => Node::new(item, ptr::null_mut(), &self.allocations),
+            None => return 0,
+        };
+        // Each item goes on top of the one before, as if pushed in order
+        let top = items.fold(bottom, |below, item| {
+            Node::new(item, below, &self.allocations)
+        });
+        self.link(top, bottom, count);
+        count
+    }
+
+    fn pop(&self) -> Option<T> {
+        let backoff = self.contention.backoff();
+        let publishing = Backoff::new();
+        let guard = epoch::pin();
+        // Acquire, here and on failure, pairs with the release in `push`
+        // so the head's `next` and data are visible
+        let mut head = self.head.load(Acquire);
+        loop {
+            if head.is_null() {
+                // A push counts its items before linking them, so give one
+                // that is between the two a few rounds to finish, like
+                // `Queue` does
+                if self.len.load(Relaxed) == 0 || publishing.is_completed() {
+                    return None;
+                }
+                publishing.snooze();
+                head = self.head.load(Acquire);
+                continue;
+            }
+            unsafe {
+                let next = (*head).next;
+                fault::inject(Point::AfterLoad);
+                fault::inject(Point::BeforeCas);
+                // SeqCst for `peekers`, see `peek`
+                match self.head.compare_exchange_weak(head, next, SeqCst, Acquire) {
+                    Ok(_) => {
+                        fault::inject(Point::AfterCas);
+                        self.peekers.wait();
+                        // Only the winner touches the data, others at most
+                        // read `next`
+                        let data = (*head).data.take();
+                        guard.defer_destroy(head);
+                        self.len.fetch_sub(1, Relaxed);
+                        return data;
+                    }
+                    Err(current) => head = current,
+                }
+            }
+            backoff.spin();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Relaxed)
+    }
+
+    /// Walk down from the head while pinned, so no node is freed under the
+    /// walk. Pops that claim an item meanwhile wait to take it until the
+    /// walk is done, see `peek.rs`.
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>)) {
+        let _guard = epoch::pin();
+        let _peeking = self.peekers.enter();
+        let mut node = self.head.load(SeqCst);
+        let mut items = iter::from_fn(|| unsafe {
+            let item = node.as_ref()?.data.as_ref();
+            node = (*node).next;
+            item
+        });
+        f(&mut items);
+    }
+
+    #[cfg(feature = "leak-check")]
+    fn allocations(&self) -> Option<leak::Allocations> {
+        Some(self.allocations.clone())
+    }
+}
+
+impl<T> fmt::Debug for Stack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stack")
+            .field("len", &self.len.load(Relaxed))
+            .finish()
+    }
+}
+
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        // `&mut self` means no other thread is in the middle of an
+        // operation, so a plain load sees the final head
+        unsafe {
+            let head = self.head.load(Relaxed);
+            if !head.is_null() {
+                let mut node = Box::from_raw(head);
+                loop {
+                    if !node.next.is_null() {
+                        node = Box::from_raw(node.next);
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    #[derive(Debug)]
+    struct Sentinel(Arc<AtomicUsize>);
+    impl Drop for Sentinel {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    #[test]
+    fn pop_and_drop() {
+        let guard = Arc::new(AtomicUsize::new(0));
+        let stack = Stack::new();
+
+        stack.push(Sentinel(guard.clone()));
+        stack.push(Sentinel(guard.clone()));
+        stack.push(Sentinel(guard.clone()));
+        stack.pop().unwrap();
+        assert_eq!(1, guard.load(Acquire));
+        stack.pop().unwrap();
+        assert_eq!(2, guard.load(Acquire));
+        stack.pop().unwrap();
+        assert_eq!(3, guard.load(Acquire));
+        assert!(stack.pop().is_none());
+    }
+
+    #[test]
+    fn drop() {
+        let guard = Arc::new(AtomicUsize::new(0));
+        {
+            let stack = Stack::new();
+
+            stack.push(Sentinel(guard.clone()));
+            stack.push(Sentinel(guard.clone()));
+            stack.push(Sentinel(guard.clone()));
+        }
+        assert_eq!(3, guard.load(Acquire));
+    }
+
+    #[test]
+    fn peek() {
+        let stack = Stack::new();
+        stack.peek(&mut |items| assert_eq!(items.next(), None));
+        for i in 0..3 {
+            stack.push(i);
+        }
+        stack.peek(&mut |items| assert_eq!(items.collect::<Vec<_>>(), [&2, &1, &0]));
+        assert_eq!(stack.pop(), Some(2));
+        assert_eq!(stack.len(), 2);
+    }
+
+    #[test]
+    fn len() {
+        let stack = Stack::new();
+        let mut len = 0;
+        for i in 0..100 {
+            stack.push(i);
+            len += 1;
+        }
+        assert_eq!(stack.len(), len)
+    }
+
+    #[test]
+    fn sanity() {
+        let stack = Stack::new();
+        stack.push(10);
+        stack.push(5);
+        stack.push(0);
+        assert_eq!(stack.pop(), Some(0));
+        assert_eq!(stack.pop(), Some(5));
+        assert_eq!(stack.pop(), Some(10));
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn concurrent_pops() {
+        let stack = Arc::new(Stack::new());
+        for i in 0..10_000u64 {
+            stack.push(i);
+        }
+        let handles = (0..4)
+            .map(|_| {
+                let stack = stack.clone();
+                ::std::thread::spawn(move || {
+                    let mut sum = 0;
+                    while let Some(i) = stack.pop() {
+                        // Push some back, so heads get reused under
+                        // concurrent pops
+                        if i % 3 == 0 {
+                            stack.push(i + 1);
+                        }
+                        sum += i;
+                    }
+                    sum
+                })
+            })
+            .collect::<Vec<_>>();
+        let sum: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        let expected: u64 = (0..10_000u64)
+            .map(|i| if i % 3 == 0 { 2 * i + 1 } else { i })
+            .sum();
+        assert_eq!(sum, expected);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+    use primitive::{thread, Arc};
+
+    #[test]
+    fn push_while_popping() {
+        loom::model(|| {
+            let stack = Arc::new(Stack::new());
+            let producer = {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    stack.push(1);
+                    stack.push(2);
+                })
+            };
+            let mut popped = Vec::new();
+            while popped.len() < 2 {
+                match stack.pop() {
+                    Some(item) => popped.push(item),
+                    None => thread::yield_now(),
+                }
+            }
+            producer.join().unwrap();
+            popped.sort();
+            assert_eq!(popped, [1, 2]);
+            assert!(stack.is_empty());
+        });
+    }
+
+    #[test]
+    fn racing_pops() {
+        loom::model(|| {
+            let stack = Arc::new(Stack::new());
+            stack.push(1);
+            stack.push(2);
+            let other = {
+                let stack = stack.clone();
+                thread::spawn(move || stack.pop())
+            };
+            let mine = stack.pop();
+            let theirs = other.join().unwrap();
+            // A pop that loses the race for the head retries on its new
+            // value, so neither comes back empty and nothing is popped
+            // twice
+            let mut popped = vec![mine.unwrap(), theirs.unwrap()];
+            popped.sort();
+            assert_eq!(popped, [1, 2]);
+            assert_eq!(stack.pop(), None);
+        });
+    }
+}
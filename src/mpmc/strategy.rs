@@ -0,0 +1,404 @@
+//! How receivers wait on an empty channel.
+//!
+//! Spinning gives the lowest wakeup latency but burns a core for as long as
+//! the channel is empty, while parking frees the core at the price of a
+//! syscall on both sides of every wakeup. Each channel picks its own
+//! tradeoff with `Builder::block_strategy`.
+
+use primitive::{fence, lock, AtomicUsize, Condvar, Mutex, Ordering::*, PoisonError};
+use std::fmt;
+use std::time::Instant;
+use sync::{Backoff, EventCount};
+
+/// Strategies that sleep must not lose a wakeup that races with a
+/// receiver going to sleep: a receiver that finds the channel empty, then
+/// sleeps just as a sender pushes and notifies, would otherwise sleep
+/// forever. A sleeping implementation has to register itself as a sleeper
+/// before each final call to `ready`, and senders notify only after
+/// publishing the message, so either the sender sees the sleeper or the
+/// final check sees the message.
+pub trait BlockStrategy: Send + Sync {
+    /// Block until `ready` returns `true`, or until `deadline` passes.
+    /// Returns whether `ready` did. `ready` is called at least once, and
+    /// again after every wakeup, spurious or not.
+    fn wait(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool;
+
+    /// Called after a message is sent, to wake one waiting receiver
+    fn notify_one(&self);
+
+    /// Called on disconnect or cancellation, to wake every waiting receiver
+    fn notify_all(&self);
+
+    /// Receivers asleep in `wait` right now, for `Stats`. Strategies that
+    /// never sleep leave this at zero.
+    fn sleepers(&self) -> usize {
+        0
+    }
+}
+
+/// Busy-wait without ever giving up the core. Only suitable when each
+/// receiver has a core to itself.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpinOnly;
+
+/// Spin briefly, then keep yielding to the scheduler between checks
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpinThenYield;
+
+/// Spin briefly, then sleep on an `EventCount` until a sender wakes us
+/// up. This is the default strategy. Senders never take a lock, and only
+/// make a syscall when a receiver is actually asleep.
+pub struct SpinThenPark {
+    events: EventCount,
+}
+
+/// Sleep on a mutex and condvar without spinning first
+pub struct CondvarPark {
+    lock: Mutex<()>,
+    cvar: Condvar,
+    sleepers: AtomicUsize,
+}
+
+/// Fence and check for sleepers, pairing with the fence a sleeper executes
+/// after announcing itself: either the notifier sees the sleeper, or the
+/// sleeper sees whatever the notifier published.
+fn has_sleepers(sleepers: &AtomicUsize) -> bool {
+    fence(SeqCst);
+    sleepers.load(Relaxed) > 0
+}
+
+fn expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Check `ready` between calls to `backoff` until the deadline
+fn spin(
+    ready: &mut dyn FnMut() -> bool,
+    deadline: Option<Instant>,
+    backoff: impl Fn(&Backoff),
+) -> bool {
+    let state = Backoff::new();
+    loop {
+        if ready() {
+            return true;
+        }
+        if expired(deadline) {
+            return false;
+        }
+        backoff(&state);
+    }
+}
+
+impl BlockStrategy for SpinOnly {
+    fn wait(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        spin(ready, deadline, Backoff::spin)
+    }
+
+    fn notify_one(&self) {}
+
+    fn notify_all(&self) {}
+}
+
+impl BlockStrategy for SpinThenYield {
+    fn wait(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        spin(ready, deadline, Backoff::snooze)
+    }
+
+    fn notify_one(&self) {}
+
+    fn notify_all(&self) {}
+}
+
+impl SpinThenPark {
+    pub fn new() -> SpinThenPark {
+        SpinThenPark {
+            events: EventCount::new(),
+        }
+    }
+}
+
+impl Default for SpinThenPark {
+    fn default() -> SpinThenPark {
+        SpinThenPark::new()
+    }
+}
+
+impl BlockStrategy for SpinThenPark {
+    fn wait(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        let backoff = Backoff::new();
+        while !backoff.is_completed() {
+            if ready() {
+                return true;
+            }
+            if expired(deadline) {
+                return false;
+            }
+            backoff.snooze();
+        }
+
+        self.events.wait_until(ready, deadline)
+    }
+
+    fn notify_one(&self) {
+        self.events.notify_one();
+    }
+
+    fn notify_all(&self) {
+        self.events.notify_all();
+    }
+
+    fn sleepers(&self) -> usize {
+        self.events.sleepers()
+    }
+}
+
+impl CondvarPark {
+    pub fn new() -> CondvarPark {
+        CondvarPark {
+            lock: Mutex::new(()),
+            cvar: Condvar::new(),
+            sleepers: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Default for CondvarPark {
+    fn default() -> CondvarPark {
+        CondvarPark::new()
+    }
+}
+
+impl BlockStrategy for CondvarPark {
+    fn wait(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        self.sleepers.fetch_add(1, SeqCst);
+        fence(SeqCst);
+        let mut guard = lock(&self.lock);
+        // Notifiers take the lock, so they can't slip in between the check
+        // and the wait
+        let ready = loop {
+            if ready() {
+                break true;
+            }
+            match deadline {
+                None => {
+                    guard = self
+                        .cvar
+                        .wait(guard)
+                        .unwrap_or_else(PoisonError::into_inner)
+                }
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break false;
+                    }
+                    guard = self
+                        .cvar
+                        .wait_timeout(guard, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+            }
+        };
+        drop(guard);
+        self.sleepers.fetch_sub(1, Relaxed);
+        ready
+    }
+
+    fn notify_one(&self) {
+        if has_sleepers(&self.sleepers) {
+            let _guard = lock(&self.lock);
+            self.cvar.notify_one();
+        }
+    }
+
+    fn notify_all(&self) {
+        if has_sleepers(&self.sleepers) {
+            let _guard = lock(&self.lock);
+            self.cvar.notify_all();
+        }
+    }
+
+    fn sleepers(&self) -> usize {
+        self.sleepers.load(Relaxed)
+    }
+}
+
+impl fmt::Debug for SpinThenPark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpinThenPark")
+            .field("sleepers", &self.events.sleepers())
+            .finish()
+    }
+}
+
+impl fmt::Debug for CondvarPark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CondvarPark")
+            .field("sleepers", &self.sleepers.load(Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::{Builder, Error};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn strategies() -> Vec<Box<dyn Fn() -> Builder>> {
+        vec![
+            Box::new(|| Builder::new().block_strategy(SpinOnly)),
+            Box::new(|| Builder::new().block_strategy(SpinThenYield)),
+            Box::new(|| Builder::new().block_strategy(SpinThenPark::new())),
+            Box::new(|| Builder::new().block_strategy(CondvarPark::new())),
+        ]
+    }
+
+    #[test]
+    fn wake_on_send() {
+        for builder in strategies() {
+            let (tx, rx) = builder().queue();
+            let handles = (0..4)
+                .map(|_| {
+                    let rx = rx.clone();
+                    thread::spawn(move || rx.recv())
+                })
+                .collect::<Vec<_>>();
+            thread::sleep(Duration::from_millis(10));
+            for i in 0..4 {
+                tx.send(i).unwrap();
+            }
+            let mut received = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap().unwrap())
+                .collect::<Vec<_>>();
+            received.sort();
+            assert_eq!(received, vec![0, 1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn wake_on_disconnect() {
+        for builder in strategies() {
+            let (tx, rx) = builder().stack::<u32>();
+            let handle = thread::spawn(move || rx.recv());
+            thread::sleep(Duration::from_millis(10));
+            tx.close();
+            assert_eq!(handle.join().unwrap(), Err(Error::Disconnected));
+        }
+    }
+
+    #[test]
+    fn notify_without_sleepers() {
+        let strategy = SpinThenPark::new();
+        strategy.notify_all();
+        let mut checks = 0;
+        let ready = strategy.wait(
+            &mut || {
+                checks += 1;
+                checks == 3
+            },
+            None,
+        );
+        assert!(ready);
+        assert_eq!(checks, 3);
+    }
+
+    #[test]
+    fn spins_before_parking() {
+        let strategy = SpinThenPark::new();
+        let mut checks = 0;
+        let mut slept = false;
+        // Nothing notifies, so if the strategy parked before spinning the
+        // later checks would only come once the deadline passed
+        let ready = strategy.wait(
+            &mut || {
+                checks += 1;
+                slept |= strategy.events.sleepers() > 0;
+                checks == 4
+            },
+            Some(Instant::now() + Duration::from_secs(1)),
+        );
+        assert!(ready);
+        assert!(!slept);
+    }
+
+    /// Shares a strategy with the test after the channel takes it
+    struct Shared(Arc<SpinThenPark>);
+
+    impl BlockStrategy for Shared {
+        fn wait(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+            self.0.wait(ready, deadline)
+        }
+
+        fn notify_one(&self) {
+            self.0.notify_one();
+        }
+
+        fn notify_all(&self) {
+            self.0.notify_all();
+        }
+    }
+
+    #[test]
+    fn send_wakes_only_sleepers() {
+        let strategy = Arc::new(SpinThenPark::new());
+        let (tx, rx) = Builder::new()
+            .block_strategy(Shared(strategy.clone()))
+            .queue();
+        for i in 0..100 {
+            tx.send(i).unwrap();
+        }
+        // Nobody was asleep, so no send went past the sleeper check
+        assert_eq!(strategy.events.generation(), 0);
+        while rx.try_recv().is_ok() {}
+
+        let handle = thread::spawn(move || rx.recv());
+        while strategy.events.sleepers() == 0 {
+            thread::yield_now();
+        }
+        tx.send(100).unwrap();
+        assert_eq!(handle.join().unwrap(), Ok(100));
+        assert_eq!(strategy.events.generation(), 1);
+    }
+
+    #[test]
+    fn no_lost_wakeups() {
+        // Each side blocks on every round, so a single lost wakeup hangs
+        // the test. Only the strategies that sleep can lose one.
+        let sleeping: Vec<Box<dyn Fn() -> Builder>> = vec![
+            Box::new(|| Builder::new().block_strategy(SpinThenPark::new())),
+            Box::new(|| Builder::new().block_strategy(CondvarPark::new())),
+        ];
+        for builder in sleeping {
+            let (ping_tx, ping_rx) = builder().queue::<u32>();
+            let (pong_tx, pong_rx) = builder().queue::<u32>();
+            let handle = thread::spawn(move || {
+                while let Ok(i) = ping_rx.recv() {
+                    pong_tx.send(i).unwrap();
+                }
+            });
+            for i in 0..2000 {
+                ping_tx.send(i).unwrap();
+                assert_eq!(pong_rx.recv(), Ok(i));
+            }
+            drop(ping_tx);
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn deadline() {
+        for builder in strategies() {
+            let (tx, rx) = builder().queue::<u32>();
+            let start = Instant::now();
+            let timeout = Duration::from_millis(10);
+            assert_eq!(rx.recv_timeout(timeout), Err(Error::Timeout));
+            assert!(start.elapsed() >= timeout);
+            tx.send(1).unwrap();
+            assert_eq!(rx.recv_timeout(timeout), Ok(1));
+        }
+    }
+}
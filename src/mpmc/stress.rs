@@ -0,0 +1,272 @@
+//! Seeded stress scenarios for the blocking protocol.
+//!
+//! Each scenario draws its shape, such as burst sizes, pauses and which
+//! end disconnects first, from a seed, and every assertion names the seed
+//! it ran with. The OS still decides the exact interleaving, but a failing
+//! seed replays the same scenario, and rerunning it in a loop reproduces
+//! most failures within a few runs. With the `deterministic` feature the
+//! scenarios also run under `testing::sched`, where the seed fixes the
+//! interleaving too.
+
+use super::fault::{self, Point};
+use super::*;
+// The scheduler's threads, when it is built in
+use primitive::thread;
+
+/// Seeds run by each scenario. A failing seed can be replayed on its own
+/// by calling the scenario with it. Miri runs each scenario far slower,
+/// so it tries fewer.
+const SEEDS: u64 = if cfg!(miri) { 2 } else { 16 };
+
+/// Xorshift, good enough to vary scenarios
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // Xorshift is stuck at zero, and nearby seeds should diverge
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn pause(&mut self) {
+        match self.below(4) {
+            0 => thread::yield_now(),
+            1 => thread::sleep(Duration::from_micros(self.below(200))),
+            _ => (),
+        }
+    }
+}
+
+fn builder(rng: &mut Rng) -> Builder {
+    match rng.below(3) {
+        0 => Builder::new().block_strategy(SpinThenPark::new()),
+        1 => Builder::new().block_strategy(CondvarPark::new()),
+        _ => Builder::new().block_strategy(SpinThenYield),
+    }
+}
+
+/// Producers send bursts separated by pauses, and every message arrives
+fn producer_bursts(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let (tx, rx) = builder(&mut rng).queue();
+    let producers = 1 + rng.below(3);
+    let consumers = 1 + rng.below(3);
+    let handles = (0..producers)
+        .map(|id| {
+            let tx = tx.clone();
+            let mut rng = Rng::new(seed ^ id);
+            thread::spawn(move || {
+                let mut sent = 0;
+                for _ in 0..1 + rng.below(8) {
+                    for _ in 0..rng.below(64) {
+                        tx.send(1u64).unwrap();
+                        sent += 1;
+                    }
+                    rng.pause();
+                }
+                sent
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(tx);
+    let receivers = (0..consumers)
+        .map(|_| {
+            let rx = rx.clone();
+            thread::spawn(move || {
+                let mut received = 0;
+                while let Ok(n) = rx.recv() {
+                    received += n;
+                }
+                received
+            })
+        })
+        .collect::<Vec<_>>();
+    let sent: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    let received: u64 = receivers.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(sent, received, "seed {}", seed);
+}
+
+/// Either end disconnects at a random point, and nothing hangs or is
+/// received after the fact
+fn disconnect_races(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let (tx, rx) = builder(&mut rng).queue::<u64>();
+    let cutoff = rng.below(100);
+    let receiver_first = rng.below(2) == 0;
+    let mut consumer_rng = Rng::new(!seed);
+    let consumer = thread::spawn(move || {
+        let mut received = 0;
+        loop {
+            if receiver_first && received == cutoff {
+                return received;
+            }
+            match rx.recv() {
+                Ok(_) => received += 1,
+                Err(Error::Disconnected) => return received,
+                Err(error) => panic!("seed {}: {:?}", seed, error),
+            }
+            consumer_rng.pause();
+        }
+    });
+    let mut sent = 0;
+    loop {
+        if !receiver_first && sent == cutoff {
+            drop(tx);
+            break;
+        }
+        if tx.send(sent).is_err() {
+            break;
+        }
+        sent += 1;
+        rng.pause();
+    }
+    let received = consumer.join().unwrap();
+    assert!(received <= sent, "seed {}", seed);
+    if !receiver_first {
+        assert_eq!(received, sent, "seed {}", seed);
+    }
+}
+
+/// Receivers go to sleep and wake again repeatedly, and no wakeup is lost
+fn sleeper_wakeups(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let (tx, rx) = builder(&mut rng).stack();
+    let sleepers = 1 + rng.below(4);
+    let rounds = 1 + rng.below(20);
+    let handles = (0..sleepers)
+        .map(|_| {
+            let rx = rx.clone();
+            thread::spawn(move || (0..rounds).map(|_| rx.recv().unwrap()).count())
+        })
+        .collect::<Vec<_>>();
+    for _ in 0..rounds {
+        // Let the receivers get back to sleep before each round
+        rng.pause();
+        for _ in 0..sleepers {
+            tx.send(()).unwrap();
+        }
+    }
+    let woken: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(woken as u64, sleepers * rounds, "seed {}", seed);
+}
+
+/// Threads push and pop while a hook stalls them at one of the points
+/// where a race does its damage, and every message comes out exactly once
+fn injected_races(seed: u64) {
+    let mut rng = Rng::new(seed);
+    let (tx, rx) = if rng.below(2) == 0 {
+        builder(&mut rng).queue()
+    } else {
+        builder(&mut rng).stack()
+    };
+    let target = [Point::AfterLoad, Point::BeforeCas, Point::AfterCas][rng.below(3) as usize];
+    let handles = (0..3)
+        .map(|id| {
+            let (tx, rx) = (tx.clone(), rx.clone());
+            let mut rng = Rng::new(seed ^ id);
+            thread::spawn(move || {
+                let mut hook_rng = Rng::new(!seed ^ id);
+                let hook = move |point| {
+                    if point == target {
+                        match hook_rng.below(64) {
+                            0 => thread::sleep(Duration::from_micros(hook_rng.below(100))),
+                            1..=15 => thread::yield_now(),
+                            _ => (),
+                        }
+                    }
+                };
+                fault::with_hook(hook, || {
+                    let mut received = Vec::new();
+                    for i in 0..100 {
+                        tx.send(id * 1000 + i).unwrap();
+                        if rng.below(3) != 0 {
+                            received.extend(rx.try_recv().ok());
+                        }
+                    }
+                    received
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut received: Vec<u64> = handles
+        .into_iter()
+        .flat_map(|h| h.join().unwrap())
+        .collect();
+    while let Ok(item) = rx.try_recv() {
+        received.push(item);
+    }
+    received.sort_unstable();
+    let sent: Vec<u64> = (0..3)
+        .flat_map(|id| (0..100).map(move |i| id * 1000 + i))
+        .collect();
+    assert_eq!(received, sent, "seed {}", seed);
+}
+
+#[test]
+fn bursts() {
+    (0..SEEDS).for_each(producer_bursts);
+}
+
+#[test]
+fn disconnects() {
+    (0..SEEDS).for_each(disconnect_races);
+}
+
+#[test]
+fn wakeups() {
+    (0..SEEDS).for_each(sleeper_wakeups);
+}
+
+#[test]
+fn faults() {
+    (0..SEEDS).for_each(injected_races);
+}
+
+#[cfg(feature = "deterministic")]
+mod deterministic {
+    use super::*;
+    use testing::sched;
+
+    /// Every scheduling decision costs a handoff between OS threads, so
+    /// fewer seeds than the free-running scenarios
+    const SEEDS: u64 = 4;
+
+    #[test]
+    fn bursts() {
+        for seed in 0..SEEDS {
+            sched::run(seed, || producer_bursts(seed));
+        }
+    }
+
+    #[test]
+    fn disconnects() {
+        for seed in 0..SEEDS {
+            sched::run(seed, || disconnect_races(seed));
+        }
+    }
+
+    #[test]
+    fn wakeups() {
+        for seed in 0..SEEDS {
+            sched::run(seed, || sleeper_wakeups(seed));
+        }
+    }
+
+    #[test]
+    fn replays() {
+        let schedule = sched::run(5, || disconnect_races(5));
+        // The consumer took turns too
+        assert!(schedule.contains(&1));
+        assert_eq!(sched::run(5, || disconnect_races(5)), schedule);
+    }
+}
@@ -0,0 +1,175 @@
+//! Waiting on several channels at once.
+//!
+//! A `Watcher` is an eventcount that any number of channels notify,
+//! alongside their own block strategy, whenever that strategy is notified.
+//! `Select` watches each of its channels with one watcher and sleeps on it
+//! until a check of the channels finds something, so a blocked select
+//! costs nothing while its channels are idle, and wakes as soon as any of
+//! them gets a message or disconnects.
+//!
+//! Each channel keeps its watchers in a list behind a lock, which only
+//! watching and unwatching take. Notifiers check a count of watchers
+//! first, after a fence that pairs with the one a watcher executes when it
+//! goes to sleep: either the notifier sees the watcher, or the watcher's
+//! last check sees what the notifier published. So with no watchers, a
+//! notification costs one more fence and load.
+
+use super::BlockStrategy;
+use primitive::{fence, lock, Arc, AtomicUsize, Mutex, Ordering::*};
+use std::fmt;
+use std::time::Instant;
+use sync::EventCount;
+
+/// Sleeps until any of the channels it watches is notified
+pub struct Watcher {
+    events: Arc<EventCount>,
+}
+
+impl Watcher {
+    pub fn new() -> Watcher {
+        Watcher {
+            events: Arc::new(EventCount::new()),
+        }
+    }
+
+    /// Like `BlockStrategy::wait`, woken by the watched channels
+    pub fn wait_until(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        self.events.wait_until(ready, deadline)
+    }
+}
+
+/// A channel's block strategy, plus the watchers to notify with it
+pub struct Notifier {
+    strategy: Box<dyn BlockStrategy>,
+    watchers: AtomicUsize,
+    watching: Mutex<Vec<Arc<EventCount>>>,
+}
+
+/// Keeps a watcher registered with a channel until dropped
+pub struct Watch<'a> {
+    notifier: &'a Notifier,
+    events: Arc<EventCount>,
+}
+
+impl Notifier {
+    pub fn new(strategy: Box<dyn BlockStrategy>) -> Notifier {
+        Notifier {
+            strategy,
+            watchers: AtomicUsize::new(0),
+            watching: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Notify `watcher` along with the strategy from now on. Check the
+    /// channel only after this, so nothing published in between is missed.
+    pub fn watch(&self, watcher: &Watcher) -> Watch<'_> {
+        lock(&self.watching).push(watcher.events.clone());
+        self.watchers.fetch_add(1, SeqCst);
+        Watch {
+            notifier: self,
+            events: watcher.events.clone(),
+        }
+    }
+
+    fn notify_watchers(&self) {
+        fence(SeqCst);
+        if self.watchers.load(Relaxed) > 0 {
+            for events in lock(&self.watching).iter() {
+                events.notify_all();
+            }
+        }
+    }
+}
+
+impl BlockStrategy for Notifier {
+    fn wait(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        self.strategy.wait(ready, deadline)
+    }
+
+    fn notify_one(&self) {
+        self.strategy.notify_one();
+        self.notify_watchers();
+    }
+
+    fn notify_all(&self) {
+        self.strategy.notify_all();
+        self.notify_watchers();
+    }
+
+    fn sleepers(&self) -> usize {
+        self.strategy.sleepers()
+    }
+}
+
+impl<'a> Drop for Watch<'a> {
+    fn drop(&mut self) {
+        let mut watching = lock(&self.notifier.watching);
+        if let Some(index) = watching
+            .iter()
+            .position(|events| Arc::ptr_eq(events, &self.events))
+        {
+            watching.swap_remove(index);
+        }
+        self.notifier.watchers.fetch_sub(1, Relaxed);
+    }
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("sleepers", &self.events.sleepers())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Notifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Notifier")
+            .field("watchers", &self.watchers.load(Relaxed))
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for Watch<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watch").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::{queue, SpinOnly};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn watch_and_unwatch() {
+        let notifier = Notifier::new(Box::new(SpinOnly));
+        let watcher = Watcher::new();
+        {
+            let _first = notifier.watch(&watcher);
+            let _second = notifier.watch(&Watcher::new());
+            assert_eq!(notifier.watchers.load(Relaxed), 2);
+        }
+        assert_eq!(notifier.watchers.load(Relaxed), 0);
+        assert!(lock(&notifier.watching).is_empty());
+        // Nobody watches, so this must not block or wake anything
+        notifier.notify_all();
+        assert!(!watcher.wait_until(&mut || false, Some(Instant::now())));
+    }
+
+    #[test]
+    fn send_wakes_watcher() {
+        let (tx, rx) = queue();
+        let watcher = Watcher::new();
+        let _watch = rx.watch(&watcher);
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            tx.send(1).unwrap();
+        });
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert!(watcher.wait_until(&mut || rx.stats().queued > 0, Some(deadline)));
+        sender.join().unwrap();
+    }
+}
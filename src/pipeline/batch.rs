@@ -0,0 +1,100 @@
+//! Grouping the messages of a channel into batches.
+
+use mpmc::Receiver;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Collects messages from a channel into batches of up to `max_items`,
+/// handing a batch over once it is full or once `max_wait` has passed
+/// since its first message arrived, whichever comes first. Useful for
+/// amortizing the cost of writes, like inserting rows into a database.
+#[derive(Clone, Copy, Debug)]
+pub struct Batcher {
+    max_items: usize,
+    max_wait: Duration,
+}
+
+impl Batcher {
+    /// Panics if `max_items` is zero.
+    pub fn new(max_items: usize, max_wait: Duration) -> Batcher {
+        assert!(max_items > 0, "batches need room for an item");
+        Batcher {
+            max_items,
+            max_wait,
+        }
+    }
+
+    /// Pass batches from `rx` to `f` on this thread until the channel
+    /// disconnects. The last batch may be partial, but is never empty.
+    pub fn run<T: Send, F: FnMut(Vec<T>)>(&self, rx: &Receiver<T>, mut f: F) {
+        while let Ok(first) = rx.recv() {
+            let deadline = Instant::now() + self.max_wait;
+            let mut batch = Vec::with_capacity(self.max_items);
+            batch.push(first);
+            while batch.len() < self.max_items {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                match rx.recv_timeout(deadline - now) {
+                    Ok(item) => batch.push(item),
+                    // Timed out, or disconnected, which the next `recv`
+                    // reports once this batch is handed over
+                    Err(_) => break,
+                }
+            }
+            f(batch);
+        }
+    }
+
+    /// Like `run`, but on a thread of its own
+    pub fn spawn<T, F>(self, rx: Receiver<T>, f: F) -> JoinHandle<()>
+    where
+        T: Send + 'static,
+        F: FnMut(Vec<T>) + Send + 'static,
+    {
+        thread::Builder::new()
+            .name("myriad-batcher".into())
+            .spawn(move || self.run(&rx, f))
+            .expect("failed to spawn batcher thread")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc;
+
+    #[test]
+    fn full_batches() {
+        let (tx, rx) = mpmc::queue();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let mut batches = Vec::new();
+        Batcher::new(4, Duration::from_secs(60)).run(&rx, |batch| batches.push(batch));
+        assert_eq!(
+            batches,
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]
+        );
+    }
+
+    #[test]
+    fn flushes_on_timeout() {
+        let (tx, rx) = mpmc::queue();
+        let (batches, received) = mpmc::queue();
+        let handle = Batcher::new(100, Duration::from_millis(10)).spawn(rx, move |batch| {
+            batches.send(batch).unwrap();
+        });
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        let start = Instant::now();
+        assert_eq!(received.recv(), Ok(vec![1, 2]));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        tx.send(3).unwrap();
+        assert_eq!(received.recv(), Ok(vec![3]));
+        drop(tx);
+        handle.join().unwrap();
+    }
+}
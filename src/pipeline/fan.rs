@@ -0,0 +1,287 @@
+//! Spreading a channel over worker threads and merging channels back
+//! together, for when a full `Pipeline` is more than you need.
+//!
+//! The threads these spawn exit once their input disconnects or the
+//! receiver they return is dropped, or for `route`, once every channel it
+//! routes to is.
+
+use super::spawn;
+use mpmc::{self, Receiver, Select, Sender};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Apply `f` to every message from `input` on `workers` threads, sending
+/// the results to the returned receiver in whatever order they finish.
+///
+/// Panics if `workers` is zero.
+pub fn fan_out<T, U, F>(input: Receiver<T>, workers: usize, f: F) -> Receiver<U>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    F: Fn(T) -> U + Send + Sync + 'static,
+{
+    assert!(workers > 0, "fan_out needs at least one worker");
+    let (tx, rx) = mpmc::queue();
+    let f = Arc::new(f);
+    for _ in 0..workers {
+        let input = input.clone();
+        let tx = tx.clone();
+        let f = f.clone();
+        spawn(move || {
+            while let Ok(item) = input.recv() {
+                if tx.send(f(item)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    rx
+}
+
+/// Like `fan_out`, but results are received in the order their inputs
+/// were. If `f` panics the output ends just before that message's result,
+/// once the other workers have drained the input.
+///
+/// Panics if `workers` is zero.
+pub fn fan_out_ordered<T, U, F>(input: Receiver<T>, workers: usize, f: F) -> Receiver<U>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    F: Fn(T) -> U + Send + Sync + 'static,
+{
+    assert!(workers > 0, "fan_out needs at least one worker");
+    let (tagged_tx, tagged) = mpmc::queue();
+    spawn(move || {
+        let mut seq = 0u64;
+        while let Ok(item) = input.recv() {
+            if tagged_tx.send((seq, item)).is_err() {
+                break;
+            }
+            seq += 1;
+        }
+    });
+    let results = fan_out(tagged, workers, move |(seq, item)| (seq, f(item)));
+
+    let (tx, rx) = mpmc::queue();
+    spawn(move || {
+        // Results that finished before an earlier one
+        let mut pending = BTreeMap::new();
+        let mut next = 0u64;
+        while let Ok((seq, item)) = results.recv() {
+            pending.insert(seq, item);
+            while let Some(item) = pending.remove(&next) {
+                if tx.send(item).is_err() {
+                    return;
+                }
+                next += 1;
+            }
+        }
+    });
+    rx
+}
+
+/// Merge the messages from every receiver in `inputs` into one channel,
+/// which disconnects once all of them have
+pub fn fan_in<T: Send + 'static>(inputs: Vec<Receiver<T>>) -> Receiver<T> {
+    let (tx, rx) = mpmc::queue();
+    for input in inputs {
+        let tx = tx.clone();
+        spawn(move || {
+            while let Ok(item) = input.recv() {
+                if tx.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    rx
+}
+
+/// Like `fan_in`, but with a single thread taking turns between the
+/// inputs, so each busy input gets an even share of the output however
+/// many messages the others have queued. While every input is idle the
+/// thread sleeps, as `fan_in`'s do, and it wakes as soon as any of them
+/// gets a message.
+pub fn merge<T: Send + 'static>(inputs: Vec<Receiver<T>>) -> Receiver<T> {
+    let (tx, rx) = mpmc::queue();
+    spawn(move || {
+        let select = Select::new(inputs.iter().collect());
+        while let Ok((_, item)) = select.select() {
+            if tx.send(item).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Pair each message from `a` with the next one from `b`, for joining two
+/// upstream stages. The pairs end with the first input to run dry, and a
+/// message left waiting for its partner then is dropped.
+pub fn zip<A, B>(a: Receiver<A>, b: Receiver<B>) -> Receiver<(A, B)>
+where
+    A: Send + 'static,
+    B: Send + 'static,
+{
+    let (tx, rx) = mpmc::queue();
+    spawn(move || {
+        while let (Ok(a), Ok(b)) = (a.recv(), b.recv()) {
+            if tx.send((a, b)).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// A test on messages, for `route`
+pub type Predicate<T> = Box<dyn Fn(&T) -> bool + Send>;
+
+/// Forward each message from `input` to the channel of the first route
+/// whose predicate it matches, or to `default` if it matches none.
+///
+/// A route whose receivers are gone drops the messages that match it,
+/// rather than handing them to a later route they weren't meant for.
+pub fn route<T: Send + 'static>(
+    input: Receiver<T>,
+    routes: Vec<(Predicate<T>, Sender<T>)>,
+    default: Sender<T>,
+) {
+    spawn(move || {
+        // Outputs found disconnected, the default last
+        let mut gone = vec![false; routes.len() + 1];
+        while let Ok(item) = input.recv() {
+            let index = routes
+                .iter()
+                .position(|(matches, _)| matches(&item))
+                .unwrap_or(routes.len());
+            let output = routes.get(index).map_or(&default, |(_, tx)| tx);
+            if output.send(item).is_err() {
+                gone[index] = true;
+                if gone.iter().all(|&gone| gone) {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn drain<T: Send>(rx: Receiver<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        while let Ok(item) = rx.recv() {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn unordered() {
+        let (tx, rx) = mpmc::queue();
+        let results = fan_out(rx, 4, |x: u32| x + 1);
+        for i in 0..100 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let mut results = drain(results);
+        results.sort();
+        assert_eq!(results, (1..101).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ordered() {
+        let (tx, rx) = mpmc::queue();
+        // Early items take longest, so they finish out of order
+        let results = fan_out_ordered(rx, 4, |x: u64| {
+            thread::sleep(Duration::from_millis(20u64.saturating_sub(x)));
+            x * 10
+        });
+        for i in 0..40 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(drain(results), (0..40).map(|x| x * 10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fan_in_merges() {
+        let (inputs, receivers): (Vec<_>, Vec<_>) = (0..3).map(|_| mpmc::queue()).unzip();
+        let merged = fan_in(receivers);
+        for (i, tx) in inputs.into_iter().enumerate() {
+            tx.send(i).unwrap();
+            tx.send(i + 10).unwrap();
+        }
+        let mut items = drain(merged);
+        items.sort();
+        assert_eq!(items, vec![0, 1, 2, 10, 11, 12]);
+    }
+
+    #[test]
+    fn merge_takes_turns() {
+        let (busy, busy_rx) = mpmc::queue();
+        let (quiet, quiet_rx) = mpmc::queue();
+        for i in 0..100 {
+            busy.send(i).unwrap();
+        }
+        quiet.send(1000).unwrap();
+        quiet.send(1001).unwrap();
+        drop((busy, quiet));
+        let items = drain(merge(vec![busy_rx, quiet_rx]));
+        assert_eq!(&items[..4], &[0, 1000, 1, 1001]);
+        assert_eq!(items.len(), 102);
+    }
+
+    #[test]
+    fn merge_wakes_when_idle_input_sends() {
+        let (first, first_rx) = mpmc::queue();
+        let (second, second_rx) = mpmc::queue();
+        let merged = merge(vec![first_rx, second_rx]);
+        // Let the merging thread go to sleep on both inputs first
+        thread::sleep(Duration::from_millis(20));
+        second.send(7).unwrap();
+        assert_eq!(merged.recv_timeout(Duration::from_secs(5)), Ok(7));
+        drop((first, second));
+        assert_eq!(merged.recv(), Err(mpmc::Error::Disconnected));
+    }
+
+    #[test]
+    fn routes() {
+        let (tx, rx) = mpmc::queue();
+        let (small, small_rx) = mpmc::queue();
+        let (even, even_rx) = mpmc::queue();
+        let (rest, rest_rx) = mpmc::queue();
+        let small_first: Predicate<u32> = Box::new(|&x| x < 4);
+        route(
+            rx,
+            vec![(small_first, small), (Box::new(|&x| x % 2 == 0), even)],
+            rest,
+        );
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(drain(small_rx), vec![0, 1, 2, 3]);
+        assert_eq!(drain(even_rx), vec![4, 6, 8]);
+        assert_eq!(drain(rest_rx), vec![5, 7, 9]);
+    }
+
+    #[test]
+    fn zip_pairs() {
+        let (numbers, numbers_rx) = mpmc::queue();
+        let (names, names_rx) = mpmc::queue();
+        let pairs = zip(numbers_rx, names_rx);
+        for i in 0..3 {
+            numbers.send(i).unwrap();
+        }
+        names.send("zero").unwrap();
+        assert_eq!(pairs.recv(), Ok((0, "zero")));
+        names.send("one").unwrap();
+        drop((numbers, names));
+        assert_eq!(drain(pairs), vec![(1, "one")]);
+    }
+}
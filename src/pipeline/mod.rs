@@ -0,0 +1,305 @@
+//! Multi-stage pipelines of worker threads.
+//!
+//! A pipeline feeds the items of an iterator through a series of stages,
+//! each run by its own worker threads, and into a sink on the calling
+//! thread:
+//!
+//! ```
+//! use myriad::pipeline::Pipeline;
+//!
+//! let mut total = 0;
+//! Pipeline::source(1..=100u64)
+//!     .then(4, |x| x * x)
+//!     .then(2, |x| x + 1)
+//!     .sink(|x| total += x)
+//!     .unwrap();
+//! assert_eq!(total, 338_450);
+//! ```
+//!
+//! Stages are connected by bounded links, so a stage that gets ahead
+//! blocks until the next one catches up, and its workers get room in the
+//! link in the order they blocked. Once the source runs dry, each
+//! stage finishes the items already queued for it and exits, which in turn
+//! lets the next stage finish. If every worker of a stage exits early,
+//! because its function panicked, the stages before it stop as well.
+//! Stages with more than one worker don't preserve the order of items.
+//! `affinity` pins the workers of the stages added after it, like a pool's
+//! workers.
+//!
+//! `fan_out`, `fan_out_ordered`, `fan_in`, `merge`, `zip` and `route` are
+//! the same building blocks on plain channels, for a single stage, and
+//! `Batcher` groups the messages of a channel into batches.
+
+use mpmc::{self, Receiver, Sender};
+use pool::Affinity;
+use std::fmt;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use sync::{OwnedSemaphorePermit, Semaphore};
+
+mod batch;
+mod fan;
+
+pub use self::batch::Batcher;
+pub use self::fan::{fan_in, fan_out, fan_out_ordered, merge, route, zip, Predicate};
+
+/// How many items each link holds when not set with `with_capacity`
+const DEFAULT_CAPACITY: usize = 64;
+
+/// An item travels with the permit for its slot in the link, which frees
+/// the slot once the next stage takes the item
+type Slot<T> = (T, OwnedSemaphorePermit);
+
+/// The sending end of a link between two stages
+struct Output<T: Send> {
+    sender: Sender<Slot<T>>,
+    slots: Arc<Semaphore>,
+}
+
+/// The receiving end of a link, shared by the workers of a stage
+struct Input<T: Send> {
+    receiver: Receiver<Slot<T>>,
+    slots: Arc<Semaphore>,
+}
+
+fn link<T: Send + 'static>(capacity: usize) -> (Output<T>, Arc<Input<T>>) {
+    let (sender, receiver) = mpmc::queue();
+    let slots = Arc::new(Semaphore::new(capacity));
+    let input = Input {
+        receiver,
+        slots: slots.clone(),
+    };
+    (Output { sender, slots }, Arc::new(input))
+}
+
+impl<T: Send> Output<T> {
+    /// Block until the link has room, then send `item`. Fails once every
+    /// worker of the next stage has exited.
+    fn send(&self, item: T) -> Result<(), T> {
+        let permit = self.slots.acquire_owned();
+        self.sender.send((item, permit)).map_err(|(item, _)| item)
+    }
+}
+
+impl<T: Send> Clone for Output<T> {
+    fn clone(&self) -> Output<T> {
+        Output {
+            sender: self.sender.clone(),
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+impl<T: Send> Input<T> {
+    /// The next item, or `None` once the previous stage has finished
+    fn recv(&self) -> Option<T> {
+        self.receiver.recv().ok().map(|(item, _permit)| item)
+    }
+}
+
+impl<T: Send> Drop for Input<T> {
+    fn drop(&mut self) {
+        // Nothing will free the slots of items left in the link, so let
+        // senders blocked on a full link through to find it disconnected
+        self.slots.add_permits(usize::MAX / 2);
+    }
+}
+
+fn spawn<F: FnOnce() + Send + 'static>(f: F) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name("myriad-pipeline".into())
+        .spawn(f)
+        .expect("failed to spawn pipeline thread")
+}
+
+/// A pipeline whose last stage produces items of type `T`. Nothing
+/// consumes them until `sink` is called; dropping the pipeline instead
+/// shuts it down.
+pub struct Pipeline<T: Send> {
+    input: Arc<Input<T>>,
+    capacity: usize,
+    /// For the workers of stages added from now on
+    affinity: Affinity,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> Pipeline<T> {
+    /// Start a pipeline that produces the items of `items`, iterated on a
+    /// thread of its own
+    pub fn source<I>(items: I) -> Pipeline<T>
+    where
+        I: IntoIterator<Item = T> + Send + 'static,
+    {
+        Pipeline::with_capacity(DEFAULT_CAPACITY, items)
+    }
+
+    /// Like `source`, but every link between stages holds at most
+    /// `capacity` items.
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity<I>(capacity: usize, items: I) -> Pipeline<T>
+    where
+        I: IntoIterator<Item = T> + Send + 'static,
+    {
+        assert!(capacity > 0, "pipeline links need room for an item");
+        let (output, input) = link(capacity);
+        let source = spawn(move || {
+            for item in items {
+                if output.send(item).is_err() {
+                    break;
+                }
+            }
+        });
+        Pipeline {
+            input,
+            capacity,
+            affinity: Affinity::Any,
+            threads: vec![source],
+        }
+    }
+
+    /// Pin the workers of the stages added after this, worker `i` of a
+    /// stage as a pool pins its worker `i`. Pinning is best-effort, as in
+    /// a pool. The source thread and the sink are left alone.
+    pub fn affinity(mut self, affinity: Affinity) -> Pipeline<T> {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Add a stage that applies `f` to every item on `workers` threads.
+    ///
+    /// Panics if `workers` is zero.
+    pub fn then<U, F>(self, workers: usize, f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        F: Fn(T) -> U + Send + Sync + 'static,
+    {
+        assert!(workers > 0, "a pipeline stage needs at least one worker");
+        let (output, input) = link(self.capacity);
+        let f = Arc::new(f);
+        let mut threads = self.threads;
+        for index in 0..workers {
+            let upstream = self.input.clone();
+            let output = output.clone();
+            let f = f.clone();
+            let affinity = self.affinity.clone();
+            threads.push(spawn(move || {
+                // An unpinned worker still does its job
+                let _ = affinity.apply(index);
+                while let Some(item) = upstream.recv() {
+                    if output.send(f(item)).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        Pipeline {
+            input,
+            capacity: self.capacity,
+            affinity: self.affinity,
+            threads,
+        }
+    }
+
+    /// Pass every item to `f` on this thread, then wait for the pipeline's
+    /// threads to exit. Returns the payload of the first stage that
+    /// panicked, if any did.
+    pub fn sink<F: FnMut(T)>(self, mut f: F) -> thread::Result<()> {
+        let Pipeline { input, threads, .. } = self;
+        while let Some(item) = input.recv() {
+            f(item);
+        }
+        drop(input);
+        let mut result = Ok(());
+        for thread in threads {
+            if let Err(payload) = thread.join() {
+                if result.is_ok() {
+                    result = Err(payload);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<T: Send> fmt::Debug for Pipeline<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("capacity", &self.capacity)
+            .field("affinity", &self.affinity)
+            .field("threads", &self.threads.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::time::Duration;
+
+    #[test]
+    fn stages() {
+        let mut items = Vec::new();
+        Pipeline::source(0..1000)
+            .then(4, |x: u32| x * 2)
+            .then(3, |x| x.to_string())
+            .sink(|x| items.push(x))
+            .unwrap();
+        let mut expected = (0..1000).map(|x| (x * 2).to_string()).collect::<Vec<_>>();
+        items.sort();
+        expected.sort();
+        assert_eq!(items, expected);
+    }
+
+    #[test]
+    fn bounded() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let count = produced.clone();
+        let items = (0..100).inspect(move |_| {
+            count.fetch_add(1, SeqCst);
+        });
+        let mut first = true;
+        Pipeline::with_capacity(2, items)
+            .sink(|_| {
+                if first {
+                    first = false;
+                    thread::sleep(Duration::from_millis(20));
+                    // The item being sunk, two in the link, and one
+                    // waiting for room
+                    assert!(produced.load(SeqCst) <= 4);
+                }
+            })
+            .unwrap();
+        assert_eq!(produced.load(SeqCst), 100);
+    }
+
+    #[cfg(all(target_os = "linux", not(miri)))]
+    #[test]
+    fn pinned_stage() {
+        // A core this thread may run on is one the workers may too
+        let core = unsafe { ::libc::sched_getcpu() } as usize;
+        let mut cores = Vec::new();
+        Pipeline::source(0..20)
+            .affinity(Affinity::Cores(vec![core]))
+            .then(2, |_| unsafe { ::libc::sched_getcpu() } as usize)
+            .sink(|core| cores.push(core))
+            .unwrap();
+        assert_eq!(cores, vec![core; 20]);
+    }
+
+    #[test]
+    fn panic_stops_upstream() {
+        let mut received = Vec::new();
+        // The source never runs dry, so this only returns if the panic
+        // shuts it down
+        let result = Pipeline::with_capacity(4, 0u64..)
+            .then(1, |x| {
+                assert!(x < 10, "stage failed");
+                x
+            })
+            .sink(|x| received.push(x));
+        assert!(result.is_err());
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+}
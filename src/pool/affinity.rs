@@ -0,0 +1,247 @@
+//! Pinning worker threads to cores.
+//!
+//! Workers that stay on one core keep their caches warm, and on
+//! multi-socket machines workers confined to one NUMA node avoid pulling
+//! queue nodes across the interconnect. Pinning is best-effort: if the
+//! platform doesn't support it or the cores don't exist, workers run
+//! unpinned.
+
+use std::fs;
+use std::io;
+
+/// Which cores the workers of a pool may run on
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Affinity {
+    /// Let the OS schedule workers anywhere
+    #[default]
+    Any,
+    /// Pin worker `i` to `cores[i % cores.len()]`
+    Cores(Vec<usize>),
+    /// Let every worker run on any core of these NUMA nodes
+    NumaNodes(Vec<usize>),
+}
+
+impl Affinity {
+    /// Apply the affinity for worker `index` to the current thread
+    pub(crate) fn apply(&self, index: usize) -> io::Result<()> {
+        match self {
+            Affinity::Any => Ok(()),
+            Affinity::Cores(cores) if cores.is_empty() => Ok(()),
+            Affinity::Cores(cores) => pin_current_thread(&[cores[index % cores.len()]]),
+            Affinity::NumaNodes(nodes) => {
+                let mut cores = Vec::new();
+                for &node in nodes {
+                    cores.extend(numa_node_cores(node)?);
+                }
+                pin_current_thread(&cores)
+            }
+        }
+    }
+}
+
+/// Restrict the current thread to run only on `cores`
+pub fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+    imp::pin_current_thread(cores)
+}
+
+/// Make the current thread's future allocations take fresh pages from
+/// the NUMA node it is running on, even if the process was started with
+/// another memory policy, for example by `numactl --interleave`. Pages
+/// already allocated stay where they are.
+pub fn prefer_local_memory() -> io::Result<()> {
+    imp::prefer_local_memory()
+}
+
+/// The cores belonging to NUMA node `node`
+pub fn numa_node_cores(node: usize) -> io::Result<Vec<usize>> {
+    let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+    let list = fs::read_to_string(path)?;
+    parse_cpu_list(list.trim())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed cpulist"))
+}
+
+/// Parse the kernel's cpulist format, such as `0-3,8,10-11`
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cores = Vec::new();
+    for range in list.split(',').filter(|range| !range.is_empty()) {
+        match range.find('-') {
+            Some(dash) => {
+                let start = range[..dash].parse::<usize>().ok()?;
+                let end = range[dash + 1..].parse::<usize>().ok()?;
+                cores.extend(start..=end);
+            }
+            None => cores.push(range.parse().ok()?),
+        }
+    }
+    Some(cores)
+}
+
+#[cfg(all(target_os = "linux", not(miri)))]
+mod imp {
+    use libc;
+    use std::io;
+    use std::mem;
+    use std::ptr;
+
+    /// From `linux/mempolicy.h`, which libc doesn't carry
+    pub const MPOL_LOCAL: libc::c_int = 4;
+
+    pub fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+        unsafe {
+            let mut set = mem::zeroed::<libc::cpu_set_t>();
+            libc::CPU_ZERO(&mut set);
+            for &core in cores {
+                if core >= libc::CPU_SETSIZE as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "core index out of range",
+                    ));
+                }
+                libc::CPU_SET(core, &mut set);
+            }
+            if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn prefer_local_memory() -> io::Result<()> {
+        let nodemask = ptr::null::<libc::c_ulong>();
+        if unsafe { libc::syscall(libc::SYS_set_mempolicy, MPOL_LOCAL, nodemask, 0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(windows, not(miri)))]
+mod imp {
+    use std::io;
+    use std::os::raw::c_void;
+
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadAffinityMask(thread: *mut c_void, mask: usize) -> usize;
+    }
+
+    pub fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+        let mut mask = 0usize;
+        for &core in cores {
+            if core >= usize::BITS as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "core index out of range",
+                ));
+            }
+            mask |= 1 << core;
+        }
+        if unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn prefer_local_memory() -> io::Result<()> {
+        // Windows already allocates from the node of the faulting thread
+        Ok(())
+    }
+}
+
+// Miri can't run the syscalls, so pinning fails there like on any other
+// unsupported platform
+#[cfg(any(miri, not(any(target_os = "linux", windows))))]
+mod imp {
+    use std::io;
+
+    pub fn pin_current_thread(_: &[usize]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "thread affinity is not supported on this platform",
+        ))
+    }
+
+    pub fn prefer_local_memory() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory policies are not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cpu_list() {
+        assert_eq!(parse_cpu_list("0"), Some(vec![0]));
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+    }
+
+    /// The first core the test process may run on, which needn't be 0
+    /// under a restricted cpuset
+    #[cfg(all(target_os = "linux", not(miri)))]
+    fn first_allowed_core() -> usize {
+        unsafe {
+            let mut set = ::std::mem::zeroed::<::libc::cpu_set_t>();
+            let size = ::std::mem::size_of::<::libc::cpu_set_t>();
+            assert_eq!(::libc::sched_getaffinity(0, size, &mut set), 0);
+            (0..::libc::CPU_SETSIZE as usize)
+                .find(|&core| ::libc::CPU_ISSET(core, &set))
+                .unwrap()
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(miri)))]
+    #[test]
+    fn pin() {
+        let core = first_allowed_core();
+        ::std::thread::spawn(move || {
+            pin_current_thread(&[core]).unwrap();
+            assert_eq!(unsafe { ::libc::sched_getcpu() } as usize, core);
+            assert!(pin_current_thread(&[usize::MAX]).is_err());
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[cfg(all(target_os = "linux", not(miri)))]
+    #[test]
+    fn local_memory() {
+        ::std::thread::spawn(|| {
+            prefer_local_memory().unwrap();
+            let mut mode: ::libc::c_int = -1;
+            let nodemask = ::std::ptr::null_mut::<::libc::c_ulong>();
+            let ret =
+                unsafe { ::libc::syscall(::libc::SYS_get_mempolicy, &mut mode, nodemask, 0, 0, 0) };
+            assert_eq!(ret, 0);
+            assert_eq!(mode, imp::MPOL_LOCAL);
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[cfg(all(target_os = "linux", not(miri)))]
+    #[test]
+    fn pool_workers() {
+        use pool::ThreadPool;
+        // Kernels built without NUMA support have no nodes to pin to
+        if !::std::path::Path::new("/sys/devices/system/node/node0").exists() {
+            return;
+        }
+        let pool = ThreadPool::builder()
+            .threads(2)
+            .affinity(Affinity::NumaNodes(vec![0]))
+            .build();
+        let core = pool.spawn(|| unsafe { ::libc::sched_getcpu() });
+        assert!(numa_node_cores(0)
+            .unwrap()
+            .contains(&(core.join().unwrap() as usize)));
+    }
+}
@@ -0,0 +1,352 @@
+//! A Chase-Lev work-stealing deque.
+//!
+//! The owning `Deque` pushes and pops at the back, so its own work is
+//! processed LIFO while it is still hot in cache. Any number of `Stealer`s
+//! take from the front, which holds the oldest and usually largest pieces
+//! of work. Only the last remaining element is contended between the owner
+//! and stealers, and that race is settled with a single CAS on `front`.
+//!
+//! The ring buffer grows when full. Stealers may still be reading the old
+//! buffer, so it is retired through the epoch collector instead of being
+//! freed directly.
+
+use epoch;
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicIsize, AtomicPtr, Ordering::*};
+use std::sync::Arc;
+
+const MIN_CAPACITY: usize = 32;
+
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+unsafe impl<T: Send> Send for Buffer<T> {}
+
+impl<T> Buffer<T> {
+    fn alloc(capacity: usize) -> *mut Buffer<T> {
+        debug_assert!(capacity.is_power_of_two());
+        let slots = (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+        Box::into_raw(Box::new(Buffer { slots }))
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: isize) -> *mut MaybeUninit<T> {
+        self.slots[index as usize & (self.capacity() - 1)].get()
+    }
+
+    unsafe fn write(&self, index: isize, value: T) {
+        (*self.slot(index)).write(value);
+    }
+
+    /// Copy the value out without taking ownership of it. The caller
+    /// decides afterwards whether the copy is the real one.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        ptr::read(self.slot(index))
+    }
+}
+
+struct Inner<T> {
+    /// Index of the oldest element, advanced by stealers and by the owner
+    /// when it takes the last element
+    front: AtomicIsize,
+    /// One past the newest element, only written by the owner
+    back: AtomicIsize,
+    buffer: AtomicPtr<Buffer<T>>,
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let front = *self.front.get_mut();
+        let back = *self.back.get_mut();
+        unsafe {
+            let buffer = Box::from_raw(*self.buffer.get_mut());
+            for index in front..back {
+                buffer.read(index).assume_init();
+            }
+        }
+    }
+}
+
+/// The owning end of the deque
+pub struct Deque<T> {
+    inner: Arc<Inner<T>>,
+    /// The owner is the only thread that replaces the buffer, so it can
+    /// skip the atomic load
+    buffer: Cell<*mut Buffer<T>>,
+    // Owner operations must stay on one thread at a time
+    _marker: PhantomData<*mut ()>,
+}
+
+unsafe impl<T: Send> Send for Deque<T> {}
+
+/// A handle that takes elements from the front of a `Deque`
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+/// The outcome of `Stealer::steal`
+#[derive(Debug, PartialEq)]
+pub enum Steal<T> {
+    Empty,
+    Success(T),
+    /// Lost a race with another thread, the deque may not be empty
+    Retry,
+}
+
+impl<T: Send + 'static> Deque<T> {
+    pub fn new() -> Deque<T> {
+        let buffer = Buffer::alloc(MIN_CAPACITY);
+        Deque {
+            inner: Arc::new(Inner {
+                front: AtomicIsize::new(0),
+                back: AtomicIsize::new(0),
+                buffer: AtomicPtr::new(buffer),
+            }),
+            buffer: Cell::new(buffer),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let back = self.inner.back.load(Relaxed);
+        let front = self.inner.front.load(SeqCst);
+        back.wrapping_sub(front).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&self, value: T) {
+        let back = self.inner.back.load(Relaxed);
+        let front = self.inner.front.load(Acquire);
+        let mut buffer = self.buffer.get();
+        let len = back.wrapping_sub(front);
+        if len >= unsafe { (*buffer).capacity() } as isize {
+            buffer = self.grow(front, back);
+        }
+        unsafe { (*buffer).write(back, value) };
+        // Publish the element before making it visible to stealers
+        fence(Release);
+        self.inner.back.store(back.wrapping_add(1), Relaxed);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let back = self.inner.back.load(Relaxed).wrapping_sub(1);
+        let buffer = self.buffer.get();
+        // Claim the element before looking at `front`. Stealers that load
+        // `back` after this will leave it alone.
+        self.inner.back.store(back, Relaxed);
+        fence(SeqCst);
+        let front = self.inner.front.load(Relaxed);
+
+        let len = back.wrapping_sub(front);
+        if len < 0 {
+            // Empty, undo the claim
+            self.inner.back.store(back.wrapping_add(1), Relaxed);
+            return None;
+        }
+        let value = unsafe { (*buffer).read(back) };
+        if len == 0 {
+            // The last element, which a stealer may be racing us for
+            let won = self
+                .inner
+                .front
+                .compare_exchange(front, front.wrapping_add(1), SeqCst, Relaxed)
+                .is_ok();
+            self.inner.back.store(back.wrapping_add(1), Relaxed);
+            if !won {
+                return None;
+            }
+        }
+        Some(unsafe { value.assume_init() })
+    }
+
+    /// Move the elements into a buffer twice the size, returning it
+    fn grow(&self, front: isize, back: isize) -> *mut Buffer<T> {
+        let old = self.buffer.get();
+        let new = Buffer::alloc(unsafe { (*old).capacity() } * 2);
+        for index in front..back {
+            unsafe { ptr::copy_nonoverlapping((*old).slot(index), (*new).slot(index), 1) };
+        }
+        let guard = epoch::pin();
+        self.buffer.set(new);
+        self.inner.buffer.store(new, Release);
+        // Stealers that loaded the old buffer are still pinned. The copies
+        // of the elements it holds are never dropped through it.
+        unsafe { guard.defer_destroy(old) };
+        new
+    }
+}
+
+impl<T: Send + 'static> Default for Deque<T> {
+    fn default() -> Deque<T> {
+        Deque::new()
+    }
+}
+
+impl<T: Send + 'static> Stealer<T> {
+    /// Take the oldest element
+    pub fn steal(&self) -> Steal<T> {
+        let front = self.inner.front.load(Acquire);
+        // Pairs with the fence in `pop`, so that we either see the owner's
+        // claim on the last element, or the owner sees our CAS
+        fence(SeqCst);
+        let guard = epoch::pin();
+        let back = self.inner.back.load(Acquire);
+        if back.wrapping_sub(front) <= 0 {
+            return Steal::Empty;
+        }
+
+        let buffer = self.inner.buffer.load(Acquire);
+        let value = unsafe { (*buffer).read(front) };
+        drop(guard);
+        match self
+            .inner
+            .front
+            .compare_exchange(front, front.wrapping_add(1), SeqCst, Relaxed)
+        {
+            Ok(_) => Steal::Success(unsafe { value.assume_init() }),
+            Err(_) => Steal::Retry,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let front = self.inner.front.load(Acquire);
+        let back = self.inner.back.load(Acquire);
+        back.wrapping_sub(front) <= 0
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> fmt::Debug for Deque<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Deque").field("len", &self.len()).finish()
+    }
+}
+
+impl<T> fmt::Debug for Stealer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Stealer { .. }")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    #[test]
+    fn owner_lifo_stealer_fifo() {
+        let deque = Deque::new();
+        let stealer = deque.stealer();
+        for i in 0..4 {
+            deque.push(i);
+        }
+        assert_eq!(deque.pop(), Some(3));
+        assert_eq!(stealer.steal(), Steal::Success(0));
+        assert_eq!(deque.len(), 2);
+        assert_eq!(deque.pop(), Some(2));
+        assert_eq!(deque.pop(), Some(1));
+        assert_eq!(deque.pop(), None);
+        assert_eq!(stealer.steal(), Steal::Empty);
+    }
+
+    #[test]
+    fn grow() {
+        let deque = Deque::new();
+        for i in 0..MIN_CAPACITY * 4 {
+            deque.push(i);
+        }
+        assert_eq!(deque.stealer().steal(), Steal::Success(0));
+        for i in (1..MIN_CAPACITY * 4).rev() {
+            assert_eq!(deque.pop(), Some(i));
+        }
+    }
+
+    #[test]
+    fn drop_remaining() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, SeqCst);
+            }
+        }
+        let deque = Deque::new();
+        for _ in 0..MIN_CAPACITY + 1 {
+            deque.push(Counted(drops.clone()));
+        }
+        drop(deque.pop());
+        drop(deque);
+        assert_eq!(drops.load(SeqCst), MIN_CAPACITY + 1);
+    }
+
+    #[test]
+    fn concurrent_steal() {
+        const COUNT: usize = 10_000;
+        let deque = Deque::new();
+        let taken = Arc::new(AtomicUsize::new(0));
+        let sum = Arc::new(AtomicUsize::new(0));
+        let thieves = (0..3)
+            .map(|_| {
+                let stealer = deque.stealer();
+                let taken = taken.clone();
+                let sum = sum.clone();
+                thread::spawn(move || {
+                    while taken.load(SeqCst) < COUNT {
+                        if let Steal::Success(i) = stealer.steal() {
+                            sum.fetch_add(i, SeqCst);
+                            taken.fetch_add(1, SeqCst);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for i in 0..COUNT {
+            deque.push(i);
+            if i % 3 == 0 {
+                if let Some(i) = deque.pop() {
+                    sum.fetch_add(i, SeqCst);
+                    taken.fetch_add(1, SeqCst);
+                }
+            }
+        }
+        while let Some(i) = deque.pop() {
+            sum.fetch_add(i, SeqCst);
+            taken.fetch_add(1, SeqCst);
+        }
+        for thief in thieves {
+            thief.join().unwrap();
+        }
+        assert_eq!(taken.load(SeqCst), COUNT);
+        assert_eq!(sum.load(SeqCst), COUNT * (COUNT - 1) / 2);
+    }
+}
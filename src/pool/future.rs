@@ -0,0 +1,281 @@
+//! Running futures on a `ThreadPool`.
+//!
+//! A spawned future becomes a task that is polled as an ordinary job.
+//! Waking the task queues another poll, unless one is already queued, and
+//! a wake that arrives while the task is being polled queues a fresh poll
+//! once the current one returns, so no wakeup is lost and the future is
+//! never polled on two workers at once.
+
+use super::join::{self, Promise};
+use super::{
+    task_local, Job, JoinHandle, PanicHandler, Shared, ThreadPool, JOB_PANICKED, JOB_UNFINISHED,
+};
+use primitive::blocking::Mutex;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering::*};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+/// Not queued, waiting for a wakeup
+const IDLE: u8 = 0;
+/// A poll is queued
+const SCHEDULED: u8 = 1;
+/// Being polled
+const RUNNING: u8 = 2;
+/// Being polled, and woken since the poll started
+const NOTIFIED: u8 = 3;
+
+/// Wraps a spawned future to catch its panics and deliver its output
+struct Spawned<F: Future> {
+    future: Pin<Box<F>>,
+    promise: Option<Promise<F::Output>>,
+    panic_handler: Option<PanicHandler>,
+}
+
+impl<F: Future> Future for Spawned<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| this.future.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(output)) => Ok(output),
+            Err(payload) => {
+                JOB_PANICKED.with(|panicked| panicked.set(true));
+                if let Some(handler) = &this.panic_handler {
+                    handler(&*payload);
+                }
+                Err(payload)
+            }
+        };
+        if let Some(promise) = this.promise.take() {
+            promise.complete(result);
+        }
+        Poll::Ready(())
+    }
+}
+
+pub(super) struct Task {
+    /// `None` once the future has completed
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    state: AtomicU8,
+    context: task_local::Snapshot,
+    shared: Arc<Shared>,
+}
+
+impl Task {
+    fn job(self: &Arc<Task>) -> Job {
+        let task = self.clone();
+        Box::new(move || task.run())
+    }
+
+    /// Queue a poll, unless the pool has shut down, in which case the task
+    /// is dropped along with its last waker
+    fn schedule(self: &Arc<Task>) {
+        let sender = self.shared.futures.lock().unwrap().clone();
+        if let Some(sender) = sender {
+            self.shared.execute(&sender, self.job());
+        }
+    }
+
+    fn run(self: Arc<Task>) {
+        self.state.store(RUNNING, SeqCst);
+        let waker = Waker::from(self.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = self.future.lock().unwrap();
+        let ready = match future.as_mut() {
+            Some(future) => task_local::enter(self.context.clone(), || {
+                future.as_mut().poll(&mut cx).is_ready()
+            }),
+            // A stale poll of a future that finished already
+            None => {
+                JOB_UNFINISHED.with(|unfinished| unfinished.set(true));
+                return;
+            }
+        };
+        if ready {
+            *future = None;
+            return;
+        }
+        JOB_UNFINISHED.with(|unfinished| unfinished.set(true));
+        drop(future);
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, SeqCst, SeqCst)
+            .is_err()
+        {
+            // Woken during the poll
+            self.state.store(SCHEDULED, SeqCst);
+            self.schedule();
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Task>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Task>) {
+        let mut state = self.state.load(SeqCst);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self.state.compare_exchange(state, next, SeqCst, SeqCst) {
+                Ok(_) if next == SCHEDULED => return self.schedule(),
+                Ok(_) => return,
+                Err(current) => state = current,
+            }
+        }
+    }
+}
+
+/// Drop the futures that are still waiting for a wakeup once the pool has
+/// closed. A future often holds its own waker, and through it its task,
+/// so it won't be dropped otherwise.
+pub(super) fn abandon(shared: &Shared) {
+    for task in shared.tasks.lock().unwrap().drain(..) {
+        if let Some(task) = task.upgrade() {
+            // A future still being polled by a worker the pool gave up on
+            // at its deadline is left to that worker
+            if let Ok(mut future) = task.future.try_lock() {
+                future.take();
+            }
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Poll `future` to completion on the workers, returning a handle to
+    /// its output.
+    ///
+    /// Each poll runs as a job, and between polls the future takes up no
+    /// worker, so one pool can host closures and many lightweight async
+    /// tasks together. If the future panics, the panic is returned by
+    /// `JoinHandle::join`. Futures still waiting for a wakeup when the
+    /// pool shuts down are dropped.
+    pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (promise, handle) = join::oneshot();
+        let spawned = Spawned {
+            future: Box::pin(future),
+            promise: Some(promise),
+            panic_handler: self.shared.panic_handler.clone(),
+        };
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(spawned))),
+            state: AtomicU8::new(SCHEDULED),
+            context: task_local::capture(),
+            shared: self.shared.clone(),
+        });
+        let mut tasks = self.shared.tasks.lock().unwrap();
+        // Forget finished tasks whenever the list would grow
+        if tasks.len() == tasks.capacity() {
+            tasks.retain(|task| task.strong_count() > 0);
+        }
+        tasks.push(Arc::downgrade(&task));
+        drop(tasks);
+        self.execute(task.job());
+        handle
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Completes once `set` is called from elsewhere
+    #[derive(Clone, Default)]
+    struct Flag(Arc<Mutex<(bool, Option<Waker>)>>);
+
+    impl Flag {
+        fn set(&self) {
+            let mut state = self.0.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    impl Future for Flag {
+        type Output = &'static str;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<&'static str> {
+            let mut state = self.0.lock().unwrap();
+            if state.0 {
+                Poll::Ready("set")
+            } else {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Wakes itself `n` times before completing
+    struct Yield(u32);
+
+    impl Future for Yield {
+        type Output = u32;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+            assert!(self.0 < 100, "yielded too often");
+            if self.0 == 0 {
+                return Poll::Ready(7);
+            }
+            self.0 -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn woken_elsewhere() {
+        let pool = ThreadPool::new(1);
+        let flag = Flag::default();
+        let handle = pool.spawn_future(flag.clone());
+        // The future doesn't hold on to the worker while it waits
+        assert_eq!(pool.spawn(|| 1).join().unwrap(), 1);
+        thread::sleep(Duration::from_millis(5));
+        assert!(!handle.is_finished());
+        flag.set();
+        assert_eq!(handle.join().unwrap(), "set");
+    }
+
+    #[test]
+    fn self_wakes_and_panics() {
+        let pool = ThreadPool::new(2);
+        assert_eq!(pool.spawn_future(Yield(10)).join().unwrap(), 7);
+        assert_eq!(
+            pool.spawn_future(::std::future::ready(3)).join().unwrap(),
+            3
+        );
+        assert!(pool.spawn_future(Yield(1000)).join().is_err());
+        // Each future counts once however often it was polled, after its
+        // handle is completed
+        while pool.stats().completed < 3 {
+            thread::yield_now();
+        }
+        let stats = pool.stats();
+        assert_eq!((stats.completed, stats.panicked), (3, 1));
+    }
+
+    #[test]
+    fn pending_dropped_on_shutdown() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.spawn_future(Flag::default());
+        // The future holds its own waker, which must not keep it alive
+        drop(pool);
+        assert!(handle.join().is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! A queue of jobs that are retried with exponential backoff.
+
+use mpmc::{self, Receiver, Sender};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use sync::{CancellationToken, WaitGroup};
+use timer;
+
+/// A job that failed on every attempt, sent to the failure channel
+#[derive(Debug)]
+pub struct Failed<T, E> {
+    pub job: T,
+    /// The error from the last attempt
+    pub error: E,
+    pub attempts: u32,
+}
+
+/// A job on its way to a worker, or waiting out its backoff
+struct Attempt<T> {
+    job: T,
+    attempt: u32,
+    /// Keeps the queue from shutting down while the job is unresolved
+    _outstanding: WaitGroup,
+}
+
+type Pending<T> = Sender<(Instant, Attempt<T>)>;
+
+/// Configures a `JobQueue` before starting it
+#[derive(Clone, Debug)]
+pub struct JobQueueBuilder {
+    workers: usize,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl JobQueueBuilder {
+    pub fn new() -> JobQueueBuilder {
+        JobQueueBuilder {
+            workers: 1,
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Number of worker threads. Defaults to 1.
+    pub fn workers(mut self, workers: usize) -> JobQueueBuilder {
+        self.workers = workers;
+        self
+    }
+
+    /// How many times a job runs before it is sent to the failure
+    /// channel. Defaults to 5.
+    pub fn max_attempts(mut self, attempts: u32) -> JobQueueBuilder {
+        self.max_attempts = attempts;
+        self
+    }
+
+    /// Wait `initial` before the first retry, doubling the wait for each
+    /// retry after that up to `max`. Defaults to 100ms doubling up to 30s.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> JobQueueBuilder {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        2u32.checked_pow(attempt - 1)
+            .and_then(|factor| self.initial_backoff.checked_mul(factor))
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+
+    /// Start the queue, running `handler` on every job. Returns the queue
+    /// along with the channel that receives jobs that failed every
+    /// attempt.
+    ///
+    /// Panics if `workers` or `max_attempts` is zero.
+    pub fn build<T, E, F>(self, handler: F) -> (JobQueue<T>, Receiver<Failed<T, E>>)
+    where
+        T: Send + 'static,
+        E: Send + 'static,
+        F: Fn(&T) -> Result<(), E> + Send + Sync + 'static,
+    {
+        assert!(self.workers > 0, "JobQueue needs at least one worker");
+        assert!(self.max_attempts > 0, "jobs need at least one attempt");
+        let (pending, timers) = mpmc::queue();
+        let (ready_tx, ready) = mpmc::queue();
+        let (failed_tx, failed) = mpmc::queue();
+        let shutdown = CancellationToken::new();
+
+        let mut threads = vec![thread::Builder::new()
+            .name("myriad-job-timer".into())
+            .spawn(move || timer::drive(timers, false, |attempt, _| ready_tx.send(attempt).is_ok()))
+            .expect("failed to spawn timer thread")];
+        let config = Arc::new(self);
+        let handler = Arc::new(handler);
+        for _ in 0..config.workers {
+            let worker = Worker {
+                config: config.clone(),
+                handler: handler.clone(),
+                ready: ready.clone(),
+                retry: pending.clone(),
+                failed: failed_tx.clone(),
+                shutdown: shutdown.clone(),
+            };
+            threads.push(
+                thread::Builder::new()
+                    .name("myriad-job-worker".into())
+                    .spawn(move || worker.run())
+                    .expect("failed to spawn worker thread"),
+            );
+        }
+        let queue = JobQueue {
+            pending: Some(pending),
+            outstanding: Some(WaitGroup::new()),
+            shutdown,
+            threads,
+        };
+        (queue, failed)
+    }
+}
+
+impl Default for JobQueueBuilder {
+    fn default() -> JobQueueBuilder {
+        JobQueueBuilder::new()
+    }
+}
+
+struct Worker<T: Send, E: Send, F> {
+    config: Arc<JobQueueBuilder>,
+    handler: Arc<F>,
+    ready: Receiver<Attempt<T>>,
+    retry: Pending<T>,
+    failed: Sender<Failed<T, E>>,
+    shutdown: CancellationToken,
+}
+
+impl<T, E, F> Worker<T, E, F>
+where
+    T: Send,
+    E: Send,
+    F: Fn(&T) -> Result<(), E>,
+{
+    fn run(self) {
+        while let Ok(attempt) = self.ready.recv_cancellable(&self.shutdown) {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| (self.handler)(&attempt.job)));
+            match result {
+                Ok(Ok(())) | Err(_) => (),
+                Ok(Err(error)) if attempt.attempt >= self.config.max_attempts => {
+                    let _ = self.failed.send(Failed {
+                        job: attempt.job,
+                        error,
+                        attempts: attempt.attempt,
+                    });
+                }
+                Ok(Err(_)) => {
+                    let deadline = Instant::now() + self.config.delay(attempt.attempt);
+                    let retry = Attempt {
+                        attempt: attempt.attempt + 1,
+                        ..attempt
+                    };
+                    let _ = self.retry.send((deadline, retry));
+                }
+            }
+        }
+    }
+}
+
+/// A queue whose workers retry failed jobs with exponential backoff.
+///
+/// A job is retried whenever the handler returns `Err`, until it has run
+/// `max_attempts` times, at which point it goes to the failure channel
+/// with the last error. A job whose handler panics is dropped without
+/// being retried. Dropping the queue waits until every job has either
+/// succeeded or failed for good.
+pub struct JobQueue<T: Send> {
+    pending: Option<Pending<T>>,
+    outstanding: Option<WaitGroup>,
+    shutdown: CancellationToken,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> JobQueue<T> {
+    /// Start a queue with `workers` threads and the default retry policy.
+    /// See `JobQueueBuilder::build`.
+    pub fn new<E, F>(workers: usize, handler: F) -> (JobQueue<T>, Receiver<Failed<T, E>>)
+    where
+        E: Send + 'static,
+        F: Fn(&T) -> Result<(), E> + Send + Sync + 'static,
+    {
+        JobQueueBuilder::new().workers(workers).build(handler)
+    }
+
+    /// Queue `job` for its first attempt
+    pub fn push(&self, job: T) {
+        let attempt = Attempt {
+            job,
+            attempt: 1,
+            _outstanding: self.outstanding.clone().unwrap(),
+        };
+        // The timer thread holds the receiving end until the queue is
+        // dropped, so this can't fail
+        let _ = self
+            .pending
+            .as_ref()
+            .unwrap()
+            .send((Instant::now(), attempt));
+    }
+}
+
+impl<T: Send> Drop for JobQueue<T> {
+    fn drop(&mut self) {
+        if let Some(outstanding) = self.outstanding.take() {
+            outstanding.wait();
+        }
+        self.shutdown.cancel();
+        self.pending.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for JobQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JobQueue")
+            .field("workers", &(self.threads.len() - 1))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::Error;
+    use std::sync::atomic::{AtomicU32, Ordering::*};
+
+    #[test]
+    fn retries_until_success() {
+        let (queue, failed) = JobQueueBuilder::new()
+            .workers(2)
+            .backoff(Duration::from_millis(1), Duration::from_millis(5))
+            .build(|tries: &Arc<AtomicU32>| {
+                if tries.fetch_add(1, SeqCst) < 2 {
+                    Err("not yet")
+                } else {
+                    Ok(())
+                }
+            });
+        let jobs = (0..4)
+            .map(|_| Arc::new(AtomicU32::new(0)))
+            .collect::<Vec<_>>();
+        for job in &jobs {
+            queue.push(job.clone());
+        }
+        drop(queue);
+        assert!(jobs.iter().all(|job| job.load(SeqCst) == 3));
+        assert_eq!(failed.try_recv().err(), Some(Error::Disconnected));
+    }
+
+    #[test]
+    fn gives_up() {
+        let (queue, failed) = JobQueueBuilder::new()
+            .max_attempts(3)
+            .backoff(Duration::from_millis(2), Duration::from_secs(1))
+            .build(|&job: &u32| Err(job * 10));
+        let start = Instant::now();
+        queue.push(7);
+        let failure = failed.recv().unwrap();
+        // Waited 2ms, then 4ms, between the three attempts
+        assert!(start.elapsed() >= Duration::from_millis(6));
+        assert_eq!((failure.job, failure.error, failure.attempts), (7, 70, 3));
+    }
+
+    #[test]
+    fn backoff() {
+        let builder =
+            JobQueueBuilder::new().backoff(Duration::from_millis(100), Duration::from_secs(1));
+        let delays = (1..6)
+            .map(|attempt| builder.delay(attempt))
+            .collect::<Vec<_>>();
+        let ms = Duration::from_millis;
+        assert_eq!(delays, vec![ms(100), ms(200), ms(400), ms(800), ms(1000)]);
+        assert_eq!(builder.delay(100), ms(1000));
+    }
+}
@@ -0,0 +1,135 @@
+//! Handles for collecting the result of a job.
+//!
+//! Each job gets a oneshot slot shared between its `JoinHandle` and a
+//! `Promise` that travels with the job. The promise fills the slot when
+//! the job returns, and fills it with an error if it is dropped first, so
+//! `join` never waits on a job that will not complete. Only the handle's
+//! owner ever waits on the slot, so it parks on a `Parker` of its own and
+//! the promise unparks it.
+
+use primitive::blocking::Mutex;
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use sync::{Parker, Unparker};
+
+struct Packet<T> {
+    result: Mutex<Option<thread::Result<T>>>,
+    done: Unparker,
+}
+
+/// An owned permission to join a job spawned on a `ThreadPool`
+pub struct JoinHandle<T> {
+    packet: Arc<Packet<T>>,
+    parker: Parker,
+}
+
+// The parker is only used by `join`, which takes the handle by value, so
+// sharing `&JoinHandle` can't lead to two threads parking on it
+unsafe impl<T: Send> Sync for JoinHandle<T> {}
+
+/// The sending half of a job's oneshot
+pub(crate) struct Promise<T> {
+    packet: Option<Arc<Packet<T>>>,
+}
+
+pub(crate) fn oneshot<T>() -> (Promise<T>, JoinHandle<T>) {
+    let parker = Parker::new();
+    let packet = Arc::new(Packet {
+        result: Mutex::new(None),
+        done: parker.unparker().clone(),
+    });
+    (
+        Promise {
+            packet: Some(packet.clone()),
+        },
+        JoinHandle { packet, parker },
+    )
+}
+
+impl<T> Packet<T> {
+    fn complete(&self, result: thread::Result<T>) {
+        *self.result.lock().unwrap() = Some(result);
+        self.done.unpark();
+    }
+}
+
+impl<T> Promise<T> {
+    pub(crate) fn complete(mut self, result: thread::Result<T>) {
+        self.packet.take().unwrap().complete(result);
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        if let Some(packet) = self.packet.take() {
+            let payload: Box<dyn Any + Send> = Box::new("job did not run to completion");
+            packet.complete(Err(payload));
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Block until the job finishes, returning its result. If the job
+    /// never ran to completion, the error holds the reason.
+    pub fn join(self) -> thread::Result<T> {
+        loop {
+            if let Some(result) = self.packet.result.lock().unwrap().take() {
+                return result;
+            }
+            // The token makes an unpark between the check and the park
+            // return straight away
+            self.parker.park();
+        }
+    }
+
+    /// Whether the job has finished, so that `join` won't block
+    pub fn is_finished(&self) -> bool {
+        self.packet.result.lock().unwrap().is_some()
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pool::ThreadPool;
+    use std::time::Duration;
+
+    #[test]
+    fn join_result() {
+        let pool = ThreadPool::new(2);
+        let handles = (0..8)
+            .map(|i| pool.spawn(move || i * 2))
+            .collect::<Vec<_>>();
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(results, (0..8).map(|i| i * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn is_finished() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.spawn(|| thread::sleep(Duration::from_millis(20)));
+        assert!(!handle.is_finished());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn dropped_promise() {
+        let (promise, handle) = oneshot::<()>();
+        drop(promise);
+        assert!(handle.is_finished());
+        assert!(handle.join().is_err());
+    }
+}
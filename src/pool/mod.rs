@@ -0,0 +1,714 @@
+//! Thread pools.
+//!
+//! `ThreadPool` is a pool of worker threads fed by an `mpmc` queue,
+//! `PriorityPool` runs the most urgent queued job first, and
+//! `WorkStealingPool` gives each worker its own deque for jobs that spawn
+//! more jobs. `ThreadPool::spawn_future` runs futures on the same workers
+//! as closures. `par_map` and `par_for_each` spread the items of an iterator
+//! over a pool of their own, `JobQueue` retries jobs that fail, and
+//! `Supervisor` restarts long-running worker threads that do.
+//!
+//! In a `ThreadPool`, jobs are boxed closures pushed onto a shared channel,
+//! and each worker loops on `recv` until the pool is dropped. Dropping the
+//! pool closes the channel, lets the workers drain whatever is still
+//! queued, and joins them. The pool can grow when jobs queue up faster
+//! than idle workers take them, and shrink again when workers sit idle.
+
+use mpmc::{self, Error, Receiver, Sender};
+use primitive::blocking::Mutex;
+use std::any::Any;
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::*};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use sync::OnceCell;
+
+mod affinity;
+mod deque;
+mod future;
+mod job_queue;
+pub(crate) mod join;
+mod par;
+mod priority;
+mod scope;
+mod stealing;
+mod supervisor;
+mod task_local;
+
+pub use self::affinity::{numa_node_cores, pin_current_thread, prefer_local_memory, Affinity};
+pub use self::deque::{Deque, Steal, Stealer};
+pub use self::job_queue::{Failed, JobQueue, JobQueueBuilder};
+pub use self::join::JoinHandle;
+pub use self::par::{par_for_each, par_map, ParMap};
+pub use self::priority::PriorityPool;
+pub use self::scope::Scope;
+pub use self::stealing::WorkStealingPool;
+pub use self::supervisor::{Escalated, Supervisor, SupervisorBuilder};
+pub use self::task_local::LocalKey;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+type PanicHandler = Arc<dyn Fn(&(dyn Any + Send)) + Send + Sync>;
+
+thread_local! {
+    /// Set by a job that caught a panic, so the worker running it can
+    /// count it
+    static JOB_PANICKED: Cell<bool> = const { Cell::new(false) };
+
+    /// Set by a job that polled a future without finishing it, so the
+    /// worker counts the future once, by the poll that finishes it
+    static JOB_UNFINISHED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// A snapshot of a `ThreadPool`'s counters. The counters are read one at a
+/// time while workers keep running, so they are only approximately
+/// consistent with each other.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Worker threads currently alive
+    pub threads: usize,
+    /// Workers waiting for a job
+    pub idle: usize,
+    /// Jobs waiting for a worker
+    pub queued: usize,
+    /// Jobs currently running
+    pub running: usize,
+    /// Jobs that have finished, including those that panicked. A future
+    /// counts once, when it finishes, however often it was polled.
+    pub completed: u64,
+    /// Jobs that panicked
+    pub panicked: u64,
+    /// The most jobs that have been queued at once
+    pub high_water: usize,
+}
+
+/// Configures a `ThreadPool` before spawning it
+#[derive(Clone)]
+pub struct Builder {
+    min_threads: usize,
+    max_threads: usize,
+    idle_timeout: Duration,
+    panic_handler: Option<PanicHandler>,
+    thread_name: String,
+    max_blocking_threads: usize,
+    affinity: Affinity,
+}
+
+impl Builder {
+    /// A fixed-size pool with one worker per available core
+    pub fn new() -> Builder {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Builder {
+            min_threads: threads,
+            max_threads: threads,
+            idle_timeout: Duration::from_secs(10),
+            panic_handler: None,
+            thread_name: "myriad-worker".into(),
+            max_blocking_threads: 512,
+            affinity: Affinity::Any,
+        }
+    }
+
+    /// Keep exactly `threads` workers
+    pub fn threads(self, threads: usize) -> Builder {
+        self.min_threads(threads).max_threads(threads)
+    }
+
+    /// Workers that are kept alive even when idle. The pool starts with
+    /// this many.
+    pub fn min_threads(mut self, threads: usize) -> Builder {
+        self.min_threads = threads;
+        self
+    }
+
+    /// The most workers the pool grows to when jobs queue up
+    pub fn max_threads(mut self, threads: usize) -> Builder {
+        self.max_threads = threads;
+        self
+    }
+
+    /// How long a worker above `min_threads` waits for a job before
+    /// exiting
+    pub fn idle_timeout(mut self, timeout: Duration) -> Builder {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Call `handler` with the payload of every panicking job, on the
+    /// worker that ran it, before the panic is handed to the job's
+    /// `JoinHandle`
+    pub fn panic_handler<F>(mut self, handler: F) -> Builder
+    where
+        F: Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Workers are named `name-N`. Defaults to `myriad-worker`.
+    pub fn thread_name(mut self, name: &str) -> Builder {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// The most threads the pool for `ThreadPool::spawn_blocking` grows
+    /// to. Defaults to 512.
+    pub fn max_blocking_threads(mut self, threads: usize) -> Builder {
+        self.max_blocking_threads = threads;
+        self
+    }
+
+    /// Pin workers to cores or NUMA nodes. Blocking workers are never
+    /// pinned. Defaults to `Affinity::Any`.
+    pub fn affinity(mut self, affinity: Affinity) -> Builder {
+        self.affinity = affinity;
+        self
+    }
+
+    /// Spawn the pool.
+    ///
+    /// Panics if `max_threads` is zero or less than `min_threads`, or if
+    /// `max_blocking_threads` is zero.
+    pub fn build(self) -> ThreadPool {
+        assert!(self.max_threads > 0, "ThreadPool needs at least one worker");
+        assert!(
+            self.min_threads <= self.max_threads,
+            "ThreadPool min_threads is larger than max_threads"
+        );
+        assert!(
+            self.max_blocking_threads > 0,
+            "ThreadPool needs at least one blocking worker"
+        );
+        // Blocking jobs get their own elastic pool, created on first use
+        let blocking = Builder {
+            min_threads: 0,
+            max_threads: self.max_blocking_threads,
+            thread_name: format!("{}-blocking", self.thread_name),
+            affinity: Affinity::Any,
+            ..self.clone()
+        };
+        let (sender, receiver) = mpmc::queue();
+        let shared = Arc::new(Shared {
+            receiver,
+            min_threads: self.min_threads,
+            max_threads: self.max_threads,
+            idle_timeout: self.idle_timeout,
+            panic_handler: self.panic_handler,
+            thread_name: self.thread_name,
+            affinity: self.affinity,
+            live: AtomicUsize::new(self.min_threads),
+            idle: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            high_water: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(0),
+            workers: Mutex::new(Vec::new()),
+            futures: Mutex::new(Some(sender.clone())),
+            tasks: Mutex::new(Vec::new()),
+        });
+        for _ in 0..self.min_threads {
+            shared.spawn_worker();
+        }
+        ThreadPool {
+            sender: Some(sender),
+            shared,
+            blocking: OnceCell::new(),
+            blocking_builder: blocking,
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("min_threads", &self.min_threads)
+            .field("max_threads", &self.max_threads)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("panic_handler", &self.panic_handler.is_some())
+            .field("thread_name", &self.thread_name)
+            .field("max_blocking_threads", &self.max_blocking_threads)
+            .field("affinity", &self.affinity)
+            .finish()
+    }
+}
+
+struct Shared {
+    receiver: Receiver<Job>,
+    min_threads: usize,
+    max_threads: usize,
+    idle_timeout: Duration,
+    panic_handler: Option<PanicHandler>,
+    thread_name: String,
+    affinity: Affinity,
+    /// Workers that are running or about to be spawned
+    live: AtomicUsize,
+    /// Workers waiting for a job
+    idle: AtomicUsize,
+    /// Jobs sent but not yet received by a worker
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    high_water: AtomicUsize,
+    next_id: AtomicUsize,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+    /// Lets woken futures queue their next poll, until the pool closes
+    futures: Mutex<Option<Sender<Job>>>,
+    /// Spawned futures, some possibly finished, to drop on close
+    tasks: Mutex<Vec<Weak<future::Task>>>,
+}
+
+impl Shared {
+    fn spawn_worker(self: &Arc<Shared>) {
+        let id = self.next_id.fetch_add(1, Relaxed);
+        let shared = self.clone();
+        let handle = thread::Builder::new()
+            .name(format!("{}-{}", self.thread_name, id))
+            .spawn(move || {
+                // Best-effort, an unpinned worker still does its job
+                let _ = shared.affinity.apply(id);
+                work(shared)
+            })
+            .expect("failed to spawn worker thread");
+        let mut workers = self.workers.lock().unwrap();
+        // Drop the handles of workers that have retired
+        workers.retain(|worker| !worker.is_finished());
+        workers.push(handle);
+    }
+
+    /// Add a worker unless the pool is already at `max_threads`
+    fn grow(self: &Arc<Shared>) {
+        let mut live = self.live.load(SeqCst);
+        while live < self.max_threads {
+            match self
+                .live
+                .compare_exchange_weak(live, live + 1, SeqCst, SeqCst)
+            {
+                Ok(_) => return self.spawn_worker(),
+                Err(current) => live = current,
+            }
+        }
+    }
+
+    fn execute(self: &Arc<Shared>, sender: &Sender<Job>, job: Job) {
+        let queued = self.queued.fetch_add(1, SeqCst) + 1;
+        if queued > self.high_water.load(Relaxed) {
+            self.high_water.fetch_max(queued, Relaxed);
+        }
+        // The pool holds a receiver, so this can't fail
+        let _ = sender.send(job);
+        if queued > self.idle.load(SeqCst) {
+            self.grow();
+        }
+    }
+
+    /// Claim permission for an idle worker to exit, unless the pool is
+    /// already down to `min_threads`
+    fn retire(&self) -> bool {
+        let mut live = self.live.load(SeqCst);
+        while live > self.min_threads {
+            match self
+                .live
+                .compare_exchange_weak(live, live - 1, SeqCst, SeqCst)
+            {
+                Ok(_) => return true,
+                Err(current) => live = current,
+            }
+        }
+        false
+    }
+}
+
+/// Wrap `f` in a job that runs with the caller's task-local values,
+/// catches its panic, and hands the result to the returned `JoinHandle`
+fn task<F, T>(f: F, panic_handler: Option<PanicHandler>) -> (Job, JoinHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (promise, handle) = join::oneshot();
+    let context = task_local::capture();
+    let job = Box::new(move || {
+        task_local::enter(context, || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            if let Err(payload) = &result {
+                JOB_PANICKED.with(|panicked| panicked.set(true));
+                if let Some(handler) = panic_handler {
+                    handler(&**payload);
+                }
+            }
+            promise.complete(result);
+        })
+    });
+    (job, handle)
+}
+
+fn work(shared: Arc<Shared>) {
+    // Jobs catch their own panics, but if anything else panics the worker
+    // goes down, so give up its slot for `grow` to refill
+    struct Exit<'a>(&'a Shared);
+    impl<'a> Drop for Exit<'a> {
+        fn drop(&mut self) {
+            if thread::panicking() {
+                self.0.live.fetch_sub(1, Relaxed);
+            }
+        }
+    }
+    let _exit = Exit(&shared);
+
+    let fixed = shared.min_threads == shared.max_threads;
+    loop {
+        shared.idle.fetch_add(1, SeqCst);
+        let job = if fixed {
+            shared.receiver.recv()
+        } else {
+            shared.receiver.recv_timeout(shared.idle_timeout)
+        };
+        shared.idle.fetch_sub(1, SeqCst);
+        match job {
+            Ok(job) => {
+                shared.queued.fetch_sub(1, SeqCst);
+                shared.running.fetch_add(1, Relaxed);
+                job();
+                shared.running.fetch_sub(1, Relaxed);
+                if JOB_UNFINISHED.with(|unfinished| unfinished.replace(false)) {
+                    continue;
+                }
+                if JOB_PANICKED.with(|panicked| panicked.replace(false)) {
+                    shared.panicked.fetch_add(1, Relaxed);
+                }
+                // Release, so whoever sees the job completed sees the
+                // other counters updated for it
+                shared.completed.fetch_add(1, Release);
+            }
+            Err(Error::Timeout) if shared.retire() => {
+                // A job sent while we were retiring may have counted on us
+                // to run it, or it saw us gone and grew the pool itself
+                if shared.queued.load(SeqCst) == 0 {
+                    return;
+                }
+                shared.live.fetch_add(1, SeqCst);
+            }
+            Err(Error::Timeout) => (),
+            // The pool was dropped and the queue is drained
+            Err(_) => return,
+        }
+    }
+}
+
+pub struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    shared: Arc<Shared>,
+    blocking: OnceCell<Box<ThreadPool>>,
+    blocking_builder: Builder,
+}
+
+impl ThreadPool {
+    /// Spawn a pool with a fixed number of workers.
+    ///
+    /// Panics if `threads` is zero.
+    pub fn new(threads: usize) -> ThreadPool {
+        Builder::new().threads(threads).build()
+    }
+
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Number of worker threads currently alive
+    pub fn threads(&self) -> usize {
+        self.shared.live.load(Relaxed)
+    }
+
+    /// Counters for the pool's own workers, not including the blocking
+    /// pool
+    pub fn stats(&self) -> Stats {
+        Stats {
+            threads: self.threads(),
+            idle: self.shared.idle.load(Relaxed),
+            queued: self.shared.queued.load(Relaxed),
+            running: self.shared.running.load(Relaxed),
+            completed: self.shared.completed.load(Acquire),
+            panicked: self.shared.panicked.load(Relaxed),
+            high_water: self.shared.high_water.load(Relaxed),
+        }
+    }
+
+    /// Run `f` on one of the workers, returning a handle to its result.
+    ///
+    /// If `f` panics, the worker survives and the panic is returned by
+    /// `JoinHandle::join`.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = task(f, self.shared.panic_handler.clone());
+        self.execute(job);
+        handle
+    }
+
+    /// Run `f` on a separate pool reserved for jobs that block on IO or
+    /// locks, so they don't tie up the workers that `spawn` uses.
+    ///
+    /// The blocking pool starts with no threads, adds one whenever a job
+    /// would otherwise wait, up to the builder's `max_blocking_threads`,
+    /// and lets them exit after `idle_timeout`.
+    pub fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.blocking
+            .get_or_init(|| Box::new(self.blocking_builder.clone().build()))
+            .spawn(f)
+    }
+
+    /// Stop accepting jobs and wait for the queued ones to run, giving
+    /// up at `deadline`. This includes jobs from `spawn_blocking`.
+    ///
+    /// Jobs still queued at the deadline are dropped without running, and
+    /// their `JoinHandle`s return an error. Workers still busy with a job
+    /// at that point are detached and exit once it returns. Returns the
+    /// number of jobs that were dropped.
+    pub fn shutdown(mut self, deadline: Instant) -> usize {
+        self.close(Some(deadline))
+    }
+
+    /// Close the queue and wait for the workers to drain it, either
+    /// indefinitely or until `deadline`
+    fn close(&mut self, deadline: Option<Instant>) -> usize {
+        drop(self.sender.take());
+        self.shared.futures.lock().unwrap().take();
+        let mut workers = self.shared.workers.lock().unwrap().split_off(0);
+        let mut abandoned = match deadline {
+            None => {
+                for worker in workers {
+                    // A worker that panicked has already reported it
+                    let _ = worker.join();
+                }
+                0
+            }
+            Some(deadline) => {
+                while !workers.is_empty() && Instant::now() < deadline {
+                    workers.retain(|worker| !worker.is_finished());
+                    thread::sleep(Duration::from_millis(1));
+                }
+                let mut abandoned = 0;
+                while let Ok(job) = self.shared.receiver.try_recv() {
+                    self.shared.queued.fetch_sub(1, SeqCst);
+                    drop(job);
+                    abandoned += 1;
+                }
+                abandoned
+            }
+        };
+        future::abandon(&self.shared);
+        if let Some(blocking) = self.blocking.get_mut() {
+            abandoned += blocking.close(deadline);
+        }
+        abandoned
+    }
+
+    fn execute(&self, job: Job) {
+        self.shared.execute(self.sender.as_ref().unwrap(), job);
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.close(None);
+    }
+}
+
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+    use sync::{Barrier, Gate};
+
+    #[test]
+    fn drop_runs_queued_jobs() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(2);
+        for _ in 0..100 {
+            let count = count.clone();
+            pool.spawn(move || {
+                count.fetch_add(1, SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(count.load(SeqCst), 100);
+    }
+
+    #[test]
+    fn worker_names() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpmc::queue();
+        pool.spawn(move || {
+            tx.send(thread::current().name().map(String::from)).unwrap();
+        });
+        assert_eq!(rx.recv(), Ok(Some("myriad-worker-0".into())));
+    }
+
+    #[test]
+    fn panic_is_captured() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let count = panics.clone();
+        let pool = ThreadPool::builder()
+            .threads(1)
+            .panic_handler(move |payload| {
+                assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+                count.fetch_add(1, SeqCst);
+            })
+            .build();
+        let payload = pool.spawn(|| panic!("boom")).join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+        assert_eq!(panics.load(SeqCst), 1);
+        // The only worker survived
+        assert_eq!(pool.spawn(|| 1).join().unwrap(), 1);
+        assert_eq!(pool.threads(), 1);
+    }
+
+    #[test]
+    fn stats() {
+        let pool = ThreadPool::new(1);
+        let gate = Arc::new(Gate::new(false));
+        let blocked = gate.clone();
+        let (started, running) = mpmc::queue();
+        let first = pool.spawn(move || {
+            started.send(()).unwrap();
+            blocked.pass();
+        });
+        running.recv().unwrap();
+        let rest = (0..3)
+            .map(|i| pool.spawn(move || assert!(i != 1)))
+            .collect::<Vec<_>>();
+        let stats = pool.stats();
+        assert_eq!((stats.threads, stats.idle), (1, 0));
+        assert_eq!((stats.queued, stats.running), (3, 1));
+
+        gate.open();
+        first.join().unwrap();
+        for handle in rest {
+            let _ = handle.join();
+        }
+        // The worker counts a job after its handle is completed
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().completed < 4 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let stats = pool.stats();
+        assert_eq!((stats.queued, stats.running), (0, 0));
+        assert_eq!((stats.completed, stats.panicked), (4, 1));
+        assert_eq!(stats.high_water, 3);
+    }
+
+    #[test]
+    fn shutdown_drains() {
+        let pool = ThreadPool::new(2);
+        let handles = (0..10).map(|i| pool.spawn(move || i)).collect::<Vec<_>>();
+        let abandoned = pool.shutdown(Instant::now() + Duration::from_secs(5));
+        assert_eq!(abandoned, 0);
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn shutdown_deadline() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpmc::queue();
+        let slow = pool.spawn(move || {
+            tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        });
+        let queued = (0..10).map(|_| pool.spawn(|| ())).collect::<Vec<_>>();
+        rx.recv().unwrap();
+        let abandoned = pool.shutdown(Instant::now() + Duration::from_millis(10));
+        assert_eq!(abandoned, 10);
+        for handle in queued {
+            assert!(handle.join().is_err());
+        }
+        // The running job was left to finish
+        slow.join().unwrap();
+    }
+
+    #[test]
+    fn spawn_blocking() {
+        let pool = ThreadPool::new(1);
+        let gate = Arc::new(Gate::new(false));
+        // Occupies the only compute worker until a blocking job opens the
+        // gate, which only works if blocking jobs run elsewhere
+        let blocked = gate.clone();
+        let compute = pool.spawn(move || blocked.pass());
+        let opener = pool.spawn_blocking(move || {
+            gate.open();
+            thread::current().name().map(String::from)
+        });
+        assert_eq!(
+            opener.join().unwrap(),
+            Some("myriad-worker-blocking-0".into())
+        );
+        compute.join().unwrap();
+        assert_eq!(pool.threads(), 1);
+    }
+
+    #[test]
+    fn grow_under_load() {
+        let pool = ThreadPool::builder().min_threads(0).max_threads(4).build();
+        assert_eq!(pool.threads(), 0);
+        // Every job blocks until all four run at once, so this only
+        // finishes if the pool grows to four workers
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            pool.spawn(move || {
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        assert_eq!(pool.threads(), 4);
+    }
+
+    #[test]
+    fn shrink_when_idle() {
+        let pool = ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(4)
+            .idle_timeout(Duration::from_millis(10))
+            .build();
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            pool.spawn(move || {
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.threads() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.threads(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+//! Parallel iterator adapters on top of `ThreadPool`.
+
+use super::{JoinHandle, ThreadPool};
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic;
+use std::sync::Arc;
+use sync::Semaphore;
+
+/// How many items per thread are in flight at once. More than one keeps
+/// workers busy while the consumer catches up.
+const ITEMS_PER_THREAD: usize = 2;
+
+/// An iterator over the results of `f` applied to each item of `iter` on
+/// a pool of `threads` workers, in the order of the items.
+///
+/// Items are taken from `iter` a few at a time as results are consumed,
+/// rather than all up front. If `f` panics, the panic resumes on the
+/// consuming thread when that item's result is reached.
+///
+/// Panics if `threads` is zero.
+pub fn par_map<I, F, R>(iter: I, threads: usize, f: F) -> ParMap<I::IntoIter, R>
+where
+    I: IntoIterator,
+    I::Item: Send + 'static,
+    F: Fn(I::Item) -> R + Send + Sync + 'static,
+    R: Send + 'static,
+{
+    assert!(threads > 0, "par_map needs at least one thread");
+    ParMap {
+        iter: iter.into_iter(),
+        f: Arc::new(f),
+        pending: VecDeque::new(),
+        window: threads * ITEMS_PER_THREAD,
+        pool: ThreadPool::new(threads),
+    }
+}
+
+/// Call `f` on each item of `iter` on `threads` scoped workers, returning
+/// once every call has. Unlike `par_map`, neither `f` nor the items need
+/// to be `'static`, and items are processed in no particular order.
+///
+/// Panics if `threads` is zero, and resumes the first panic from `f`.
+pub fn par_for_each<I, F>(iter: I, threads: usize, f: F)
+where
+    I: IntoIterator,
+    I::Item: Send,
+    F: Fn(I::Item) + Sync,
+{
+    assert!(threads > 0, "par_for_each needs at least one thread");
+    let pool = ThreadPool::new(threads);
+    let slots = Semaphore::new(threads * ITEMS_PER_THREAD);
+    let f = &f;
+    pool.scope(|s| {
+        for item in iter {
+            let permit = slots.acquire();
+            s.spawn(move || {
+                let _permit = permit;
+                f(item)
+            });
+        }
+    });
+}
+
+/// The iterator returned by `par_map`
+pub struct ParMap<I: Iterator, R> {
+    iter: I,
+    f: Arc<dyn Fn(I::Item) -> R + Send + Sync>,
+    /// Results in the order they will be yielded
+    pending: VecDeque<JoinHandle<R>>,
+    window: usize,
+    pool: ThreadPool,
+}
+
+impl<I, R> Iterator for ParMap<I, R>
+where
+    I: Iterator,
+    I::Item: Send + 'static,
+    R: Send + 'static,
+{
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        while self.pending.len() < self.window {
+            match self.iter.next() {
+                Some(item) => {
+                    let f = self.f.clone();
+                    self.pending.push_back(self.pool.spawn(move || f(item)));
+                }
+                None => break,
+            }
+        }
+        let handle = self.pending.pop_front()?;
+        match handle.join() {
+            Ok(result) => Some(result),
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+impl<I: Iterator, R> fmt::Debug for ParMap<I, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParMap")
+            .field("pending", &self.pending.len())
+            .field("threads", &self.pool.threads())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn ordered() {
+        let results = par_map(0..100u64, 4, |x| {
+            thread::sleep(Duration::from_micros(100 - x));
+            x * x
+        });
+        assert_eq!(
+            results.collect::<Vec<_>>(),
+            (0..100).map(|x| x * x).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn lazy() {
+        let taken = Arc::new(AtomicUsize::new(0));
+        let count = taken.clone();
+        let items = (0..).inspect(move |_| {
+            count.fetch_add(1, SeqCst);
+        });
+        let first = par_map(items, 2, |x: u32| x + 1)
+            .take(3)
+            .collect::<Vec<_>>();
+        assert_eq!(first, vec![1, 2, 3]);
+        assert!(taken.load(SeqCst) <= 3 + 2 * ITEMS_PER_THREAD);
+    }
+
+    #[test]
+    fn for_each_borrows() {
+        let items = (0..1000).collect::<Vec<usize>>();
+        let sum = AtomicUsize::new(0);
+        par_for_each(&items, 3, |&x| {
+            sum.fetch_add(x, SeqCst);
+        });
+        assert_eq!(sum.load(SeqCst), 999 * 1000 / 2);
+    }
+}
@@ -0,0 +1,174 @@
+//! A pool that runs queued jobs in priority order.
+//!
+//! Jobs wait in an `mpmc::priority` channel, so whenever a worker frees up
+//! it takes the highest priority job queued, and jobs of equal priority
+//! run in the order they were spawned. Latency-critical jobs can share
+//! workers with batch work without queueing behind it. Jobs that are
+//! already running are never preempted.
+
+use super::{task, Job, JoinHandle};
+use mpmc::{self, Receiver, Sender};
+use std::cmp::{Ordering, Reverse};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering::*};
+use std::thread;
+
+struct Prioritized {
+    priority: u32,
+    seq: u64,
+    job: Job,
+}
+
+impl Prioritized {
+    /// Higher priorities first, then earlier spawns
+    fn key(&self) -> (u32, Reverse<u64>) {
+        (self.priority, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Prioritized {
+    fn eq(&self, other: &Prioritized) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Prioritized {}
+
+impl PartialOrd for Prioritized {
+    fn partial_cmp(&self, other: &Prioritized) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Prioritized {
+    fn cmp(&self, other: &Prioritized) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+pub struct PriorityPool {
+    sender: Option<Sender<Prioritized>>,
+    seq: AtomicU64,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl PriorityPool {
+    /// Spawn a pool with `threads` workers.
+    ///
+    /// Panics if `threads` is zero.
+    pub fn new(threads: usize) -> PriorityPool {
+        assert!(threads > 0, "PriorityPool needs at least one worker");
+        let (sender, receiver) = mpmc::priority();
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("myriad-priority-{}", i))
+                    .spawn(move || work(receiver))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        PriorityPool {
+            sender: Some(sender),
+            seq: AtomicU64::new(0),
+            workers,
+        }
+    }
+
+    /// Number of worker threads
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Run `f` on one of the workers once no job with a higher priority is
+    /// queued. If `f` panics, the worker survives and the panic is
+    /// returned by `JoinHandle::join`.
+    pub fn spawn<F, T>(&self, priority: u32, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = task(f, None);
+        let job = Prioritized {
+            priority,
+            seq: self.seq.fetch_add(1, Relaxed),
+            job,
+        };
+        // Workers only exit once the sender is dropped, so this can't fail
+        let _ = self.sender.as_ref().unwrap().send(job);
+        handle
+    }
+}
+
+fn work(receiver: Receiver<Prioritized>) {
+    while let Ok(job) = receiver.recv() {
+        (job.job)();
+    }
+}
+
+impl Drop for PriorityPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl fmt::Debug for PriorityPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PriorityPool")
+            .field("threads", &self.threads())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use sync::Gate;
+
+    #[test]
+    fn priority_order() {
+        let pool = PriorityPool::new(1);
+        // Hold the only worker until everything is queued
+        let gate = Arc::new(Gate::new(false));
+        let blocker = gate.clone();
+        let (started, running) = mpmc::queue();
+        pool.spawn(0, move || {
+            started.send(()).unwrap();
+            blocker.pass();
+        });
+        running.recv().unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let handles = [
+            (1, "batch a"),
+            (5, "urgent a"),
+            (1, "batch b"),
+            (5, "urgent b"),
+        ]
+        .iter()
+        .map(|&(priority, name)| {
+            let order = order.clone();
+            pool.spawn(priority, move || order.lock().unwrap().push(name))
+        })
+        .collect::<Vec<_>>();
+        gate.open();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["urgent a", "urgent b", "batch a", "batch b"]
+        );
+    }
+
+    #[test]
+    fn panic_is_captured() {
+        let pool = PriorityPool::new(1);
+        assert!(pool.spawn(0, || panic!("boom")).join().is_err());
+        assert_eq!(pool.spawn(0, || 1).join().unwrap(), 1);
+    }
+}
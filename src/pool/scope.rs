@@ -0,0 +1,159 @@
+//! Jobs that may borrow from the stack of the thread that spawned them.
+//!
+//! `ThreadPool::scope` doesn't return until every job spawned in the scope
+//! has finished, even if the scope closure or one of the jobs panics, so
+//! jobs can safely hold references to anything that outlives the call.
+
+use super::{task_local, Job, ThreadPool, JOB_PANICKED};
+use primitive::blocking::Mutex;
+use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use sync::WaitGroup;
+
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    pending: &'scope WaitGroup,
+    /// The first panic raised by a job, resumed when the scope ends
+    panic: &'scope Mutex<Option<Box<dyn Any + Send>>>,
+    // Invariant in both lifetimes, like `std::thread::Scope`
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl ThreadPool {
+    /// Create a scope for spawning jobs that borrow non-`'static` data.
+    ///
+    /// Returns once `f` and every job spawned in the scope have finished.
+    /// If any of them panicked, the panic is resumed on the caller.
+    ///
+    /// Jobs run on the pool's workers, so calling `scope` from inside a job
+    /// can deadlock if every worker ends up waiting on a scope.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        // Kept outside the scope, which stays borrowed until its last use
+        let pending = WaitGroup::new();
+        let job_panic = Mutex::new(None);
+        let scope = Scope {
+            pool: self,
+            pending: &pending,
+            panic: &job_panic,
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        pending.wait();
+
+        let job_panic = job_panic.into_inner().unwrap_or_else(|e| e.into_inner());
+        match (ret, job_panic) {
+            (Err(payload), _) | (Ok(_), Some(payload)) => panic::resume_unwind(payload),
+            (Ok(ret), None) => ret,
+        }
+    }
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Run `f` on one of the pool's workers. `f` may borrow anything that
+    /// outlives the scope, including the scope itself for nested spawns.
+    pub fn spawn<F: FnOnce() + Send + 'scope>(&'scope self, f: F) {
+        let pending = self.pending.clone();
+        let context = task_local::capture();
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let result = task_local::enter(context, || panic::catch_unwind(AssertUnwindSafe(f)));
+            if let Err(payload) = result {
+                // Counted in the pool's stats, like a spawned job's panic
+                JOB_PANICKED.with(|panicked| panicked.set(true));
+                let mut panic = self.panic.lock().unwrap_or_else(|e| e.into_inner());
+                panic.get_or_insert(payload);
+            }
+            // Last, since the scope may end as soon as this is dropped
+            drop(pending);
+        });
+        // The scope waits for `pending` before any `'scope` borrow expires
+        let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.execute(job);
+    }
+}
+
+impl<'scope, 'env> fmt::Debug for Scope<'scope, 'env> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn borrow_stack() {
+        let pool = ThreadPool::new(4);
+        let data = (0..100).collect::<Vec<usize>>();
+        let sum = AtomicUsize::new(0);
+        pool.scope(|s| {
+            for chunk in data.chunks(10) {
+                let sum = &sum;
+                s.spawn(move || {
+                    sum.fetch_add(chunk.iter().sum(), SeqCst);
+                });
+            }
+        });
+        assert_eq!(sum.load(SeqCst), 4950);
+    }
+
+    #[test]
+    fn nested_spawn() {
+        let pool = ThreadPool::new(2);
+        let count = AtomicUsize::new(0);
+        let ret = pool.scope(|s| {
+            let count = &count;
+            s.spawn(move || {
+                count.fetch_add(1, SeqCst);
+                s.spawn(move || {
+                    count.fetch_add(1, SeqCst);
+                });
+            });
+            "done"
+        });
+        assert_eq!(ret, "done");
+        assert_eq!(count.load(SeqCst), 2);
+    }
+
+    #[test]
+    fn job_panic_is_resumed() {
+        let pool = ThreadPool::new(2);
+        let count = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.spawn(|| panic!("job failed"));
+                s.spawn(|| {
+                    count.fetch_add(1, SeqCst);
+                });
+            })
+        }));
+        assert!(result.is_err());
+        // The other job still ran, and the workers survived
+        assert_eq!(count.load(SeqCst), 1);
+        pool.scope(|s| {
+            s.spawn(|| {
+                count.fetch_add(1, SeqCst);
+            })
+        });
+        assert_eq!(count.load(SeqCst), 2);
+        // The worker counts a job just after the scope sees it finish
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.stats().completed < 3 && Instant::now() < deadline {
+            std::thread::yield_now();
+        }
+        let stats = pool.stats();
+        assert_eq!((stats.completed, stats.panicked), (3, 1));
+    }
+}
@@ -0,0 +1,289 @@
+//! A work-stealing executor.
+//!
+//! Each worker owns a `Deque`. Jobs spawned from inside a worker go onto
+//! its own deque, and everything else, including overflow from a full
+//! deque, goes onto a shared injector queue. A worker looks for work in its
+//! own deque first, then the injector, then steals from its siblings,
+//! so recursive fork-join style jobs mostly stay on the thread that created
+//! them while idle workers still balance the load.
+
+use super::deque::{Deque, Steal, Stealer};
+use super::{task_local, Job};
+use mpmc::{self, BlockStrategy, Receiver, Sender, SpinThenPark};
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering::*};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Jobs beyond this many on a worker's own deque go to the injector
+/// instead, where any worker can pick them up without stealing
+const LOCAL_CAPACITY: usize = 256;
+
+struct Shared {
+    injector: Sender<Job>,
+    injected: Receiver<Job>,
+    stealers: Vec<Stealer<Job>>,
+    /// Idle workers wait here for new jobs
+    idle: SpinThenPark,
+    shutdown: AtomicBool,
+}
+
+/// Identifies the pool and deque of the worker running on this thread
+#[derive(Clone, Copy)]
+struct Context {
+    shared: *const Shared,
+    deque: *const Deque<Job>,
+}
+
+thread_local! {
+    static CONTEXT: Cell<Option<Context>> = const { Cell::new(None) };
+}
+
+/// Dropping the pool waits for every queued job to run, so it must not be
+/// dropped from inside one of its own jobs.
+pub struct WorkStealingPool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl WorkStealingPool {
+    /// Spawn a pool with `threads` workers.
+    ///
+    /// Panics if `threads` is zero.
+    pub fn new(threads: usize) -> WorkStealingPool {
+        assert!(threads > 0, "WorkStealingPool needs at least one worker");
+        let deques = (0..threads).map(|_| Deque::new()).collect::<Vec<_>>();
+        let (injector, injected) = mpmc::queue();
+        let shared = Arc::new(Shared {
+            injector,
+            injected,
+            stealers: deques.iter().map(Deque::stealer).collect(),
+            idle: SpinThenPark::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let workers = deques
+            .into_iter()
+            .enumerate()
+            .map(|(index, deque)| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("myriad-stealer-{}", index))
+                    .spawn(move || work(&shared, index, deque))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        WorkStealingPool { shared, workers }
+    }
+
+    /// Number of worker threads
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Run `f` on one of the workers. When called from a job running on
+    /// this pool, `f` is queued on the current worker's own deque. If `f`
+    /// panics, the panic hook reports it and the worker carries on.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let context = task_local::capture();
+        let job: Job = Box::new(move || task_local::enter(context, f));
+        let shared = &*self.shared as *const Shared;
+        let local = CONTEXT.with(|context| match context.get() {
+            Some(context) if context.shared == shared => Some(context.deque),
+            _ => None,
+        });
+        match local {
+            Some(deque) if unsafe { (*deque).len() } < LOCAL_CAPACITY => unsafe {
+                (*deque).push(job)
+            },
+            _ => {
+                // Workers hold the receiving end for as long as the pool
+                // is alive, so this can't fail
+                let _ = self.shared.injector.send(job);
+            }
+        }
+        self.shared.idle.notify_one();
+    }
+}
+
+impl Shared {
+    fn find_job(&self, index: usize, deque: &Deque<Job>) -> Option<Job> {
+        if let Some(job) = deque.pop() {
+            return Some(job);
+        }
+        if let Ok(job) = self.injected.try_recv() {
+            return Some(job);
+        }
+        // Start with the next sibling so thieves spread out over victims
+        loop {
+            let mut retry = false;
+            for offset in 1..self.stealers.len() {
+                let victim = (index + offset) % self.stealers.len();
+                match self.stealers[victim].steal() {
+                    Steal::Success(job) => return Some(job),
+                    Steal::Retry => retry = true,
+                    Steal::Empty => (),
+                }
+            }
+            if !retry {
+                return None;
+            }
+        }
+    }
+}
+
+fn work(shared: &Shared, index: usize, deque: Deque<Job>) {
+    CONTEXT.with(|context| {
+        context.set(Some(Context {
+            shared,
+            deque: &deque,
+        }))
+    });
+    // The CONTEXT pointers must not outlive the deque, even if the worker
+    // goes down some other way than a job panicking
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CONTEXT.with(|context| context.set(None));
+        }
+    }
+    let _reset = Reset;
+
+    loop {
+        let mut job = None;
+        shared.idle.wait(
+            &mut || {
+                job = shared.find_job(index, &deque);
+                job.is_some() || shared.shutdown.load(Acquire)
+            },
+            None,
+        );
+        match job {
+            // A panicking job has already been reported by the panic hook.
+            // Catching it keeps the worker, and the jobs on its deque,
+            // from going down with it.
+            Some(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            // Shutting down and nothing left to run
+            None => return,
+        }
+    }
+}
+
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Release);
+        self.shared.idle.notify_all();
+        for worker in self.workers.drain(..) {
+            // A worker that panicked has already reported it
+            let _ = worker.join();
+        }
+    }
+}
+
+impl fmt::Debug for WorkStealingPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WorkStealingPool")
+            .field("threads", &self.threads())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    #[test]
+    fn drop_runs_queued_jobs() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pool = WorkStealingPool::new(4);
+        for _ in 0..1000 {
+            let count = count.clone();
+            pool.spawn(move || {
+                count.fetch_add(1, SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(count.load(SeqCst), 1000);
+    }
+
+    /// Drop `pool` from this thread, once jobs holding a clone are gone
+    fn join(pool: Arc<WorkStealingPool>) {
+        while Arc::strong_count(&pool) > 1 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn nested_jobs_are_stolen() {
+        let pool = Arc::new(WorkStealingPool::new(4));
+        let (tx, rx) = mpmc::queue();
+        let inner = pool.clone();
+        pool.spawn(move || {
+            // Queued on this worker's deque, but this worker is busy until
+            // they have all run, so the siblings must steal them
+            let done = Arc::new(AtomicUsize::new(0));
+            for _ in 0..8 {
+                let tx = tx.clone();
+                let done = done.clone();
+                inner.spawn(move || {
+                    tx.send(thread::current().id()).unwrap();
+                    done.fetch_add(1, SeqCst);
+                });
+            }
+            while done.load(SeqCst) < 8 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            tx.send(thread::current().id()).unwrap();
+        });
+        let ids = (0..9).map(|_| rx.recv().unwrap()).collect::<Vec<_>>();
+        assert!(!ids[..8].contains(&ids[8]));
+        join(pool);
+    }
+
+    #[test]
+    fn panic_keeps_siblings() {
+        let pool = Arc::new(WorkStealingPool::new(1));
+        let count = Arc::new(AtomicUsize::new(0));
+        let inner = pool.clone();
+        let total = count.clone();
+        pool.spawn(move || {
+            // Queued on the deque of the worker that is about to panic
+            for _ in 0..8 {
+                let count = total.clone();
+                inner.spawn(move || {
+                    count.fetch_add(1, SeqCst);
+                });
+            }
+            drop(inner);
+            panic!("job failed");
+        });
+        join(pool);
+        assert_eq!(count.load(SeqCst), 8);
+    }
+
+    #[test]
+    fn overflow_to_injector() {
+        let pool = Arc::new(WorkStealingPool::new(1));
+        let count = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpmc::queue();
+        let inner = pool.clone();
+        let total = count.clone();
+        pool.spawn(move || {
+            for _ in 0..LOCAL_CAPACITY * 2 {
+                let count = total.clone();
+                inner.spawn(move || {
+                    count.fetch_add(1, SeqCst);
+                });
+            }
+            tx.send(inner.shared.injector.size_hint()).unwrap();
+        });
+        assert_eq!(rx.recv(), Ok(LOCAL_CAPACITY));
+        join(pool);
+        assert_eq!(count.load(SeqCst), LOCAL_CAPACITY * 2);
+    }
+}
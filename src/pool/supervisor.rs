@@ -0,0 +1,283 @@
+//! Supervised worker threads, restarted when they fail.
+
+use primitive::blocking::Mutex;
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use sync::CancellationToken;
+
+type EscalationHandler = Box<dyn Fn(&Escalated) + Send + Sync>;
+
+/// Why a supervisor gave up: one of its children needed restarting more
+/// often than the restart limit allows
+#[derive(Clone, Debug, PartialEq)]
+pub struct Escalated {
+    /// The name the child was spawned with
+    pub child: String,
+    /// How many times it was restarted within the period
+    pub restarts: usize,
+}
+
+impl fmt::Display for Escalated {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "child {} restarted {} times, exceeding the restart limit",
+            self.child, self.restarts
+        )
+    }
+}
+
+/// Configures a `Supervisor`
+pub struct SupervisorBuilder {
+    max_restarts: usize,
+    period: Duration,
+    on_escalate: Option<EscalationHandler>,
+}
+
+impl SupervisorBuilder {
+    pub fn new() -> SupervisorBuilder {
+        SupervisorBuilder {
+            max_restarts: 3,
+            period: Duration::from_secs(5),
+            on_escalate: None,
+        }
+    }
+
+    /// Escalate once a child has been restarted more than `restarts` times
+    /// within `period`. Defaults to 3 restarts in 5 seconds.
+    pub fn restart_limit(mut self, restarts: usize, period: Duration) -> SupervisorBuilder {
+        self.max_restarts = restarts;
+        self.period = period;
+        self
+    }
+
+    /// Call `handler` when a child exceeds the restart limit, just before
+    /// the supervisor shuts every child down
+    pub fn on_escalate<F>(mut self, handler: F) -> SupervisorBuilder
+    where
+        F: Fn(&Escalated) + Send + Sync + 'static,
+    {
+        self.on_escalate = Some(Box::new(handler));
+        self
+    }
+
+    pub fn build(self) -> Supervisor {
+        Supervisor {
+            shared: Arc::new(Shared {
+                config: self,
+                token: CancellationToken::new(),
+                escalated: Mutex::new(None),
+            }),
+            children: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl Default for SupervisorBuilder {
+    fn default() -> SupervisorBuilder {
+        SupervisorBuilder::new()
+    }
+}
+
+impl fmt::Debug for SupervisorBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SupervisorBuilder")
+            .field("max_restarts", &self.max_restarts)
+            .field("period", &self.period)
+            .finish()
+    }
+}
+
+struct Shared {
+    config: SupervisorBuilder,
+    /// Cancelled to shut every child down
+    token: CancellationToken,
+    escalated: Mutex<Option<Escalated>>,
+}
+
+/// Owns a set of long-running worker threads, typically each looping on a
+/// channel, and restarts any that panic or return.
+///
+/// Each child is passed the supervisor's `CancellationToken` and should
+/// return soon after it is cancelled, for instance by receiving with
+/// `recv_cancellable`. A child that fails more often than the restart
+/// limit allows escalates: the supervisor cancels the token, shutting
+/// every child down, and `wait` reports the failure.
+pub struct Supervisor {
+    shared: Arc<Shared>,
+    children: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Supervisor {
+    /// A supervisor with the default restart limit
+    pub fn new() -> Supervisor {
+        SupervisorBuilder::new().build()
+    }
+
+    pub fn builder() -> SupervisorBuilder {
+        SupervisorBuilder::new()
+    }
+
+    /// Run `f` on a thread of its own, running it again whenever it panics
+    /// or returns until the supervisor shuts down
+    pub fn spawn<F>(&self, name: &str, f: F)
+    where
+        F: Fn(&CancellationToken) + Send + 'static,
+    {
+        let shared = self.shared.clone();
+        let child = name.to_string();
+        let handle = thread::Builder::new()
+            .name(child.clone())
+            .spawn(move || shared.supervise(child, f))
+            .expect("failed to spawn supervised thread");
+        self.children.lock().unwrap().push(handle);
+    }
+
+    /// The token children are shut down with. Cancelling it shuts the
+    /// supervisor down.
+    pub fn token(&self) -> CancellationToken {
+        self.shared.token.clone()
+    }
+
+    /// Block until the supervisor shuts down, either through its token or
+    /// because a child escalated, and every child has returned
+    pub fn wait(self) -> Result<(), Escalated> {
+        self.shared.token.wait_cancelled();
+        self.join()
+    }
+
+    /// Shut every child down and wait for them to return
+    pub fn shutdown(self) -> Result<(), Escalated> {
+        self.shared.token.cancel();
+        self.join()
+    }
+
+    fn join(&self) -> Result<(), Escalated> {
+        for child in self.children.lock().unwrap().drain(..) {
+            // Children run in catch_unwind, so only a panicking escalation
+            // handler gets here
+            let _ = child.join();
+        }
+        match self.shared.escalated.lock().unwrap().clone() {
+            Some(escalated) => Err(escalated),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Shared {
+    fn supervise<F: Fn(&CancellationToken)>(&self, child: String, f: F) {
+        let mut restarts = VecDeque::new();
+        loop {
+            // Panics that reach here have been reported by the panic hook
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| f(&self.token)));
+            if self.token.is_cancelled() {
+                return;
+            }
+            let now = Instant::now();
+            while restarts
+                .front()
+                .is_some_and(|&restart| now.duration_since(restart) > self.config.period)
+            {
+                restarts.pop_front();
+            }
+            restarts.push_back(now);
+            if restarts.len() > self.config.max_restarts {
+                let escalated = Escalated {
+                    child,
+                    restarts: restarts.len(),
+                };
+                if let Some(handler) = &self.config.on_escalate {
+                    handler(&escalated);
+                }
+                let mut first = self.escalated.lock().unwrap();
+                first.get_or_insert(escalated);
+                self.token.cancel();
+                return;
+            }
+        }
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Supervisor {
+        Supervisor::new()
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.shared.token.cancel();
+        let _ = self.join();
+    }
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("children", &self.children.lock().unwrap().len())
+            .field("cancelled", &self.shared.token.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc::{self, Error};
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+
+    #[test]
+    fn restarts_failed_children() {
+        let supervisor = Supervisor::new();
+        let (tx, rx) = mpmc::queue();
+        let (results, received) = mpmc::queue();
+        supervisor.spawn("doubler", move |token| {
+            // Dies on every odd message, losing it
+            while let Ok(x) = rx.recv_cancellable(token) {
+                assert!(x % 2 == 0, "odd message");
+                results.send(x * 2).unwrap();
+            }
+        });
+        for x in [1, 2, 3, 4] {
+            tx.send(x).unwrap();
+        }
+        assert_eq!(received.recv(), Ok(4));
+        assert_eq!(received.recv(), Ok(8));
+        assert_eq!(supervisor.shutdown(), Ok(()));
+        assert_eq!(received.recv(), Err(Error::Disconnected));
+    }
+
+    #[test]
+    fn escalates() {
+        let escalations = Arc::new(AtomicUsize::new(0));
+        let count = escalations.clone();
+        let supervisor = Supervisor::builder()
+            .restart_limit(2, Duration::from_secs(60))
+            .on_escalate(move |_| {
+                count.fetch_add(1, SeqCst);
+            })
+            .build();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let count = runs.clone();
+        // Returning counts as a failure just like panicking does
+        supervisor.spawn("quitter", move |_| {
+            count.fetch_add(1, SeqCst);
+        });
+        supervisor.spawn("steady", |token| token.wait_cancelled());
+        let escalated = supervisor.wait().unwrap_err();
+        assert_eq!(
+            escalated,
+            Escalated {
+                child: "quitter".into(),
+                restarts: 3,
+            }
+        );
+        assert_eq!(runs.load(SeqCst), 3);
+        assert_eq!(escalations.load(SeqCst), 1);
+    }
+}
@@ -0,0 +1,199 @@
+//! Values scoped to a task rather than a thread.
+//!
+//! A `task_local!` value is set for the duration of a closure with
+//! `LocalKey::scope`. Jobs spawned on one of this crate's pools while a
+//! value is set see the same value when they run, even though they run on
+//! a worker thread, so per-request context like request IDs or tracing
+//! spans follows work across the queue.
+//!
+//! ```
+//! # #[macro_use] extern crate myriad;
+//! # fn main() {
+//! use myriad::pool::ThreadPool;
+//!
+//! task_local! {
+//!     static REQUEST_ID: u64;
+//! }
+//!
+//! let pool = ThreadPool::new(2);
+//! let handle = REQUEST_ID.scope(7, || pool.spawn(|| REQUEST_ID.get()));
+//! assert_eq!(handle.join().unwrap(), 7);
+//! # }
+//! ```
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+type Value = Arc<dyn Any + Send + Sync>;
+
+/// Every task-local value set on a thread. Kept immutable behind an `Arc`
+/// so that capturing it for a spawned job is a refcount bump.
+type Values = Option<Arc<Vec<(usize, Value)>>>;
+
+thread_local! {
+    static CURRENT: RefCell<Values> = const { RefCell::new(None) };
+}
+
+/// Declare task-local values, accessed through a `LocalKey`
+#[macro_export]
+macro_rules! task_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty;)+) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::pool::LocalKey<$t> = $crate::pool::LocalKey::new();
+        )+
+    };
+}
+
+/// A key for a task-local value, declared with `task_local!`
+pub struct LocalKey<T> {
+    // Gives each static its own address, which identifies the key
+    _unique: u8,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// The task-local values of a spawning thread, captured for a job
+#[derive(Clone)]
+pub(crate) struct Snapshot(Values);
+
+/// Capture the current thread's values, to be reinstated with `enter`
+pub(crate) fn capture() -> Snapshot {
+    Snapshot(CURRENT.with(|current| current.borrow().clone()))
+}
+
+/// Run `f` with the thread's task-local values replaced by `snapshot`
+pub(crate) fn enter<R, F: FnOnce() -> R>(snapshot: Snapshot, f: F) -> R {
+    struct Restore(Values);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let values = self.0.take();
+            CURRENT.with(|current| *current.borrow_mut() = values);
+        }
+    }
+
+    let previous = CURRENT.with(|current| current.replace(snapshot.0));
+    let _restore = Restore(previous);
+    f()
+}
+
+impl<T: Send + Sync + 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new() -> LocalKey<T> {
+        LocalKey {
+            _unique: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    fn id(&'static self) -> usize {
+        self as *const LocalKey<T> as usize
+    }
+
+    /// Set the value to `value` while `f` runs, for this thread and for
+    /// jobs spawned from it
+    pub fn scope<R, F: FnOnce() -> R>(&'static self, value: T, f: F) -> R {
+        let id = self.id();
+        let mut values = CURRENT.with(|current| {
+            current
+                .borrow()
+                .as_ref()
+                .map_or_else(Vec::new, |values| (**values).clone())
+        });
+        values.retain(|&(key, _)| key != id);
+        values.push((id, Arc::new(value)));
+        enter(Snapshot(Some(Arc::new(values))), f)
+    }
+
+    /// Call `f` with the current value, or `None` if it isn't set
+    pub fn try_with<R, F: FnOnce(Option<&T>) -> R>(&'static self, f: F) -> R {
+        let id = self.id();
+        // Clone the value out so `f` may call `scope` without a borrow
+        // conflict
+        let value = CURRENT.with(|current| {
+            current.borrow().as_ref().and_then(|values| {
+                values
+                    .iter()
+                    .find(|&&(key, _)| key == id)
+                    .map(|(_, value)| value.clone())
+            })
+        });
+        f(value.as_ref().and_then(|value| value.downcast_ref::<T>()))
+    }
+
+    /// Call `f` with the current value.
+    ///
+    /// Panics if the value isn't set.
+    pub fn with<R, F: FnOnce(&T) -> R>(&'static self, f: F) -> R {
+        self.try_with(|value| f(value.expect("task-local value is not set")))
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> LocalKey<T> {
+    /// A copy of the current value.
+    ///
+    /// Panics if the value isn't set.
+    pub fn get(&'static self) -> T {
+        self.with(T::clone)
+    }
+}
+
+impl<T> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("LocalKey { .. }")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pool::{ThreadPool, WorkStealingPool};
+
+    task_local! {
+        static ID: u32;
+        static NAME: String;
+    }
+
+    #[test]
+    fn scope_nesting() {
+        assert_eq!(ID.try_with(|id| id.cloned()), None);
+        ID.scope(1, || {
+            NAME.scope("outer".into(), || {
+                ID.scope(2, || {
+                    assert_eq!(ID.get(), 2);
+                    assert_eq!(NAME.get(), "outer");
+                });
+                assert_eq!(ID.get(), 1);
+            });
+            assert_eq!(NAME.try_with(|name| name.cloned()), None);
+        });
+        assert_eq!(ID.try_with(|id| id.cloned()), None);
+    }
+
+    #[test]
+    fn follows_jobs() {
+        let pool = ThreadPool::new(1);
+        let (tagged, blocking) = ID.scope(7, || {
+            (pool.spawn(|| ID.get()), pool.spawn_blocking(|| ID.get()))
+        });
+        assert_eq!(tagged.join().unwrap(), 7);
+        assert_eq!(blocking.join().unwrap(), 7);
+        // The worker's own context is restored after the job
+        let untagged = pool.spawn(|| ID.try_with(|id| id.cloned()));
+        assert_eq!(untagged.join().unwrap(), None);
+    }
+
+    #[test]
+    fn scoped_and_stealing_jobs() {
+        let pool = ThreadPool::new(1);
+        ID.scope(3, || {
+            pool.scope(|s| s.spawn(|| assert_eq!(ID.get(), 3)));
+        });
+
+        let stealing = WorkStealingPool::new(1);
+        let (tx, rx) = ::mpmc::queue();
+        ID.scope(4, || stealing.spawn(move || tx.send(ID.get()).unwrap()));
+        assert_eq!(rx.recv(), Ok(4));
+    }
+}
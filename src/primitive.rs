@@ -0,0 +1,243 @@
+//! The synchronization primitives the lock-free code is built from.
+//!
+//! `mpmc`, the epoch collector and the backoff in `sync` take their
+//! atomics, locks, `Arc` and spin hints from here rather than from `std`
+//! directly. That keeps the set of primitives they rely on in one place,
+//! and when the crate is built with `--cfg loom` this swaps in loom's
+//! instrumented versions, so loom can check the algorithms' memory
+//! orderings without them changing.
+//!
+//! loom is a dev-dependency only when the cfg is set, so ordinary builds
+//! don't pull it in. To run the model-checking tests, build with the cfg
+//! set, keeping to the tests written for it:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom_test
+//! ```
+//!
+//! The `deterministic` feature swaps in versions that double as scheduling
+//! points for `testing::sched` instead.
+//!
+//! The blocking primitives in `sync` and `pool`, and anything else that
+//! only needs an ordinary lock, take theirs from `blocking`, which loom and
+//! the scheduler leave alone. The `parking_lot` feature backs those, and
+//! the lock-free code's locks outside of loom and the scheduler, with
+//! parking_lot's, behind std's API so no caller changes. parking_lot's
+//! locks don't poison, so every `lock` succeeds.
+
+#[cfg(not(any(loom, feature = "deterministic")))]
+pub use self::blocking::{Condvar, Mutex, MutexGuard};
+#[cfg(not(any(loom, feature = "deterministic")))]
+pub use std::sync::atomic::{
+    fence, AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering,
+};
+#[cfg(not(loom))]
+pub use std::sync::Arc;
+#[cfg(not(any(loom, feature = "deterministic")))]
+pub use std::{hint, thread};
+
+// With the `deterministic` feature, every operation is also a scheduling
+// point for `testing::sched`
+#[cfg(all(feature = "deterministic", not(loom)))]
+pub(crate) use testing::sched::shim::{
+    fence, hint, thread, AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Condvar, Mutex,
+    MutexGuard, Ordering,
+};
+
+#[cfg(loom)]
+pub use loom::sync::atomic::{
+    fence, AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering,
+};
+#[cfg(loom)]
+pub use loom::sync::{Arc, Condvar, Mutex, MutexGuard};
+#[cfg(loom)]
+pub use loom::{hint, thread};
+
+// loom's locks report poisoning with std's error types
+pub use std::sync::PoisonError;
+
+/// Lock `mutex`, ignoring poisoning.
+///
+/// The mutexes in `mpmc` only guard structures that stay valid when user
+/// code, such as an `Ord` or key function, panics partway through an
+/// operation: no item is lost or duplicated, though a priority queue whose
+/// `Ord` panicked may return its items out of order. So a panic fails just
+/// the call that panicked, and the channel keeps working for everyone else
+/// instead of every later call panicking on the poison.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Locks for code that doesn't need checking by loom or the scheduler
+pub mod blocking {
+    // Which of these are used depends on the features and cfgs
+    #[cfg(feature = "parking_lot")]
+    #[allow(unused_imports)]
+    pub use self::parking::{
+        Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    };
+    #[cfg(not(feature = "parking_lot"))]
+    #[allow(unused_imports)]
+    pub use std::sync::{Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+    /// parking_lot's locks with std's signatures
+    #[cfg(feature = "parking_lot")]
+    mod parking {
+        use parking_lot;
+        use std::fmt;
+        use std::ops::{Deref, DerefMut};
+        use std::sync::{LockResult, TryLockError, TryLockResult};
+        use std::time::Duration;
+
+        #[derive(Default)]
+        pub struct Mutex<T: ?Sized>(parking_lot::Mutex<T>);
+
+        pub struct MutexGuard<'a, T: ?Sized + 'a>(parking_lot::MutexGuard<'a, T>);
+
+        impl<T> Mutex<T> {
+            pub const fn new(value: T) -> Mutex<T> {
+                Mutex(parking_lot::Mutex::new(value))
+            }
+
+            pub fn into_inner(self) -> LockResult<T> {
+                Ok(self.0.into_inner())
+            }
+        }
+
+        impl<T: ?Sized> Mutex<T> {
+            pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+                Ok(MutexGuard(self.0.lock()))
+            }
+
+            pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+                self.0
+                    .try_lock()
+                    .map(MutexGuard)
+                    .ok_or(TryLockError::WouldBlock)
+            }
+        }
+
+        impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+
+        impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'a, T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                (**self).fmt(f)
+            }
+        }
+
+        #[derive(Default)]
+        pub struct RwLock<T: ?Sized>(parking_lot::RwLock<T>);
+
+        pub struct RwLockReadGuard<'a, T: ?Sized + 'a>(parking_lot::RwLockReadGuard<'a, T>);
+
+        pub struct RwLockWriteGuard<'a, T: ?Sized + 'a>(parking_lot::RwLockWriteGuard<'a, T>);
+
+        impl<T> RwLock<T> {
+            pub const fn new(value: T) -> RwLock<T> {
+                RwLock(parking_lot::RwLock::new(value))
+            }
+        }
+
+        impl<T: ?Sized> RwLock<T> {
+            pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+                Ok(RwLockReadGuard(self.0.read()))
+            }
+
+            pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+                Ok(RwLockWriteGuard(self.0.write()))
+            }
+        }
+
+        impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+
+        /// What `Condvar::wait_timeout` returns alongside the guard
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub struct WaitTimeoutResult(bool);
+
+        impl WaitTimeoutResult {
+            // Part of std's API, though nothing here checks it yet
+            #[allow(dead_code)]
+            pub fn timed_out(&self) -> bool {
+                self.0
+            }
+        }
+
+        #[derive(Debug, Default)]
+        pub struct Condvar(parking_lot::Condvar);
+
+        impl Condvar {
+            pub const fn new() -> Condvar {
+                Condvar(parking_lot::Condvar::new())
+            }
+
+            pub fn wait<'a, T>(
+                &self,
+                mut guard: MutexGuard<'a, T>,
+            ) -> LockResult<MutexGuard<'a, T>> {
+                self.0.wait(&mut guard.0);
+                Ok(guard)
+            }
+
+            pub fn wait_timeout<'a, T>(
+                &self,
+                mut guard: MutexGuard<'a, T>,
+                timeout: Duration,
+            ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+                let result = self.0.wait_for(&mut guard.0, timeout);
+                Ok((guard, WaitTimeoutResult(result.timed_out())))
+            }
+
+            pub fn notify_one(&self) {
+                self.0.notify_one();
+            }
+
+            pub fn notify_all(&self) {
+                self.0.notify_all();
+            }
+        }
+    }
+}
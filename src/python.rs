@@ -0,0 +1,181 @@
+//! A Python module of queue channels, built with the `python` feature.
+//!
+//! A `Channel` carries any object `pickle` can handle, pickled on `send`
+//! and unpickled on receive, so each receiver gets its own copy. Waiting
+//! for a message releases the GIL, so Python threads blocked on a channel
+//! don't hold up the rest of the interpreter, and Rust threads feeding the
+//! channel never need it.
+//!
+//! ```text
+//! from myriad import Channel
+//! channel = Channel()
+//! channel.send({"id": 1})
+//! assert channel.recv() == {"id": 1}
+//! channel.close()
+//! ```
+//!
+//! `recv` and `recv_timeout` raise `Disconnected` once the channel is
+//! closed and drained, `try_recv` raises `Empty` if nothing is queued, and
+//! `recv_timeout` raises `TimeoutError` if nothing arrived in time. To
+//! build the module, enable pyo3's `extension-module` feature too, for
+//! example with maturin:
+//!
+//! ```text
+//! maturin build --release --features python,pyo3/extension-module
+//! ```
+
+use mpmc::{self, Error, Receiver, Sender};
+use primitive::blocking::Mutex;
+use primitive::PoisonError;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyTimeoutError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::time::Duration;
+
+create_exception!(
+    myriad,
+    Disconnected,
+    PyException,
+    "The channel is closed and drained"
+);
+create_exception!(myriad, Empty, PyException, "Nothing is queued");
+
+/// A queue channel of pickled objects. Every thread shares both ends.
+#[pyclass(frozen)]
+pub struct Channel {
+    /// Taken by `close`, which disconnects the channel
+    sender: Mutex<Option<Sender<Vec<u8>>>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl Channel {
+    fn unpickle(py: Python, result: Result<Vec<u8>, Error>) -> PyResult<PyObject> {
+        match result {
+            Ok(bytes) => {
+                let loads = py.import("pickle")?.getattr("loads")?;
+                Ok(loads.call1((PyBytes::new(py, &bytes),))?.unbind())
+            }
+            Err(Error::Empty) => Err(Empty::new_err(())),
+            Err(Error::Timeout) => Err(PyTimeoutError::new_err(())),
+            Err(_) => Err(Disconnected::new_err(())),
+        }
+    }
+}
+
+#[pymethods]
+impl Channel {
+    #[new]
+    fn new() -> Channel {
+        let (sender, receiver) = mpmc::queue();
+        Channel {
+            sender: Mutex::new(Some(sender)),
+            receiver,
+        }
+    }
+
+    /// Pickle `item` and queue it
+    fn send(&self, py: Python, item: &Bound<PyAny>) -> PyResult<()> {
+        let dumps = py.import("pickle")?.getattr("dumps")?;
+        let bytes = dumps.call1((item,))?.extract::<Vec<u8>>()?;
+        let sender = self.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        match *sender {
+            Some(ref sender) if sender.send(bytes).is_ok() => Ok(()),
+            _ => Err(Disconnected::new_err(())),
+        }
+    }
+
+    /// Block until an item arrives, with the GIL released
+    fn recv(&self, py: Python) -> PyResult<PyObject> {
+        let result = py.allow_threads(|| self.receiver.recv());
+        Channel::unpickle(py, result)
+    }
+
+    fn try_recv(&self, py: Python) -> PyResult<PyObject> {
+        Channel::unpickle(py, self.receiver.try_recv())
+    }
+
+    /// Like `recv`, giving up after `seconds`
+    fn recv_timeout(&self, py: Python, seconds: f64) -> PyResult<PyObject> {
+        let timeout = Duration::from_secs_f64(seconds);
+        let result = py.allow_threads(|| self.receiver.recv_timeout(timeout));
+        Channel::unpickle(py, result)
+    }
+
+    /// Stop accepting items. Receivers get what is still queued, then
+    /// `Disconnected`.
+    fn close(&self) {
+        self.sender
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+
+    fn __len__(&self) -> usize {
+        self.receiver.stats().queued
+    }
+}
+
+#[pymodule]
+fn myriad(module: &Bound<PyModule>) -> PyResult<()> {
+    module.add_class::<Channel>()?;
+    module.add("Disconnected", module.py().get_type::<Disconnected>())?;
+    module.add("Empty", module.py().get_type::<Empty>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn objects_round_trip() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let channel = Channel::new();
+            let item = PyDict::new(py);
+            item.set_item("id", 1).unwrap();
+            channel.send(py, &item).unwrap();
+            let received = channel.recv(py).unwrap();
+            assert!(received.bind(py).eq(&item).unwrap());
+            assert!(channel
+                .try_recv(py)
+                .unwrap_err()
+                .is_instance_of::<Empty>(py));
+            channel.close();
+            assert!(channel
+                .send(py, &item)
+                .unwrap_err()
+                .is_instance_of::<Disconnected>(py));
+            assert!(channel
+                .recv(py)
+                .unwrap_err()
+                .is_instance_of::<Disconnected>(py));
+        });
+    }
+
+    #[test]
+    fn recv_releases_gil() {
+        pyo3::prepare_freethreaded_python();
+        let channel = Arc::new(Channel::new());
+        let sender = {
+            let channel = channel.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                // Only gets the GIL if the receiver let go of it
+                Python::with_gil(|py| {
+                    let item = 7i32.into_pyobject(py).unwrap().into_any();
+                    channel.send(py, &item).unwrap();
+                })
+            })
+        };
+        Python::with_gil(|py| {
+            let received = channel.recv_timeout(py, 5.0).unwrap();
+            assert_eq!(received.extract::<i32>(py).unwrap(), 7);
+        });
+        sender.join().unwrap();
+    }
+}
@@ -0,0 +1,255 @@
+//! Exponential backoff for retry loops.
+//!
+//! Lock-free algorithms retry a CAS when another thread got there first.
+//! Retrying immediately just keeps the cache line bouncing between cores,
+//! so `Backoff` spaces out attempts: `spin` doubles the number of spin
+//! hints each call, and `snooze` additionally falls back to yielding the
+//! thread. Once `is_completed` returns true, spinning is unlikely to help
+//! and the caller should block instead.
+//!
+//! How long to back off depends on how contended a structure is, so a
+//! structure can keep a `Contention` estimate and back off with
+//! `Contention::backoff`. When its operations have rarely retried lately,
+//! the first retry happens immediately, and when they retry a lot, backoff
+//! starts out long and ends in yielding the thread rather than spinning.
+
+use primitive::{hint, thread, AtomicU32, Ordering::*};
+use std::cell::Cell;
+use std::fmt;
+
+/// `spin` stops growing after `1 << SPIN_LIMIT` spin hints
+const SPIN_LIMIT: u32 = 6;
+/// `snooze` yields instead of spinning after `SPIN_LIMIT` steps, and
+/// reports completion after `YIELD_LIMIT` steps
+const YIELD_LIMIT: u32 = 10;
+/// `Contention` keeps its estimate with this many fractional bits
+const ESTIMATE_SHIFT: u32 = 4;
+/// Average retries per operation beyond which spinning gives way to
+/// yielding
+const HIGH_CONTENTION: u32 = 4;
+
+pub struct Backoff {
+    step: Cell<u32>,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff { step: Cell::new(0) }
+    }
+
+    /// Start over from the shortest backoff
+    pub fn reset(&self) {
+        self.step.set(0);
+    }
+
+    /// Back off in a lock-free retry loop, after a failed CAS
+    pub fn spin(&self) {
+        for _ in 0..1 << self.step.get().min(SPIN_LIMIT) {
+            hint::spin_loop();
+        }
+        if self.step.get() <= SPIN_LIMIT {
+            self.step.set(self.step.get() + 1);
+        }
+    }
+
+    /// Back off while waiting for another thread to make progress, such as
+    /// a lock holder releasing the lock
+    pub fn snooze(&self) {
+        if self.step.get() <= SPIN_LIMIT {
+            for _ in 0..1 << self.step.get() {
+                hint::spin_loop();
+            }
+        } else {
+            thread::yield_now();
+        }
+        if self.step.get() <= YIELD_LIMIT {
+            self.step.set(self.step.get() + 1);
+        }
+    }
+
+    /// Whether the caller should stop spinning and park the thread
+    pub fn is_completed(&self) -> bool {
+        self.step.get() > YIELD_LIMIT
+    }
+}
+
+/// A running estimate of how many retries a structure's operations need,
+/// shared by its CAS loops
+pub struct Contention {
+    /// Exponentially weighted average of retries per operation, in fixed
+    /// point
+    estimate: AtomicU32,
+}
+
+impl Contention {
+    #[cfg(not(loom))]
+    pub const fn new() -> Contention {
+        Contention {
+            estimate: AtomicU32::new(0),
+        }
+    }
+
+    // loom's atomics can't be created in a const context
+    #[cfg(loom)]
+    pub fn new() -> Contention {
+        Contention {
+            estimate: AtomicU32::new(0),
+        }
+    }
+
+    /// A backoff for one operation, which records how many retries the
+    /// operation needed when dropped
+    pub fn backoff(&self) -> AdaptiveBackoff<'_> {
+        AdaptiveBackoff {
+            contention: self,
+            backoff: Backoff::new(),
+            retries: Cell::new(0),
+        }
+    }
+
+    /// Average retries per operation lately
+    pub fn retries(&self) -> u32 {
+        self.estimate.load(Relaxed) >> ESTIMATE_SHIFT
+    }
+
+    fn record(&self, retries: u32) {
+        // A plain load and store rather than a read-modify-write: the
+        // estimate tolerates lost updates better than the structure
+        // tolerates another contended cache line
+        let estimate = self.estimate.load(Relaxed);
+        let sample = retries.min(1 << 16) << ESTIMATE_SHIFT;
+        let updated = estimate - (estimate >> 3) + (sample >> 3);
+        if updated != estimate {
+            self.estimate.store(updated, Relaxed);
+        }
+    }
+}
+
+impl Default for Contention {
+    fn default() -> Contention {
+        Contention::new()
+    }
+}
+
+/// Backoff for one operation on a structure with a `Contention` estimate
+pub struct AdaptiveBackoff<'a> {
+    contention: &'a Contention,
+    backoff: Backoff,
+    retries: Cell<u32>,
+}
+
+impl<'a> AdaptiveBackoff<'a> {
+    /// Back off in a lock-free retry loop, after a failed CAS
+    pub fn spin(&self) {
+        let retries = self.retries.get();
+        self.retries.set(retries + 1);
+        let contended = self.contention.retries();
+        if retries == 0 {
+            if contended == 0 {
+                // Probably an unlucky collision, retry at once
+                return;
+            }
+            // Skip the short spins that recent operations needed anyway
+            let start = 32 - contended.leading_zeros();
+            self.backoff.step.set(start.min(SPIN_LIMIT));
+        }
+        if self.backoff.step.get() > SPIN_LIMIT && contended >= HIGH_CONTENTION {
+            thread::yield_now();
+        } else {
+            self.backoff.spin();
+        }
+    }
+}
+
+impl<'a> Drop for AdaptiveBackoff<'a> {
+    fn drop(&mut self) {
+        self.contention.record(self.retries.get());
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff::new()
+    }
+}
+
+impl fmt::Debug for Backoff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Backoff")
+            .field("step", &self.step.get())
+            .field("is_completed", &self.is_completed())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Contention {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Contention")
+            .field("retries", &self.retries())
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for AdaptiveBackoff<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AdaptiveBackoff")
+            .field("retries", &self.retries.get())
+            .field("contention", self.contention)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snooze_completes() {
+        let backoff = Backoff::new();
+        let mut steps = 0;
+        while !backoff.is_completed() {
+            backoff.snooze();
+            steps += 1;
+        }
+        assert_eq!(steps, YIELD_LIMIT + 1);
+        backoff.reset();
+        assert!(!backoff.is_completed());
+    }
+
+    #[test]
+    fn spin_never_completes() {
+        let backoff = Backoff::new();
+        for _ in 0..100 {
+            backoff.spin();
+        }
+        assert!(!backoff.is_completed());
+    }
+
+    #[test]
+    fn contention_adapts() {
+        let contention = Contention::new();
+        {
+            // Uncontended, so the first retry doesn't wait
+            let backoff = contention.backoff();
+            backoff.spin();
+            assert_eq!(backoff.backoff.step.get(), 0);
+        }
+        for _ in 0..64 {
+            let backoff = contention.backoff();
+            for _ in 0..8 {
+                backoff.spin();
+            }
+        }
+        assert!(contention.retries() >= HIGH_CONTENTION);
+        let backoff = contention.backoff();
+        backoff.spin();
+        assert!(backoff.backoff.step.get() > 1);
+        drop(backoff);
+
+        // Operations that don't retry bring the estimate back down
+        for _ in 0..128 {
+            drop(contention.backoff());
+        }
+        assert_eq!(contention.retries(), 0);
+    }
+}
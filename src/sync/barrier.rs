@@ -0,0 +1,145 @@
+//! A reusable sense-reversing barrier.
+//!
+//! Every phase, `parties` threads call `wait`. The last thread to arrive
+//! resets the arrival count, flips the shared sense flag, and is handed the
+//! leader token. Waiters spin for a short while on the sense flag before
+//! parking, since in bulk-synchronous pipelines the phases are usually
+//! short and roughly balanced.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
+use sync::Backoff;
+
+pub struct Barrier {
+    parties: usize,
+    count: AtomicUsize,
+    sense: AtomicBool,
+    guard: Mutex<()>,
+    waker: Condvar,
+}
+
+/// Returned from `Barrier::wait`. Exactly one thread per phase is the
+/// leader.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Create a barrier for `parties` threads. A barrier with zero parties
+    /// behaves like a barrier with one party.
+    pub fn new(parties: usize) -> Barrier {
+        Barrier {
+            parties: parties.max(1),
+            count: AtomicUsize::new(0),
+            sense: AtomicBool::new(false),
+            guard: Mutex::new(()),
+            waker: Condvar::new(),
+        }
+    }
+
+    /// Block until all parties have called `wait` for the current phase
+    pub fn wait(&self) -> BarrierWaitResult {
+        let sense = self.sense.load(Acquire);
+        if self.count.fetch_add(1, AcqRel) + 1 == self.parties {
+            // Nobody can arrive for the next phase until the sense flips,
+            // so resetting the count first is safe
+            self.count.store(0, Relaxed);
+            let _guard = self.guard.lock().unwrap();
+            self.sense.store(!sense, Release);
+            self.waker.notify_all();
+            return BarrierWaitResult(true);
+        }
+
+        let backoff = Backoff::new();
+        while !backoff.is_completed() {
+            if self.sense.load(Acquire) != sense {
+                return BarrierWaitResult(false);
+            }
+            backoff.snooze();
+        }
+
+        let mut guard = self.guard.lock().unwrap();
+        while self.sense.load(Acquire) == sense {
+            guard = self.waker.wait(guard).unwrap();
+        }
+        BarrierWaitResult(false)
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Barrier")
+            .field("parties", &self.parties)
+            .field("waiting", &self.count.load(Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn single_party() {
+        let barrier = Barrier::new(1);
+        assert!(barrier.wait().is_leader());
+        assert!(barrier.wait().is_leader());
+    }
+
+    #[test]
+    fn one_leader_per_phase() {
+        const THREADS: usize = 8;
+        const PHASES: usize = 100;
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let leaders = Arc::new(AtomicUsize::new(0));
+        let handles = (0..THREADS)
+            .map(|_| {
+                let barrier = barrier.clone();
+                let leaders = leaders.clone();
+                thread::spawn(move || {
+                    for _ in 0..PHASES {
+                        if barrier.wait().is_leader() {
+                            leaders.fetch_add(1, Relaxed);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(leaders.load(Relaxed), PHASES);
+    }
+
+    #[test]
+    fn phases_are_separated() {
+        const THREADS: usize = 4;
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let arrived = Arc::new(AtomicUsize::new(0));
+        let handles = (0..THREADS)
+            .map(|_| {
+                let barrier = barrier.clone();
+                let arrived = arrived.clone();
+                thread::spawn(move || {
+                    for phase in 1..=10 {
+                        arrived.fetch_add(1, SeqCst);
+                        barrier.wait();
+                        assert!(arrived.load(SeqCst) >= phase * THREADS);
+                        barrier.wait();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
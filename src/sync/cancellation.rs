@@ -0,0 +1,189 @@
+//! Hierarchical cancellation tokens.
+//!
+//! Cancelling a token cancels every child token derived from it, but
+//! cancelling a child leaves its parent untouched. This maps onto shutdown
+//! of nested subsystems: the service-wide token cancels everything, while a
+//! single pipeline can be torn down through its own child token.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering::*};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+type Waker = Box<dyn Fn() + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    children: Vec<Weak<Node>>,
+    /// Callbacks that wake threads blocked on something other than this
+    /// token, such as receivers in `recv_cancellable`
+    wakers: Vec<(usize, Waker)>,
+    next_waker: usize,
+}
+
+#[derive(Default)]
+struct Node {
+    cancelled: AtomicBool,
+    state: Mutex<State>,
+    waker: Condvar,
+}
+
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Create a token that is cancelled along with this one
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut state = self.node.state.lock().unwrap();
+        if self.is_cancelled() {
+            child.node.cancelled.store(true, Release);
+        } else {
+            state.children.retain(|child| child.strong_count() > 0);
+            state.children.push(Arc::downgrade(&child.node));
+        }
+        child
+    }
+
+    /// Cancel this token and all of its descendants, waking every thread
+    /// waiting on any of them
+    pub fn cancel(&self) {
+        self.node.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Acquire)
+    }
+
+    /// Block until the token is cancelled
+    pub fn wait_cancelled(&self) {
+        let mut state = self.node.state.lock().unwrap();
+        while !self.is_cancelled() {
+            state = self.node.waker.wait(state).unwrap();
+        }
+    }
+
+    /// Block until the token is cancelled or `timeout` elapses. Returns
+    /// `true` if the token was cancelled.
+    pub fn wait_cancelled_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.node.state.lock().unwrap();
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            state = self
+                .node
+                .waker
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+        true
+    }
+
+    /// Call `waker` when the token is cancelled, returning a key for
+    /// `unregister`. If the token is already cancelled, `waker` is dropped
+    /// without being called and callers should check `is_cancelled`.
+    ///
+    /// Wakers are called with the token's lock held, so once `unregister`
+    /// returns, the waker is guaranteed not to be running.
+    pub(crate) fn register(&self, waker: Waker) -> usize {
+        let mut state = self.node.state.lock().unwrap();
+        let key = state.next_waker;
+        state.next_waker += 1;
+        if !self.is_cancelled() {
+            state.wakers.push((key, waker));
+        }
+        key
+    }
+
+    pub(crate) fn unregister(&self, key: usize) {
+        self.node
+            .state
+            .lock()
+            .unwrap()
+            .wakers
+            .retain(|&(waker, _)| waker != key);
+    }
+}
+
+impl Node {
+    fn cancel(&self) {
+        let children = {
+            let mut state = self.state.lock().unwrap();
+            if self.cancelled.swap(true, AcqRel) {
+                return;
+            }
+            self.waker.notify_all();
+            for (_, waker) in state.wakers.drain(..) {
+                waker();
+            }
+            mem::take(&mut state.children)
+        };
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn cancel_children() {
+        let root = CancellationToken::new();
+        let child = root.child_token();
+        let grandchild = child.child_token();
+        let sibling = root.child_token();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        assert!(!root.is_cancelled());
+        assert!(!sibling.is_cancelled());
+
+        root.cancel();
+        assert!(sibling.is_cancelled());
+        assert!(root.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn wait() {
+        let token = CancellationToken::new();
+        assert!(!token.wait_cancelled_timeout(Duration::from_millis(10)));
+        let child = token.child_token();
+        let handle = thread::spawn(move || child.wait_cancelled());
+        thread::sleep(Duration::from_millis(10));
+        token.cancel();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn dropped_children_are_pruned() {
+        let root = CancellationToken::new();
+        for _ in 0..10 {
+            drop(root.child_token());
+        }
+        assert_eq!(root.node.state.lock().unwrap().children.len(), 1);
+    }
+}
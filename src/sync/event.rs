@@ -0,0 +1,116 @@
+//! A manual-reset event.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering::*};
+use std::time::{Duration, Instant};
+
+/// Threads calling `wait` block until the event is `set`. The event stays
+/// set, releasing every current and future waiter, until it is `reset`.
+pub struct Event {
+    flag: AtomicBool,
+    guard: Mutex<()>,
+    waker: Condvar,
+}
+
+impl Event {
+    pub fn new() -> Event {
+        Event {
+            flag: AtomicBool::new(false),
+            guard: Mutex::new(()),
+            waker: Condvar::new(),
+        }
+    }
+
+    /// Set the event, waking all waiters
+    pub fn set(&self) {
+        let _guard = self.guard.lock().unwrap();
+        self.flag.store(true, Release);
+        self.waker.notify_all();
+    }
+
+    /// Clear the event, so that subsequent calls to `wait` block again
+    pub fn reset(&self) {
+        self.flag.store(false, Release);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.flag.load(Acquire)
+    }
+
+    /// Block until the event is set
+    pub fn wait(&self) {
+        if self.is_set() {
+            return;
+        }
+        let mut guard = self.guard.lock().unwrap();
+        while !self.is_set() {
+            guard = self.waker.wait(guard).unwrap();
+        }
+    }
+
+    /// Block until the event is set or `timeout` elapses. Returns `true` if
+    /// the event was set.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        if self.is_set() {
+            return true;
+        }
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.guard.lock().unwrap();
+        while !self.is_set() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            guard = self.waker.wait_timeout(guard, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+impl Default for Event {
+    fn default() -> Event {
+        Event::new()
+    }
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("set", &self.is_set())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn set_and_reset() {
+        let event = Event::new();
+        assert!(!event.is_set());
+        event.set();
+        event.wait();
+        assert!(event.is_set());
+        event.reset();
+        assert!(!event.wait_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn wake_waiters() {
+        let event = Arc::new(Event::new());
+        let waiters = (0..4)
+            .map(|_| {
+                let event = event.clone();
+                thread::spawn(move || event.wait())
+            })
+            .collect::<Vec<_>>();
+        event.set();
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    }
+}
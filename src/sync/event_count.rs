@@ -0,0 +1,235 @@
+//! An eventcount: a condition variable without the mutex.
+//!
+//! Waiters announce themselves, then read a generation counter, check
+//! their condition, and sleep on the counter only if the condition still
+//! doesn't hold. Notifiers publish their change, then bump the counter and
+//! wake sleepers, but only if there are any, so notifying an eventcount
+//! nobody waits on costs a fence and a load, and never a lock or syscall.
+//!
+//! The atomics come from `primitive`, so loom checks the handshake between
+//! the two sides. loom can't see a futex sleep, so under loom waiters sleep
+//! on one of its condvars instead, with the futex's semantics.
+
+use primitive::{fence, AtomicU32, AtomicUsize, Ordering::*};
+use std::fmt;
+use std::time::{Duration, Instant};
+#[cfg(not(loom))]
+use sync::futex;
+
+pub struct EventCount {
+    /// Bumped on every notification that finds sleepers. Waiters sleep on
+    /// it with `futex::wait_on`.
+    generation: AtomicU32,
+    sleepers: AtomicUsize,
+    #[cfg(loom)]
+    futex: loom_futex::Futex,
+}
+
+impl EventCount {
+    pub fn new() -> EventCount {
+        EventCount {
+            generation: AtomicU32::new(0),
+            sleepers: AtomicUsize::new(0),
+            #[cfg(loom)]
+            futex: loom_futex::Futex::new(),
+        }
+    }
+
+    /// Block until `ready` returns `true`, or until `deadline` passes.
+    /// Returns whether `ready` did. `ready` is called at least once, and
+    /// again after every wakeup, spurious or not.
+    pub fn wait_until(&self, ready: &mut dyn FnMut() -> bool, deadline: Option<Instant>) -> bool {
+        self.sleepers.fetch_add(1, SeqCst);
+        // Pairs with the fence in `has_sleepers`: either the notifier sees
+        // this sleeper, or `ready` sees whatever the notifier published
+        fence(SeqCst);
+        let ready = loop {
+            // Read the generation before checking, so that any notification
+            // after the check makes the wait below return immediately
+            let generation = self.generation.load(SeqCst);
+            if ready() {
+                break true;
+            }
+            match deadline {
+                None => self.sleep(generation, None),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break false;
+                    }
+                    self.sleep(generation, Some(deadline - now));
+                }
+            }
+        };
+        self.sleepers.fetch_sub(1, Relaxed);
+        ready
+    }
+
+    #[cfg(not(loom))]
+    fn sleep(&self, generation: u32, timeout: Option<Duration>) {
+        match timeout {
+            None => futex::wait_on(&self.generation, generation),
+            Some(timeout) => futex::wait_on_timeout(&self.generation, generation, timeout),
+        }
+    }
+
+    #[cfg(not(loom))]
+    fn wake(&self, n: u32) {
+        futex::wake(&self.generation, n);
+    }
+
+    #[cfg(loom)]
+    fn sleep(&self, generation: u32, _timeout: Option<Duration>) {
+        self.futex.wait(&self.generation, generation);
+    }
+
+    #[cfg(loom)]
+    fn wake(&self, _n: u32) {
+        self.futex.wake();
+    }
+
+    fn has_sleepers(&self) -> bool {
+        fence(SeqCst);
+        self.sleepers.load(Relaxed) > 0
+    }
+
+    /// Wake one waiter, after publishing whatever it waits for
+    pub fn notify_one(&self) {
+        if self.has_sleepers() {
+            self.generation.fetch_add(1, SeqCst);
+            self.wake(1);
+        }
+    }
+
+    /// Wake every waiter, after publishing whatever they wait for
+    pub fn notify_all(&self) {
+        if self.has_sleepers() {
+            self.generation.fetch_add(1, SeqCst);
+            self.wake(u32::MAX);
+        }
+    }
+
+    /// Number of threads currently waiting
+    pub fn sleepers(&self) -> usize {
+        self.sleepers.load(Relaxed)
+    }
+
+    /// Notifications that found sleepers, so that tests can tell whether
+    /// a notification took the slow path
+    #[cfg(test)]
+    pub(crate) fn generation(&self) -> u32 {
+        self.generation.load(Relaxed)
+    }
+}
+
+impl Default for EventCount {
+    fn default() -> EventCount {
+        EventCount::new()
+    }
+}
+
+impl fmt::Debug for EventCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventCount")
+            .field("sleepers", &self.sleepers())
+            .finish()
+    }
+}
+
+/// A futex built from loom's locks, so loom sees waiters block
+#[cfg(loom)]
+mod loom_futex {
+    use primitive::{lock, AtomicU32, Condvar, Mutex, Ordering::SeqCst};
+
+    pub struct Futex {
+        lock: Mutex<()>,
+        cvar: Condvar,
+    }
+
+    impl Futex {
+        pub fn new() -> Futex {
+            Futex {
+                lock: Mutex::new(()),
+                cvar: Condvar::new(),
+            }
+        }
+
+        /// Block while `atomic` holds `expected`. Checking under the lock
+        /// makes the check and the sleep atomic with respect to `wake`.
+        pub fn wait(&self, atomic: &AtomicU32, expected: u32) {
+            let guard = lock(&self.lock);
+            if atomic.load(SeqCst) == expected {
+                drop(self.cvar.wait(guard));
+            }
+        }
+
+        pub fn wake(&self) {
+            let _guard = lock(&self.lock);
+            self.cvar.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn notify_without_sleepers() {
+        let events = EventCount::new();
+        events.notify_all();
+        events.notify_one();
+        assert_eq!(events.generation.load(Relaxed), 0);
+        assert!(!events.wait_until(&mut || false, Some(Instant::now())));
+        assert_eq!(events.sleepers(), 0);
+    }
+
+    #[test]
+    fn wakes_waiters() {
+        let events = Arc::new(EventCount::new());
+        let flag = Arc::new(AtomicBool::new(false));
+        let handles = (0..3)
+            .map(|_| {
+                let (events, flag) = (events.clone(), flag.clone());
+                thread::spawn(move || events.wait_until(&mut || flag.load(SeqCst), None))
+            })
+            .collect::<Vec<_>>();
+        while events.sleepers() < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        flag.store(true, SeqCst);
+        events.notify_all();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+    use primitive::{thread, Arc, AtomicBool};
+
+    #[test]
+    fn notify_reaches_waiter() {
+        loom::model(|| {
+            let events = Arc::new(EventCount::new());
+            let flag = Arc::new(AtomicBool::new(false));
+            let notifier = {
+                let (events, flag) = (events.clone(), flag.clone());
+                thread::spawn(move || {
+                    flag.store(true, SeqCst);
+                    events.notify_one();
+                })
+            };
+            // A lost wakeup leaves this blocked for good, which loom
+            // reports as a deadlock
+            assert!(events.wait_until(&mut || flag.load(SeqCst), None));
+            notifier.join().unwrap();
+        });
+    }
+}
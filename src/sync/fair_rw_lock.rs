@@ -0,0 +1,308 @@
+//! A reader-writer lock with an explicit fairness policy.
+//!
+//! `std::sync::RwLock` defers to the platform, so whether writers can be
+//! starved by a steady stream of readers depends on the OS. `FairRwLock`
+//! makes the choice explicit:
+//!
+//! * `Policy::WriterPriority` stops admitting new readers as soon as a
+//!   writer is waiting. Writers can't be starved, readers can be if writes
+//!   are constant.
+//! * `Policy::Fifo` admits acquirers strictly in arrival order, with
+//!   consecutive readers sharing the lock. Nobody is starved.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    WriterPriority,
+    Fifo,
+}
+
+#[derive(Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    waiting_writers: usize,
+    /// Next ticket handed out to an arriving thread (FIFO only)
+    next_ticket: u64,
+    /// Ticket of the thread allowed to acquire next (FIFO only)
+    serving: u64,
+}
+
+pub struct FairRwLock<T: ?Sized> {
+    policy: Policy,
+    state: Mutex<State>,
+    waker: Condvar,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for FairRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for FairRwLock<T> {}
+
+pub struct FairRwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a FairRwLock<T>,
+}
+
+pub struct FairRwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a FairRwLock<T>,
+}
+
+impl<T> FairRwLock<T> {
+    pub fn new(data: T, policy: Policy) -> FairRwLock<T> {
+        FairRwLock {
+            policy,
+            state: Mutex::new(State::default()),
+            waker: Condvar::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> FairRwLock<T> {
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Acquire shared read access
+    pub fn read(&self) -> FairRwLockReadGuard<'_, T> {
+        let mut state = self.state.lock().unwrap();
+        match self.policy {
+            Policy::WriterPriority => {
+                while state.writer || state.waiting_writers > 0 {
+                    state = self.waker.wait(state).unwrap();
+                }
+            }
+            Policy::Fifo => {
+                let ticket = state.next_ticket;
+                state.next_ticket += 1;
+                while state.writer || state.serving != ticket {
+                    state = self.waker.wait(state).unwrap();
+                }
+                // Let the next reader in line share the lock with us
+                state.serving += 1;
+                self.waker.notify_all();
+            }
+        }
+        state.readers += 1;
+        FairRwLockReadGuard { lock: self }
+    }
+
+    /// Acquire exclusive write access
+    pub fn write(&self) -> FairRwLockWriteGuard<'_, T> {
+        let mut state = self.state.lock().unwrap();
+        match self.policy {
+            Policy::WriterPriority => {
+                state.waiting_writers += 1;
+                while state.writer || state.readers > 0 {
+                    state = self.waker.wait(state).unwrap();
+                }
+                state.waiting_writers -= 1;
+            }
+            Policy::Fifo => {
+                let ticket = state.next_ticket;
+                state.next_ticket += 1;
+                while state.writer || state.readers > 0 || state.serving != ticket {
+                    state = self.waker.wait(state).unwrap();
+                }
+                state.serving += 1;
+            }
+        }
+        state.writer = true;
+        FairRwLockWriteGuard { lock: self }
+    }
+
+    /// Acquire read access if it can be granted without waiting and without
+    /// jumping ahead of queued threads
+    pub fn try_read(&self) -> Option<FairRwLockReadGuard<'_, T>> {
+        let mut state = self.state.lock().unwrap();
+        let blocked = match self.policy {
+            Policy::WriterPriority => state.writer || state.waiting_writers > 0,
+            Policy::Fifo => state.writer || state.serving != state.next_ticket,
+        };
+        if blocked {
+            return None;
+        }
+        if self.policy == Policy::Fifo {
+            state.next_ticket += 1;
+            state.serving += 1;
+        }
+        state.readers += 1;
+        Some(FairRwLockReadGuard { lock: self })
+    }
+
+    /// Acquire write access if it can be granted without waiting and without
+    /// jumping ahead of queued threads
+    pub fn try_write(&self) -> Option<FairRwLockWriteGuard<'_, T>> {
+        let mut state = self.state.lock().unwrap();
+        let blocked = state.writer
+            || state.readers > 0
+            || match self.policy {
+                Policy::WriterPriority => state.waiting_writers > 0,
+                Policy::Fifo => state.serving != state.next_ticket,
+            };
+        if blocked {
+            return None;
+        }
+        if self.policy == Policy::Fifo {
+            state.next_ticket += 1;
+            state.serving += 1;
+        }
+        state.writer = true;
+        Some(FairRwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for FairRwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.lock().unwrap();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.lock.waker.notify_all();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for FairRwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.lock().unwrap().writer = false;
+        self.lock.waker.notify_all();
+    }
+}
+
+impl<'a, T: ?Sized> Deref for FairRwLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for FairRwLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for FairRwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for FairRwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("FairRwLock");
+        s.field("policy", &self.policy);
+        match self.try_read() {
+            Some(guard) => s.field("data", &&*guard),
+            None => s.field("data", &"<locked>"),
+        };
+        s.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn shared_readers() {
+        for &policy in &[Policy::WriterPriority, Policy::Fifo] {
+            let lock = FairRwLock::new(5, policy);
+            let a = lock.read();
+            let b = lock.try_read().unwrap();
+            assert!(lock.try_write().is_none());
+            assert_eq!(*a + *b, 10);
+            drop(a);
+            drop(b);
+            *lock.write() += 1;
+            assert_eq!(lock.into_inner(), 6);
+        }
+    }
+
+    #[test]
+    fn waiting_writer_blocks_new_readers() {
+        for &policy in &[Policy::WriterPriority, Policy::Fifo] {
+            let lock = Arc::new(FairRwLock::new(0, policy));
+            let reader = lock.read();
+            let writer = {
+                let lock = lock.clone();
+                thread::spawn(move || *lock.write() = 1)
+            };
+            // Wait for the writer to queue up behind the reader
+            while lock.try_read().is_some() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            drop(reader);
+            writer.join().unwrap();
+            assert_eq!(*lock.read(), 1);
+        }
+    }
+
+    #[test]
+    fn fifo_order() {
+        let lock = Arc::new(FairRwLock::new(Vec::new(), Policy::Fifo));
+        let guard = lock.write();
+        let mut handles = Vec::new();
+        for i in 0..4 {
+            let writer = lock.clone();
+            handles.push(thread::spawn(move || writer.write().push(i)));
+            // Make sure each writer has taken its ticket before the next
+            loop {
+                let state = lock.state.lock().unwrap();
+                if state.next_ticket == i as u64 + 2 {
+                    break;
+                }
+                drop(state);
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        drop(guard);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*lock.read(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn concurrent() {
+        for &policy in &[Policy::WriterPriority, Policy::Fifo] {
+            let lock = Arc::new(FairRwLock::new((0usize, 0usize), policy));
+            let handles = (0..8)
+                .map(|i| {
+                    let lock = lock.clone();
+                    thread::spawn(move || {
+                        for _ in 0..500 {
+                            if i % 2 == 0 {
+                                let mut pair = lock.write();
+                                pair.0 += 1;
+                                pair.1 += 1;
+                            } else {
+                                let pair = lock.read();
+                                assert_eq!(pair.0, pair.1);
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            assert_eq!(*lock.read(), (2000, 2000));
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Portable wait/wake on a table of condvars.
+//!
+//! Each atomic hashes to one bucket. Waiters check the value while holding
+//! the bucket's mutex, and wakers take the same mutex before notifying, so
+//! a waker can't slip in between the check and the sleep. Unrelated atomics
+//! sharing a bucket only cause spurious wakeups.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering::*};
+use std::time::Duration;
+
+const BUCKETS: usize = 64;
+
+struct Bucket {
+    guard: Mutex<()>,
+    waker: Condvar,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const BUCKET: Bucket = Bucket {
+    guard: Mutex::new(()),
+    waker: Condvar::new(),
+};
+
+static TABLE: [Bucket; BUCKETS] = [BUCKET; BUCKETS];
+
+fn bucket(atomic: &AtomicU32) -> &'static Bucket {
+    let addr = atomic as *const AtomicU32 as usize;
+    // Atomics are 4-byte aligned, and nearby atomics are likely unrelated
+    &TABLE[(addr >> 2).wrapping_mul(0x9e37_79b9) % BUCKETS]
+}
+
+pub fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+    let bucket = bucket(atomic);
+    let guard = bucket.guard.lock().unwrap_or_else(|e| e.into_inner());
+    if atomic.load(SeqCst) != expected {
+        return;
+    }
+    match timeout {
+        None => drop(bucket.waker.wait(guard)),
+        Some(timeout) => drop(bucket.waker.wait_timeout(guard, timeout)),
+    }
+}
+
+pub fn wake(atomic: &AtomicU32, _n: u32) {
+    let bucket = bucket(atomic);
+    drop(bucket.guard.lock().unwrap_or_else(|e| e.into_inner()));
+    // Other atomics may share the bucket, so waking just `n` threads could
+    // pick the wrong ones
+    bucket.waker.notify_all();
+}
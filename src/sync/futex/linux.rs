@@ -0,0 +1,34 @@
+use libc;
+use std::ptr;
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+pub fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+    // FUTEX_WAIT takes a relative timeout
+    let timeout = timeout.map(|timeout| libc::timespec {
+        tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as libc::c_long,
+    });
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic as *const AtomicU32,
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timeout
+                .as_ref()
+                .map_or(ptr::null(), |timeout| timeout as *const libc::timespec),
+        );
+    }
+}
+
+pub fn wake(atomic: &AtomicU32, n: u32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic as *const AtomicU32,
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            n.min(i32::MAX as u32) as i32,
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Blocking on the value of an atomic.
+//!
+//! `wait_on` blocks the calling thread as long as an `AtomicU32` holds an
+//! expected value, and `wake` wakes threads blocked on that atomic. The
+//! check and the sleep are atomic with respect to `wake`: a thread that
+//! changes the value and then calls `wake` can never miss a waiter that
+//! saw the old value. This is enough to build parkers, eventcounts and
+//! locks without a `Mutex` and `Condvar` per object.
+//!
+//! On Linux this is a thin wrapper around the `futex` syscall, and on
+//! Windows around `WaitOnAddress`. Elsewhere, waiters sleep on a fixed
+//! table of condvars hashed by address.
+//!
+//! Waits may return spuriously, so callers must recheck their condition in
+//! a loop.
+
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+#[cfg(feature = "deterministic")]
+use testing;
+
+#[cfg(any(test, not(any(target_os = "linux", windows))))]
+mod fallback;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(windows)]
+mod windows;
+
+#[cfg(not(any(target_os = "linux", windows)))]
+use self::fallback as imp;
+#[cfg(target_os = "linux")]
+use self::linux as imp;
+#[cfg(windows)]
+use self::windows as imp;
+
+/// Block while `atomic` holds `expected`
+pub fn wait_on(atomic: &AtomicU32, expected: u32) {
+    #[cfg(feature = "deterministic")]
+    if testing::sched::yield_wait() {
+        return;
+    }
+    imp::wait(atomic, expected, None)
+}
+
+/// Block while `atomic` holds `expected`, for at most `timeout`
+pub fn wait_on_timeout(atomic: &AtomicU32, expected: u32, timeout: Duration) {
+    #[cfg(feature = "deterministic")]
+    if testing::sched::yield_wait() {
+        return;
+    }
+    imp::wait(atomic, expected, Some(timeout))
+}
+
+/// Wake up to `n` threads blocked on `atomic`. Backends that can only wake
+/// one or all threads wake all of them when `n` is larger than one.
+pub fn wake(atomic: &AtomicU32, n: u32) {
+    imp::wake(atomic, n)
+}
+
+/// Wake every thread blocked on `atomic`
+pub fn wake_all(atomic: &AtomicU32) {
+    imp::wake(atomic, u32::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::Ordering::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+
+    type Wait = fn(&AtomicU32, u32, Option<Duration>);
+    type Wake = fn(&AtomicU32, u32);
+
+    const IMPLS: [(Wait, Wake); 2] = [(imp::wait, imp::wake), (fallback::wait, fallback::wake)];
+
+    #[test]
+    fn value_mismatch_returns() {
+        for &(wait, _) in &IMPLS {
+            let atomic = AtomicU32::new(1);
+            wait(&atomic, 0, None);
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        for &(wait, _) in &IMPLS {
+            let atomic = AtomicU32::new(0);
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_millis(10) {
+                wait(&atomic, 0, Some(Duration::from_millis(10)));
+            }
+        }
+    }
+
+    #[test]
+    fn wake_waiters() {
+        for &(wait, wake) in &IMPLS {
+            let atomic = Arc::new(AtomicU32::new(0));
+            let handles = (0..4)
+                .map(|_| {
+                    let atomic = atomic.clone();
+                    thread::spawn(move || {
+                        while atomic.load(Acquire) == 0 {
+                            wait(&atomic, 0, None);
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            thread::sleep(Duration::from_millis(10));
+            atomic.store(1, Release);
+            wake(&atomic, u32::MAX);
+            for handle in handles {
+                handle.join().unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::os::raw::c_void;
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+const INFINITE: u32 = u32::MAX;
+
+#[link(name = "synchronization")]
+extern "system" {
+    fn WaitOnAddress(
+        address: *const c_void,
+        compare: *const c_void,
+        size: usize,
+        milliseconds: u32,
+    ) -> i32;
+    fn WakeByAddressSingle(address: *const c_void);
+    fn WakeByAddressAll(address: *const c_void);
+}
+
+pub fn wait(atomic: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+    // Round up so a short timeout doesn't turn into a busy loop, and keep
+    // long timeouts below INFINITE
+    let milliseconds = timeout.map_or(INFINITE, |timeout| {
+        let millis = timeout.as_nanos().div_ceil(1_000_000);
+        millis.min(u128::from(INFINITE - 1)) as u32
+    });
+    unsafe {
+        WaitOnAddress(
+            atomic as *const AtomicU32 as *const c_void,
+            &expected as *const u32 as *const c_void,
+            4,
+            milliseconds,
+        );
+    }
+}
+
+pub fn wake(atomic: &AtomicU32, n: u32) {
+    let address = atomic as *const AtomicU32 as *const c_void;
+    unsafe {
+        match n {
+            0 => (),
+            1 => WakeByAddressSingle(address),
+            _ => WakeByAddressAll(address),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! A gate for pausing a fleet of worker threads.
+//!
+//! Workers call `pass` at the top of their loop. While the gate is open
+//! this returns immediately; while it is closed every worker blocks in
+//! `pass` until the gate is reopened. Closing the gate doesn't interrupt
+//! work in progress, so a worker finishes its current message before it
+//! pauses.
+
+use std::fmt;
+use std::time::Duration;
+use sync::Event;
+
+pub struct Gate {
+    event: Event,
+}
+
+impl Gate {
+    pub fn new(open: bool) -> Gate {
+        let gate = Gate {
+            event: Event::new(),
+        };
+        if open {
+            gate.open();
+        }
+        gate
+    }
+
+    /// Let all blocked and future threads pass
+    pub fn open(&self) {
+        self.event.set();
+    }
+
+    /// Block threads at their next `pass`
+    pub fn close(&self) {
+        self.event.reset();
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.event.is_set()
+    }
+
+    /// Block until the gate is open
+    pub fn pass(&self) {
+        self.event.wait();
+    }
+
+    /// Block until the gate is open or `timeout` elapses. Returns `true` if
+    /// the gate was open.
+    pub fn pass_timeout(&self, timeout: Duration) -> bool {
+        self.event.wait_timeout(timeout)
+    }
+}
+
+impl Default for Gate {
+    /// An open gate
+    fn default() -> Gate {
+        Gate::new(true)
+    }
+}
+
+impl fmt::Debug for Gate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Gate")
+            .field("open", &self.is_open())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn open_and_close() {
+        let gate = Gate::default();
+        gate.pass();
+        gate.close();
+        assert!(!gate.is_open());
+        assert!(!gate.pass_timeout(Duration::from_millis(10)));
+        gate.open();
+        assert!(gate.pass_timeout(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn pause_workers() {
+        let gate = Arc::new(Gate::new(false));
+        let passed = Arc::new(AtomicUsize::new(0));
+        let handles = (0..4)
+            .map(|_| {
+                let gate = gate.clone();
+                let passed = passed.clone();
+                thread::spawn(move || {
+                    gate.pass();
+                    passed.fetch_add(1, SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(passed.load(SeqCst), 0);
+        gate.open();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(passed.load(SeqCst), 4);
+    }
+}
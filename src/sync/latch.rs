@@ -0,0 +1,111 @@
+//! A one-shot latch that releases waiting threads once it has been counted
+//! down to zero.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A latch initialized with a count. Threads calling `wait` block until
+/// `count_down` has been called `count` times. Once open, the latch stays
+/// open.
+pub struct CountDownLatch {
+    count: Mutex<usize>,
+    waker: Condvar,
+}
+
+impl CountDownLatch {
+    pub fn new(count: usize) -> CountDownLatch {
+        CountDownLatch {
+            count: Mutex::new(count),
+            waker: Condvar::new(),
+        }
+    }
+
+    /// Decrement the count, releasing all waiters if it reaches zero.
+    /// Counting down an open latch has no effect.
+    pub fn count_down(&self) {
+        let mut count = self.count.lock().unwrap();
+        if *count > 0 {
+            *count -= 1;
+            if *count == 0 {
+                self.waker.notify_all();
+            }
+        }
+    }
+
+    /// Current remaining count
+    pub fn count(&self) -> usize {
+        *self.count.lock().unwrap()
+    }
+
+    /// Block until the count reaches zero
+    pub fn wait(&self) {
+        let mut count = self.count.lock().unwrap();
+        while *count > 0 {
+            count = self.waker.wait(count).unwrap();
+        }
+    }
+
+    /// Block until the count reaches zero or `timeout` elapses. Returns
+    /// `true` if the latch is open.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock().unwrap();
+        while *count > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            count = self.waker.wait_timeout(count, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+impl fmt::Debug for CountDownLatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CountDownLatch")
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn open_at_zero() {
+        let latch = CountDownLatch::new(0);
+        latch.wait();
+        latch.count_down();
+        assert_eq!(latch.count(), 0);
+    }
+
+    #[test]
+    fn release_waiters() {
+        let latch = Arc::new(CountDownLatch::new(4));
+        let waiters = (0..4)
+            .map(|_| {
+                let latch = latch.clone();
+                thread::spawn(move || latch.wait())
+            })
+            .collect::<Vec<_>>();
+        for _ in 0..4 {
+            latch.count_down();
+        }
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let latch = CountDownLatch::new(1);
+        assert!(!latch.wait_timeout(Duration::from_millis(10)));
+        latch.count_down();
+        assert!(latch.wait_timeout(Duration::from_millis(10)));
+    }
+}
@@ -0,0 +1,39 @@
+//! Synchronization primitives for coordinating threads that communicate
+//! over myriad channels.
+
+mod backoff;
+mod barrier;
+mod cache_padded;
+mod cancellation;
+mod event;
+mod event_count;
+mod fair_rw_lock;
+pub mod futex;
+mod gate;
+mod latch;
+mod once_cell;
+mod parker;
+mod rate_limiter;
+mod rcu;
+mod semaphore;
+mod sharded_lock;
+mod spin_lock;
+mod wait_group;
+
+pub use self::backoff::{AdaptiveBackoff, Backoff, Contention};
+pub use self::barrier::{Barrier, BarrierWaitResult};
+pub use self::cache_padded::CachePadded;
+pub use self::cancellation::CancellationToken;
+pub use self::event::Event;
+pub use self::event_count::EventCount;
+pub use self::fair_rw_lock::{FairRwLock, FairRwLockReadGuard, FairRwLockWriteGuard, Policy};
+pub use self::gate::Gate;
+pub use self::latch::CountDownLatch;
+pub use self::once_cell::{Lazy, OnceCell};
+pub use self::parker::{Parker, Unparker};
+pub use self::rate_limiter::RateLimiter;
+pub use self::rcu::{Rcu, RcuReadGuard};
+pub use self::semaphore::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+pub use self::sharded_lock::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
+pub use self::spin_lock::{SpinLock, SpinLockGuard};
+pub use self::wait_group::WaitGroup;
@@ -0,0 +1,236 @@
+//! Cells that are initialized exactly once, from any number of threads.
+//!
+//! The first thread to call `get_or_init` runs the initializer, and every
+//! other thread blocks until the value is available. If the initializer
+//! panics, the cell is left uninitialized and one of the blocked threads
+//! takes over.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering::*};
+
+const INCOMPLETE: usize = 0;
+const RUNNING: usize = 1;
+const COMPLETE: usize = 2;
+
+pub struct OnceCell<T> {
+    state: AtomicUsize,
+    guard: Mutex<()>,
+    waker: Condvar,
+    value: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<T: Send> Send for OnceCell<T> {}
+unsafe impl<T: Send + Sync> Sync for OnceCell<T> {}
+
+/// Resets the cell if the initializer panics
+struct Reset<'a, T> {
+    cell: &'a OnceCell<T>,
+}
+
+impl<'a, T> Drop for Reset<'a, T> {
+    fn drop(&mut self) {
+        let _guard = self.cell.guard.lock().unwrap();
+        self.cell.state.store(INCOMPLETE, Release);
+        self.cell.waker.notify_all();
+    }
+}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> OnceCell<T> {
+        OnceCell {
+            state: AtomicUsize::new(INCOMPLETE),
+            guard: Mutex::new(()),
+            waker: Condvar::new(),
+            value: UnsafeCell::new(None),
+        }
+    }
+
+    /// The value, if the cell has been initialized
+    pub fn get(&self) -> Option<&T> {
+        if self.state.load(Acquire) == COMPLETE {
+            unsafe { (*self.value.get()).as_ref() }
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        unsafe { (*self.value.get()).as_mut() }
+    }
+
+    /// Initialize the cell with `value`, returning it back if the cell was
+    /// already initialized
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.initialize(|| value.take().unwrap());
+        match value {
+            Some(value) => Err(value),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the value, running `init` to produce it if the cell is empty.
+    /// Calling `get_or_init` on the same cell from within `init` deadlocks.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, init: F) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+        self.initialize(init);
+        self.get().unwrap()
+    }
+
+    fn initialize<F: FnOnce() -> T>(&self, init: F) {
+        let mut init = Some(init);
+        loop {
+            match self
+                .state
+                .compare_exchange(INCOMPLETE, RUNNING, Acquire, Acquire)
+            {
+                Ok(_) => {
+                    let reset = Reset { cell: self };
+                    let value = (init.take().unwrap())();
+                    unsafe { *self.value.get() = Some(value) };
+                    mem::forget(reset);
+
+                    let _guard = self.guard.lock().unwrap();
+                    self.state.store(COMPLETE, Release);
+                    self.waker.notify_all();
+                    return;
+                }
+                Err(COMPLETE) => return,
+                Err(_) => {
+                    let mut guard = self.guard.lock().unwrap();
+                    while self.state.load(Acquire) == RUNNING {
+                        guard = self.waker.wait(guard).unwrap();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> OnceCell<T> {
+        OnceCell::new()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OnceCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("OnceCell").field(value).finish(),
+            None => f.write_str("OnceCell(<uninit>)"),
+        }
+    }
+}
+
+/// A value initialized by `init` on first access
+pub struct Lazy<T, F = fn() -> T> {
+    cell: OnceCell<T>,
+    init: Cell<Option<F>>,
+}
+
+// `init` is only taken by the thread that wins the right to initialize
+unsafe impl<T: Send + Sync, F: Send> Sync for Lazy<T, F> {}
+
+impl<T, F: FnOnce() -> T> Lazy<T, F> {
+    pub const fn new(init: F) -> Lazy<T, F> {
+        Lazy {
+            cell: OnceCell::new(),
+            init: Cell::new(Some(init)),
+        }
+    }
+
+    /// Run the initializer if it hasn't run yet, and return the value
+    pub fn force(this: &Lazy<T, F>) -> &T {
+        this.cell.get_or_init(|| match this.init.take() {
+            Some(init) => init(),
+            None => panic!("Lazy initializer panicked previously"),
+        })
+    }
+}
+
+impl<T, F: FnOnce() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for Lazy<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Lazy").field("cell", &self.cell).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn set_once() {
+        let cell = OnceCell::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(1), Ok(()));
+        assert_eq!(cell.set(2), Err(2));
+        assert_eq!(cell.get_or_init(|| 3), &1);
+        assert_eq!(cell.into_inner(), Some(1));
+    }
+
+    #[test]
+    fn init_runs_once() {
+        let cell = Arc::new(OnceCell::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handles = (0..8)
+            .map(|i| {
+                let cell = cell.clone();
+                let calls = calls.clone();
+                thread::spawn(move || {
+                    *cell.get_or_init(|| {
+                        calls.fetch_add(1, SeqCst);
+                        i
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        let values = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(calls.load(SeqCst), 1);
+        assert!(values.iter().all(|&v| v == values[0]));
+    }
+
+    #[test]
+    fn panicking_init() {
+        let cell = OnceCell::new();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            cell.get_or_init(|| panic!("init failed"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.get_or_init(|| 5), &5);
+    }
+
+    #[test]
+    fn lazy_static() {
+        static VALUE: Lazy<Vec<u32>> = Lazy::new(|| vec![1, 2, 3]);
+        let handles = (0..4)
+            .map(|_| thread::spawn(|| VALUE.len()))
+            .collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 3);
+        }
+    }
+}
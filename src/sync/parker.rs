@@ -0,0 +1,170 @@
+//! Thread parking with token semantics.
+//!
+//! A `Parker` holds at most one token. `Unparker::unpark` makes the token
+//! available, and `Parker::park` consumes it, blocking until it becomes
+//! available if necessary. Because the token is sticky, an `unpark` that
+//! races ahead of the matching `park` is never lost: the `park` simply
+//! returns immediately.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, Ordering::*};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sync::futex;
+
+const EMPTY: u32 = 0;
+const NOTIFIED: u32 = 1;
+/// `EMPTY - 1`, so that parking is a single decrement
+const PARKED: u32 = u32::MAX;
+
+struct Inner {
+    state: AtomicU32,
+}
+
+/// The parking half, owned by the thread that blocks
+pub struct Parker {
+    unparker: Unparker,
+    // Only one thread may park on a token at a time
+    _marker: PhantomData<*const ()>,
+}
+
+unsafe impl Send for Parker {}
+
+/// The waking half, which can be cloned and shared between threads
+pub struct Unparker {
+    inner: Arc<Inner>,
+}
+
+impl Parker {
+    pub fn new() -> Parker {
+        Parker {
+            unparker: Unparker {
+                inner: Arc::new(Inner {
+                    state: AtomicU32::new(EMPTY),
+                }),
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Block until the token is available, then consume it. May also
+    /// return spuriously, so callers should recheck their condition.
+    pub fn park(&self) {
+        self.unparker.inner.park(None);
+    }
+
+    /// Like `park`, but gives up after `timeout`
+    pub fn park_timeout(&self, timeout: Duration) {
+        self.park_deadline(Instant::now() + timeout);
+    }
+
+    /// Like `park`, but gives up at `deadline`
+    pub fn park_deadline(&self, deadline: Instant) {
+        self.unparker.inner.park(Some(deadline));
+    }
+
+    pub fn unparker(&self) -> &Unparker {
+        &self.unparker
+    }
+}
+
+impl Default for Parker {
+    fn default() -> Parker {
+        Parker::new()
+    }
+}
+
+impl Unparker {
+    /// Make the token available, waking the parked thread if there is one
+    pub fn unpark(&self) {
+        self.inner.unpark();
+    }
+}
+
+impl Clone for Unparker {
+    fn clone(&self) -> Unparker {
+        Unparker {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Inner {
+    fn park(&self, deadline: Option<Instant>) {
+        // NOTIFIED -> EMPTY consumes the token, EMPTY -> PARKED announces
+        // that we are about to sleep
+        if self.state.fetch_sub(1, Acquire) == NOTIFIED {
+            return;
+        }
+        loop {
+            match deadline {
+                None => futex::wait_on(&self.state, PARKED),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        // Timed out, but consume a token if one just arrived
+                        self.state.swap(EMPTY, Acquire);
+                        return;
+                    }
+                    futex::wait_on_timeout(&self.state, PARKED, deadline - now);
+                }
+            }
+            if self
+                .state
+                .compare_exchange(NOTIFIED, EMPTY, Acquire, Relaxed)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+
+    fn unpark(&self) {
+        if self.state.swap(NOTIFIED, Release) == PARKED {
+            futex::wake(&self.state, 1);
+        }
+    }
+}
+
+impl fmt::Debug for Parker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Parker { .. }")
+    }
+}
+
+impl fmt::Debug for Unparker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Unparker { .. }")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn token_is_sticky() {
+        let parker = Parker::new();
+        parker.unparker().unpark();
+        parker.unparker().unpark();
+        // Only one token is stored
+        parker.park();
+        let start = Instant::now();
+        parker.park_timeout(Duration::from_millis(10));
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+
+    #[test]
+    fn unpark_from_thread() {
+        let parker = Parker::new();
+        let unparker = parker.unparker().clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            unparker.unpark();
+        });
+        parker.park();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! A token-bucket rate limiter.
+//!
+//! The bucket refills continuously at `tokens` per `interval` and holds at
+//! most `burst` tokens. Each acquired token is one permitted operation, so
+//! a producer calling `acquire` before every send is throttled to the
+//! configured rate, while short bursts of up to `burst` sends go through
+//! immediately after a quiet period.
+
+use primitive::blocking::Mutex;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+pub struct RateLimiter {
+    /// Tokens added per second
+    rate: f64,
+    burst: u32,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    /// Allow `tokens` operations per `interval`, with bursts of up to
+    /// `burst` operations. The bucket starts full.
+    ///
+    /// Panics if `tokens`, `interval`, or `burst` is zero.
+    pub fn new(tokens: u32, interval: Duration, burst: u32) -> RateLimiter {
+        assert!(tokens > 0, "RateLimiter needs a non-zero rate");
+        assert!(
+            interval > Duration::from_secs(0),
+            "RateLimiter needs a non-zero interval"
+        );
+        assert!(burst > 0, "RateLimiter needs a non-zero burst size");
+        RateLimiter {
+            rate: f64::from(tokens) / interval.as_secs_f64(),
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(burst),
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(f64::from(self.burst));
+        bucket.last = now;
+    }
+
+    /// Take `n` tokens if available, otherwise return how long until they
+    /// will be
+    fn take(&self, n: u32) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        let n = f64::from(n);
+        if bucket.tokens >= n {
+            bucket.tokens -= n;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((n - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Block until a token is available
+    pub fn acquire(&self) {
+        self.acquire_many(1)
+    }
+
+    /// Block until `n` tokens are available, and take them all at once.
+    ///
+    /// Panics if `n` is larger than the burst size, since the bucket could
+    /// never hold that many tokens.
+    pub fn acquire_many(&self, n: u32) {
+        assert!(n <= self.burst, "requested more tokens than the burst size");
+        while let Err(wait) = self.take(n) {
+            thread::sleep(wait);
+        }
+    }
+
+    /// Take a token if one is available, without blocking
+    pub fn try_acquire(&self) -> bool {
+        self.take(1).is_ok()
+    }
+
+    /// Take `n` tokens if they are all available, without blocking
+    pub fn try_acquire_many(&self, n: u32) -> bool {
+        self.take(n).is_ok()
+    }
+
+    /// Tokens currently available, rounded down
+    pub fn available(&self) -> u32 {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        bucket.tokens as u32
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn burst() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60), 3);
+        assert!(limiter.try_acquire_many(2));
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        assert_eq!(limiter.available(), 0);
+    }
+
+    #[test]
+    fn refill() {
+        let limiter = RateLimiter::new(100, Duration::from_millis(100), 1);
+        assert!(limiter.try_acquire());
+        thread::sleep(Duration::from_millis(20));
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn throttle() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(10), 1);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire();
+        }
+        // The first token is available immediately
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+}
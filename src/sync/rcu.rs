@@ -0,0 +1,158 @@
+//! A read-copy-update cell for read-mostly data.
+//!
+//! Readers get a reference to the current version under an epoch guard,
+//! which costs a thread-local store and a fence but no atomic
+//! read-modify-write, so readers never contend with each other. Writers
+//! swap in a whole new version; the old one is freed through the epoch
+//! collector once no reader can still be looking at it.
+
+use epoch::{self, Guard};
+use std::fmt;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering::*};
+use sync::Backoff;
+
+pub struct Rcu<T: Send + Sync + 'static> {
+    ptr: AtomicPtr<T>,
+}
+
+unsafe impl<T: Send + Sync + 'static> Send for Rcu<T> {}
+unsafe impl<T: Send + Sync + 'static> Sync for Rcu<T> {}
+
+/// A snapshot of the value at the time of the read. The snapshot stays
+/// valid even if a writer replaces the value concurrently.
+pub struct RcuReadGuard<'a, T: 'a> {
+    value: &'a T,
+    _guard: Guard,
+}
+
+impl<T: Send + Sync + 'static> Rcu<T> {
+    pub fn new(value: T) -> Rcu<T> {
+        Rcu {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+        }
+    }
+
+    /// Take a snapshot of the current version
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        let guard = epoch::pin();
+        let value = unsafe { &*self.ptr.load(Acquire) };
+        RcuReadGuard {
+            value,
+            _guard: guard,
+        }
+    }
+
+    /// Publish a new version. Readers holding a snapshot of the old version
+    /// keep seeing it until they drop their guard.
+    pub fn store(&self, value: T) {
+        let new = Box::into_raw(Box::new(value));
+        let guard = epoch::pin();
+        let old = self.ptr.swap(new, AcqRel);
+        unsafe { guard.defer_destroy(old) };
+    }
+
+    /// Publish a version derived from the current one. `f` may be called
+    /// more than once if other writers publish concurrently.
+    pub fn update<F: FnMut(&T) -> T>(&self, mut f: F) {
+        let guard = epoch::pin();
+        let backoff = Backoff::new();
+        let mut current = self.ptr.load(Acquire);
+        loop {
+            let new = Box::into_raw(Box::new(f(unsafe { &*current })));
+            match self.ptr.compare_exchange(current, new, AcqRel, Acquire) {
+                Ok(old) => {
+                    unsafe { guard.defer_destroy(old) };
+                    return;
+                }
+                Err(actual) => {
+                    drop(unsafe { Box::from_raw(new) });
+                    current = actual;
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut **self.ptr.get_mut() }
+    }
+
+    pub fn into_inner(self) -> T {
+        let ptr = self.ptr.swap(ptr::null_mut(), Relaxed);
+        *unsafe { Box::from_raw(ptr) }
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        let ptr = *self.ptr.get_mut();
+        if !ptr.is_null() {
+            drop(unsafe { Box::from_raw(ptr) });
+        }
+    }
+}
+
+impl<T: Send + Sync + Default + 'static> Default for Rcu<T> {
+    fn default() -> Rcu<T> {
+        Rcu::new(T::default())
+    }
+}
+
+impl<'a, T> Deref for RcuReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: Send + Sync + fmt::Debug + 'static> fmt::Debug for Rcu<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Rcu").field(&*self.read()).finish()
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for RcuReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.value, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn snapshot_survives_store() {
+        let rcu = Rcu::new(vec![1]);
+        let snapshot = rcu.read();
+        rcu.store(vec![2]);
+        assert_eq!(*snapshot, vec![1]);
+        assert_eq!(*rcu.read(), vec![2]);
+        drop(snapshot);
+        assert_eq!(rcu.into_inner(), vec![2]);
+    }
+
+    #[test]
+    fn concurrent_updates() {
+        let rcu = Arc::new(Rcu::new(0usize));
+        let handles = (0..4)
+            .map(|_| {
+                let rcu = rcu.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        rcu.update(|v| v + 1);
+                        let _ = *rcu.read();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*rcu.read(), 4000);
+    }
+}
@@ -0,0 +1,295 @@
+//! A counting semaphore handing out RAII permits.
+//!
+//! Blocked acquirers are served in the order they arrived, so a thread
+//! can't be starved by luckier competitors grabbing every released
+//! permit. The flip side is that a waiter asking for many permits holds
+//! up everyone behind it, even those whose requests would already fit,
+//! and `try_acquire` fails while anyone is waiting.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::fmt;
+use std::sync::Arc;
+
+pub struct Semaphore {
+    state: Mutex<State>,
+    waker: Condvar,
+}
+
+struct State {
+    permits: usize,
+    /// Ticket handed to the next acquirer that has to wait
+    next: u64,
+    /// Ticket of the waiter at the front of the line
+    serving: u64,
+}
+
+/// Permits borrowed from a `Semaphore`, released when dropped
+pub struct SemaphorePermit<'a> {
+    sem: &'a Semaphore,
+    count: usize,
+}
+
+/// Permits acquired through an `Arc<Semaphore>`. Since they don't borrow
+/// the semaphore they can be sent along with a work item through a channel
+/// and released by the consumer.
+pub struct OwnedSemaphorePermit {
+    sem: Arc<Semaphore>,
+    count: usize,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: Mutex::new(State {
+                permits,
+                next: 0,
+                serving: 0,
+            }),
+            waker: Condvar::new(),
+        }
+    }
+
+    /// Number of permits currently available
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    /// Add `n` permits to the semaphore, waking blocked acquirers
+    pub fn add_permits(&self, n: usize) {
+        self.state.lock().unwrap().permits += n;
+        self.waker.notify_all();
+    }
+
+    fn take(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        if state.serving == state.next && state.permits >= n {
+            // Nobody waiting, and no need to
+            state.permits -= n;
+            return;
+        }
+        let ticket = state.next;
+        state.next += 1;
+        while state.serving != ticket || state.permits < n {
+            state = self.waker.wait(state).unwrap();
+        }
+        state.permits -= n;
+        state.serving += 1;
+        // The next in line may fit in what is left
+        self.waker.notify_all();
+    }
+
+    fn try_take(&self, n: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        // Don't jump the line
+        if state.serving == state.next && state.permits >= n {
+            state.permits -= n;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Block until a permit is available
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Block until `n` permits are available, and take them all at once.
+    /// Requesting more permits than will ever be available blocks forever.
+    pub fn acquire_many(&self, n: usize) -> SemaphorePermit<'_> {
+        self.take(n);
+        SemaphorePermit {
+            sem: self,
+            count: n,
+        }
+    }
+
+    /// Take a permit if one is available, without blocking
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Take `n` permits if they are all available, without blocking
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        if self.try_take(n) {
+            Some(SemaphorePermit {
+                sem: self,
+                count: n,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Block until a permit is available, returning a permit that keeps
+    /// the semaphore alive
+    pub fn acquire_owned(self: &Arc<Self>) -> OwnedSemaphorePermit {
+        self.acquire_many_owned(1)
+    }
+
+    /// Block until `n` permits are available, returning a permit that keeps
+    /// the semaphore alive
+    pub fn acquire_many_owned(self: &Arc<Self>, n: usize) -> OwnedSemaphorePermit {
+        self.take(n);
+        OwnedSemaphorePermit {
+            sem: self.clone(),
+            count: n,
+        }
+    }
+
+    /// Take a permit if one is available, without blocking
+    pub fn try_acquire_owned(self: &Arc<Self>) -> Option<OwnedSemaphorePermit> {
+        if self.try_take(1) {
+            Some(OwnedSemaphorePermit {
+                sem: self.clone(),
+                count: 1,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> SemaphorePermit<'a> {
+    /// Number of permits held
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Drop the permit without returning it to the semaphore
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl OwnedSemaphorePermit {
+    /// Number of permits held
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Drop the permit without returning it to the semaphore
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl<'a> Drop for SemaphorePermit<'a> {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.sem.add_permits(self.count);
+        }
+    }
+}
+
+impl Drop for OwnedSemaphorePermit {
+    fn drop(&mut self) {
+        if self.count > 0 {
+            self.sem.add_permits(self.count);
+        }
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for SemaphorePermit<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SemaphorePermit")
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+impl fmt::Debug for OwnedSemaphorePermit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedSemaphorePermit")
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn release_on_drop() {
+        let sem = Semaphore::new(2);
+        let a = sem.acquire();
+        let b = sem.try_acquire().unwrap();
+        assert!(sem.try_acquire().is_none());
+        drop(a);
+        assert_eq!(sem.available_permits(), 1);
+        drop(b);
+        assert_eq!(sem.available_permits(), 2);
+    }
+
+    #[test]
+    fn acquire_many() {
+        let sem = Semaphore::new(4);
+        let permit = sem.acquire_many(3);
+        assert_eq!(permit.count(), 3);
+        assert!(sem.try_acquire_many(2).is_none());
+        permit.forget();
+        assert_eq!(sem.available_permits(), 1);
+    }
+
+    #[test]
+    fn caps_in_flight() {
+        let sem = Arc::new(Semaphore::new(3));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let handles = (0..16)
+            .map(|_| {
+                let permit = sem.acquire_owned();
+                let in_flight = in_flight.clone();
+                assert!(in_flight.fetch_add(1, SeqCst) < 3);
+                thread::spawn(move || {
+                    in_flight.fetch_sub(1, SeqCst);
+                    drop(permit);
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(sem.available_permits(), 3);
+    }
+
+    #[test]
+    fn waiters_served_in_order() {
+        let sem = Arc::new(Semaphore::new(0));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let handles = (0..3)
+            .map(|id| {
+                let (sem, order) = (sem.clone(), order.clone());
+                let handle = thread::spawn(move || {
+                    sem.acquire().forget();
+                    order.lock().unwrap().push(id);
+                });
+                // Give each thread time to queue up before the next
+                thread::sleep(Duration::from_millis(10));
+                handle
+            })
+            .collect::<Vec<_>>();
+        assert!(sem.try_acquire().is_none());
+        for served in 1..=3 {
+            sem.add_permits(1);
+            while order.lock().unwrap().len() < served {
+                thread::yield_now();
+            }
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,211 @@
+//! A reader-writer lock split into per-core shards.
+//!
+//! Each reader only touches the shard assigned to its thread, so readers on
+//! different cores never contend on the same cache line. Writers pay for
+//! this by acquiring every shard, which makes `ShardedLock` a good fit for
+//! read-dominated data like routing tables that are consulted on every send
+//! but rarely updated.
+
+use primitive::blocking::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use primitive::PoisonError;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering::*};
+use std::thread;
+use sync::CachePadded;
+
+/// Upper bound on the number of shards, regardless of core count
+const MAX_SHARDS: usize = 64;
+
+pub struct ShardedLock<T: ?Sized> {
+    /// Each shard is on its own cache line
+    shards: Box<[CachePadded<RwLock<()>>]>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for ShardedLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for ShardedLock<T> {}
+
+pub struct ShardedLockReadGuard<'a, T: ?Sized> {
+    lock: &'a ShardedLock<T>,
+    _guard: RwLockReadGuard<'a, ()>,
+}
+
+pub struct ShardedLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a ShardedLock<T>,
+    _guards: Vec<RwLockWriteGuard<'a, ()>>,
+}
+
+/// Index of the calling thread, assigned round-robin on first use
+fn thread_index() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local! {
+        static INDEX: usize = NEXT.fetch_add(1, Relaxed);
+    }
+    INDEX.with(|index| *index)
+}
+
+impl<T> ShardedLock<T> {
+    /// Create a lock with one shard per available core
+    pub fn new(data: T) -> ShardedLock<T> {
+        let shards = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        ShardedLock::with_shards(data, shards)
+    }
+
+    /// Create a lock with a specific number of read shards
+    pub fn with_shards(data: T, shards: usize) -> ShardedLock<T> {
+        let shards = shards.clamp(1, MAX_SHARDS);
+        ShardedLock {
+            shards: (0..shards)
+                .map(|_| CachePadded::new(RwLock::new(())))
+                .collect(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> ShardedLock<T> {
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Acquire shared read access through the calling thread's shard.
+    ///
+    /// The shards only guard `()`, so a writer that panicked doesn't poison
+    /// the lock for everyone after it. The data is left as the writer left
+    /// it.
+    pub fn read(&self) -> ShardedLockReadGuard<'_, T> {
+        let shard = &self.shards[thread_index() % self.shards.len()];
+        ShardedLockReadGuard {
+            lock: self,
+            _guard: shard.read().unwrap_or_else(PoisonError::into_inner),
+        }
+    }
+
+    /// Acquire exclusive write access. Shards are always locked in the same
+    /// order, so concurrent writers can't deadlock.
+    pub fn write(&self) -> ShardedLockWriteGuard<'_, T> {
+        ShardedLockWriteGuard {
+            lock: self,
+            _guards: self
+                .shards
+                .iter()
+                .map(|shard| shard.write().unwrap_or_else(PoisonError::into_inner))
+                .collect(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+impl<T: Default> Default for ShardedLock<T> {
+    fn default() -> ShardedLock<T> {
+        ShardedLock::new(T::default())
+    }
+}
+
+impl<'a, T: ?Sized> Deref for ShardedLockReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for ShardedLockWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for ShardedLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ShardedLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedLock")
+            .field("shards", &self.shards.len())
+            .field("data", &&*self.read())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn read_write() {
+        let lock = ShardedLock::with_shards(vec![1, 2], 4);
+        assert_eq!(lock.shards(), 4);
+        {
+            let a = lock.read();
+            let b = lock.read();
+            assert_eq!(*a, *b);
+        }
+        lock.write().push(3);
+        assert_eq!(*lock.read(), vec![1, 2, 3]);
+        assert_eq!(lock.into_inner(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn shard_bounds() {
+        assert_eq!(ShardedLock::with_shards((), 0).shards(), 1);
+        assert_eq!(ShardedLock::with_shards((), 1000).shards(), MAX_SHARDS);
+    }
+
+    #[test]
+    fn concurrent_readers_and_writers() {
+        let lock = Arc::new(ShardedLock::new((0usize, 0usize)));
+        let handles = (0..8)
+            .map(|i| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        if i % 4 == 0 {
+                            let mut pair = lock.write();
+                            pair.0 += 1;
+                            pair.1 += 1;
+                        } else {
+                            let pair = lock.read();
+                            assert_eq!(pair.0, pair.1);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*lock.read(), (2000, 2000));
+    }
+
+    #[test]
+    fn survives_panicking_writer() {
+        let lock = Arc::new(ShardedLock::with_shards(0, 4));
+        let writer = lock.clone();
+        let result = thread::spawn(move || {
+            let mut value = writer.write();
+            *value = 1;
+            panic!("while writing");
+        })
+        .join();
+        assert!(result.is_err());
+        assert_eq!(*lock.read(), 1);
+        *lock.write() = 2;
+        assert_eq!(*lock.read(), 2);
+    }
+}
@@ -0,0 +1,209 @@
+//! A spinlock for tiny critical sections.
+//!
+//! Contended acquirers go through three stages: busy-waiting with an
+//! exponentially growing number of spin hints, yielding the thread, and
+//! finally parking on a condvar until the holder releases the lock. The
+//! parking stage keeps a preempted lock holder from having its time slice
+//! stolen by a crowd of spinning waiters.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering::*};
+use sync::Backoff;
+
+const UNLOCKED: usize = 0;
+const LOCKED: usize = 1;
+/// Locked, and at least one thread may be parked waiting for it
+const PARKED: usize = 2;
+
+pub struct SpinLock<T: ?Sized> {
+    state: AtomicUsize,
+    guard: Mutex<()>,
+    waker: Condvar,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+
+/// Releases the lock when dropped
+pub struct SpinLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinLock<T>,
+}
+
+unsafe impl<'a, T: ?Sized + Sync> Sync for SpinLockGuard<'a, T> {}
+
+impl<T> SpinLock<T> {
+    pub fn new(data: T) -> SpinLock<T> {
+        SpinLock {
+            state: AtomicUsize::new(UNLOCKED),
+            guard: Mutex::new(()),
+            waker: Condvar::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> SpinLock<T> {
+    /// Acquire the lock, blocking the current thread until it is available
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+        SpinLockGuard { lock: self }
+    }
+
+    fn lock_contended(&self) {
+        let backoff = Backoff::new();
+        while !backoff.is_completed() {
+            backoff.snooze();
+            if self.state.load(Relaxed) == UNLOCKED
+                && self
+                    .state
+                    .compare_exchange_weak(UNLOCKED, LOCKED, Acquire, Relaxed)
+                    .is_ok()
+            {
+                return;
+            }
+        }
+
+        // Marking the lock as PARKED under the guard mutex means the
+        // releasing thread can't miss us: it must take the guard to notify.
+        // We may acquire the lock in the PARKED state while nobody else is
+        // actually parked, which only costs one spurious notification.
+        let mut guard = self.guard.lock().unwrap();
+        while self.state.swap(PARKED, Acquire) != UNLOCKED {
+            guard = self.waker.wait(guard).unwrap();
+        }
+    }
+
+    /// Acquire the lock if it is not currently held
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+            .ok()
+            .map(|_| SpinLockGuard { lock: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.load(Relaxed) != UNLOCKED
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Release) == PARKED {
+            let _guard = self.guard.lock().unwrap();
+            self.waker.notify_one();
+        }
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> SpinLock<T> {
+        SpinLock::new(T::default())
+    }
+}
+
+impl<'a, T: ?Sized> Deref for SpinLockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for SpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for SpinLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("SpinLock").field("data", &&*guard).finish(),
+            None => f
+                .debug_struct("SpinLock")
+                .field("data", &"<locked>")
+                .finish(),
+        }
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for SpinLockGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn try_lock() {
+        let lock = SpinLock::new(0);
+        let guard = lock.lock();
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+        assert!(lock.try_lock().is_some());
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn contended() {
+        const THREADS: usize = 8;
+        const ITERS: usize = 10_000;
+        let lock = Arc::new(SpinLock::new(0));
+        let handles = (0..THREADS)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    for _ in 0..ITERS {
+                        *lock.lock() += 1;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(*lock.lock(), THREADS * ITERS);
+    }
+
+    #[test]
+    fn parked_holder() {
+        let lock = Arc::new(SpinLock::new(Vec::new()));
+        let guard = lock.lock();
+        let waiter = {
+            let lock = lock.clone();
+            thread::spawn(move || lock.lock().push(2))
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(guard);
+        waiter.join().unwrap();
+        assert_eq!(*lock.lock(), vec![2]);
+    }
+}
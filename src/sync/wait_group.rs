@@ -0,0 +1,111 @@
+//! A group of participants that can be waited on until every member has
+//! finished.
+
+use primitive::blocking::{Condvar, Mutex};
+use std::fmt;
+use std::sync::Arc;
+
+struct Inner {
+    count: Mutex<usize>,
+    waker: Condvar,
+}
+
+/// Cloning a `WaitGroup` adds a participant, and dropping one marks that
+/// participant as done. `wait` blocks until all other participants are done.
+pub struct WaitGroup {
+    inner: Arc<Inner>,
+}
+
+impl WaitGroup {
+    pub fn new() -> WaitGroup {
+        WaitGroup {
+            inner: Arc::new(Inner {
+                count: Mutex::new(1),
+                waker: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Mark this participant as done and block until every other
+    /// participant has been dropped
+    pub fn wait(self) {
+        let inner = self.inner.clone();
+        drop(self);
+        let mut count = inner.count.lock().unwrap();
+        while *count > 0 {
+            count = inner.waker.wait(count).unwrap();
+        }
+    }
+}
+
+impl Default for WaitGroup {
+    fn default() -> WaitGroup {
+        WaitGroup::new()
+    }
+}
+
+impl Clone for WaitGroup {
+    fn clone(&self) -> WaitGroup {
+        *self.inner.count.lock().unwrap() += 1;
+        WaitGroup {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Drop for WaitGroup {
+    fn drop(&mut self) {
+        let mut count = self.inner.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.inner.waker.notify_all();
+        }
+    }
+}
+
+impl fmt::Debug for WaitGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = *self.inner.count.lock().unwrap();
+        f.debug_struct("WaitGroup").field("count", &count).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+    use std::thread;
+
+    #[test]
+    fn wait_single() {
+        let wg = WaitGroup::new();
+        wg.wait();
+    }
+
+    #[test]
+    fn wait_for_workers() {
+        let wg = WaitGroup::new();
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..8 {
+            let wg = wg.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                done.fetch_add(1, Relaxed);
+                drop(wg);
+            });
+        }
+        wg.wait();
+        assert_eq!(done.load(Relaxed), 8);
+    }
+
+    #[test]
+    fn count() {
+        let wg = WaitGroup::new();
+        let a = wg.clone();
+        let b = a.clone();
+        assert_eq!(*wg.inner.count.lock().unwrap(), 3);
+        drop(a);
+        drop(b);
+        assert_eq!(*wg.inner.count.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,439 @@
+//! Linearizability checking for concurrent histories.
+//!
+//! `check` in the parent module compares a channel with its model one op at
+//! a time, on one thread. Here several threads run ops at once through a
+//! `History`, which stamps each op with when it was invoked and when it
+//! returned. `linearizable` then searches for a single sequential order of
+//! the ops that respects those stamps, so an op that returned before
+//! another was invoked comes first, and that gives every op the result the
+//! model would have. A channel or backend with no such order for some
+//! history has a bug, whichever interleaving produced it.
+//!
+//! ```
+//! use myriad::mpmc;
+//! use myriad::testing::history::History;
+//! use myriad::testing::Order;
+//! use std::thread;
+//!
+//! let (tx, rx) = mpmc::queue();
+//! let history = History::new();
+//! let workers: Vec<_> = (0..2u64)
+//!     .map(|id| {
+//!         let (tx, rx, history) = (tx.clone(), rx.clone(), history.clone());
+//!         thread::spawn(move || {
+//!             for i in 0..20 {
+//!                 history.send(&tx, id * 100 + i);
+//!                 history.try_recv(&rx);
+//!             }
+//!         })
+//!     })
+//!     .collect();
+//! for worker in workers {
+//!     worker.join().unwrap();
+//! }
+//! history.check(Order::Fifo).unwrap();
+//! ```
+//!
+//! Backend authors can wrap a `LockFree` implementation in `Recorded` to
+//! record every push and pop made through it.
+//!
+//! The search is exponential in the number of ops that overlap in time, so
+//! histories should stay at a few hundred ops, and pushed values should be
+//! distinct.
+
+use super::{Model, Order};
+use mpmc::{LockFree, Receiver, Sender};
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A completed op and its result
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Call<T> {
+    /// A push the structure took. Pushes it declined, as dedup does for a
+    /// duplicate, leave no trace in the history
+    Push(T),
+    Pop(Option<T>),
+}
+
+/// One op in a history, with the logical times it was invoked and
+/// returned. Only the order of the stamps matters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event<T> {
+    pub call: Call<T>,
+    pub invoked: u64,
+    pub returned: u64,
+}
+
+struct Shared<T> {
+    clock: AtomicU64,
+    events: Mutex<Vec<Event<T>>>,
+}
+
+/// Records ops from any number of threads. Clones share one history.
+pub struct History<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> History<T> {
+    pub fn new() -> History<T> {
+        History {
+            shared: Arc::new(Shared {
+                clock: AtomicU64::new(0),
+                events: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.shared.clock.fetch_add(1, SeqCst)
+    }
+
+    fn log(&self, call: Call<T>, invoked: u64) {
+        let returned = self.tick();
+        self.shared
+            .events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Event {
+                call,
+                invoked,
+                returned,
+            });
+    }
+
+    /// Run `push`, which hands `item` to the structure and returns whether
+    /// it was taken, and record it
+    pub fn push<F: FnOnce(T) -> bool>(&self, item: T, push: F) -> bool
+    where
+        T: Clone,
+    {
+        let invoked = self.tick();
+        let taken = push(item.clone());
+        if taken {
+            self.log(Call::Push(item), invoked);
+        }
+        taken
+    }
+
+    /// Run `pop` and record what it returned
+    pub fn pop<F: FnOnce() -> Option<T>>(&self, pop: F) -> Option<T>
+    where
+        T: Clone,
+    {
+        let invoked = self.tick();
+        let item = pop();
+        self.log(Call::Pop(item.clone()), invoked);
+        item
+    }
+
+    /// Send `item` on `sender`, and record it if the channel took it
+    pub fn send(&self, sender: &Sender<T>, item: T) -> bool
+    where
+        T: Send + Clone,
+    {
+        self.push(item, |item| sender.send(item).is_ok())
+    }
+
+    /// Try to receive from `receiver` and record the result
+    pub fn try_recv(&self, receiver: &Receiver<T>) -> Option<T>
+    where
+        T: Send + Clone,
+    {
+        self.pop(|| receiver.try_recv().ok())
+    }
+
+    /// Every op recorded so far, in the order they returned
+    pub fn events(&self) -> Vec<Event<T>>
+    where
+        T: Clone,
+    {
+        self.shared
+            .events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Check the history so far with `linearizable`
+    pub fn check(&self, order: Order) -> Result<(), Violation<T>>
+    where
+        T: Clone + Eq + Hash,
+    {
+        linearizable(order, &self.events())
+    }
+}
+
+impl<T> Clone for History<T> {
+    fn clone(&self) -> History<T> {
+        History {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Default for History<T> {
+    fn default() -> History<T> {
+        History::new()
+    }
+}
+
+impl<T> fmt::Debug for History<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let events = self
+            .shared
+            .events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("History")
+            .field("events", &events.len())
+            .finish()
+    }
+}
+
+/// A `LockFree` implementation that records every push and pop made
+/// through it. Batch operations fall back to the default methods, so a
+/// batch shows up as one op per item.
+pub struct Recorded<T, L> {
+    inner: L,
+    history: History<T>,
+}
+
+impl<T, L: LockFree<T>> Recorded<T, L> {
+    pub fn new(inner: L, history: History<T>) -> Recorded<T, L> {
+        Recorded { inner, history }
+    }
+}
+
+impl<T: Send + Clone, L: LockFree<T>> LockFree<T> for Recorded<T, L> {
+    fn push(&self, item: T) -> bool {
+        self.history.push(item, |item| self.inner.push(item))
+    }
+
+    fn pop(&self) -> Option<T> {
+        self.history.pop(|| self.inner.pop())
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &T>)) {
+        self.inner.peek(f)
+    }
+}
+
+impl<T, L> fmt::Debug for Recorded<T, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorded")
+            .field("history", &self.history)
+            .finish()
+    }
+}
+
+/// A history with no sequential order that matches the model
+#[derive(Clone, PartialEq)]
+pub struct Violation<T> {
+    /// The longest order found before the search gave up, which ends just
+    /// before the ops that couldn't be placed
+    pub longest: Vec<Call<T>>,
+}
+
+impl<T: fmt::Debug> fmt::Debug for Violation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "not linearizable after {:?}", self.longest)
+    }
+}
+
+struct Search<'a, T> {
+    events: &'a [Event<T>],
+    done: Vec<u64>,
+    path: Vec<usize>,
+    longest: Vec<usize>,
+    // Sets of placed ops, with the model they left, already known to lead
+    // nowhere
+    dead: HashSet<(Vec<u64>, Vec<T>)>,
+}
+
+impl<'a, T: Clone + Eq + Hash> Search<'a, T> {
+    fn is_done(&self, index: usize) -> bool {
+        self.done[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn toggle(&mut self, index: usize) {
+        self.done[index / 64] ^= 1 << (index % 64);
+    }
+
+    fn run(&mut self, model: &Model<T>) -> bool {
+        if self.path.len() == self.events.len() {
+            return true;
+        }
+        let key = (self.done.clone(), model.items.iter().cloned().collect());
+        if self.dead.contains(&key) {
+            return false;
+        }
+        // Any op invoked before every remaining op has returned could be
+        // the next to take effect
+        let deadline = (0..self.events.len())
+            .filter(|&i| !self.is_done(i))
+            .map(|i| self.events[i].returned)
+            .min()
+            .unwrap_or(u64::MAX);
+        for i in 0..self.events.len() {
+            if self.is_done(i) || self.events[i].invoked > deadline {
+                continue;
+            }
+            let mut next = model.clone();
+            let fits = match self.events[i].call {
+                Call::Push(ref item) => {
+                    next.items.push_back(item.clone());
+                    true
+                }
+                Call::Pop(ref item) => next.take() == *item,
+            };
+            if !fits {
+                continue;
+            }
+            self.toggle(i);
+            self.path.push(i);
+            if self.path.len() > self.longest.len() {
+                self.longest = self.path.clone();
+            }
+            if self.run(&next) {
+                return true;
+            }
+            self.path.pop();
+            self.toggle(i);
+        }
+        self.dead.insert(key);
+        false
+    }
+}
+
+/// Check that `events` has a sequential order, consistent with when each
+/// op was invoked and returned, in which every op gets the result a `Model`
+/// with `order` would give it. The structure should start out empty.
+pub fn linearizable<T>(order: Order, events: &[Event<T>]) -> Result<(), Violation<T>>
+where
+    T: Clone + Eq + Hash,
+{
+    let mut search = Search {
+        events,
+        done: vec![0; events.len().div_ceil(64)],
+        path: Vec::new(),
+        longest: Vec::new(),
+        dead: HashSet::new(),
+    };
+    if search.run(&Model::new(order)) {
+        Ok(())
+    } else {
+        Err(Violation {
+            longest: search
+                .longest
+                .iter()
+                .map(|&i| events[i].call.clone())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc;
+    use std::collections::VecDeque;
+    use std::thread;
+
+    fn event(call: Call<u64>, invoked: u64, returned: u64) -> Event<u64> {
+        Event {
+            call,
+            invoked,
+            returned,
+        }
+    }
+
+    #[test]
+    fn channels_are_linearizable() {
+        for &(order, stack) in &[(Order::Fifo, false), (Order::Lifo, true)] {
+            let (tx, rx) = if stack { mpmc::stack() } else { mpmc::queue() };
+            let history = History::new();
+            let workers: Vec<_> = (0..3u64)
+                .map(|id| {
+                    let (tx, rx, history) = (tx.clone(), rx.clone(), history.clone());
+                    thread::spawn(move || {
+                        for i in 0..30 {
+                            history.send(&tx, id * 1000 + i);
+                            if i % 2 == 0 {
+                                history.try_recv(&rx);
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            while history.try_recv(&rx).is_some() {}
+            history.check(order).unwrap();
+        }
+    }
+
+    #[test]
+    fn overlap_allows_either_order() {
+        // The pushes overlap, so 2 may have gone in first
+        let events = vec![
+            event(Call::Push(1), 0, 3),
+            event(Call::Push(2), 1, 2),
+            event(Call::Pop(Some(2)), 4, 5),
+        ];
+        assert!(linearizable(Order::Fifo, &events).is_ok());
+
+        // Now 1 returned before 2 was invoked
+        let events = vec![
+            event(Call::Push(1), 0, 1),
+            event(Call::Push(2), 2, 3),
+            event(Call::Pop(Some(2)), 4, 5),
+            event(Call::Pop(None), 6, 7),
+        ];
+        let violation = linearizable(Order::Fifo, &events).unwrap_err();
+        assert_eq!(violation.longest, vec![Call::Push(1), Call::Push(2)]);
+        assert!(linearizable(Order::Lifo, &events).is_err());
+    }
+
+    /// Claims to be a queue, but hands out the newest item
+    struct Backwards(Mutex<VecDeque<u64>>);
+
+    impl LockFree<u64> for Backwards {
+        fn push(&self, item: u64) -> bool {
+            self.0.lock().unwrap().push_back(item);
+            true
+        }
+
+        fn pop(&self) -> Option<u64> {
+            self.0.lock().unwrap().pop_back()
+        }
+
+        fn len(&self) -> usize {
+            self.0.lock().unwrap().len()
+        }
+
+        fn peek(&self, f: &mut dyn FnMut(&mut dyn Iterator<Item = &u64>)) {
+            f(&mut self.0.lock().unwrap().iter().rev())
+        }
+    }
+
+    #[test]
+    fn catches_broken_backend() {
+        let history = History::new();
+        let backend = Recorded::new(Backwards(Mutex::new(VecDeque::new())), history.clone());
+        for i in 0..4 {
+            backend.push(i);
+        }
+        assert_eq!(backend.pop_batch(2), vec![3, 2]);
+        assert!(history.check(Order::Lifo).is_ok());
+        let violation = history.check(Order::Fifo).unwrap_err();
+        assert_eq!(violation.longest.len(), 4);
+    }
+}
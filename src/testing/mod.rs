@@ -0,0 +1,303 @@
+//! Helpers for property-testing code built on this crate's channels.
+//!
+//! A property test runs a sequence of `Op`s against a real channel and
+//! against a `Model`, a plain `VecDeque`, and checks that both give the
+//! same results at every step. `ops` draws sequences from a seed, so this
+//! works from an ordinary `#[test]` loop over seeds, and the sequences can
+//! as easily come from a property-testing crate's own generators instead.
+//! When a sequence fails, `minimize` cuts it down to the ops that matter.
+//!
+//! ```
+//! use myriad::mpmc;
+//! use myriad::testing::{self, Order};
+//!
+//! for seed in 0..32 {
+//!     let ops = testing::ops(seed, 100);
+//!     let (tx, rx) = mpmc::queue();
+//!     testing::check(Order::Fifo, &ops, &tx, &rx).unwrap();
+//! }
+//! ```
+//!
+//! The crate doesn't depend on a property-testing framework, but `ops` and
+//! `shrink` are the two halves of one. With proptest, a strategy is a seed
+//! mapped through `ops`:
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn matches_model(ops in any::<u64>().prop_map(|seed| testing::ops(seed, 100))) {
+//!         let (tx, rx) = mpmc::queue();
+//!         prop_assert!(testing::check(Order::Fifo, &ops, &tx, &rx).is_ok());
+//!     }
+//! }
+//! ```
+//!
+//! and with quickcheck, `shrink` supplies the smaller candidates:
+//!
+//! ```ignore
+//! #[derive(Clone, Debug)]
+//! struct Ops(Vec<Op<u64>>);
+//!
+//! impl Arbitrary for Ops {
+//!     fn arbitrary(g: &mut Gen) -> Ops {
+//!         Ops(testing::ops(u64::arbitrary(g), g.size()))
+//!     }
+//!
+//!     fn shrink(&self) -> Box<dyn Iterator<Item = Ops>> {
+//!         Box::new(testing::shrink(&self.0).into_iter().map(Ops))
+//!     }
+//! }
+//! ```
+//!
+//! The model is sequential, so `check` runs the ops on one thread. Code
+//! that adds its own ordering on top of a channel can still be checked by
+//! wrapping it in a `Sender` and `Receiver` pair with the same behavior.
+//! For ops run on several threads at once, `history` checks that the
+//! results are linearizable, and the `deterministic` feature adds `sched`,
+//! which replays an interleaving from a seed.
+
+pub mod history;
+#[cfg(feature = "deterministic")]
+pub mod sched;
+
+use mpmc::{Receiver, Sender};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// One step of a property test
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op<T> {
+    Send(T),
+    TryRecv,
+    /// Receive up to this many queued messages at once, without blocking
+    RecvBatch(usize),
+}
+
+/// The result of an `Op`, from either the channel or the model
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome<T> {
+    Sent,
+    Received(Option<T>),
+    Batch(Vec<T>),
+}
+
+/// Which message a channel hands out next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    /// The oldest, like `mpmc::queue`
+    Fifo,
+    /// The newest, like `mpmc::stack`
+    Lifo,
+}
+
+/// A sequential reference model of a channel
+#[derive(Clone, Debug)]
+pub struct Model<T> {
+    items: VecDeque<T>,
+    order: Order,
+}
+
+impl<T> Model<T> {
+    pub fn new(order: Order) -> Model<T> {
+        Model {
+            items: VecDeque::new(),
+            order,
+        }
+    }
+
+    /// Messages currently queued
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn take(&mut self) -> Option<T> {
+        match self.order {
+            Order::Fifo => self.items.pop_front(),
+            Order::Lifo => self.items.pop_back(),
+        }
+    }
+
+    /// Apply `op` and return what a channel should have returned
+    pub fn apply(&mut self, op: Op<T>) -> Outcome<T> {
+        match op {
+            Op::Send(item) => {
+                self.items.push_back(item);
+                Outcome::Sent
+            }
+            Op::TryRecv => Outcome::Received(self.take()),
+            Op::RecvBatch(max) => Outcome::Batch((0..max).map_while(|_| self.take()).collect()),
+        }
+    }
+}
+
+/// Apply `op` to a channel
+pub fn apply<T: Send>(op: Op<T>, sender: &Sender<T>, receiver: &Receiver<T>) -> Outcome<T> {
+    match op {
+        // A message the channel refuses never comes back out, which the
+        // model will notice on a later receive
+        Op::Send(item) => {
+            let _ = sender.send(item);
+            Outcome::Sent
+        }
+        Op::TryRecv => Outcome::Received(receiver.try_recv().ok()),
+        Op::RecvBatch(max) => {
+            let mut items = Vec::new();
+            // Fails only when nothing was queued, which leaves `items`
+            // empty
+            let _ = receiver.try_recv_batch(&mut items, max);
+            Outcome::Batch(items)
+        }
+    }
+}
+
+/// The first step where a channel and the model disagreed
+#[derive(Clone, PartialEq)]
+pub struct Mismatch<T> {
+    /// Index of the op in the sequence
+    pub step: usize,
+    pub op: Op<T>,
+    pub expected: Outcome<T>,
+    pub actual: Outcome<T>,
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mismatch<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {} ({:?}): expected {:?}, got {:?}",
+            self.step, self.op, self.expected, self.actual
+        )
+    }
+}
+
+/// Run `ops` against the channel and a fresh model, and stop at the first
+/// step where they disagree. The channel should start out empty.
+pub fn check<T>(
+    order: Order,
+    ops: &[Op<T>],
+    sender: &Sender<T>,
+    receiver: &Receiver<T>,
+) -> Result<(), Mismatch<T>>
+where
+    T: Send + Clone + PartialEq,
+{
+    let mut model = Model::new(order);
+    for (step, op) in ops.iter().enumerate() {
+        let expected = model.apply(op.clone());
+        let actual = apply(op.clone(), sender, receiver);
+        if actual != expected {
+            return Err(Mismatch {
+                step,
+                op: op.clone(),
+                expected,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Draw `len` ops from `seed`. Sends carry distinct values, so a message
+/// that is lost, duplicated or reordered never goes unnoticed, and runs of
+/// sends and receives vary so the channel is sometimes drained dry and
+/// sometimes left to grow.
+pub fn ops(seed: u64, len: usize) -> Vec<Op<u64>> {
+    // Xorshift is stuck at zero, and nearby seeds should diverge
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut ops = Vec::with_capacity(len);
+    let mut value = 0;
+    while ops.len() < len {
+        let run = 1 + next() % 8;
+        let kind = next() % 4;
+        for _ in 0..run.min((len - ops.len()) as u64) {
+            ops.push(match kind {
+                0 | 1 => {
+                    value += 1;
+                    Op::Send(value)
+                }
+                2 => Op::TryRecv,
+                _ => Op::RecvBatch(1 + (next() % 8) as usize),
+            });
+        }
+    }
+    ops
+}
+
+/// Smaller sequences to try in place of a failing `ops`: first with half
+/// of it removed, then smaller and smaller chunks, down to single ops.
+pub fn shrink<T: Clone>(ops: &[Op<T>]) -> Vec<Vec<Op<T>>> {
+    let mut candidates = Vec::new();
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        for start in (0..ops.len()).step_by(chunk) {
+            let end = (start + chunk).min(ops.len());
+            let mut candidate = ops[..start].to_vec();
+            candidate.extend_from_slice(&ops[end..]);
+            candidates.push(candidate);
+        }
+        chunk /= 2;
+    }
+    candidates
+}
+
+/// Shrink a failing sequence for as long as one of its `shrink` candidates
+/// still makes `fails` return `true`. Returns `ops` itself if none does.
+pub fn minimize<T: Clone, F: FnMut(&[Op<T>]) -> bool>(ops: &[Op<T>], mut fails: F) -> Vec<Op<T>> {
+    let mut ops = ops.to_vec();
+    while let Some(smaller) = shrink(&ops).into_iter().find(|candidate| fails(candidate)) {
+        ops = smaller;
+    }
+    ops
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc;
+
+    #[test]
+    fn channels_match_model() {
+        for seed in 0..16 {
+            let ops = ops(seed, 200);
+            let (tx, rx) = mpmc::queue();
+            check(Order::Fifo, &ops, &tx, &rx).unwrap();
+            let (tx, rx) = mpmc::stack();
+            check(Order::Lifo, &ops, &tx, &rx).unwrap();
+        }
+    }
+
+    #[test]
+    fn reports_mismatch() {
+        let ops = vec![Op::Send(1), Op::Send(2), Op::TryRecv];
+        let (tx, rx) = mpmc::stack();
+        let mismatch = check(Order::Fifo, &ops, &tx, &rx).unwrap_err();
+        assert_eq!(mismatch.step, 2);
+        assert_eq!(mismatch.expected, Outcome::Received(Some(1)));
+        assert_eq!(mismatch.actual, Outcome::Received(Some(2)));
+    }
+
+    #[test]
+    fn minimize_keeps_failure() {
+        // Only fails while both sends and the receive are there
+        let fails = |ops: &[Op<u64>]| {
+            let (tx, rx) = mpmc::stack();
+            check(Order::Fifo, ops, &tx, &rx).is_err()
+        };
+        let mut ops = ops(3, 100);
+        ops.extend(vec![Op::TryRecv; 20]);
+        assert!(fails(&ops));
+        let small = minimize(&ops, fails);
+        assert!(fails(&small));
+        assert_eq!(small.len(), 3);
+    }
+}
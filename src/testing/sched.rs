@@ -0,0 +1,550 @@
+//! A seeded scheduler that makes thread interleavings reproducible.
+//!
+//! `run` executes a test with its threads taking turns: only one runs at a
+//! time, and at every atomic operation, fence, lock and yield in `mpmc`,
+//! `epoch` and `sync`'s backoff, the scheduler draws the thread that runs
+//! next from the seed. The same seed and the same test give the same
+//! interleaving, so a failure found by looping over seeds replays by
+//! running its seed again.
+//!
+//! ```
+//! use myriad::mpmc;
+//! use myriad::testing::sched;
+//!
+//! for seed in 0..8 {
+//!     sched::run(seed, || {
+//!         let (tx, rx) = mpmc::queue();
+//!         let consumer = sched::spawn(move || rx.recv());
+//!         tx.send(1).unwrap();
+//!         assert_eq!(consumer.join().unwrap(), Ok(1));
+//!     });
+//! }
+//! ```
+//!
+//! Threads take part only if spawned with `spawn` from inside `run`;
+//! anything else runs as usual, alongside. Waits that would block, on a
+//! futex, a condvar or a contended lock, give the turn away and then
+//! return or retry, which all of them are allowed to do spuriously, so the
+//! blocking protocol is exercised without any thread actually sleeping.
+//!
+//! Only available with the `deterministic` feature, which costs every
+//! atomic operation in the crate a thread-local check even outside `run`.
+
+use std::cell::Cell;
+use std::fmt;
+use std::mem;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+struct State {
+    rng: u64,
+    /// The thread whose turn it is
+    current: usize,
+    /// Whether each thread has finished, indexed by id. The thread that
+    /// called `run` is 0.
+    finished: Vec<bool>,
+    /// Who got each turn, in order
+    schedule: Vec<usize>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+/// Notified whenever the turn passes
+static TURN: Condvar = Condvar::new();
+/// Keeps runs from overlapping
+static RUN: Mutex<()> = Mutex::new(());
+
+thread_local! {
+    /// The scheduler's id for this thread, while it takes part in a run
+    static ID: Cell<Option<usize>> = const { Cell::new(None) };
+    /// Finishes a spawned thread once its other thread-locals are dropped
+    static FINISH: Finish = const { Finish };
+}
+
+fn state() -> MutexGuard<'static, Option<State>> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn id() -> Option<usize> {
+    ID.try_with(Cell::get).ok().flatten()
+}
+
+impl State {
+    /// Hand the turn to a random unfinished thread, possibly the current
+    /// one, and return it
+    fn pick(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let runnable = self.finished.iter().filter(|&&done| !done).count();
+        let nth = (self.rng % runnable as u64) as usize;
+        let (next, _) = self
+            .finished
+            .iter()
+            .enumerate()
+            .filter(|&(_, &done)| !done)
+            .nth(nth)
+            .expect("no thread left to run");
+        self.current = next;
+        self.schedule.push(next);
+        next
+    }
+}
+
+/// Block until it is thread `id`'s turn. If the run ends, for example
+/// because its test panicked, every thread just carries on.
+fn wait_turn(mut state: MutexGuard<'static, Option<State>>, id: usize) {
+    while state.as_ref().is_some_and(|state| state.current != id) {
+        state = TURN.wait(state).unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+/// A scheduling point: let the scheduler pick who runs next. Does nothing
+/// on threads outside a run.
+pub(crate) fn switch() {
+    let id = match id() {
+        Some(id) => id,
+        None => return,
+    };
+    let mut state = state();
+    let next = match state.as_mut() {
+        Some(state) => state.pick(),
+        None => return,
+    };
+    if next != id {
+        TURN.notify_all();
+        wait_turn(state, id);
+    }
+}
+
+/// Stand in for a blocking wait: inside a run, give the turn away and
+/// return `true` so the caller reports a spurious wakeup instead of
+/// sleeping while holding the turn
+pub(crate) fn yield_wait() -> bool {
+    if id().is_none() {
+        return false;
+    }
+    switch();
+    true
+}
+
+struct Finish;
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        if let Some(id) = id() {
+            let _ = ID.try_with(|cell| cell.set(None));
+            let mut state = state();
+            if let Some(state) = state.as_mut() {
+                state.finished[id] = true;
+                state.pick();
+            }
+            TURN.notify_all();
+        }
+    }
+}
+
+/// Run `test` under the scheduler, seeded with `seed`, and wait for every
+/// thread it spawned to finish. Returns the schedule, the id of the thread
+/// that got each turn, which is the same every time for the same seed.
+///
+/// Runs don't overlap: a run started while another is in progress, on
+/// another thread, waits for it.
+pub fn run<F: FnOnce()>(seed: u64, test: F) -> Vec<usize> {
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            ID.with(|id| id.set(None));
+            state().take();
+            TURN.notify_all();
+        }
+    }
+
+    let _run = RUN.lock().unwrap_or_else(PoisonError::into_inner);
+    *state() = Some(State {
+        // Nearby seeds should diverge, and xorshift is stuck at zero
+        rng: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        current: 0,
+        finished: vec![false],
+        schedule: Vec::new(),
+    });
+    ID.with(|id| id.set(Some(0)));
+    let reset = Reset;
+    test();
+    // Let stragglers the test didn't join finish too
+    while state()
+        .as_ref()
+        .is_some_and(|state| state.finished[1..].contains(&false))
+    {
+        switch();
+    }
+    let schedule = state()
+        .as_mut()
+        .map(|state| mem::take(&mut state.schedule))
+        .unwrap_or_default();
+    drop(reset);
+    schedule
+}
+
+/// Spawn a thread that takes turns with the others in the current run. Like
+/// `std::thread::spawn` outside a run.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let id = match (id(), state().as_mut()) {
+        (Some(_), Some(state)) => {
+            state.finished.push(false);
+            state.finished.len() - 1
+        }
+        _ => {
+            return JoinHandle {
+                id: None,
+                inner: thread::spawn(f),
+            }
+        }
+    };
+    let inner = thread::spawn(move || {
+        ID.with(|cell| cell.set(Some(id)));
+        // Registered first so it is dropped last, which keeps the thread
+        // scheduled while the thread-locals `f` used are dropped
+        FINISH.with(|_| ());
+        wait_turn(state(), id);
+        f()
+    });
+    JoinHandle {
+        id: Some(id),
+        inner,
+    }
+}
+
+/// Owned permission to join a thread spawned with `spawn`
+pub struct JoinHandle<T> {
+    /// The scheduler's id for the thread, if it takes part in a run
+    id: Option<usize>,
+    inner: thread::JoinHandle<T>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Wait for the thread to finish, taking turns meanwhile, and return
+    /// its result or the payload it panicked with
+    pub fn join(self) -> thread::Result<T> {
+        // Waits on the scheduler's record rather than the OS thread, so
+        // the number of turns taken meanwhile doesn't depend on timing.
+        // Once finished, the thread has nothing left that needs a turn.
+        if let Some(id) = self.id {
+            while state().as_ref().is_some_and(|state| !state.finished[id]) {
+                switch();
+            }
+        }
+        self.inner.join()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.inner.is_finished()
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("JoinHandle { .. }")
+    }
+}
+
+/// The primitives `primitive` hands out with the `deterministic` feature:
+/// `std`'s, with a scheduling point before every operation
+pub(crate) mod shim {
+    use super::switch;
+    use std::fmt;
+    use std::ops::{Deref, DerefMut};
+    use std::sync::{self, atomic, LockResult, PoisonError, TryLockError, TryLockResult};
+    use std::time::Duration;
+
+    pub use std::sync::atomic::Ordering;
+
+    macro_rules! atomic {
+        ($($name:ident($value:ty)),*) => {$(
+            #[derive(Default)]
+            pub struct $name(atomic::$name);
+
+            impl $name {
+                pub const fn new(value: $value) -> $name {
+                    $name(atomic::$name::new(value))
+                }
+            }
+
+            impl Deref for $name {
+                type Target = atomic::$name;
+
+                fn deref(&self) -> &atomic::$name {
+                    switch();
+                    &self.0
+                }
+            }
+
+            impl DerefMut for $name {
+                // Exclusive access can't race, so no scheduling point
+                fn deref_mut(&mut self) -> &mut atomic::$name {
+                    &mut self.0
+                }
+            }
+
+            impl fmt::Debug for $name {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    self.0.fmt(f)
+                }
+            }
+        )*};
+    }
+
+    atomic!(
+        AtomicBool(bool),
+        AtomicU32(u32),
+        AtomicU64(u64),
+        AtomicUsize(usize)
+    );
+
+    pub struct AtomicPtr<T>(atomic::AtomicPtr<T>);
+
+    impl<T> AtomicPtr<T> {
+        pub const fn new(ptr: *mut T) -> AtomicPtr<T> {
+            AtomicPtr(atomic::AtomicPtr::new(ptr))
+        }
+    }
+
+    impl<T> Deref for AtomicPtr<T> {
+        type Target = atomic::AtomicPtr<T>;
+
+        fn deref(&self) -> &atomic::AtomicPtr<T> {
+            switch();
+            &self.0
+        }
+    }
+
+    impl<T> DerefMut for AtomicPtr<T> {
+        fn deref_mut(&mut self) -> &mut atomic::AtomicPtr<T> {
+            &mut self.0
+        }
+    }
+
+    impl<T> fmt::Debug for AtomicPtr<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    pub fn fence(order: Ordering) {
+        switch();
+        atomic::fence(order);
+    }
+
+    /// A mutex whose waiters take turns instead of blocking
+    #[derive(Default)]
+    pub struct Mutex<T: ?Sized> {
+        inner: sync::Mutex<T>,
+    }
+
+    pub struct MutexGuard<'a, T: ?Sized + 'a> {
+        mutex: &'a Mutex<T>,
+        inner: sync::MutexGuard<'a, T>,
+    }
+
+    impl<T> Mutex<T> {
+        pub const fn new(value: T) -> Mutex<T> {
+            Mutex {
+                inner: sync::Mutex::new(value),
+            }
+        }
+    }
+
+    impl<T: ?Sized> Mutex<T> {
+        fn wrap<'a>(&'a self, inner: sync::MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+            MutexGuard { mutex: self, inner }
+        }
+
+        pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+            if super::id().is_none() {
+                return match self.inner.lock() {
+                    Ok(inner) => Ok(self.wrap(inner)),
+                    Err(poison) => Err(PoisonError::new(self.wrap(poison.into_inner()))),
+                };
+            }
+            loop {
+                match self.try_lock() {
+                    Ok(guard) => return Ok(guard),
+                    Err(TryLockError::Poisoned(poison)) => return Err(poison),
+                    Err(TryLockError::WouldBlock) => (),
+                }
+            }
+        }
+
+        pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+            switch();
+            match self.inner.try_lock() {
+                Ok(inner) => Ok(self.wrap(inner)),
+                Err(TryLockError::Poisoned(poison)) => Err(TryLockError::Poisoned(
+                    PoisonError::new(self.wrap(poison.into_inner())),
+                )),
+                Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+            }
+        }
+    }
+
+    impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.inner.fmt(f)
+        }
+    }
+
+    impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.inner
+        }
+    }
+
+    impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.inner
+        }
+    }
+
+    /// A condvar whose waits give the turn away and wake spuriously
+    #[derive(Debug, Default)]
+    pub struct Condvar {
+        inner: sync::Condvar,
+    }
+
+    impl Condvar {
+        pub const fn new() -> Condvar {
+            Condvar {
+                inner: sync::Condvar::new(),
+            }
+        }
+
+        pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+            let MutexGuard { mutex, inner } = guard;
+            if super::id().is_none() {
+                return match self.inner.wait(inner) {
+                    Ok(inner) => Ok(mutex.wrap(inner)),
+                    Err(poison) => Err(PoisonError::new(mutex.wrap(poison.into_inner()))),
+                };
+            }
+            drop(inner);
+            mutex.lock()
+        }
+
+        pub fn wait_timeout<'a, T>(
+            &self,
+            guard: MutexGuard<'a, T>,
+            timeout: Duration,
+        ) -> LockResult<(MutexGuard<'a, T>, sync::WaitTimeoutResult)> {
+            let guard = if super::id().is_none() {
+                guard
+            } else {
+                // Let others run with the lock released, then make a
+                // zero-length wait for a result to return
+                let MutexGuard { mutex, inner } = guard;
+                drop(inner);
+                match mutex.lock() {
+                    Ok(guard) => guard,
+                    Err(poison) => poison.into_inner(),
+                }
+            };
+            let timeout = if super::id().is_none() {
+                timeout
+            } else {
+                Duration::ZERO
+            };
+            let MutexGuard { mutex, inner } = guard;
+            match self.inner.wait_timeout(inner, timeout) {
+                Ok((inner, result)) => Ok((mutex.wrap(inner), result)),
+                Err(poison) => {
+                    let (inner, result) = poison.into_inner();
+                    Err(PoisonError::new((mutex.wrap(inner), result)))
+                }
+            }
+        }
+
+        pub fn notify_one(&self) {
+            switch();
+            self.inner.notify_one();
+        }
+
+        pub fn notify_all(&self) {
+            switch();
+            self.inner.notify_all();
+        }
+    }
+
+    pub mod hint {
+        pub fn spin_loop() {
+            super::switch();
+            ::std::hint::spin_loop();
+        }
+    }
+
+    pub mod thread {
+        // Stands in for all of `std::thread`, though only tests spawn
+        #[allow(unused_imports)]
+        pub use std::thread::*;
+        #[allow(unused_imports)]
+        pub use testing::sched::{spawn, JoinHandle};
+
+        pub fn yield_now() {
+            super::switch();
+            ::std::thread::yield_now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mpmc;
+
+    fn scenario() {
+        let (tx, rx) = mpmc::queue();
+        let producers = (0..2)
+            .map(|id| {
+                let tx = tx.clone();
+                spawn(move || {
+                    for i in 0..20 {
+                        tx.send(id * 100 + i).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+        let mut received = Vec::new();
+        while let Ok(item) = rx.recv() {
+            received.push(item);
+        }
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert_eq!(received.len(), 40);
+    }
+
+    #[test]
+    fn same_seed_same_schedule() {
+        let schedule = run(7, scenario);
+        assert!(schedule.len() > 40);
+        assert_eq!(run(7, scenario), schedule);
+        assert_ne!(run(8, scenario), schedule);
+    }
+
+    #[test]
+    fn panics_reach_join() {
+        run(1, || {
+            let handle = spawn(|| panic!("boom"));
+            assert!(handle.join().is_err());
+        });
+    }
+
+    #[test]
+    fn outside_run() {
+        assert_eq!(spawn(|| 1).join().unwrap(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+//! Spawning groups of named threads that report how they exit.
+//!
+//! Ad-hoc worker threads tend to end up with inconsistent names and with
+//! panics that nobody collects. A `Builder` names every thread it spawns
+//! after the group, with an index, and sends each thread's result, or
+//! panic, to the receiver returned alongside the `Spawner`:
+//!
+//! ```
+//! use myriad::thread::Builder;
+//!
+//! let (spawner, exits) = Builder::new("ingest").build();
+//! for shard in 0..4 {
+//!     spawner.spawn(move || shard * 10).unwrap();
+//! }
+//! drop(spawner);
+//! let mut total = 0;
+//! while let Ok(exit) = exits.recv() {
+//!     assert!(exit.name.starts_with("ingest-"));
+//!     total += exit.result.unwrap();
+//! }
+//! assert_eq!(total, 60);
+//! ```
+
+use mpmc::{self, Receiver, Sender};
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering::*};
+use std::sync::Arc;
+use std::thread;
+
+/// Configures a group of threads
+#[derive(Clone, Debug)]
+pub struct Builder {
+    name: String,
+    stack_size: Option<usize>,
+}
+
+/// How a thread spawned by a `Spawner` exited
+pub struct Exit<T> {
+    /// The thread's name, `{group}-{index}`
+    pub name: String,
+    /// What the thread returned, or its panic payload
+    pub result: thread::Result<T>,
+}
+
+/// Spawns the threads of a group. Once every clone is dropped and every
+/// thread has exited, the exit channel disconnects.
+pub struct Spawner<T: Send> {
+    config: Arc<Builder>,
+    next: Arc<AtomicUsize>,
+    exits: Sender<Exit<T>>,
+}
+
+impl Builder {
+    /// A builder for threads named `{name}-0`, `{name}-1`, and so on
+    pub fn new(name: &str) -> Builder {
+        Builder {
+            name: name.to_string(),
+            stack_size: None,
+        }
+    }
+
+    /// Set the stack size of each thread, in bytes. Defaults to the
+    /// standard library's default.
+    pub fn stack_size(mut self, bytes: usize) -> Builder {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Create the group's spawner, and the channel that receives an `Exit`
+    /// for each thread it spawns
+    pub fn build<T: Send + 'static>(self) -> (Spawner<T>, Receiver<Exit<T>>) {
+        let (exits, rx) = mpmc::queue();
+        let spawner = Spawner {
+            config: Arc::new(self),
+            next: Arc::new(AtomicUsize::new(0)),
+            exits,
+        };
+        (spawner, rx)
+    }
+}
+
+impl<T: Send + 'static> Spawner<T> {
+    /// Spawn a thread running `f`, returning its name
+    pub fn spawn<F>(&self, f: F) -> io::Result<String>
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let name = format!("{}-{}", self.config.name, self.next.fetch_add(1, Relaxed));
+        let mut builder = thread::Builder::new().name(name.clone());
+        if let Some(bytes) = self.config.stack_size {
+            builder = builder.stack_size(bytes);
+        }
+        let exits = self.exits.clone();
+        let thread_name = name.clone();
+        builder.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f));
+            // Nobody is listening if the receiver is gone
+            let _ = exits.send(Exit {
+                name: thread_name,
+                result,
+            });
+        })?;
+        Ok(name)
+    }
+}
+
+impl<T: Send> Clone for Spawner<T> {
+    fn clone(&self) -> Spawner<T> {
+        Spawner {
+            config: self.config.clone(),
+            next: self.next.clone(),
+            exits: self.exits.clone(),
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for Spawner<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Spawner")
+            .field("name", &self.config.name)
+            .field("spawned", &self.next.load(Relaxed))
+            .finish()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Exit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Exit")
+            .field("name", &self.name)
+            .field("result", &self.result)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_and_results() {
+        let (spawner, exits) = Builder::new("worker").build();
+        let names = (0..3)
+            .map(|_| {
+                spawner
+                    .spawn(|| thread::current().name().unwrap().to_string())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["worker-0", "worker-1", "worker-2"]);
+        drop(spawner);
+        let mut exits = (0..3).map(|_| exits.recv().unwrap()).collect::<Vec<_>>();
+        exits.sort_by(|a, b| a.name.cmp(&b.name));
+        for (exit, name) in exits.into_iter().zip(names) {
+            assert_eq!(exit.result.unwrap(), name);
+            assert_eq!(exit.name, name);
+        }
+    }
+
+    #[test]
+    fn collects_panics() {
+        let (spawner, exits) = Builder::new("fragile").stack_size(256 * 1024).build::<()>();
+        spawner.spawn(|| panic!("worker failed")).unwrap();
+        let exit = exits.recv().unwrap();
+        assert_eq!(exit.name, "fragile-0");
+        let payload = exit.result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"worker failed"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Timers.
+//!
+//! `DelayQueue` holds each item back until its delay has elapsed, then
+//! hands it out like any other channel, and `Scheduler` runs jobs on a
+//! thread pool at a set time or periodically. Both keep their deadlines in
+//! a hashed timer wheel driven by a dedicated timer thread, so scheduling
+//! a timer costs the same however many are pending.
+
+use mpmc::{self, Error, Receiver, Sender};
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod scheduler;
+mod wheel;
+
+pub use self::scheduler::Scheduler;
+use self::wheel::Wheel;
+
+/// A channel whose items only become available to `recv` once their delay
+/// has elapsed. Items on delays that elapse together are received in the
+/// order they were sent.
+pub struct DelayQueue<T: Send> {
+    timers: Sender<(Instant, T)>,
+    ready: Receiver<T>,
+}
+
+impl<T: Send + 'static> DelayQueue<T> {
+    /// Create a queue along with the timer thread that serves it. The
+    /// thread exits once the queue is dropped and every pending item has
+    /// been delivered, or every receiver is gone.
+    pub fn new() -> DelayQueue<T> {
+        let (timers, pending) = mpmc::queue();
+        let (expired, ready) = mpmc::queue();
+        thread::Builder::new()
+            .name("myriad-timer".into())
+            .spawn(move || drive(pending, true, |item, _| expired.send(item).is_ok()))
+            .expect("failed to spawn timer thread");
+        DelayQueue { timers, ready }
+    }
+
+    /// Make `item` available once `delay` has elapsed
+    pub fn send(&self, item: T, delay: Duration) {
+        self.send_at(item, Instant::now() + delay)
+    }
+
+    /// Make `item` available once `deadline` has passed
+    pub fn send_at(&self, item: T, deadline: Instant) {
+        // The timer thread holds the receiving end for as long as the
+        // queue is alive, so this can't fail
+        let _ = self.timers.send((deadline, item));
+    }
+}
+
+impl<T: Send> DelayQueue<T> {
+    /// Take an item whose delay has elapsed, without blocking
+    pub fn try_recv(&self) -> Result<T, Error> {
+        self.ready.try_recv()
+    }
+
+    /// Block until an item's delay has elapsed
+    pub fn recv(&self) -> Result<T, Error> {
+        self.ready.recv()
+    }
+
+    /// Block until an item's delay has elapsed, or `timeout` does
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, Error> {
+        self.ready.recv_timeout(timeout)
+    }
+
+    /// A receiver for items whose delay has elapsed, which keeps working
+    /// after the queue is dropped until the remaining items are delivered
+    pub fn receiver(&self) -> Receiver<T> {
+        self.ready.clone()
+    }
+}
+
+impl<T: Send + 'static> Default for DelayQueue<T> {
+    fn default() -> DelayQueue<T> {
+        DelayQueue::new()
+    }
+}
+
+/// Keep a wheel of the items arriving on `pending`, passing each to `fire`
+/// once its deadline passes, until `fire` returns `false` or `pending`
+/// disconnects. Items arriving already due skip the wheel. With `drain`
+/// set, items still in the wheel when `pending` disconnects fire on
+/// schedule before this returns; otherwise they are dropped.
+pub(crate) fn drive<T, F>(pending: Receiver<(Instant, T)>, drain: bool, mut fire: F)
+where
+    T: Send,
+    F: FnMut(T, &mut Wheel<T>) -> bool,
+{
+    let mut wheel = Wheel::new(Instant::now());
+    let mut arrived = Vec::new();
+    let mut due = Vec::new();
+    let mut open = true;
+    while open || (drain && !wheel.is_empty()) {
+        let deadline = wheel.next_deadline();
+        if open {
+            let received = match deadline {
+                None => pending.recv(),
+                Some(deadline) => {
+                    pending.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+            };
+            match received {
+                Ok(item) => arrived.push(item),
+                Err(Error::Disconnected) => open = false,
+                Err(_) => (),
+            }
+            while let Ok(item) = pending.try_recv() {
+                arrived.push(item);
+            }
+        } else if let Some(deadline) = deadline {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+
+        let now = Instant::now();
+        wheel.advance(now, |item| due.push(item));
+        for (deadline, item) in arrived.drain(..) {
+            if deadline <= now {
+                due.push(item);
+            } else {
+                wheel.insert(deadline, item);
+            }
+        }
+        for item in due.drain(..) {
+            if !fire(item, &mut wheel) {
+                return;
+            }
+        }
+    }
+}
+
+impl<T: Send> fmt::Debug for DelayQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DelayQueue")
+            .field("ready", &self.ready.stats().queued)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delays() {
+        let queue = DelayQueue::new();
+        let start = Instant::now();
+        queue.send('c', Duration::from_millis(30));
+        queue.send('a', Duration::from_millis(5));
+        queue.send('b', Duration::from_millis(15));
+        assert_eq!(queue.try_recv(), Err(Error::Empty));
+        assert_eq!(queue.recv(), Ok('a'));
+        assert!(start.elapsed() >= Duration::from_millis(5));
+        assert_eq!(queue.recv(), Ok('b'));
+        assert_eq!(queue.recv(), Ok('c'));
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn delivers_after_drop() {
+        let queue = DelayQueue::new();
+        let rx = queue.receiver();
+        queue.send(1, Duration::from_millis(10));
+        queue.send_at(0, Instant::now());
+        drop(queue);
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(rx.recv(), Ok(1));
+        // The timer thread exits once nothing is pending
+        assert_eq!(rx.recv(), Err(Error::Disconnected));
+    }
+}
@@ -0,0 +1,177 @@
+//! Running jobs on a thread pool at set times.
+
+use super::drive;
+use mpmc::{self, Sender};
+use pool::ThreadPool;
+use std::fmt;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use sync::CancellationToken;
+
+enum Task {
+    Once(Box<dyn FnOnce() + Send>),
+    Every(Arc<dyn Fn() + Send + Sync>, Duration),
+}
+
+struct Timer {
+    task: Task,
+    deadline: Instant,
+    token: CancellationToken,
+}
+
+/// Runs jobs on a `ThreadPool` once a deadline passes, or periodically.
+///
+/// Each scheduling call returns a `CancellationToken`; cancelling it stops
+/// the job from running again, but doesn't interrupt a run that has
+/// already started. Dropping the scheduler cancels every pending timer,
+/// then waits for jobs already handed to the pool, so it must not be
+/// dropped from inside one of its own jobs.
+pub struct Scheduler {
+    timers: Option<Sender<(Instant, Timer)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    /// A scheduler running its jobs on a pool of `threads` workers
+    pub fn new(threads: usize) -> Scheduler {
+        Scheduler::with_pool(ThreadPool::new(threads))
+    }
+
+    /// A scheduler running its jobs on `pool`
+    pub fn with_pool(pool: ThreadPool) -> Scheduler {
+        let (timers, pending) = mpmc::queue();
+        let thread = thread::Builder::new()
+            .name("myriad-scheduler".into())
+            .spawn(move || {
+                drive(pending, false, |timer: Timer, wheel| {
+                    if timer.token.is_cancelled() {
+                        return true;
+                    }
+                    match timer.task {
+                        Task::Once(f) => drop(pool.spawn(f)),
+                        Task::Every(f, period) => {
+                            let job = f.clone();
+                            drop(pool.spawn(move || job()));
+                            // Runs that fell behind are skipped rather
+                            // than fired back to back
+                            let now = Instant::now();
+                            let mut deadline = timer.deadline + period;
+                            if deadline <= now {
+                                deadline = now + period;
+                            }
+                            let timer = Timer {
+                                task: Task::Every(f, period),
+                                deadline,
+                                token: timer.token,
+                            };
+                            wheel.insert(deadline, timer);
+                        }
+                    }
+                    true
+                });
+            })
+            .expect("failed to spawn scheduler thread");
+        Scheduler {
+            timers: Some(timers),
+            thread: Some(thread),
+        }
+    }
+
+    fn schedule(&self, deadline: Instant, task: Task) -> CancellationToken {
+        let token = CancellationToken::new();
+        let timer = Timer {
+            task,
+            deadline,
+            token: token.clone(),
+        };
+        // The scheduler thread holds the receiving end until the
+        // scheduler is dropped, so this can't fail
+        let _ = self.timers.as_ref().unwrap().send((deadline, timer));
+        token
+    }
+
+    /// Run `f` once `deadline` has passed
+    pub fn at<F: FnOnce() + Send + 'static>(&self, deadline: Instant, f: F) -> CancellationToken {
+        self.schedule(deadline, Task::Once(Box::new(f)))
+    }
+
+    /// Run `f` every `period`, starting one period from now, until the
+    /// returned token is cancelled. A run that takes longer than `period`
+    /// may overlap with the next one.
+    ///
+    /// Panics if `period` is zero.
+    pub fn every<F>(&self, period: Duration, f: F) -> CancellationToken
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        assert!(period > Duration::ZERO, "period must be nonzero");
+        self.schedule(Instant::now() + period, Task::Every(Arc::new(f), period))
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.timers.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scheduler").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering::*};
+
+    #[test]
+    fn at() {
+        let scheduler = Scheduler::new(1);
+        let (tx, rx) = mpmc::queue();
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(10);
+        scheduler.at(deadline, move || tx.send(Instant::now()).unwrap());
+        let cancelled = scheduler.at(start, || panic!("cancelled job ran"));
+        cancelled.cancel();
+        assert!(rx.recv().unwrap() >= deadline);
+    }
+
+    #[test]
+    fn every_until_cancelled() {
+        let scheduler = Scheduler::new(2);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let count = runs.clone();
+        let token = scheduler.every(Duration::from_millis(2), move || {
+            count.fetch_add(1, SeqCst);
+        });
+        while runs.load(SeqCst) < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        token.cancel();
+        // Allow a run that was already handed to the pool to finish
+        thread::sleep(Duration::from_millis(10));
+        let stopped = runs.load(SeqCst);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(runs.load(SeqCst), stopped);
+    }
+
+    #[test]
+    fn drop_cancels_pending() {
+        let scheduler = Scheduler::new(1);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let count = runs.clone();
+        scheduler.at(Instant::now() + Duration::from_secs(60), move || {
+            count.fetch_add(1, SeqCst);
+        });
+        let start = Instant::now();
+        drop(scheduler);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(runs.load(SeqCst), 0);
+    }
+}
@@ -0,0 +1,152 @@
+//! A hashed timer wheel.
+//!
+//! Time is split into ticks, and each entry goes into the slot for its
+//! deadline's tick modulo the number of slots, so inserting is constant
+//! time however far away the deadline is. Advancing the wheel visits only
+//! the slots for the ticks that passed, firing the entries whose tick has
+//! come and leaving those that are still a lap or more away.
+
+use std::time::{Duration, Instant};
+
+const SLOTS: usize = 512;
+
+/// The wheel's granularity. Entries never fire early, but may fire up to
+/// one tick late.
+const TICK: Duration = Duration::from_millis(1);
+
+struct Entry<T> {
+    tick: u64,
+    item: T,
+}
+
+pub(crate) struct Wheel<T> {
+    start: Instant,
+    slots: Vec<Vec<Entry<T>>>,
+    /// Every entry for a tick before this one has fired
+    tick: u64,
+    len: usize,
+}
+
+impl<T> Wheel<T> {
+    pub(crate) fn new(start: Instant) -> Wheel<T> {
+        Wheel {
+            start,
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            tick: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn ticks(&self, duration: Duration) -> u64 {
+        (duration.as_nanos() / TICK.as_nanos()) as u64
+    }
+
+    /// Schedule `item` to fire once `deadline` has passed
+    pub(crate) fn insert(&mut self, deadline: Instant, item: T) {
+        let elapsed = deadline.saturating_duration_since(self.start);
+        let mut tick = self.ticks(elapsed);
+        // Round up, so the entry can't fire before its deadline
+        if !elapsed.as_nanos().is_multiple_of(TICK.as_nanos()) {
+            tick += 1;
+        }
+        let tick = tick.max(self.tick);
+        self.slots[(tick % SLOTS as u64) as usize].push(Entry { tick, item });
+        self.len += 1;
+    }
+
+    /// Fire every entry whose deadline is at or before `now`
+    pub(crate) fn advance(&mut self, now: Instant, mut fire: impl FnMut(T)) {
+        let target = self.ticks(now.saturating_duration_since(self.start));
+        if target < self.tick {
+            return;
+        }
+        // After a full lap every slot has been visited once
+        let last = target.min(self.tick + SLOTS as u64 - 1);
+        for tick in self.tick..=last {
+            let slot = &mut self.slots[(tick % SLOTS as u64) as usize];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick <= target {
+                    fire(slot.remove(i).item);
+                    self.len -= 1;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.tick = target + 1;
+    }
+
+    /// The earliest time an entry might fire, or `None` if the wheel is
+    /// empty. This is when the next occupied slot comes up, so its
+    /// entries may turn out to be laps away yet.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        if self.is_empty() {
+            return None;
+        }
+        (0..SLOTS as u64)
+            .map(|offset| self.tick + offset)
+            .find(|&tick| !self.slots[(tick % SLOTS as u64) as usize].is_empty())
+            .map(|tick| self.start + Duration::from_nanos(tick * TICK.as_nanos() as u64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fired<T>(wheel: &mut Wheel<T>, now: Instant) -> Vec<T> {
+        let mut items = Vec::new();
+        wheel.advance(now, |item| items.push(item));
+        items
+    }
+
+    #[test]
+    fn fires_in_order() {
+        let start = Instant::now();
+        let mut wheel = Wheel::new(start);
+        let ms = Duration::from_millis;
+        wheel.insert(start + ms(5), 'b');
+        wheel.insert(start + ms(2), 'a');
+        wheel.insert(start + ms(20), 'c');
+        assert_eq!(wheel.next_deadline(), Some(start + ms(2)));
+        assert!(fired(&mut wheel, start + ms(1)).is_empty());
+        assert_eq!(fired(&mut wheel, start + ms(5)), vec!['a', 'b']);
+        assert!(!wheel.is_empty());
+        assert_eq!(fired(&mut wheel, start + ms(100)), vec!['c']);
+        assert!(wheel.is_empty());
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn laps() {
+        let start = Instant::now();
+        let mut wheel = Wheel::new(start);
+        let lap = TICK * SLOTS as u32;
+        // Same slot, different laps
+        wheel.insert(start + TICK * 3, 1);
+        wheel.insert(start + lap + TICK * 3, 2);
+        wheel.insert(start + lap * 3, 3);
+        assert_eq!(fired(&mut wheel, start + lap), vec![1]);
+        assert_eq!(fired(&mut wheel, start + lap + TICK * 3), vec![2]);
+        // Jumping several laps at once still visits every slot
+        assert_eq!(fired(&mut wheel, start + lap * 10), vec![3]);
+    }
+
+    #[test]
+    fn never_early() {
+        let start = Instant::now();
+        let mut wheel = Wheel::new(start);
+        let deadline = start + TICK / 2;
+        wheel.insert(deadline, ());
+        assert!(fired(&mut wheel, start).is_empty());
+        assert_eq!(fired(&mut wheel, start + TICK).len(), 1);
+        // A deadline already passed fires on the next tick
+        wheel.insert(start, ());
+        assert_eq!(fired(&mut wheel, start + TICK * 2).len(), 1);
+    }
+}